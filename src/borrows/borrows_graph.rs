@@ -1,15 +1,16 @@
 use rustc_interface::{
     ast::Mutability,
     borrowck::consumers::BorrowIndex,
-    data_structures::fx::FxHashSet,
-    middle::mir::{self, BasicBlock, Location},
-    middle::ty::{Region, TyCtxt},
+    data_structures::fx::{FxHashMap, FxHashSet},
+    middle::mir::{self, BasicBlock, Local, Location},
+    middle::ty::TyCtxt,
 };
 use serde_json::json;
 
 use crate::{
+    r#loop::LoopAnalysis,
     rustc_interface,
-    utils::{Place, PlaceRepacker},
+    utils::{assumptions::check_assumption, Place, PlaceRepacker, PlaceSnapshot, SnapshotLocation},
 };
 
 use super::{
@@ -17,8 +18,9 @@ use super::{
     borrows_visitor::DebugCtx,
     deref_expansion::DerefExpansion,
     domain::{
-        AbstractionBlockEdge, AbstractionTarget, AbstractionType, LoopAbstraction, MaybeOldPlace,
-        Reborrow, ReborrowBlockedPlace, ToJsonWithRepacker,
+        AbstractionBlockEdge, AbstractionTarget, AbstractionType, FunctionCallAbstraction,
+        LoopAbstraction, MaybeOldPlace, Reborrow, ReborrowBlockedPlace, ReborrowId, ReborrowKind,
+        ToJsonWithRepacker,
     },
     latest::Latest,
     path_condition::{PathCondition, PathConditions},
@@ -44,6 +46,35 @@ impl<'tcx> BorrowsGraph<'tcx> {
         self.0.iter()
     }
 
+    /// The edges of the graph in an order that doesn't depend on how the
+    /// graph was built: by kind, then by the places they block and the
+    /// places blocking them, then by conditions. Used for output that should
+    /// be reproducible (e.g. JSON dumps and visualizations).
+    pub fn ordered_edges(&self, repacker: PlaceRepacker<'_, 'tcx>) -> Vec<&BorrowsEdge<'tcx>> {
+        fn sorted_strings(places: impl IntoIterator<Item = impl std::fmt::Debug>) -> Vec<String> {
+            let mut strings: Vec<_> = places.into_iter().map(|p| format!("{:?}", p)).collect();
+            strings.sort();
+            strings
+        }
+        let mut edges: Vec<_> = self.0.iter().collect();
+        edges.sort_by_cached_key(|edge| {
+            let kind = match edge.kind {
+                BorrowsEdgeKind::Reborrow(_) => 0,
+                BorrowsEdgeKind::DerefExpansion(_) => 1,
+                BorrowsEdgeKind::RegionAbstraction(_) => 2,
+                BorrowsEdgeKind::RegionProjectionMember(_) => 3,
+            };
+            (
+                kind,
+                sorted_strings(edge.blocked_places()),
+                sorted_strings(edge.blocked_by_places(repacker)),
+                format!("{:?}", edge.kind),
+                format!("{:?}", edge.conditions),
+            )
+        });
+        edges
+    }
+
     pub fn abstraction_edges(&self) -> FxHashSet<Conditioned<AbstractionEdge<'tcx>>> {
         self.0
             .iter()
@@ -57,6 +88,87 @@ impl<'tcx> BorrowsGraph<'tcx> {
             .collect()
     }
 
+    /// Stitches the graph of a callee at its return to the abstraction `call`
+    /// of a call to it in a caller: returns the graph containing the edges of
+    /// `call` that are realised by the callee, i.e. where the returned
+    /// reference derives from the corresponding argument in `self`. The
+    /// places of the returned graph are those of the caller, `repacker` is
+    /// the one of the callee.
+    ///
+    /// For now only edges from the target of an argument reference to the
+    /// target of the result (e.g. for `fn f(x: &mut T) -> &mut T`) are
+    /// considered; region projections are ignored.
+    pub fn project_through_call(
+        &self,
+        call: &FunctionCallAbstraction<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> BorrowsGraph<'tcx> {
+        let mut result = BorrowsGraph::new();
+        let return_place: Place<'tcx> = mir::RETURN_PLACE.into();
+        if !return_place.ty(repacker).ty.is_ref() {
+            return result;
+        }
+
+        // The arguments whose targets the target of the result may derive from
+        let mut sources = FxHashSet::default();
+        let mut visited = FxHashSet::default();
+        let mut worklist: Vec<MaybeOldPlace<'tcx>> =
+            vec![return_place.project_deref(repacker).into()];
+        while let Some(place) = worklist.pop() {
+            if !visited.insert(place) {
+                continue;
+            }
+            for edge in self.0.iter() {
+                if !edge.blocked_by_places(repacker).contains(&place) {
+                    continue;
+                }
+                for blocked in edge.blocked_places() {
+                    match blocked {
+                        ReborrowBlockedPlace::Local(place) => worklist.push(place),
+                        ReborrowBlockedPlace::Remote(local) => {
+                            sources.insert(local);
+                        }
+                        ReborrowBlockedPlace::Constant(_) => {}
+                    }
+                }
+            }
+        }
+
+        let edges: Vec<_> = call
+            .edges()
+            .iter()
+            .filter(|(idx, edge)| {
+                matches!(
+                    (edge.input, edge.output),
+                    (
+                        AbstractionTarget::Place(ReborrowBlockedPlace::Local(_)),
+                        AbstractionTarget::Place(_)
+                    )
+                ) && sources.contains(&mir::Local::from_usize(idx + 1))
+            })
+            .copied()
+            .collect();
+        // The assumption checked by `FunctionCallAbstraction::new` holds for
+        // non-empty `edges`
+        if !edges.is_empty()
+            && let Some(abstraction) = FunctionCallAbstraction::new(
+                call.location(),
+                call.def_id(),
+                call.substs(),
+                edges,
+                false,
+            )
+        {
+            result.insert(BorrowsEdge::new(
+                BorrowsEdgeKind::RegionAbstraction(AbstractionEdge::new(
+                    AbstractionType::FunctionCall(abstraction),
+                )),
+                PathConditions::new(call.location().block),
+            ));
+        }
+        result
+    }
+
     pub fn deref_expansions(&self) -> FxHashSet<Conditioned<DerefExpansion<'tcx>>> {
         self.0
             .iter()
@@ -83,6 +195,52 @@ impl<'tcx> BorrowsGraph<'tcx> {
             .collect()
     }
 
+    /// The length of the longest chain of reborrows in the graph, where each
+    /// reborrow in the chain blocks the place assigned by the previous one.
+    pub fn max_reborrow_chain_length(&self) -> usize {
+        let reborrows: Vec<_> = self.reborrows().into_iter().map(|r| r.value).collect();
+        let mut blocking: FxHashMap<ReborrowBlockedPlace<'tcx>, Vec<usize>> = FxHashMap::default();
+        for (idx, reborrow) in reborrows.iter().enumerate() {
+            blocking
+                .entry(reborrow.blocked_place)
+                .or_default()
+                .push(idx);
+        }
+        let successors: Vec<Vec<usize>> = reborrows
+            .iter()
+            .map(|reborrow| {
+                blocking
+                    .get(&reborrow.assigned_place.into())
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect();
+        longest_chain(&successors)
+    }
+
+    /// The length of the longest chain of deref expansions in the graph, where
+    /// the base of each expansion is a place in the previous expansion.
+    pub fn max_deref_expansion_depth(&self, repacker: PlaceRepacker<'_, 'tcx>) -> usize {
+        let expansions: Vec<_> = self
+            .deref_expansions()
+            .into_iter()
+            .map(|e| e.value)
+            .collect();
+        let successors: Vec<Vec<usize>> = expansions
+            .iter()
+            .map(|expansion| {
+                let expanded = expansion.expansion(repacker);
+                expansions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| expanded.contains(&e.base()))
+                    .map(|(idx, _)| idx)
+                    .collect()
+            })
+            .collect();
+        longest_chain(&successors)
+    }
+
     pub fn has_reborrow_at_location(&self, location: Location) -> bool {
         self.0.iter().any(|edge| match &edge.kind {
             BorrowsEdgeKind::Reborrow(reborrow) => reborrow.reserve_location() == location,
@@ -90,6 +248,13 @@ impl<'tcx> BorrowsGraph<'tcx> {
         })
     }
 
+    pub fn has_reborrow(&self, id: ReborrowId) -> bool {
+        self.0.iter().any(|edge| match &edge.kind {
+            BorrowsEdgeKind::Reborrow(reborrow) => reborrow.id() == id,
+            _ => false,
+        })
+    }
+
     pub fn reborrows_blocked_by(
         &self,
         place: MaybeOldPlace<'tcx>,
@@ -123,10 +288,17 @@ impl<'tcx> BorrowsGraph<'tcx> {
             .all(|p| !self.has_edge_blocking(*p))
     }
 
+    pub fn leaf_edges_iter<'slf>(
+        &'slf self,
+        repacker: PlaceRepacker<'slf, 'tcx>,
+    ) -> impl Iterator<Item = &'slf BorrowsEdge<'tcx>> {
+        self.0
+            .iter()
+            .filter(move |edge| self.is_leaf_edge(edge, repacker))
+    }
+
     pub fn leaf_edges(&self, repacker: PlaceRepacker<'_, 'tcx>) -> FxHashSet<BorrowsEdge<'tcx>> {
-        let mut candidates = self.0.clone();
-        candidates.retain(|edge| self.is_leaf_edge(edge, repacker));
-        candidates
+        self.leaf_edges_iter(repacker).cloned().collect()
     }
 
     pub fn num_paths_between(
@@ -135,22 +307,113 @@ impl<'tcx> BorrowsGraph<'tcx> {
         blocked: ReborrowBlockedPlace<'tcx>,
         repacker: PlaceRepacker<'_, 'tcx>,
     ) -> usize {
+        self.num_paths_between_memoized(blocking, blocked, repacker, &mut FxHashMap::default())
+    }
+
+    /// `counts` caches the number of paths from each intermediate place to
+    /// `blocked`, so that places reachable along several paths (e.g. in
+    /// diamond-shaped graphs) are only explored once. The places on the
+    /// current path are mapped to `None`: a cycle in the graph (which
+    /// [`Self::assert_acyclic`] would report) contributes no paths instead of
+    /// making this recurse forever.
+    fn num_paths_between_memoized(
+        &self,
+        blocking: MaybeOldPlace<'tcx>,
+        blocked: ReborrowBlockedPlace<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+        counts: &mut FxHashMap<MaybeOldPlace<'tcx>, Option<usize>>,
+    ) -> usize {
+        match counts.get(&blocking) {
+            Some(Some(count)) => return *count,
+            Some(None) => return 0,
+            None => {
+                counts.insert(blocking, None);
+            }
+        }
         let mut count = 0;
         for blocked_edge in self.edges_blocked_by(blocking.into(), repacker) {
             for blocked_place in blocked_edge.blocked_places() {
                 if blocked_place == blocked {
                     count += 1;
-                } else {
-                    if let Some(blocked_place) = blocked_place.as_local() {
-                        count += self.num_paths_between(blocked_place, blocked, repacker);
-                    }
+                } else if let Some(blocked_place) = blocked_place.as_local() {
+                    count +=
+                        self.num_paths_between_memoized(blocked_place, blocked, repacker, counts);
                 }
             }
         }
+        counts.insert(blocking, Some(count));
         count
     }
 
+    /// Panics if the graph contains a cycle, i.e. a place that (transitively)
+    /// blocks itself. The panic message lists the places on the cycle.
+    pub fn assert_acyclic(&self, repacker: PlaceRepacker<'_, 'tcx>) {
+        let mut blocked: FxHashMap<MaybeOldPlace<'tcx>, Vec<MaybeOldPlace<'tcx>>> =
+            FxHashMap::default();
+        for edge in self.0.iter() {
+            let blocked_places = edge
+                .blocked_places()
+                .into_iter()
+                .filter_map(|place| place.as_local())
+                .collect::<Vec<_>>();
+            for blocking in edge.blocked_by_places(repacker) {
+                blocked
+                    .entry(blocking)
+                    .or_default()
+                    .extend(blocked_places.iter().copied());
+            }
+        }
+        let mut finished = FxHashSet::default();
+        let mut path = vec![];
+        for place in blocked.keys() {
+            Self::assert_acyclic_from(*place, &blocked, &mut finished, &mut path, repacker);
+        }
+    }
+
+    fn assert_acyclic_from(
+        place: MaybeOldPlace<'tcx>,
+        blocked: &FxHashMap<MaybeOldPlace<'tcx>, Vec<MaybeOldPlace<'tcx>>>,
+        finished: &mut FxHashSet<MaybeOldPlace<'tcx>>,
+        path: &mut Vec<MaybeOldPlace<'tcx>>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) {
+        if finished.contains(&place) {
+            return;
+        }
+        if let Some(start) = path.iter().position(|p| *p == place) {
+            let cycle = path[start..]
+                .iter()
+                .chain(std::iter::once(&place))
+                .map(|p| p.to_short_string(repacker))
+                .collect::<Vec<_>>();
+            panic!("Cycle in borrows graph: {}", cycle.join(" -> "));
+        }
+        path.push(place);
+        for blocked_place in blocked.get(&place).into_iter().flatten() {
+            Self::assert_acyclic_from(*blocked_place, blocked, finished, path, repacker);
+        }
+        path.pop();
+        finished.insert(place);
+    }
+
     pub fn assert_invariants_satisfied(&self, repacker: PlaceRepacker<'_, 'tcx>) {
+        self.assert_acyclic(repacker);
+        // Reborrows are attached to the dereference of the reference they are
+        // stored in (see [`BorrowsGraph::add_reborrow`])
+        for edge in self.0.iter() {
+            if let BorrowsEdgeKind::Reborrow(reborrow) = &edge.kind {
+                assert!(
+                    Reborrow::is_valid_assigned_place(
+                        reborrow.assigned_place.place(),
+                        reborrow.mutability
+                    ),
+                    "The assigned place {:?} of reborrow {:?} (at {:?}) is not a dereference",
+                    reborrow.assigned_place,
+                    reborrow,
+                    edge.conditions
+                );
+            }
+        }
         for root_edge in self.root_edges(repacker) {
             match root_edge.kind {
                 BorrowsEdgeKind::Reborrow(reborrow) => {
@@ -224,18 +487,28 @@ impl<'tcx> BorrowsGraph<'tcx> {
         }
         Some(subgraph)
     }
+    /// A subgraph to replace with a loop abstraction, if any. The subgraphs of
+    /// reborrows created in the innermost loops are considered first, so
+    /// that an inner loop is abstracted before the loops containing it.
     pub fn loop_abstraction_subgraph(
         &self,
         repacker: PlaceRepacker<'_, 'tcx>,
+        loops: &LoopAnalysis,
     ) -> Option<BorrowsGraph<'tcx>> {
-        for r1 in self.reborrows().into_iter() {
-            if self.is_leaf_edge(&r1.clone().to_borrows_edge(), repacker) {
-                if let Some(graph) = self.loop_abstraction_subgraph_from(repacker, r1) {
-                    return Some(graph);
-                }
-            }
-        }
-        return None;
+        let mut leaf_reborrows = self
+            .reborrows()
+            .into_iter()
+            .filter(|r1| self.is_leaf_edge(&r1.clone().to_borrows_edge(), repacker))
+            .collect::<Vec<_>>();
+        // The loop depth of the source of a back edge doesn't count the loops
+        // containing the loop of the edge, but its innermost loop is correct
+        leaf_reborrows.sort_by_key(|r1| {
+            let block = r1.value.reserve_location().block;
+            std::cmp::Reverse(loops.innermost_loop(block).map(|l| loops.loop_nest_depth(l)))
+        });
+        leaf_reborrows
+            .into_iter()
+            .find_map(|r1| self.loop_abstraction_subgraph_from(repacker, r1))
     }
 
     pub fn root_edges(&self, repacker: PlaceRepacker<'_, 'tcx>) -> FxHashSet<BorrowsEdge<'tcx>> {
@@ -246,7 +519,7 @@ impl<'tcx> BorrowsGraph<'tcx> {
                     ReborrowBlockedPlace::Local(maybe_old_place) => {
                         self.is_root(*maybe_old_place, repacker)
                     }
-                    ReborrowBlockedPlace::Remote(local) => true,
+                    ReborrowBlockedPlace::Remote(_) | ReborrowBlockedPlace::Constant(_) => true,
                 })
             })
             .cloned()
@@ -263,6 +536,16 @@ impl<'tcx> BorrowsGraph<'tcx> {
             .collect()
     }
 
+    /// The places blocked by the edges of the graph. Checking membership in
+    /// this set is equivalent to [`Self::has_edge_blocking`], but cheaper
+    /// when there are many places to check.
+    pub fn blocked_places(&self) -> FxHashSet<ReborrowBlockedPlace<'tcx>> {
+        self.0
+            .iter()
+            .flat_map(|edge| edge.blocked_places())
+            .collect()
+    }
+
     pub fn has_edge_blocking(&self, place: MaybeOldPlace<'tcx>) -> bool {
         self.0
             .iter()
@@ -295,16 +578,40 @@ impl<'tcx> BorrowsGraph<'tcx> {
             .collect()
     }
 
+    /// The current places in the graph that have `place` as a prefix and are
+    /// not blocked by any edge
+    pub fn leaf_places_with_prefix(
+        &self,
+        place: Place<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> FxHashSet<Place<'tcx>> {
+        self.0
+            .iter()
+            .flat_map(|edge| {
+                edge.blocked_places()
+                    .into_iter()
+                    .flat_map(|p| p.as_local())
+                    .chain(edge.blocked_by_places(repacker))
+            })
+            .filter(|p| p.is_current() && place.is_prefix(p.place()))
+            .filter(|p| !self.has_edge_blocking(*p))
+            .map(|p| p.place())
+            .collect()
+    }
+
     pub fn make_place_old(
         &mut self,
         place: Place<'tcx>,
         latest: &Latest,
         _debug_ctx: Option<DebugCtx>,
     ) {
-        self.mut_edges(|edge| {
-            edge.make_place_old(place, latest);
-            true
-        });
+        self.mut_maybe_old_places(
+            |p| p.is_current() && place.is_prefix(p.place()),
+            |p| {
+                p.make_place_old(place, latest);
+                true
+            },
+        );
     }
 
     pub fn abstract_subgraph(
@@ -318,8 +625,7 @@ impl<'tcx> BorrowsGraph<'tcx> {
             self.remove(edge, DebugCtx::Other);
         }
         let edges = subgraph
-            .leaf_edges(repacker)
-            .into_iter()
+            .leaf_edges_iter(repacker)
             .flat_map(|edge| {
                 edge.blocked_by_places(repacker)
                     .into_iter()
@@ -351,27 +657,49 @@ impl<'tcx> BorrowsGraph<'tcx> {
         place: MaybeOldPlace<'tcx>,
         repacker: PlaceRepacker<'_, 'tcx>,
     ) -> FxHashSet<ReborrowBlockedPlace<'tcx>> {
-        self.edges_blocked_by(place, repacker)
-            .into_iter()
-            .flat_map(|edge| {
-                edge.blocked_places().into_iter().flat_map(|p| match p {
-                    ReborrowBlockedPlace::Local(maybe_old_place) => {
-                        if self.is_root(maybe_old_place, repacker) {
-                            vec![p].into_iter().collect()
-                        } else {
-                            self.roots_blocked_by(maybe_old_place, repacker)
-                        }
+        self.roots_blocked_by_visiting(place, &mut FxHashSet::default(), repacker)
+    }
+
+    /// See [`Self::roots_blocked_by`]. The places in `visited` are not
+    /// visited again, so that a cycle in the graph (which
+    /// [`Self::assert_acyclic`] would report) doesn't make this recurse
+    /// forever: the roots found before reaching the cycle are returned.
+    fn roots_blocked_by_visiting(
+        &self,
+        place: MaybeOldPlace<'tcx>,
+        visited: &mut FxHashSet<MaybeOldPlace<'tcx>>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> FxHashSet<ReborrowBlockedPlace<'tcx>> {
+        let mut roots = FxHashSet::default();
+        if !visited.insert(place) {
+            return roots;
+        }
+        for edge in self.edges_blocked_by(place, repacker) {
+            for p in edge.blocked_places() {
+                match p {
+                    ReborrowBlockedPlace::Local(maybe_old_place)
+                        if !self.is_root(maybe_old_place, repacker) =>
+                    {
+                        roots.extend(self.roots_blocked_by_visiting(
+                            maybe_old_place,
+                            visited,
+                            repacker,
+                        ));
                     }
-                    ReborrowBlockedPlace::Remote(local) => vec![p].into_iter().collect(),
-                })
-            })
-            .collect()
+                    _ => {
+                        roots.insert(p);
+                    }
+                }
+            }
+        }
+        roots
     }
 
     pub fn join(
         &mut self,
         other: &Self,
         post_block: BasicBlock,
+        loops: &LoopAnalysis,
         repacker: PlaceRepacker<'_, 'tcx>,
     ) -> bool {
         let mut changed = false;
@@ -394,24 +722,222 @@ impl<'tcx> BorrowsGraph<'tcx> {
                 }
             }
         }
-        // if post_block.as_usize() == 1 {
-        // TODO
-        while let Some(subgraph_to_abstract) = self.loop_abstraction_subgraph(repacker) {
+        // The abstraction belongs to the innermost loop containing the join,
+        // identified by its header (the join may also be e.g. after a branch
+        // in the body of the loop)
+        let loop_head = loops
+            .innermost_loop(post_block)
+            .map_or(post_block, |l| loops[l]);
+        while let Some(subgraph_to_abstract) = self.loop_abstraction_subgraph(repacker, loops) {
             changed = true;
-            self.abstract_subgraph(post_block, subgraph_to_abstract, repacker);
+            self.abstract_subgraph(loop_head, subgraph_to_abstract, repacker);
         }
-        // }
         changed
     }
 
+    /// Widens `self` and `other` before they are joined, by removing the
+    /// deref expansions that are in only one of them and leaving the
+    /// expansions common to both. An expansion is only removed if none of its
+    /// expanded places are blocked or blocking otherwise, in either graph.
+    /// Removing it may make the expansion of its base removable in turn, so
+    /// the deepest expansions are considered first. Expansions of old places
+    /// are left to be removed along with the old places. Returns whether
+    /// edges were removed from `self`.
+    pub fn collapse_divergent_expansions(
+        &mut self,
+        other: &mut Self,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> bool {
+        let ours: FxHashSet<&BorrowsEdgeKind<'tcx>> = self.0.iter().map(|e| &e.kind).collect();
+        let theirs: FxHashSet<&BorrowsEdgeKind<'tcx>> = other.0.iter().map(|e| &e.kind).collect();
+        let mut divergent: Vec<(bool, DerefExpansion<'tcx>, BorrowsEdge<'tcx>)> = self
+            .0
+            .iter()
+            .filter(|edge| !theirs.contains(&edge.kind))
+            .map(|edge| (true, edge))
+            .chain(
+                other
+                    .0
+                    .iter()
+                    .filter(|edge| !ours.contains(&edge.kind))
+                    .map(|edge| (false, edge)),
+            )
+            .filter_map(|(in_self, edge)| match &edge.kind {
+                BorrowsEdgeKind::DerefExpansion(expansion) if expansion.base().is_current() => {
+                    Some((in_self, expansion.clone(), edge.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        if divergent.is_empty() {
+            return false;
+        }
+        // Ties are broken on the edges themselves, so that which expansions
+        // are removed does not depend on the iteration order of the graphs
+        divergent.sort_by_cached_key(|(in_self, expansion, edge)| {
+            (
+                std::cmp::Reverse(expansion.base().place().projection.len()),
+                format!("{:?}", edge),
+                !in_self,
+            )
+        });
+        // The number of edges of both graphs that block or are blocked by
+        // each place
+        let mut uses: FxHashMap<MaybeOldPlace<'tcx>, usize> = FxHashMap::default();
+        for edge in self.0.iter().chain(other.0.iter()) {
+            for place in edge
+                .blocked_places()
+                .into_iter()
+                .flat_map(|p| p.as_local())
+                .chain(edge.blocked_by_places(repacker))
+            {
+                *uses.entry(place).or_default() += 1;
+            }
+        }
+        let mut changed = false;
+        for (in_self, expansion, edge) in divergent {
+            // Each expanded place is used by the expansion itself
+            if expansion
+                .expansion(repacker)
+                .iter()
+                .all(|place| uses[place] == 1)
+            {
+                *uses.get_mut(&expansion.base()).unwrap() -= 1;
+                if in_self {
+                    self.0.remove(&edge);
+                    changed = true;
+                } else {
+                    other.0.remove(&edge);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Whether `self` is below `other` in the lattice, i.e. every edge of
+    /// `self` is also in `other` (ignoring path conditions). A reborrow of
+    /// `self` may instead have been replaced by a loop abstraction of
+    /// `other`: it is then enough for one of the inputs of the abstraction to
+    /// be a root that the reborrow blocks in `self`.
+    ///
+    /// The edges are compared modulo the snapshots of their old places: the
+    /// join gives the old places of a local assigned differently on each side
+    /// the `Join` snapshot (see [`super::borrows_state::BorrowsState::join`]).
+    pub fn leq(&self, other: &Self, repacker: PlaceRepacker<'_, 'tcx>) -> bool {
+        let (this, other) = (self.without_snapshots(), other.without_snapshots());
+        let abstracted_roots: FxHashSet<ReborrowBlockedPlace<'tcx>> = other
+            .0
+            .iter()
+            .filter_map(|edge| match &edge.kind {
+                BorrowsEdgeKind::RegionAbstraction(AbstractionEdge {
+                    abstraction_type: AbstractionType::Loop(abstraction),
+                }) => Some(abstraction),
+                _ => None,
+            })
+            .flat_map(|abstraction| abstraction.inputs())
+            .filter_map(|input| match input {
+                AbstractionTarget::Place(place) => Some(place),
+                AbstractionTarget::RegionProjection(_) => None,
+            })
+            .collect();
+        this.0.iter().all(|edge| match &edge.kind {
+            kind if other.0.iter().any(|other_edge| other_edge.kind == *kind) => true,
+            BorrowsEdgeKind::Reborrow(reborrow) => this
+                .roots_of_reborrow(reborrow, repacker)
+                .iter()
+                .any(|root| abstracted_roots.contains(root)),
+            BorrowsEdgeKind::DerefExpansion(_)
+            | BorrowsEdgeKind::RegionAbstraction(_)
+            | BorrowsEdgeKind::RegionProjectionMember(_) => false,
+        })
+    }
+
+    /// The roots of the graph that `reborrow` blocks, possibly through the
+    /// other edges of the graph
+    fn roots_of_reborrow(
+        &self,
+        reborrow: &Reborrow<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> FxHashSet<ReborrowBlockedPlace<'tcx>> {
+        match reborrow.blocked_place {
+            ReborrowBlockedPlace::Local(place) if !self.is_root(place, repacker) => {
+                self.roots_blocked_by(place, repacker)
+            }
+            blocked_place => [blocked_place].into_iter().collect(),
+        }
+    }
+
+    /// Merges the edges of the same kind into a single edge whose conditions
+    /// are the join of theirs. [`Self::join`] only merges an incoming edge
+    /// with an edge of the same kind, so edges that diverged before (e.g. when
+    /// path conditions were added on only some of the paths) remain separate
+    /// otherwise. Edges required at different blocks are not merged.
+    ///
+    /// An edge is merged into the first edge of its kind that it can be
+    /// merged with, so the edges are considered in a fixed order: the result
+    /// must not depend on the iteration order of the graph.
+    pub fn canonicalize(&mut self) -> bool {
+        let len = self.0.len();
+        let mut edges: Vec<_> = self.0.drain().collect();
+        edges.sort_by_cached_key(|edge| (format!("{:?}", edge.kind), edge.conditions.clone()));
+        let mut conditions: FxHashMap<BorrowsEdgeKind<'tcx>, Vec<PathConditions>> =
+            FxHashMap::default();
+        for edge in edges {
+            let merged = conditions.entry(edge.kind).or_default();
+            match merged.iter_mut().find(|c| {
+                !matches!((&**c, &edge.conditions),
+                    (PathConditions::AtBlock(b1), PathConditions::AtBlock(b2)) if b1 != b2)
+            }) {
+                Some(c) => {
+                    c.join(&edge.conditions);
+                }
+                None => merged.push(edge.conditions),
+            }
+        }
+        self.0 = conditions
+            .into_iter()
+            .flat_map(|(kind, conditions)| {
+                conditions
+                    .into_iter()
+                    .map(move |c| BorrowsEdge::new(kind.clone(), c))
+            })
+            .collect();
+        self.0.len() != len
+    }
+
     pub fn change_maybe_old_place(
         &mut self,
         old_place: MaybeOldPlace<'tcx>,
         new_place: MaybeOldPlace<'tcx>,
     ) -> bool {
-        self.mut_maybe_old_places(|place| {
-            if *place == old_place {
-                *place = new_place;
+        self.mut_maybe_old_places(
+            |place| place == old_place,
+            |place| {
+                if *place == old_place {
+                    *place = new_place;
+                    true
+                } else {
+                    false
+                }
+            },
+        )
+    }
+
+    /// Gives the snapshot `to` to the old places of `local` that have the
+    /// snapshot `from`
+    pub fn rename_snapshots(
+        &mut self,
+        local: Local,
+        from: SnapshotLocation,
+        to: SnapshotLocation,
+    ) -> bool {
+        let renamed = |place: MaybeOldPlace<'tcx>| {
+            matches!(place, MaybeOldPlace::OldPlace(snapshot)
+                if snapshot.place.local == local && snapshot.at == from)
+        };
+        self.mut_maybe_old_places(renamed, |place| {
+            if renamed(*place) {
+                *place = MaybeOldPlace::OldPlace(PlaceSnapshot::new(place.place(), to));
                 true
             } else {
                 false
@@ -419,21 +945,80 @@ impl<'tcx> BorrowsGraph<'tcx> {
         })
     }
 
+    /// A copy of `self` where every old place has the same snapshot
+    fn without_snapshots(&self) -> Self {
+        let mut graph = self.clone();
+        graph.mut_maybe_old_places(
+            |place| !place.is_current(),
+            |place| match place {
+                MaybeOldPlace::OldPlace(snapshot) => {
+                    snapshot.at = SnapshotLocation::Location(Location::START);
+                    true
+                }
+                MaybeOldPlace::Current { .. } => false,
+            },
+        );
+        graph
+    }
+
+    /// Every place in the graph, current or old: the places blocked by an
+    /// edge, the places blocking one, and the places of the region
+    /// projection members whichever their direction.
+    pub fn all_places(&self, repacker: PlaceRepacker<'_, 'tcx>) -> FxHashSet<MaybeOldPlace<'tcx>> {
+        let mut places = FxHashSet::default();
+        for edge in self.0.iter() {
+            places.extend(
+                edge.blocked_places()
+                    .into_iter()
+                    .filter_map(|p| p.as_local()),
+            );
+            places.extend(edge.blocked_by_places(repacker));
+            if let BorrowsEdgeKind::RegionProjectionMember(member) = &edge.kind {
+                places.insert(member.place);
+                places.insert(member.projection.place);
+            }
+        }
+        places
+    }
+
+    /// The snapshots of the old places of the graph
+    pub fn old_places(&self) -> FxHashSet<PlaceSnapshot<'tcx>> {
+        self.0
+            .iter()
+            .flat_map(|edge| edge.kind.maybe_old_places())
+            .filter_map(|place| match place {
+                MaybeOldPlace::OldPlace(snapshot) => Some(snapshot),
+                MaybeOldPlace::Current { .. } => None,
+            })
+            .collect()
+    }
+
     pub fn add_reborrow(
         &mut self,
         blocked_place: ReborrowBlockedPlace<'tcx>,
         assigned_place: Place<'tcx>,
         mutability: Mutability,
+        kind: ReborrowKind<'tcx>,
         location: Location,
-        region: Region<'tcx>,
+        index: usize,
     ) -> bool {
+        // The reborrowed value is accessed through the reference stored in
+        // the assigned place, so the edge is attached to its dereference. A
+        // shared reference copied into a field is attached to the field.
+        assert!(
+            Reborrow::is_valid_assigned_place(assigned_place, mutability),
+            "The assigned place of the reborrow at {:?} should be a dereference, but is {:?}",
+            location,
+            assigned_place
+        );
         self.insert(
             Reborrow::new(
                 blocked_place.into(),
                 assigned_place.into(),
                 mutability,
+                kind,
                 location,
-                region,
+                index,
             )
             .to_borrows_edge(PathConditions::new(location.block)),
         )
@@ -452,7 +1037,8 @@ impl<'tcx> BorrowsGraph<'tcx> {
             .filter(move |edge| edge.blocked_places().contains(&place))
     }
 
-    pub fn remove_abstraction_at(&mut self, location: Location) {
+    pub fn remove_abstraction_at(&mut self, location: Location) -> bool {
+        let num_edges = self.0.len();
         self.0.retain(|edge| {
             if let BorrowsEdgeKind::RegionAbstraction(abstraction) = &edge.kind {
                 abstraction.location() != location
@@ -460,6 +1046,7 @@ impl<'tcx> BorrowsGraph<'tcx> {
                 true
             }
         });
+        self.0.len() != num_edges
     }
 
     pub fn remove(&mut self, edge: &BorrowsEdge<'tcx>, debug_ctx: DebugCtx) -> bool {
@@ -471,16 +1058,38 @@ impl<'tcx> BorrowsGraph<'tcx> {
         old_projection_place: MaybeOldPlace<'tcx>,
         new_projection_place: MaybeOldPlace<'tcx>,
         repacker: PlaceRepacker<'_, 'tcx>,
+        lenient: bool,
     ) {
-        self.mut_edges(|edge| {
-            if let BorrowsEdgeKind::RegionProjectionMember(member) = &mut edge.kind {
-                if member.projection.place == old_projection_place {
-                    let idx = member.projection_index(repacker);
-                    member.projection = new_projection_place.region_projection(idx, repacker);
+        self.mut_edges(
+            |edge| {
+                matches!(&edge.kind, BorrowsEdgeKind::RegionProjectionMember(member)
+                    if member.projection.place == old_projection_place)
+            },
+            |edge| {
+                if let BorrowsEdgeKind::RegionProjectionMember(member) = &mut edge.kind {
+                    if member.projection.place == old_projection_place {
+                        let idx = member.projection_index(repacker);
+                        let new_projection = new_projection_place.region_projection(idx, repacker);
+                        if !check_assumption(lenient, new_projection.is_some(), None, || {
+                            format!(
+                                "Cannot move region projection {} of {:?} to {:?}: its type {:?} \
+                                 only has {} region projections",
+                                idx,
+                                old_projection_place,
+                                new_projection_place,
+                                new_projection_place.ty(repacker).ty,
+                                new_projection_place.region_projections(repacker).len()
+                            )
+                        }) {
+                            return false;
+                        }
+                        member.projection = new_projection.unwrap();
+                        return true;
+                    }
                 }
-            }
-            true
-        });
+                false
+            },
+        );
     }
 
     pub fn move_reborrows(
@@ -488,14 +1097,21 @@ impl<'tcx> BorrowsGraph<'tcx> {
         orig_assigned_place: MaybeOldPlace<'tcx>,
         new_assigned_place: MaybeOldPlace<'tcx>,
     ) {
-        self.mut_edges(|edge| {
-            if let BorrowsEdgeKind::Reborrow(reborrow) = &mut edge.kind {
-                if reborrow.assigned_place == orig_assigned_place {
-                    reborrow.assigned_place = new_assigned_place;
+        self.mut_edges(
+            |edge| {
+                matches!(&edge.kind, BorrowsEdgeKind::Reborrow(reborrow)
+                    if reborrow.assigned_place == orig_assigned_place)
+            },
+            |edge| {
+                if let BorrowsEdgeKind::Reborrow(reborrow) = &mut edge.kind {
+                    if reborrow.assigned_place == orig_assigned_place {
+                        reborrow.assigned_place = new_assigned_place;
+                        return true;
+                    }
                 }
-            }
-            true
-        });
+                false
+            },
+        );
     }
 
     pub fn contains_deref_expansion_from(&self, place: &MaybeOldPlace<'tcx>) -> bool {
@@ -508,6 +1124,10 @@ impl<'tcx> BorrowsGraph<'tcx> {
         })
     }
 
+    /// Adds the deref expansions leading to `place` that are not in the
+    /// graph yet. The deref expansion edges of the graph are the only
+    /// representation of the expansions of the borrows state, this is the
+    /// single place where they are created.
     pub fn ensure_deref_expansion_to_at_least(
         &mut self,
         place: Place<'tcx>,
@@ -530,7 +1150,18 @@ impl<'tcx> BorrowsGraph<'tcx> {
                         => {
                             vec![place.project_deeper(&[elem], tcx).into()]
                         }
-                        _ => place.expand_field(None, PlaceRepacker::new(&body, tcx)),
+                        _ => match place.last_projection() {
+                            // A field of an enum variant: the variant is
+                            // known, so its whole payload is expanded. Other
+                            // downcasts (e.g. of coroutines) expand the fields
+                            // of the downcast place.
+                            Some((base, mir::ProjectionElem::Downcast(_, variant_idx)))
+                                if base.ty(PlaceRepacker::new(body, tcx)).ty.is_enum() =>
+                            {
+                                base.expand_variant(variant_idx, PlaceRepacker::new(body, tcx))
+                            }
+                            _ => place.expand_field(None, PlaceRepacker::new(&body, tcx)),
+                        },
                     };
                     self.insert_deref_expansion(
                         origin_place,
@@ -554,7 +1185,7 @@ impl<'tcx> BorrowsGraph<'tcx> {
             assert!(p.projection.len() > place.place().projection.len());
         }
         let de = if place.place().is_owned(repacker.body(), repacker.tcx()) {
-            DerefExpansion::OwnedExpansion { base: place }
+            DerefExpansion::owned(place, expansion, repacker)
         } else {
             DerefExpansion::borrowed(place, expansion, location, repacker)
         };
@@ -564,11 +1195,16 @@ impl<'tcx> BorrowsGraph<'tcx> {
         });
     }
 
+    /// Applies `f` to the places of the edges that have a place for which
+    /// `may_change` holds, see [`Self::mut_edges`]
     fn mut_maybe_old_places(
         &mut self,
+        may_change: impl Fn(MaybeOldPlace<'tcx>) -> bool,
         mut f: impl FnMut(&mut MaybeOldPlace<'tcx>) -> bool,
     ) -> bool {
-        self.mut_edges(|edge| {
+        let edge_may_change =
+            |edge: &BorrowsEdge<'tcx>| edge.kind.maybe_old_places().into_iter().any(&may_change);
+        self.mut_edges(edge_may_change, |edge| {
             let maybe_old_places: Vec<&mut MaybeOldPlace<'tcx>> = match edge.mut_kind() {
                 BorrowsEdgeKind::Reborrow(reborrow) => {
                     let mut vec = vec![&mut reborrow.assigned_place];
@@ -579,7 +1215,7 @@ impl<'tcx> BorrowsGraph<'tcx> {
                 }
                 BorrowsEdgeKind::DerefExpansion(de) => vec![de.mut_base()],
                 BorrowsEdgeKind::RegionAbstraction(ra) => ra.maybe_old_places(),
-                BorrowsEdgeKind::RegionProjectionMember(_) => todo!(),
+                BorrowsEdgeKind::RegionProjectionMember(member) => member.maybe_old_places(),
             };
             let mut changed = false;
             for p in maybe_old_places {
@@ -590,18 +1226,33 @@ impl<'tcx> BorrowsGraph<'tcx> {
             changed
         })
     }
-    fn mut_edges(&mut self, mut f: impl FnMut(&mut BorrowsEdge<'tcx>) -> bool) -> bool {
-        let mut changed = false;
-        self.0 = self
+    /// Applies `f` to each edge for which `may_change` holds, `f` should
+    /// return `true` iff it changed the edge. Every field of an edge
+    /// contributes to its hash, so these edges are removed and re-inserted;
+    /// the others are neither cloned nor moved.
+    fn mut_edges(
+        &mut self,
+        may_change: impl Fn(&BorrowsEdge<'tcx>) -> bool,
+        mut f: impl FnMut(&mut BorrowsEdge<'tcx>) -> bool,
+    ) -> bool {
+        let edges = self
             .0
-            .drain()
-            .map(|mut edge| {
-                if f(&mut edge) {
-                    changed = true;
-                }
-                edge
-            })
-            .collect();
+            .iter()
+            .filter(|edge| may_change(edge))
+            .cloned()
+            .collect::<Vec<_>>();
+        // Remove all of the edges before inserting the new ones, a new edge
+        // may be equal to the old version of another edge
+        for edge in edges.iter() {
+            self.0.remove(edge);
+        }
+        let mut changed = false;
+        for mut edge in edges {
+            if f(&mut edge) {
+                changed = true;
+            }
+            self.0.insert(edge);
+        }
         changed
     }
 
@@ -610,10 +1261,46 @@ impl<'tcx> BorrowsGraph<'tcx> {
     }
 
     pub fn add_path_condition(&mut self, pc: PathCondition) -> bool {
-        self.mut_edges(|edge| edge.conditions.insert(pc.clone()))
+        self.mut_edges(|_| true, |edge| edge.conditions.insert(pc.clone()))
     }
 }
 
+/// The number of nodes on the longest path in the graph with the given
+/// `successors` of each node (identified by its index).
+fn longest_chain(successors: &[Vec<usize>]) -> usize {
+    /// `lengths` caches the length of the longest chain starting at each
+    /// node, so that nodes reachable along several chains (e.g. in
+    /// diamond-shaped graphs) are only explored once. Nodes on the current
+    /// chain are skipped, which breaks cycles.
+    fn chain_length(
+        from: usize,
+        successors: &[Vec<usize>],
+        lengths: &mut [Option<usize>],
+        on_chain: &mut [bool],
+    ) -> usize {
+        if let Some(length) = lengths[from] {
+            return length;
+        }
+        on_chain[from] = true;
+        let mut longest_suffix = 0;
+        for &successor in &successors[from] {
+            if !on_chain[successor] {
+                longest_suffix =
+                    longest_suffix.max(chain_length(successor, successors, lengths, on_chain));
+            }
+        }
+        on_chain[from] = false;
+        lengths[from] = Some(1 + longest_suffix);
+        1 + longest_suffix
+    }
+    let mut lengths = vec![None; successors.len()];
+    let mut on_chain = vec![false; successors.len()];
+    (0..successors.len())
+        .map(|idx| chain_length(idx, successors, &mut lengths, &mut on_chain))
+        .max()
+        .unwrap_or(0)
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct BorrowsEdge<'tcx> {
     conditions: PathConditions,
@@ -726,6 +1413,34 @@ impl<'tcx> BorrowsEdgeKind<'tcx> {
         self.blocked_places().contains(&place)
     }
 
+    /// The places of the edge that may be current or old, i.e. those visited
+    /// by [`BorrowsGraph::mut_maybe_old_places`]
+    pub fn maybe_old_places(&self) -> Vec<MaybeOldPlace<'tcx>> {
+        match self {
+            BorrowsEdgeKind::Reborrow(reborrow) => reborrow
+                .blocked_place
+                .as_local()
+                .into_iter()
+                .chain([reborrow.assigned_place])
+                .collect(),
+            BorrowsEdgeKind::DerefExpansion(de) => vec![de.base()],
+            BorrowsEdgeKind::RegionAbstraction(ra) => ra
+                .abstraction_type
+                .edges()
+                .into_iter()
+                .flat_map(|mut edge| {
+                    edge.maybe_old_places()
+                        .into_iter()
+                        .map(|place| *place)
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            BorrowsEdgeKind::RegionProjectionMember(member) => {
+                vec![member.place, member.projection.place]
+            }
+        }
+    }
+
     pub fn blocked_by_place(
         &self,
         place: MaybeOldPlace<'tcx>,
@@ -820,3 +1535,637 @@ impl<'tcx, T: ToBorrowsEdge<'tcx>> Conditioned<T> {
         self.value.to_borrows_edge(self.conditions)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        borrows::{
+            borrows_state::BorrowsState,
+            domain::RegionProjection,
+            path_condition::{PCGraph, PathCondition},
+        },
+        run_combined_pcs,
+        rustc_interface::{
+            middle::{
+                mir::{Rvalue, StatementKind, START_BLOCK},
+                ty::RegionVid,
+            },
+            target::abi::VariantIdx,
+        },
+        test_utils::{find_statement, local_named, run_on_fn, run_on_fns},
+        PcsOptions,
+    };
+
+    /// Inserted directly, a mutable reborrow assigned to `y` rather than to
+    /// `*y` bypasses the check of [`BorrowsGraph::add_reborrow`]
+    #[test]
+    #[should_panic(expected = "is not a dereference")]
+    fn reborrow_assigned_to_local_violates_invariants() {
+        run_on_fn("14_reborrow_targets.rs", "copy_ref", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = Place::from(local_named(&body.body, "x"));
+            let y = Place::from(local_named(&body.body, "y"));
+            let mut graph = BorrowsGraph::new();
+            graph.insert(
+                Reborrow::new(
+                    x.project_deref(rp).into(),
+                    y.into(),
+                    Mutability::Mut,
+                    ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                    Location::START,
+                    0,
+                )
+                .to_borrows_edge(PathConditions::new(START_BLOCK)),
+            );
+            graph.assert_invariants_satisfied(rp);
+        });
+    }
+
+    /// Canonicalizing does not depend on the order in which the edges were
+    /// inserted, even when an edge could be merged into several others
+    #[test]
+    fn canonicalize_is_independent_of_insertion_order() {
+        run_on_fn("14_reborrow_targets.rs", "copy_ref", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = Place::from(local_named(&body.body, "x"));
+            let y = Place::from(local_named(&body.body, "y"));
+            let (bb1, bb2) = (BasicBlock::from_u32(1), BasicBlock::from_u32(2));
+            let paths =
+                |to| PathConditions::Paths(PCGraph::singleton(PathCondition::new(START_BLOCK, to)));
+            let mut edges = vec![];
+            for (blocked, assigned) in [(x, y), (y, x)] {
+                let reborrow = Reborrow::new(
+                    blocked.project_deref(rp).into(),
+                    assigned.project_deref(rp).into(),
+                    Mutability::Not,
+                    ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                    Location::START,
+                    0,
+                );
+                for conditions in [
+                    PathConditions::AtBlock(bb1),
+                    PathConditions::AtBlock(bb2),
+                    paths(bb1),
+                    paths(bb2),
+                ] {
+                    edges.push(reborrow.clone().to_borrows_edge(conditions));
+                }
+            }
+            let mut reversed = edges.clone();
+            reversed.reverse();
+            let [forward, backward] = [edges, reversed].map(|edges| {
+                let mut graph = BorrowsGraph::new();
+                for edge in edges {
+                    graph.insert(edge);
+                }
+                graph.canonicalize();
+                graph
+            });
+            assert_eq!(forward, backward);
+            // The path conditions are subsumed by the blocks of each reborrow
+            assert_eq!(forward.0.len(), 4);
+        });
+    }
+
+    #[test]
+    fn num_paths_between_counts_both_sides_of_diamond() {
+        run_on_fn("26_two_paths_to_place.rs", "main", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let p = Place::from(local_named(&body.body, "p"));
+            let r = Place::from(local_named(&body.body, "r"));
+            let z = Place::from(local_named(&body.body, "z"));
+            // The arguments of `choose(&mut r.a, &mut r.b, true)`
+            let args = body
+                .body
+                .basic_blocks
+                .iter()
+                .find_map(|data| match &data.terminator().kind {
+                    mir::TerminatorKind::Call { args, .. } => Some(
+                        args.iter()
+                            .filter_map(|arg| arg.node.place())
+                            .map(Place::from)
+                            .collect::<Vec<_>>(),
+                    ),
+                    _ => None,
+                })
+                .unwrap();
+            let [x, y] = args[..] else {
+                panic!("Expected two reference arguments, got {:?}", args);
+            };
+            let fields = r.project_deref(rp).expand_field(None, rp);
+            let [a, b] = fields[..] else {
+                panic!("Expected two fields of `*r`, got {:?}", fields);
+            };
+            let mut graph = BorrowsGraph::new();
+            // `*z` blocks `p` through both `r.a` and `r.b`
+            for (blocked, assigned) in [
+                (p, r),
+                (a, x),
+                (b, y),
+                (x.project_deref(rp), z),
+                (y.project_deref(rp), z),
+            ] {
+                assert!(graph.add_reborrow(
+                    blocked.into(),
+                    assigned.project_deref(rp),
+                    Mutability::Mut,
+                    ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                    Location::START,
+                    0,
+                ));
+            }
+            graph.ensure_deref_expansion_to_at_least(a, &body.body, tcx, Location::START);
+            assert_eq!(
+                graph.num_paths_between(z.project_deref(rp).into(), p.into(), rp),
+                2
+            );
+        });
+    }
+
+    #[test]
+    fn only_reborrows_of_abstracted_roots_are_below_loop_abstraction() {
+        run_on_fn("26_two_paths_to_place.rs", "main", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let p = Place::from(local_named(&body.body, "p"));
+            let r = Place::from(local_named(&body.body, "r"));
+            let z = Place::from(local_named(&body.body, "z"));
+            let mut abstracted = BorrowsGraph::new();
+            abstracted.insert(
+                AbstractionEdge::new(AbstractionType::Loop(LoopAbstraction::new(
+                    vec![AbstractionBlockEdge::new(
+                        AbstractionTarget::Place(p.into()),
+                        AbstractionTarget::Place(r.project_deref(rp).into()),
+                    )],
+                    START_BLOCK,
+                )))
+                .to_borrows_edge(PathConditions::new(START_BLOCK)),
+            );
+            let mut graph = BorrowsGraph::new();
+            for (blocked, assigned) in [(p, r), (z, r)] {
+                graph.add_reborrow(
+                    blocked.into(),
+                    assigned.project_deref(rp),
+                    Mutability::Mut,
+                    ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                    Location::START,
+                    0,
+                );
+                // The abstraction only replaces the reborrows of `p`
+                assert_eq!(graph.leq(&abstracted, rp), blocked == p);
+            }
+        });
+    }
+
+    #[test]
+    fn expansions_made_on_one_side_of_join_are_collapsed() {
+        run_on_fn("33_join_field_expansions.rs", "main", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = Place::from(local_named(&body.body, "x"));
+            let fields = x.project_deref(rp).expand_field(None, rp);
+            let [f, g] = fields[..] else {
+                panic!("Expected two fields of `*x`, got {:?}", fields);
+            };
+            // One branch writes to `*x.f`, the other to `*x.g`
+            let mut ours = BorrowsGraph::new();
+            ours.ensure_deref_expansion_to_at_least(
+                f.project_deref(rp),
+                &body.body,
+                tcx,
+                Location::START,
+            );
+            let mut theirs = BorrowsGraph::new();
+            theirs.ensure_deref_expansion_to_at_least(
+                g.project_deref(rp),
+                &body.body,
+                tcx,
+                Location::START,
+            );
+            assert!(ours.collapse_divergent_expansions(&mut theirs, rp));
+            // Only the expansions of `x` and `*x` are common to both
+            let common: FxHashSet<_> = [x, x.project_deref(rp)].into_iter().collect();
+            for graph in [&ours, &theirs] {
+                let bases: FxHashSet<_> = graph
+                    .deref_expansions()
+                    .into_iter()
+                    .map(|expansion| expansion.value.base().place())
+                    .collect();
+                assert_eq!(bases, common);
+            }
+        });
+    }
+
+    /// The same reborrow, added on each branch of a switch, is merged into
+    /// one edge holding on both branches
+    #[test]
+    fn edges_with_complementary_conditions_are_merged() {
+        run_on_fn("14_reborrow_targets.rs", "copy_ref", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = Place::from(local_named(&body.body, "x"));
+            let y = Place::from(local_named(&body.body, "y"));
+            let reborrow = Reborrow::new(
+                x.project_deref(rp).into(),
+                y.project_deref(rp).into(),
+                Mutability::Not,
+                ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                Location::START,
+                0,
+            );
+            let branch = |to| {
+                let mut conditions = PathConditions::new(START_BLOCK);
+                conditions.insert(PathCondition::new(START_BLOCK, BasicBlock::from_u32(to)));
+                conditions
+            };
+            let mut graph = BorrowsGraph::new();
+            for conditions in [branch(1), branch(2)] {
+                assert!(graph.insert(reborrow.clone().to_borrows_edge(conditions)));
+            }
+            assert!(graph.canonicalize());
+            let mut both = branch(1);
+            both.join(&branch(2));
+            let edges: Vec<_> = graph.edges().collect();
+            assert_eq!(edges.len(), 1);
+            assert_eq!(edges[0].conditions(), &both);
+            assert!(!graph.canonicalize());
+        });
+    }
+
+    /// `reborrow` returns a reborrow of its argument and `first` one of its
+    /// first argument: only the edges of the calls from these arguments are
+    /// kept, with the places of `main`
+    #[test]
+    fn call_returning_reborrowed_argument_is_projected_to_caller() {
+        run_on_fns(
+            "35_call_returns_reborrow.rs",
+            &["main", "reborrow", "first"],
+            |tcx, bodies| {
+                let [main, callees @ ..] = bodies else {
+                    unreachable!()
+                };
+                let mut output =
+                    run_combined_pcs(main, tcx, None, false, None, None, PcsOptions::default());
+                for (callee, body) in ["reborrow", "first"].into_iter().zip(callees) {
+                    let callee_rp = PlaceRepacker::new(&body.body, tcx);
+                    let return_block = body
+                        .body
+                        .basic_blocks
+                        .iter_enumerated()
+                        .find(|(_, data)| {
+                            matches!(data.terminator().kind, mir::TerminatorKind::Return)
+                        })
+                        .unwrap()
+                        .0;
+                    let callee_graph =
+                        run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default())
+                            .get_all_for_bb(return_block)
+                            .terminator
+                            .extra
+                            .after
+                            .graph()
+                            .clone();
+
+                    // `destination = callee(move arg, ..)`
+                    let (call_block, arg, destination) = main
+                        .body
+                        .basic_blocks
+                        .iter_enumerated()
+                        .find_map(|(block, data)| match &data.terminator().kind {
+                            mir::TerminatorKind::Call {
+                                func,
+                                args,
+                                destination,
+                                ..
+                            } if func.const_fn_def().is_some_and(|(def_id, _)| {
+                                tcx.item_name(def_id).as_str() == callee
+                            }) =>
+                            {
+                                Some((block, args[0].node.place()?, *destination))
+                            }
+                            _ => None,
+                        })
+                        .unwrap();
+                    let call = output
+                        .get_all_for_bb(call_block)
+                        .terminator
+                        .succs
+                        .remove(0)
+                        .extra
+                        .after
+                        .graph()
+                        .abstraction_edges()
+                        .into_iter()
+                        .find_map(|abstraction| match abstraction.value.abstraction_type {
+                            AbstractionType::FunctionCall(call)
+                                if call.location().block == call_block =>
+                            {
+                                Some(call)
+                            }
+                            _ => None,
+                        })
+                        .unwrap();
+                    let projected: Vec<_> = callee_graph
+                        .project_through_call(&call, callee_rp)
+                        .abstraction_edges()
+                        .into_iter()
+                        .collect();
+                    let [projected] = &projected[..] else {
+                        panic!("Expected one projected abstraction for `{callee}`");
+                    };
+                    let AbstractionType::FunctionCall(projected) =
+                        &projected.value.abstraction_type
+                    else {
+                        panic!("Expected the abstraction of the call to `{callee}`");
+                    };
+                    let [(0, edge)] = projected.edges()[..] else {
+                        panic!("Expected only the edge of the first argument of `{callee}`");
+                    };
+                    let rp = output.repacker();
+                    let AbstractionTarget::Place(ReborrowBlockedPlace::Local(input)) = edge.input
+                    else {
+                        panic!("Expected a place of `main` as input");
+                    };
+                    assert_eq!(input.place(), Place::from(arg).project_deref(rp));
+                    let AbstractionTarget::Place(output_place) = edge.output else {
+                        panic!("Expected a place of `main` as output");
+                    };
+                    assert_eq!(
+                        output_place.place(),
+                        Place::from(destination).project_deref(rp)
+                    );
+                }
+            },
+        );
+    }
+
+    /// `*x` and `*y` block each other, and `*y` is also blocked by the
+    /// remote place of `x`: the search stops when it comes back to `*x`
+    #[test]
+    fn roots_blocked_by_terminates_on_cycle() {
+        run_on_fn("22_two_reference_args.rs", "pick", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = local_named(&body.body, "x");
+            let x_deref = Place::from(x).project_deref(rp);
+            let y_deref = Place::from(local_named(&body.body, "y")).project_deref(rp);
+            let edges = [
+                (MaybeOldPlace::from(x_deref).into(), y_deref),
+                (MaybeOldPlace::from(y_deref).into(), x_deref),
+                (ReborrowBlockedPlace::Remote(x), y_deref),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(index, (blocked, assigned))| {
+                Reborrow::new(
+                    blocked,
+                    assigned.into(),
+                    Mutability::Mut,
+                    ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                    Location::START,
+                    index,
+                )
+                .to_borrows_edge(PathConditions::new(START_BLOCK))
+            });
+            let state = BorrowsState::from_edges(edges, Latest::new());
+            assert_eq!(
+                state.graph().roots_blocked_by(x_deref.into(), rp),
+                FxHashSet::from_iter([ReborrowBlockedPlace::Remote(x)])
+            );
+        });
+    }
+
+    /// `*x` and `*y` block each other, and `*y` is also blocked by the
+    /// remote place of `x`: the search stops when it comes back to `*x`
+    #[test]
+    fn num_paths_between_terminates_on_cycle() {
+        run_on_fn("22_two_reference_args.rs", "pick", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = local_named(&body.body, "x");
+            let x_deref = Place::from(x).project_deref(rp);
+            let y_deref = Place::from(local_named(&body.body, "y")).project_deref(rp);
+            let edges = [
+                (MaybeOldPlace::from(x_deref).into(), y_deref),
+                (MaybeOldPlace::from(y_deref).into(), x_deref),
+                (ReborrowBlockedPlace::Remote(x), y_deref),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(index, (blocked, assigned))| {
+                Reborrow::new(
+                    blocked,
+                    assigned.into(),
+                    Mutability::Mut,
+                    ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                    Location::START,
+                    index,
+                )
+                .to_borrows_edge(PathConditions::new(START_BLOCK))
+            });
+            let state = BorrowsState::from_edges(edges, Latest::new());
+            // `*x -> *y -> Remote(x)`; the path back to `*x` is not counted
+            assert_eq!(
+                state.graph().num_paths_between(
+                    x_deref.into(),
+                    ReborrowBlockedPlace::Remote(x),
+                    rp
+                ),
+                1
+            );
+        });
+    }
+
+    /// `*x` is reborrowed into `*y` and from the old `*y`, `x` is expanded to
+    /// `*x`, and `y` is a member of a region projection of `x`. The remote
+    /// place blocked by the reborrow into `*x` is not a place of the body.
+    #[test]
+    fn all_places_counts_places_of_every_edge_kind() {
+        run_on_fn("22_two_reference_args.rs", "pick", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = Place::from(local_named(&body.body, "x"));
+            let y = Place::from(local_named(&body.body, "y"));
+            let old_y_deref = MaybeOldPlace::new(y.project_deref(rp), Some(Location::START));
+            let conditions = PathConditions::new(START_BLOCK);
+            let mut graph = BorrowsGraph::new();
+            for (index, (blocked, assigned)) in [
+                (x.project_deref(rp).into(), y.project_deref(rp)),
+                (old_y_deref.into(), x.project_deref(rp)),
+                (ReborrowBlockedPlace::Remote(x.local), x.project_deref(rp)),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                graph.insert(
+                    Reborrow::new(
+                        blocked,
+                        assigned.into(),
+                        Mutability::Mut,
+                        ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                        Location::START,
+                        index,
+                    )
+                    .to_borrows_edge(conditions.clone()),
+                );
+            }
+            graph.insert(
+                DerefExpansion::owned(x.into(), vec![x.project_deref(rp)], rp)
+                    .to_borrows_edge(conditions.clone()),
+            );
+            graph.insert(BorrowsEdge::new(
+                BorrowsEdgeKind::RegionProjectionMember(RegionProjectionMember::new(
+                    y.into(),
+                    RegionProjection::new(RegionVid::from_u32(0), x.into()),
+                    Location::START,
+                    RegionProjectionMemberDirection::PlaceIsRegionInput,
+                )),
+                conditions,
+            ));
+            assert_eq!(graph.all_places(rp).len(), 5);
+            assert_eq!(
+                graph.all_places(rp),
+                FxHashSet::from_iter([
+                    x.into(),
+                    y.into(),
+                    x.project_deref(rp).into(),
+                    y.project_deref(rp).into(),
+                    old_y_deref,
+                ])
+            );
+        });
+    }
+
+    /// `r` is expanded to `*r` and the borrowed `*r` to `**r`; the expansion of
+    /// `inner` to `*inner` starts a separate chain
+    #[test]
+    fn max_deref_expansion_depth_follows_nested_expansions() {
+        run_on_fn("12_double_deref.rs", "reborrow_inner", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let r = Place::from(local_named(&body.body, "r"));
+            let inner = Place::from(local_named(&body.body, "inner"));
+            let conditions = PathConditions::new(START_BLOCK);
+            let mut graph = BorrowsGraph::new();
+            for expansion in [
+                DerefExpansion::owned(r.into(), vec![r.project_deref(rp)], rp),
+                DerefExpansion::borrowed(
+                    r.project_deref(rp).into(),
+                    vec![r.project_deref(rp).project_deref(rp)],
+                    Location::START,
+                    rp,
+                ),
+                DerefExpansion::owned(inner.into(), vec![inner.project_deref(rp)], rp),
+            ] {
+                graph.insert(expansion.to_borrows_edge(conditions.clone()));
+            }
+            assert_eq!(graph.max_deref_expansion_depth(rp), 2);
+        });
+    }
+
+    /// `head = &mut ((*list) as Cons).0` expands the whole payload of
+    /// `Cons`, i.e. both the `u32` and the `Box<List>` fields
+    #[test]
+    fn downcast_expands_all_fields_of_variant() {
+        run_on_fn("44_enum_payload_fields.rs", "bump_head", |tcx, body| {
+            let list = Place::from(local_named(&body.body, "list"));
+            let head = local_named(&body.body, "head");
+            let location = find_statement(&body.body, |statement| {
+                matches!(
+                    statement.kind.as_assign(),
+                    Some((place, Rvalue::Ref(..))) if place.local == head
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let fields = list
+                .project_deref(rp)
+                .expand_variant(VariantIdx::from_u32(0), rp);
+            assert_eq!(fields.len(), 2);
+            assert!(fields[0].ty(rp).ty.is_integral());
+            assert!(fields[1].ty(rp).ty.is_box());
+            let state = output
+                .get_all_for_bb(location.block)
+                .statements
+                .remove(location.statement_index)
+                .extra
+                .after;
+            for field in fields {
+                assert!(
+                    state
+                        .graph()
+                        .deref_expansions()
+                        .iter()
+                        .any(|expansion| expansion
+                            .value
+                            .expansion(rp)
+                            .iter()
+                            .any(|place| place.place() == field)),
+                    "{field:?}"
+                );
+            }
+        });
+    }
+
+    /// In `zero_all`, `*inner` reborrows `(*l).tail` before the inner loop
+    /// and `(**n).tail` in it, whereas `*l` reborrows `*lists` before the
+    /// loops and `**t` in the outer one
+    #[test]
+    fn inner_loop_is_abstracted_first_at_its_header() {
+        run_on_fn("31_nested_while_let.rs", "zero_all", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let loops = LoopAnalysis::find_loops(&body.body);
+            let [lists, l, inner, n, t] = ["lists", "l", "inner", "n", "t"]
+                .map(|name| Place::from(local_named(&body.body, name)));
+            let borrow_of = |local: Place<'_>| {
+                find_statement(&body.body, |stmt| {
+                    matches!(&stmt.kind, StatementKind::Assign(box (_, Rvalue::Ref(_, _, borrowed)))
+                        if borrowed.local == local.local)
+                })
+            };
+            let (l_tail, n_tail, t_target) = (borrow_of(l), borrow_of(n), borrow_of(t));
+            let [outer_loop, inner_loop, t_loop] = [l_tail, n_tail, t_target]
+                .map(|location| loops.innermost_loop(location.block).unwrap());
+            assert_eq!(t_loop, outer_loop);
+            assert_eq!(loops.loop_nest_depth(inner_loop), 1);
+            assert!(loops.in_loop(loops[inner_loop], outer_loop));
+            let [tail_of_l, tail_of_n] =
+                [l.project_deref(rp), n.project_deref(rp).project_deref(rp)]
+                    .map(|list| list.expand_field(None, rp)[1]);
+            let mut graph = BorrowsGraph::new();
+            for (blocked, assigned, location) in [
+                (tail_of_l, inner, l_tail),
+                (tail_of_n, inner, n_tail),
+                (lists.project_deref(rp), l, Location::START),
+                (t.project_deref(rp).project_deref(rp), l, t_target),
+            ] {
+                graph.insert(
+                    Reborrow::new(
+                        blocked.into(),
+                        assigned.project_deref(rp).into(),
+                        Mutability::Mut,
+                        ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                        location,
+                        0,
+                    )
+                    .to_borrows_edge(PathConditions::new(START_BLOCK)),
+                );
+            }
+            let subgraph = graph.loop_abstraction_subgraph(rp, &loops).unwrap();
+            assert!(subgraph.reborrows().iter().all(|reborrow| reborrow
+                .value
+                .assigned_place
+                .place()
+                == inner.project_deref(rp)));
+            // Both subgraphs are abstracted at the header of the inner loop,
+            // rather than at the block in its body where the join happens
+            let header = loops[inner_loop];
+            assert_ne!(header, n_tail.block);
+            assert!(graph.join(&BorrowsGraph::new(), n_tail.block, &loops, rp));
+            let blocks: Vec<_> = graph
+                .abstraction_edges()
+                .into_iter()
+                .map(|edge| match edge.value.abstraction_type {
+                    AbstractionType::Loop(abstraction) => abstraction.location().block,
+                    other => panic!("Expected a loop abstraction, got {:?}", other),
+                })
+                .collect();
+            assert_eq!(blocks, vec![header, header]);
+        });
+    }
+}
@@ -1,17 +1,21 @@
+use std::collections::VecDeque;
+
 use rustc_interface::{
     ast::Mutability,
     borrowck::consumers::BorrowIndex,
-    data_structures::fx::FxHashSet,
+    data_structures::fx::{FxHashMap, FxHashSet},
     dataflow::JoinSemiLattice,
     middle::mir::{self, BasicBlock, Location},
-    middle::ty::{self, TyCtxt},
+    middle::ty::TyCtxt,
 };
 use serde_json::{json, Value};
 
 use crate::{
+    combined_pcs::UnblockAction,
     free_pcs::{CapabilityKind, CapabilityLocal, CapabilitySummary},
+    r#loop::LoopAnalysis,
     rustc_interface,
-    utils::{Place, PlaceRepacker, SnapshotLocation},
+    utils::{assumptions::check_assumption, Place, PlaceRepacker, PlaceSnapshot, SnapshotLocation},
     ReborrowBridge,
 };
 
@@ -19,7 +23,10 @@ use super::{
     borrows_graph::{BorrowsEdge, BorrowsEdgeKind, BorrowsGraph, Conditioned, ToBorrowsEdge},
     borrows_visitor::DebugCtx,
     deref_expansion::DerefExpansion,
-    domain::{MaybeOldPlace, Reborrow, ReborrowBlockedPlace, RegionProjection},
+    domain::{
+        AbstractionType, MaybeOldPlace, Reborrow, ReborrowBlockedPlace, ReborrowId, ReborrowKind,
+        RegionProjection, ToJsonWithRepacker,
+    },
     latest::Latest,
     path_condition::{PathCondition, PathConditions},
     region_abstraction::AbstractionEdge,
@@ -41,6 +48,10 @@ pub struct RegionProjectionMember<'tcx> {
 }
 
 impl<'tcx> RegionProjectionMember<'tcx> {
+    pub fn maybe_old_places(&mut self) -> Vec<&mut MaybeOldPlace<'tcx>> {
+        vec![&mut self.place, &mut self.projection.place]
+    }
+
     pub fn make_place_old(&mut self, place: Place<'tcx>, latest: &Latest) {
         self.place.make_place_old(place, latest);
         self.projection.make_place_old(place, latest);
@@ -69,10 +80,89 @@ impl<'tcx> RegionProjectionMember<'tcx> {
     }
 }
 
+impl<'tcx> ToJsonWithRepacker<'tcx> for RegionProjectionMember<'tcx> {
+    fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
+        json!({
+            "place": self.place.to_json(repacker),
+            "projection": self.projection.to_json(repacker),
+            "location": format!("{:?}", self.location),
+            "direction": format!("{:?}", self.direction),
+        })
+    }
+}
+
+/// Determines which places in the graph become old when a place is moved out
+/// of. Places that are overwritten or go out of scope always age their whole
+/// subtree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum AgingPolicy {
+    /// Every place in the graph that has the moved place as a prefix is made
+    /// old, including places that are themselves expanded further (e.g.
+    /// moving `x` ages `x`, `x.f` and `x.f.g`).
+    #[default]
+    Subtree,
+    /// Only the leaves of the graph that have the moved place as a prefix are
+    /// made old; places that are expanded further keep referring to the
+    /// current value (e.g. moving `x` with `x` expanded to `x.f` ages only
+    /// `x.f`).
+    LeafOnly,
+}
+
+/// Determines which shared reborrows [`BorrowsState::minimize`] and
+/// [`BorrowsState::trim_old_leaves`] may remove
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SharedReborrowPolicy {
+    /// Shared reborrows whose loan is still in scope according to the borrow
+    /// checker are kept, even if they are structurally removable (e.g. an old
+    /// leaf of the graph whose loan is used again later).
+    #[default]
+    KeepInScope,
+    /// Shared reborrows are removed based on the structure of the graph only,
+    /// like mutable ones
+    Structural,
+}
+
+/// The event that caused a place to become old
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AgingReason {
+    /// The place was moved out of
+    Move,
+    /// The place was moved out of as an argument to a function call
+    CallArgument,
+    /// The place was overwritten by an assignment (or a coroutine resumption)
+    Reassign,
+    /// The storage of the place's local was deallocated
+    StorageDead,
+    /// The place was deinitialized by a `Deinit` statement
+    Deinit,
+    /// The discriminant of the place was set by a `SetDiscriminant` statement
+    SetDiscriminant,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BorrowsState<'tcx> {
     pub latest: Latest,
     graph: BorrowsGraph<'tcx>,
+    aging_policy: AgingPolicy,
+    /// See [`crate::PcsOptions::lenient`]
+    lenient: bool,
+    /// The places that were made old, with the reason they were made old
+    aging_reasons: FxHashMap<PlaceSnapshot<'tcx>, AgingReason>,
+}
+
+/// Whether the aging of `aged` made `old` old
+fn explains<'tcx>(aged: &PlaceSnapshot<'tcx>, old: &PlaceSnapshot<'tcx>) -> bool {
+    aged.at == old.at && aged.place.is_prefix(old.place)
+}
+
+/// Whether `edge` is a shared reborrow reserved at one of `shared_loans_to_keep`
+fn is_kept_shared_reborrow(
+    edge: &BorrowsEdge<'_>,
+    shared_loans_to_keep: &FxHashSet<Location>,
+) -> bool {
+    matches!(edge.kind(), BorrowsEdgeKind::Reborrow(reborrow)
+        if reborrow.mutability == Mutability::Not
+            && shared_loans_to_keep.contains(&reborrow.reserve_location()))
 }
 
 fn subtract_deref_expansions<'tcx>(
@@ -87,25 +177,99 @@ impl<'tcx> BorrowsState<'tcx> {
         self.graph.assert_invariants_satisfied(repacker);
     }
 
+    /// See [`BorrowsGraph::canonicalize`]
+    pub fn canonicalize(&mut self) -> bool {
+        self.graph.canonicalize()
+    }
+
     pub fn join<'mir>(
         &mut self,
         other: &Self,
         post_block: BasicBlock,
+        loops: &LoopAnalysis,
         repacker: PlaceRepacker<'_, 'tcx>,
     ) -> bool {
         let mut changed = false;
-        if self.graph.join(&other.graph, post_block, repacker) {
+        // The old places of a local that has a different latest snapshot on
+        // each side refer to the value given to it on that side: once the
+        // latest snapshots are joined, they refer to the value at the join
+        let mut latest = self.latest.clone();
+        latest.join(&other.latest, post_block);
+        let join = SnapshotLocation::Join(post_block);
+        let renamed = |side: &Latest, aged: PlaceSnapshot<'tcx>| {
+            if side.snapshot_of(aged.place.local) == Some(aged.at)
+                && latest.snapshot_of(aged.place.local) == Some(join)
+            {
+                PlaceSnapshot::new(aged.place, join)
+            } else {
+                aged
+            }
+        };
+        let mut other_graph = None;
+        for (local, joined) in latest.iter().filter(|(_, joined)| *joined == join) {
+            if let Some(self_loc) = self.latest.snapshot_of(local)
+                && self_loc != joined
+                && self.graph.rename_snapshots(local, self_loc, joined)
+            {
+                changed = true;
+            }
+            if let Some(other_loc) = other.latest.snapshot_of(local)
+                && other_loc != joined
+            {
+                other_graph
+                    .get_or_insert_with(|| other.graph.clone())
+                    .rename_snapshots(local, other_loc, joined);
+            }
+        }
+        let other_graph = other_graph.as_ref().unwrap_or(&other.graph);
+        if self.graph.join(other_graph, post_block, loops, repacker) {
             changed = true;
         }
-        if self.latest.join(&other.latest, post_block) {
-            // TODO: Setting changed to true prevents divergence for loops,
-            // think about how latest should work in loops
-
-            // changed = true;
+        // Only the reasons that explain an old place of the joined graph are
+        // kept, so that the reasons of places that are no longer in the graph
+        // don't accumulate
+        let old_places = self.graph.old_places();
+        let explains_old_place =
+            |aged: &PlaceSnapshot<'tcx>| old_places.iter().any(|old| explains(aged, old));
+        let aging_reasons = std::mem::take(&mut self.aging_reasons);
+        for (aged, reason) in aging_reasons.iter() {
+            let aged = renamed(&self.latest, *aged);
+            if explains_old_place(&aged) {
+                self.aging_reasons.entry(aged).or_insert(*reason);
+            }
+        }
+        if self.aging_reasons != aging_reasons {
+            changed = true;
+        }
+        for (aged, reason) in other.aging_reasons.iter() {
+            let aged = renamed(&other.latest, *aged);
+            if explains_old_place(&aged) && !self.aging_reasons.contains_key(&aged) {
+                self.aging_reasons.insert(aged, *reason);
+                changed = true;
+            }
+        }
+        if self.latest != latest {
+            self.latest = latest;
+            changed = true;
         }
         changed
     }
 
+    /// See [`BorrowsGraph::collapse_divergent_expansions`]
+    pub fn collapse_divergent_expansions(
+        &mut self,
+        other: &mut Self,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> bool {
+        self.graph
+            .collapse_divergent_expansions(&mut other.graph, repacker)
+    }
+
+    /// See [`BorrowsGraph::leq`]
+    pub fn leq(&self, other: &Self, repacker: PlaceRepacker<'_, 'tcx>) -> bool {
+        self.graph.leq(&other.graph, repacker)
+    }
+
     pub fn change_maybe_old_place(
         &mut self,
         old_place: MaybeOldPlace<'tcx>,
@@ -114,12 +278,13 @@ impl<'tcx> BorrowsState<'tcx> {
         self.graph.change_maybe_old_place(old_place, new_place)
     }
 
-    pub fn remove_edge_and_set_latest(
+    pub fn remove_edge_and_set_latest<T: Into<SnapshotLocation>>(
         &mut self,
         edge: &BorrowsEdge<'tcx>,
         repacker: PlaceRepacker<'_, 'tcx>,
-        location: Location,
+        location: T,
     ) -> bool {
+        let location = location.into();
         if !edge.is_shared_borrow() {
             for place in edge.blocked_places() {
                 match place {
@@ -130,7 +295,7 @@ impl<'tcx> BorrowsState<'tcx> {
                 }
             }
         }
-        self.graph.remove(edge, DebugCtx::new(location))
+        self.graph.remove(edge, location.into())
     }
 
     pub fn reborrow_edges_reserved_at(
@@ -151,16 +316,33 @@ impl<'tcx> BorrowsState<'tcx> {
             .collect()
     }
 
-    pub fn minimize(&mut self, repacker: PlaceRepacker<'_, 'tcx>, location: Location) {
+    /// Removes the edges that no longer block anything relevant: old edges
+    /// whose blocking places are themselves unblocked, and deref expansions of
+    /// borrowed places none of whose children are blocked. Shared reborrows
+    /// reserved at one of `shared_loans_to_keep` are kept even if they look
+    /// removable, see [`SharedReborrowPolicy`].
+    pub fn minimize<T: Into<SnapshotLocation>>(
+        &mut self,
+        repacker: PlaceRepacker<'_, 'tcx>,
+        location: T,
+        shared_loans_to_keep: &FxHashSet<Location>,
+    ) {
+        let location = location.into();
+        // Each iteration but the last removes at least one edge
+        let max_iterations = self.graph.edge_count();
+        let mut iterations = 0;
         loop {
+            let blocked = self.graph.blocked_places();
+            let is_blocked = |place: MaybeOldPlace<'tcx>| blocked.contains(&place.into());
             let to_remove = self
                 .graph
                 .edges()
+                .filter(|edge| !is_kept_shared_reborrow(edge, shared_loans_to_keep))
                 .filter(|edge| {
                     let is_old_unblocked = edge
                         .blocked_by_places(repacker)
                         .iter()
-                        .all(|p| p.is_old() && !self.graph.has_edge_blocking((*p).into()));
+                        .all(|p| p.is_old() && !is_blocked(*p));
                     is_old_unblocked
                         || match &edge.kind() {
                             BorrowsEdgeKind::DerefExpansion(de) => {
@@ -168,7 +350,7 @@ impl<'tcx> BorrowsState<'tcx> {
                                     && de
                                         .expansion(repacker)
                                         .into_iter()
-                                        .all(|p| !self.graph.has_edge_blocking(p.into()))
+                                        .all(|p| !is_blocked(p.into()))
                             }
                             _ => false,
                         }
@@ -178,6 +360,14 @@ impl<'tcx> BorrowsState<'tcx> {
             if to_remove.is_empty() {
                 break;
             }
+            iterations += 1;
+            debug_assert!(
+                iterations <= max_iterations,
+                "minimize at {location:?} did not terminate after {max_iterations} iterations"
+            );
+            if iterations > max_iterations {
+                break;
+            }
             for edge in to_remove {
                 self.remove_edge_and_set_latest(&edge, repacker, location);
             }
@@ -192,12 +382,13 @@ impl<'tcx> BorrowsState<'tcx> {
         self.graph.filter_for_path(path);
     }
 
-    pub fn delete_descendants_of(
+    pub fn delete_descendants_of<T: Into<SnapshotLocation>>(
         &mut self,
         place: MaybeOldPlace<'tcx>,
         repacker: PlaceRepacker<'_, 'tcx>,
-        location: Location,
+        location: T,
     ) -> bool {
+        let location = location.into();
         let mut changed = false;
         let edges = self
             .edges_blocking(place.into())
@@ -233,10 +424,26 @@ impl<'tcx> BorrowsState<'tcx> {
         self.graph.edges_blocking(place)
     }
 
+    pub fn edge_count(&self) -> usize {
+        self.graph.edge_count()
+    }
+
     pub fn graph_edges(&self) -> impl Iterator<Item = &BorrowsEdge<'tcx>> {
         self.graph.edges()
     }
 
+    pub fn graph(&self) -> &BorrowsGraph<'tcx> {
+        &self.graph
+    }
+
+    /// See [`BorrowsGraph::ordered_edges`]
+    pub fn ordered_graph_edges(
+        &self,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Vec<&BorrowsEdge<'tcx>> {
+        self.graph.ordered_edges(repacker)
+    }
+
     pub fn deref_expansions(&self) -> FxHashSet<Conditioned<DerefExpansion<'tcx>>> {
         self.graph.deref_expansions()
     }
@@ -251,6 +458,7 @@ impl<'tcx> BorrowsState<'tcx> {
             old_projection_place,
             new_projection_place,
             repacker,
+            self.lenient,
         );
     }
 
@@ -283,7 +491,7 @@ impl<'tcx> BorrowsState<'tcx> {
         let added_reborrows: FxHashSet<Conditioned<Reborrow<'tcx>>> = to
             .reborrows()
             .into_iter()
-            .filter(|rb| !self.has_reborrow_at_location(rb.value.reserve_location()))
+            .filter(|rb| !self.has_reborrow(rb.value.id()))
             .collect();
 
         let expands = to
@@ -293,9 +501,10 @@ impl<'tcx> BorrowsState<'tcx> {
             .collect();
 
         let mut ug = UnblockGraph::new();
+        ug.set_lenient(self.lenient);
 
         for reborrow in self.reborrows() {
-            if !to.has_reborrow_at_location(reborrow.value.reserve_location()) {
+            if !to.has_reborrow(reborrow.value.id()) {
                 ug.kill_reborrow(reborrow, self, repacker);
             }
         }
@@ -317,6 +526,22 @@ impl<'tcx> BorrowsState<'tcx> {
         }
     }
 
+    /// The unblock actions that the [`BorrowsState::bridge`] to `to` would
+    /// perform, computed without applying them to a state, e.g. to preview
+    /// the transition between two program points. As when applying the
+    /// bridge, the approximate actions are used if the unblock graph has an
+    /// error.
+    pub fn bridge_actions(
+        &self,
+        to: &Self,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Vec<UnblockAction<'tcx>> {
+        self.bridge(to, DebugCtx::Other, repacker)
+            .ug
+            .actions(repacker)
+            .unwrap_or_else(|err| err.approximate_actions)
+    }
+
     pub fn ensure_deref_expansions_to_fpcs(
         &mut self,
         tcx: TyCtxt<'tcx>,
@@ -359,23 +584,46 @@ impl<'tcx> BorrowsState<'tcx> {
             .collect()
     }
 
+    /// Unblocks `place` for an `access` of the given mutability (shared
+    /// reborrows of `place` are kept for a read, see
+    /// [`UnblockGraph::unblock_place_for_read`]) and ensures the graph is
+    /// expanded to it
     pub fn ensure_expansion_to_exactly(
         &mut self,
         tcx: TyCtxt<'tcx>,
         body: &mir::Body<'tcx>,
         place: Place<'tcx>,
+        access: Mutability,
         location: Location,
+        unblocked_at: SnapshotLocation,
     ) {
         let mut ug = UnblockGraph::new();
+        ug.set_lenient(self.lenient);
         let repacker = PlaceRepacker::new(body, tcx);
-        ug.unblock_place(place.into(), self, repacker);
-        self.apply_unblock_graph(ug, repacker, location);
+        match access {
+            Mutability::Not => ug.unblock_place_for_read(place.into(), self, repacker),
+            Mutability::Mut => ug.unblock_place(place.into(), self, repacker),
+        }
+        self.apply_unblock_graph(ug, repacker, unblocked_at);
 
         // Originally we may not have been expanded enough
         self.graph
             .ensure_deref_expansion_to_at_least(place.into(), body, tcx, location);
     }
 
+    /// Ensures that the graph contains the deref expansions leading to
+    /// `place`, without unblocking anything
+    pub fn ensure_deref_expansion_to_at_least(
+        &mut self,
+        tcx: TyCtxt<'tcx>,
+        body: &mir::Body<'tcx>,
+        place: Place<'tcx>,
+        location: Location,
+    ) {
+        self.graph
+            .ensure_deref_expansion_to_at_least(place, body, tcx, location);
+    }
+
     pub fn roots(
         &self,
         repacker: PlaceRepacker<'_, 'tcx>,
@@ -383,12 +631,13 @@ impl<'tcx> BorrowsState<'tcx> {
         self.graph.roots(repacker)
     }
 
-    pub fn kill_reborrows(
+    pub fn kill_reborrows<T: Into<SnapshotLocation>>(
         &mut self,
         reserve_location: Location,
-        kill_location: Location,
+        kill_location: T,
         repacker: PlaceRepacker<'_, 'tcx>,
     ) -> bool {
+        let kill_location = kill_location.into();
         let edges_to_remove = self.reborrow_edges_reserved_at(reserve_location);
         if edges_to_remove.is_empty() {
             return false;
@@ -399,38 +648,78 @@ impl<'tcx> BorrowsState<'tcx> {
         true
     }
 
-    pub fn apply_unblock_graph(
+    /// Kills the reborrow `id` only, leaving the other reborrows reserved at
+    /// the same location untouched. Returns true iff the reborrow existed.
+    pub fn kill_reborrow<T: Into<SnapshotLocation>>(
+        &mut self,
+        id: ReborrowId,
+        kill_location: T,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> bool {
+        let Some(edge) = self
+            .reborrow_edges_reserved_at(id.reserve_location)
+            .into_iter()
+            .find(|edge| edge.value.id() == id)
+        else {
+            return false;
+        };
+        self.remove_edge_and_set_latest(&edge.to_borrows_edge(), repacker, kill_location);
+        true
+    }
+
+    pub fn apply_unblock_graph<T: Into<SnapshotLocation>>(
         &mut self,
         graph: UnblockGraph<'tcx>,
         repacker: PlaceRepacker<'_, 'tcx>,
-        location: Location,
+        location: T,
     ) -> bool {
+        let location = location.into();
         let mut changed = false;
-        if graph.has_error() {
-            eprintln!("{:?} unblock graph has error", location);
-        }
-        for action in graph.actions(repacker) {
-            match action {
-                crate::combined_pcs::UnblockAction::TerminateReborrow {
-                    reserve_location, ..
-                } => {
-                    if self.kill_reborrows(reserve_location, location, repacker) {
-                        changed = true;
-                    }
-                }
-                crate::combined_pcs::UnblockAction::Collapse(place, _) => {
-                    if self.delete_descendants_of(place, repacker, location) {
-                        changed = true;
-                    }
-                }
-                crate::combined_pcs::UnblockAction::TerminateAbstraction(location, _call) => {
-                    self.graph.remove_abstraction_at(location);
-                }
+        // A cycle makes the actions approximate; cycles that remain in the
+        // state are reported with the bridges of the results (see
+        // `FpcsOutput::borrow_cycles`)
+        let actions = graph
+            .actions(repacker)
+            .unwrap_or_else(|err| err.approximate_actions);
+        for action in actions {
+            if self.apply_action(&action, repacker, location) {
+                changed = true;
             }
         }
         changed
     }
 
+    /// Applies a single unblock action (e.g. one obtained from the results of
+    /// the analysis) to this state. Returns true iff the state changed.
+    pub fn apply_action<T: Into<SnapshotLocation>>(
+        &mut self,
+        action: &UnblockAction<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+        location: T,
+    ) -> bool {
+        let location = location.into();
+        match action {
+            UnblockAction::TerminateReborrow {
+                reserve_location,
+                index,
+                ..
+            } => self.kill_reborrow(
+                ReborrowId {
+                    reserve_location: *reserve_location,
+                    index: *index,
+                },
+                location,
+                repacker,
+            ),
+            UnblockAction::Collapse(place, _) => {
+                self.delete_descendants_of(*place, repacker, location)
+            }
+            UnblockAction::TerminateAbstraction(location, _call) => {
+                self.graph.remove_abstraction_at(*location)
+            }
+        }
+    }
+
     pub fn set_latest<T: Into<SnapshotLocation>>(&mut self, place: Place<'tcx>, location: T) {
         self.latest.insert(place.local, location.into());
     }
@@ -459,6 +748,72 @@ impl<'tcx> BorrowsState<'tcx> {
             .collect()
     }
 
+    /// The reborrows assigned to the value that `place` held when it was last
+    /// assigned: either `place` itself, or its snapshot at its latest location
+    /// if it was made old since (e.g. because it was moved out).
+    pub fn reborrows_assigned_to_place(
+        &self,
+        place: mir::Place<'tcx>,
+    ) -> FxHashSet<Conditioned<Reborrow<'tcx>>> {
+        let place: Place<'tcx> = place.into();
+        let current = MaybeOldPlace::Current { place };
+        let latest = MaybeOldPlace::new(place, Some(self.get_latest(&place)));
+        self.graph
+            .edges()
+            .filter_map(|edge| match edge.kind() {
+                BorrowsEdgeKind::Reborrow(reborrow)
+                    if reborrow.assigned_place == current || reborrow.assigned_place == latest =>
+                {
+                    Some(Conditioned::new(
+                        reborrow.clone(),
+                        edge.conditions().clone(),
+                    ))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The projections of `base` that are not blocked by any reborrow, e.g.
+    /// `{x.b, x.c}` for a base `x` while `x.a` is borrowed. Places blocked by a
+    /// reborrow are expanded just enough to separate them from their siblings,
+    /// the result is `[base]` if nothing in `base` is borrowed and empty if
+    /// `base` itself is (or is within) a borrowed place.
+    ///
+    /// As with [`Place::expand_one_level`], the siblings are incomplete when
+    /// the expansion goes through an `Index`, `Subslice` or `Downcast`.
+    pub fn accessible_projections(
+        &self,
+        base: Place<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Vec<Place<'tcx>> {
+        let blocked = self
+            .reborrows()
+            .into_iter()
+            .filter_map(|rb| match rb.value.blocked_place {
+                ReborrowBlockedPlace::Local(MaybeOldPlace::Current { place }) => Some(place),
+                _ => None,
+            })
+            .filter(|place| place.local == base.local)
+            .collect::<Vec<_>>();
+        let mut accessible = Vec::new();
+        let mut worklist = vec![base];
+        while let Some(place) = worklist.pop() {
+            if blocked.iter().any(|b| b.is_prefix(place)) {
+                continue;
+            }
+            match blocked.iter().find(|b| place.is_prefix(**b)) {
+                Some(b) => {
+                    let (next, siblings, _) = place.expand_one_level(*b, repacker);
+                    worklist.push(next);
+                    worklist.extend(siblings);
+                }
+                None => accessible.push(place),
+            }
+        }
+        accessible
+    }
+
     pub fn add_region_projection_member(&mut self, member: RegionProjectionMember<'tcx>) {
         self.graph.insert(
             member
@@ -467,51 +822,160 @@ impl<'tcx> BorrowsState<'tcx> {
         );
     }
 
-    pub fn trim_old_leaves(&mut self, repacker: PlaceRepacker<'_, 'tcx>, location: Location) {
+    /// Removes the leaf edges whose blocking places are all old, until there
+    /// are none left. As in [`Self::minimize`], shared reborrows reserved at
+    /// one of `shared_loans_to_keep` are kept.
+    pub fn trim_old_leaves<T: Into<SnapshotLocation>>(
+        &mut self,
+        repacker: PlaceRepacker<'_, 'tcx>,
+        location: T,
+        shared_loans_to_keep: &FxHashSet<Location>,
+    ) {
+        let location = location.into();
         loop {
-            let mut cont = false;
-            let edges = self.graph.leaf_edges(repacker);
-            for edge in edges {
-                if edge.blocked_by_places(repacker).iter().all(|p| p.is_old()) {
-                    self.remove_edge_and_set_latest(&edge, repacker, location);
-                    cont = true;
-                }
-            }
-            if !cont {
+            let edges = self
+                .graph
+                .leaf_edges_iter(repacker)
+                .filter(|edge| {
+                    !is_kept_shared_reborrow(edge, shared_loans_to_keep)
+                        && edge.blocked_by_places(repacker).iter().all(|p| p.is_old())
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if edges.is_empty() {
                 break;
             }
+            for edge in edges {
+                self.remove_edge_and_set_latest(&edge, repacker, location);
+            }
         }
     }
 
+    /// Adds the reborrow of `blocked_place` into `assigned_place` reserved at
+    /// `location`. `index` distinguishes the reborrows reserved at the same
+    /// location, see [`ReborrowId`]. Returns true iff the reborrow was added.
+    ///
+    /// The reborrow is skipped if `assigned_place` is not a valid assigned
+    /// place (see [`check_assumption`]).
     pub fn add_reborrow(
         &mut self,
         blocked_place: ReborrowBlockedPlace<'tcx>,
         assigned_place: Place<'tcx>,
         mutability: Mutability,
+        kind: ReborrowKind<'tcx>,
         location: Location,
-        region: ty::Region<'tcx>,
-    ) {
-        self.graph
-            .add_reborrow(blocked_place, assigned_place, mutability, location, region);
+        index: usize,
+    ) -> bool {
+        let valid = Reborrow::is_valid_assigned_place(assigned_place, mutability);
+        if !check_assumption(self.lenient, valid, Some(location), || {
+            format!(
+                "The assigned place of the reborrow should be a dereference, but is {:?}",
+                assigned_place
+            )
+        }) {
+            return false;
+        }
+        self.graph.add_reborrow(
+            blocked_place,
+            assigned_place,
+            mutability,
+            kind,
+            location,
+            index,
+        )
+    }
+
+    pub fn max_reborrow_chain_length(&self) -> usize {
+        self.graph.max_reborrow_chain_length()
+    }
+
+    pub fn max_deref_expansion_depth(&self, repacker: PlaceRepacker<'_, 'tcx>) -> usize {
+        self.graph.max_deref_expansion_depth(repacker)
     }
 
     pub fn has_reborrow_at_location(&self, location: Location) -> bool {
         self.graph.has_reborrow_at_location(location)
     }
 
+    pub fn has_reborrow(&self, id: ReborrowId) -> bool {
+        self.graph.has_reborrow(id)
+    }
+
     pub fn region_abstractions(&self) -> FxHashSet<Conditioned<AbstractionEdge<'tcx>>> {
         self.graph.abstraction_edges()
     }
 
-    pub fn to_json(&self, _repacker: PlaceRepacker<'_, 'tcx>) -> Value {
-        json!({})
+    /// The latest map and the edges of the graph, grouped by kind. The edges
+    /// of each kind are in the order of [`BorrowsGraph::ordered_edges`] so
+    /// that two dumps of the same state are equal.
+    pub fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> Value {
+        let mut reborrows = vec![];
+        let mut deref_expansions = vec![];
+        let mut region_abstractions = vec![];
+        let mut region_projection_members = vec![];
+        for edge in self.graph.ordered_edges(repacker) {
+            let (edges, value) = match edge.kind() {
+                BorrowsEdgeKind::Reborrow(reborrow) => (&mut reborrows, reborrow.to_json(repacker)),
+                BorrowsEdgeKind::DerefExpansion(de) => {
+                    (&mut deref_expansions, de.to_json(repacker))
+                }
+                BorrowsEdgeKind::RegionAbstraction(abstraction) => {
+                    (&mut region_abstractions, abstraction.to_json(repacker))
+                }
+                BorrowsEdgeKind::RegionProjectionMember(member) => {
+                    (&mut region_projection_members, member.to_json(repacker))
+                }
+            };
+            edges.push(json!({
+                "conditions": edge.conditions().to_json(repacker),
+                "value": value,
+            }));
+        }
+        json!({
+            "latest": self.latest.to_json(),
+            "reborrows": reborrows,
+            "deref_expansions": deref_expansions,
+            "region_abstractions": region_abstractions,
+            "region_projection_members": region_projection_members,
+        })
     }
 
     pub fn new() -> Self {
         Self {
             latest: Latest::new(),
             graph: BorrowsGraph::new(),
+            aging_policy: AgingPolicy::default(),
+            lenient: false,
+            aging_reasons: FxHashMap::default(),
+        }
+    }
+
+    /// A state consisting of the given edges, e.g. to exercise operations on
+    /// the graph without running the analysis. The `latest` map should contain
+    /// the snapshot locations referenced by the old places of the edges.
+    pub fn from_edges(edges: impl IntoIterator<Item = BorrowsEdge<'tcx>>, latest: Latest) -> Self {
+        let mut state = Self::new();
+        for edge in edges {
+            state.graph.insert(edge);
         }
+        state.latest = latest;
+        state
+    }
+
+    pub fn aging_policy(&self) -> AgingPolicy {
+        self.aging_policy
+    }
+
+    pub fn set_aging_policy(&mut self, aging_policy: AgingPolicy) {
+        self.aging_policy = aging_policy;
+    }
+
+    pub fn is_lenient(&self) -> bool {
+        self.lenient
+    }
+
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
     }
 
     pub fn add_region_abstraction(
@@ -523,12 +987,625 @@ impl<'tcx> BorrowsState<'tcx> {
             .insert(abstraction.to_borrows_edge(PathConditions::new(block)));
     }
 
+    /// Why the old place `place` was made old, if known
+    pub fn explain_old(&self, place: PlaceSnapshot<'tcx>) -> Option<AgingReason> {
+        self.aging_reasons
+            .iter()
+            .find(|(aged, _)| explains(aged, &place))
+            .map(|(_, reason)| *reason)
+    }
+
+    /// Explains why `place` is blocked, by following the edges of the graph
+    /// from `place` to the places that (transitively) block it, e.g. "x is
+    /// blocked because (*_2) reborrows x at bb0[1], and (*_3) reborrows (*_2)
+    /// at bb0[2]".
+    pub fn explain_blocked(
+        &self,
+        place: ReborrowBlockedPlace<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> String {
+        let describe_places = |places: &FxHashSet<MaybeOldPlace<'tcx>>| {
+            let mut places = places.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            places.sort();
+            if places.len() == 1 {
+                places.pop().unwrap()
+            } else {
+                format!("{{{}}}", places.join(", "))
+            }
+        };
+        let mut reasons = vec![];
+        let mut visited = FxHashSet::default();
+        let mut worklist = VecDeque::from([place]);
+        while let Some(blocked) = worklist.pop_front() {
+            if !visited.insert(blocked) {
+                continue;
+            }
+            let mut edge_reasons = vec![];
+            for edge in self.edges_blocking(blocked) {
+                let blocking = edge.blocked_by_places(repacker);
+                edge_reasons.push(match edge.kind() {
+                    BorrowsEdgeKind::Reborrow(reborrow) => format!(
+                        "{} {} {} at {:?}",
+                        reborrow.assigned_place,
+                        if reborrow.mutability.is_mut() {
+                            "mutably reborrows"
+                        } else {
+                            "reborrows"
+                        },
+                        blocked,
+                        reborrow.reserve_location()
+                    ),
+                    BorrowsEdgeKind::DerefExpansion(_) => format!(
+                        "{} is expanded into {}",
+                        blocked,
+                        describe_places(&blocking)
+                    ),
+                    BorrowsEdgeKind::RegionAbstraction(abstraction) => {
+                        let origin = match abstraction.abstraction_type {
+                            AbstractionType::FunctionCall(_) => "call",
+                            AbstractionType::Loop(_) => "loop",
+                        };
+                        format!(
+                            "{} is blocked by {} through the {} at {:?}",
+                            blocked,
+                            describe_places(&blocking),
+                            origin,
+                            abstraction.location()
+                        )
+                    }
+                    BorrowsEdgeKind::RegionProjectionMember(member) => format!(
+                        "{} flows into {} at {:?}",
+                        blocked,
+                        describe_places(&blocking),
+                        member.location()
+                    ),
+                });
+                worklist.extend(blocking.into_iter().map(ReborrowBlockedPlace::Local));
+            }
+            edge_reasons.sort();
+            reasons.extend(edge_reasons);
+        }
+        if reasons.is_empty() {
+            format!("{} is not blocked", place)
+        } else {
+            format!("{} is blocked because {}", place, reasons.join(", and "))
+        }
+    }
+
+    fn record_aging(&mut self, place: Place<'tcx>, reason: AgingReason) {
+        self.aging_reasons
+            .insert(PlaceSnapshot::new(place, self.latest.get(&place)), reason);
+    }
+
     pub fn make_place_old(
         &mut self,
         place: Place<'tcx>,
         _repacker: PlaceRepacker<'_, 'tcx>,
         debug_ctx: Option<DebugCtx>,
+        reason: AgingReason,
     ) {
+        self.record_aging(place, reason);
         self.graph.make_place_old(place, &self.latest, debug_ctx);
     }
+
+    /// Removes the borrows behind `place`, which is deinitialized by a
+    /// `Deinit` statement at `location`: its former value is made old, the
+    /// old leaves are trimmed and the edges still blocking the place are
+    /// deleted.
+    pub fn deinit(
+        &mut self,
+        place: Place<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+        location: Location,
+        debug_ctx: Option<DebugCtx>,
+        shared_loans_to_keep: &FxHashSet<Location>,
+    ) {
+        self.make_place_old(place, repacker, debug_ctx, AgingReason::Deinit);
+        self.trim_old_leaves(repacker, location, shared_loans_to_keep);
+        self.delete_descendants_of(MaybeOldPlace::Current { place }, repacker, location);
+    }
+
+    /// Refreshes the resume argument `resume_arg` of a `Yield` at `location`,
+    /// which holds a fresh value when the coroutine is resumed: its previous
+    /// value is made old, and the old leaves are trimmed.
+    pub fn resume(
+        &mut self,
+        resume_arg: Place<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+        location: Location,
+        debug_ctx: Option<DebugCtx>,
+        shared_loans_to_keep: &FxHashSet<Location>,
+    ) {
+        self.set_latest(resume_arg, location);
+        self.make_place_old(resume_arg, repacker, debug_ctx, AgingReason::Reassign);
+        self.trim_old_leaves(repacker, location, shared_loans_to_keep);
+    }
+
+    /// Makes the places affected by a move out of `place` old, according to
+    /// the aging policy of this state. The moved-out value is snapshotted at
+    /// `moved_at`, which is a [`SnapshotLocation::BeforeStatement`] for a move
+    /// out of an operand of the statement.
+    pub fn make_moved_place_old(
+        &mut self,
+        place: Place<'tcx>,
+        moved_at: SnapshotLocation,
+        repacker: PlaceRepacker<'_, 'tcx>,
+        debug_ctx: Option<DebugCtx>,
+        reason: AgingReason,
+    ) {
+        self.set_latest(place, moved_at);
+        self.record_aging(place, reason);
+        match self.aging_policy {
+            AgingPolicy::Subtree => self.graph.make_place_old(place, &self.latest, debug_ctx),
+            AgingPolicy::LeafOnly => {
+                for leaf in self.graph.leaf_places_with_prefix(place, repacker) {
+                    self.graph.make_place_old(leaf, &self.latest, debug_ctx);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use super::*;
+    use crate::{
+        run_combined_pcs,
+        rustc_interface::middle::mir::{Operand, Rvalue, StatementKind, TerminatorKind},
+        test_utils::{find_statement, local_named, run_on_fn},
+        utils::assumptions::take_assumption_violations,
+        PcsOptions,
+    };
+
+    #[test]
+    fn reborrow_assigned_to_non_deref_is_rejected() {
+        run_on_fn("14_reborrow_targets.rs", "copy_ref", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = Place::from(local_named(&body.body, "x"));
+            let y = Place::from(local_named(&body.body, "y"));
+            let mut state = BorrowsState::new();
+            let mut add_reborrow = |lenient| {
+                state.set_lenient(lenient);
+                state.add_reborrow(
+                    x.project_deref(rp).into(),
+                    y,
+                    Mutability::Not,
+                    ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                    Location::START,
+                    0,
+                )
+            };
+            assert!(catch_unwind(AssertUnwindSafe(|| add_reborrow(false))).is_err());
+            assert!(!add_reborrow(true));
+            assert_eq!(take_assumption_violations().len(), 1);
+            assert!(state.graph().is_empty());
+        });
+    }
+
+    /// Whether a place inside `outer.inner`, which is moved by
+    /// `consume(outer.inner)` in `tests/07_nested_move.rs`, is still current in
+    /// the graph after the move
+    fn moved_place_current_after_move(aging_policy: AgingPolicy) -> bool {
+        let mut current = None;
+        run_on_fn("07_nested_move.rs", "main", |tcx, body| {
+            let outer = local_named(&body.body, "outer");
+            let mut moved = None;
+            let location = find_statement(&body.body, |statement| {
+                if let StatementKind::Assign(box (_, Rvalue::Use(Operand::Move(place)))) =
+                    &statement.kind
+                    && place.local == outer
+                {
+                    moved = Some(Place::from(*place));
+                }
+                moved.is_some()
+            });
+            let moved = moved.unwrap();
+            let options = PcsOptions {
+                aging_policy,
+                ..Default::default()
+            };
+            let mut output = run_combined_pcs(body, tcx, None, false, None, None, options);
+            let rp = output.repacker();
+            let statement = output
+                .get_all_for_bb(location.block)
+                .statements
+                .remove(location.statement_index);
+            current = Some(statement.extra.after.graph_edges().any(|edge| {
+                edge.blocked_by_places(rp)
+                    .into_iter()
+                    .chain(
+                        edge.blocked_places()
+                            .into_iter()
+                            .filter_map(|p| p.as_local()),
+                    )
+                    .any(|p| p.is_current() && moved.is_prefix(p.place()))
+            }));
+        });
+        current.unwrap()
+    }
+
+    #[test]
+    fn subtree_aging_ages_expanded_places() {
+        assert!(!moved_place_current_after_move(AgingPolicy::Subtree));
+    }
+
+    #[test]
+    fn leaf_only_aging_keeps_expanded_places_current() {
+        assert!(moved_place_current_after_move(AgingPolicy::LeafOnly));
+    }
+
+    /// `Deinit(p)` removes the reborrow of `x` into `*p.a`, which is behind
+    /// a field of `p`
+    #[test]
+    fn deinit_removes_reborrow_behind_field() {
+        run_on_fn("48_struct_with_reference_fields.rs", "main", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = Place::from(local_named(&body.body, "x"));
+            let p = Place::from(local_named(&body.body, "p"));
+            let a = p.expand_field(None, rp)[0];
+            let mut state = BorrowsState::new();
+            assert!(state.add_reborrow(
+                x.into(),
+                a.project_deref(rp),
+                Mutability::Mut,
+                ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                Location::START,
+                0,
+            ));
+            state.deinit(p, rp, Location::START, None, &FxHashSet::default());
+            assert!(!state
+                .graph_edges()
+                .any(|edge| matches!(edge.kind(), BorrowsEdgeKind::Reborrow(_))));
+        });
+    }
+
+    /// When the coroutine is resumed at `resume = yield ..`, the reborrow
+    /// `r = &mut *resume` of the previous resume argument refers to its old
+    /// value, and the new value is not borrowed
+    #[test]
+    fn resume_argument_is_refreshed_after_yield() {
+        run_on_fn("08_resume_arg.rs", "main::{closure#0}", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let r = Place::from(local_named(&body.body, "r"));
+            let (location, resume_arg) = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .find_map(|(block, data)| match &data.terminator().kind {
+                    TerminatorKind::Yield { resume_arg, .. } => {
+                        Some((body.body.terminator_loc(block), Place::from(*resume_arg)))
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            let mut state = BorrowsState::new();
+            assert!(state.add_reborrow(
+                resume_arg.project_deref(rp).into(),
+                r.project_deref(rp),
+                Mutability::Mut,
+                ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                Location::START,
+                0,
+            ));
+            state.resume(resume_arg, rp, location, None, &FxHashSet::default());
+            let previous = MaybeOldPlace::OldPlace(PlaceSnapshot::new(
+                resume_arg.project_deref(rp),
+                SnapshotLocation::Location(location),
+            ));
+            assert!(state.graph_edges().any(|edge| matches!(
+                edge.kind(),
+                BorrowsEdgeKind::Reborrow(reborrow)
+                    if reborrow.blocked_place == previous.into()
+                        && reborrow.assigned_place == r.project_deref(rp).into()
+            )));
+            assert!(!state.graph_edges().any(|edge| edge
+                .blocked_places()
+                .into_iter()
+                .filter_map(|place| place.as_local())
+                .chain(edge.blocked_by_places(rp))
+                .any(|place| place.is_current() && place.place().local == resume_arg.local)));
+        });
+    }
+
+    #[test]
+    fn siblings_of_borrowed_fields_are_accessible() {
+        run_on_fn("20_borrow_one_field.rs", "main", |tcx, body| {
+            let x = local_named(&body.body, "x");
+            let mut borrowed = vec![];
+            let mut assigned = vec![];
+            for data in body.body.basic_blocks.iter() {
+                for statement in data.statements.iter() {
+                    match &statement.kind {
+                        StatementKind::Assign(box (_, Rvalue::Ref(_, _, place)))
+                            if place.local == x =>
+                        {
+                            borrowed.push(Place::from(*place))
+                        }
+                        StatementKind::Assign(box (target, Rvalue::Use(Operand::Constant(_))))
+                            if target.local == x =>
+                        {
+                            assigned.push(Place::from(*target))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            // `x.a` and `x.c.0` are borrowed, `x.b` and `x.c.1` are assigned
+            // while they are
+            let [_, last_borrowed] = borrowed[..] else {
+                panic!("Expected two borrows of `x`, got {:?}", borrowed);
+            };
+            let location = find_statement(&body.body, |statement| {
+                matches!(
+                    &statement.kind,
+                    StatementKind::Assign(box (_, Rvalue::Ref(_, _, place)))
+                        if Place::from(*place) == last_borrowed
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let state = output
+                .get_all_for_bb(location.block)
+                .statements
+                .remove(location.statement_index)
+                .extra
+                .after;
+            let accessible = state.accessible_projections(x.into(), rp);
+            assert_eq!(
+                accessible.into_iter().collect::<FxHashSet<_>>(),
+                assigned.into_iter().collect::<FxHashSet<_>>()
+            );
+        });
+    }
+
+    #[test]
+    fn apply_terminate_reborrow_action_removes_reborrow() {
+        run_on_fn("10_terminate_reborrow.rs", "main", |tcx, body| {
+            let x = local_named(&body.body, "x");
+            let location = find_statement(&body.body, |statement| {
+                matches!(
+                    &statement.kind,
+                    StatementKind::Assign(box (_, Rvalue::Ref(_, _, place))) if place.local == x
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let mut state = output
+                .get_all_for_bb(location.block)
+                .statements
+                .remove(location.statement_index)
+                .extra
+                .after;
+            let reborrow = state
+                .reborrows()
+                .into_iter()
+                .find(|reborrow| reborrow.value.reserve_location() == location)
+                .unwrap()
+                .value;
+            let action = UnblockAction::TerminateReborrow {
+                blocked_place: reborrow.blocked_place,
+                assigned_place: reborrow.assigned_place,
+                reserve_location: reborrow.reserve_location(),
+                index: reborrow.id().index,
+                is_mut: reborrow.mutability == Mutability::Mut,
+            };
+            assert!(state.apply_action(&action, rp, location.successor_within_block()));
+            assert!(state.reborrows().iter().all(|r| r.value != reborrow));
+            assert!(!state.apply_action(&action, rp, location.successor_within_block()));
+        });
+    }
+
+    #[test]
+    fn reborrow_is_found_after_assigned_place_is_aged() {
+        run_on_fn("25_reborrow_of_moved_reference.rs", "main", |tcx, body| {
+            // `consume(s)` reborrows `*s` into a temporary, which is moved
+            // into the call
+            let (block, arg) = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .find_map(|(block, data)| match &data.terminator().kind {
+                    TerminatorKind::Call { args, .. } => Some((block, args[0].node.place()?)),
+                    _ => None,
+                })
+                .unwrap();
+            let reserve_location = find_statement(&body.body, |statement| {
+                matches!(
+                    &statement.kind,
+                    StatementKind::Assign(box (target, Rvalue::Ref(..))) if *target == arg
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let state = output
+                .get_all_for_bb(block)
+                .terminator
+                .succs
+                .remove(0)
+                .extra
+                .after;
+            let place = Place::from(arg).project_deref(rp).to_rust_place(rp);
+            let reborrows = state.reborrows_assigned_to_place(place);
+            let [reborrow] = &reborrows.into_iter().collect::<Vec<_>>()[..] else {
+                panic!("Expected a single reborrow assigned to `*{:?}`", arg);
+            };
+            assert_eq!(reborrow.value.reserve_location(), reserve_location);
+            assert!(reborrow.value.assigned_place.is_old());
+        });
+    }
+
+    /// In `r = take(r)` of `tests/37_move_after_reborrow.rs`, `r` is moved
+    /// into the call before the call assigns it: the moved-out value and the
+    /// value given by the call have distinct snapshots
+    #[test]
+    fn moves_before_statement_have_their_own_snapshots() {
+        run_on_fn("37_move_after_reborrow.rs", "main", |tcx, body| {
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let mut before_statement = FxHashSet::default();
+            let mut at_statement = FxHashSet::default();
+            for block in body.body.basic_blocks.indices() {
+                for statement in output.get_all_for_bb(block).statements {
+                    for snapshot in statement.extra.after.graph().old_places() {
+                        match snapshot.at {
+                            SnapshotLocation::BeforeStatement(location) => {
+                                before_statement.insert(location);
+                            }
+                            SnapshotLocation::Location(location) => {
+                                at_statement.insert(location);
+                            }
+                            SnapshotLocation::Join(_) => {}
+                        }
+                    }
+                }
+            }
+            assert!(!before_statement.is_disjoint(&at_statement));
+        });
+    }
+
+    #[test]
+    fn old_places_of_local_assigned_in_both_branches_get_join_snapshot() {
+        run_on_fn(
+            "40_assign_in_both_branches.rs",
+            "assign_in_both_branches",
+            |tcx, body| {
+                let x = body
+                    .body
+                    .basic_blocks
+                    .iter()
+                    .flat_map(|data| data.statements.iter())
+                    .find_map(|statement| match &statement.kind {
+                        StatementKind::StorageDead(local) => Some(*local),
+                        _ => None,
+                    })
+                    .unwrap();
+                let join = body
+                    .body
+                    .basic_blocks
+                    .iter_enumerated()
+                    .find(|(_, data)| data.terminator().successors().count() == 0)
+                    .map(|(block, _)| block)
+                    .unwrap();
+                let mut output =
+                    run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+                let state = output
+                    .get_all_for_bb(join)
+                    .statements
+                    .remove(0)
+                    .extra
+                    .before_start;
+                assert_eq!(state.get_latest(&x.into()), SnapshotLocation::Join(join));
+                let old_places_of_x = state
+                    .graph()
+                    .old_places()
+                    .into_iter()
+                    .filter(|snapshot| snapshot.place.local == x)
+                    .collect::<Vec<_>>();
+                assert!(!old_places_of_x.is_empty());
+                for snapshot in old_places_of_x {
+                    assert_eq!(snapshot.at, SnapshotLocation::Join(join));
+                    assert_eq!(
+                        state.explain_old(snapshot),
+                        Some(AgingReason::StorageDead),
+                        "{snapshot:?}"
+                    );
+                }
+            },
+        );
+    }
+
+    /// The bridge from the state after `r = &mut x` to the state before it
+    /// terminates the reborrow: its dry-run actions are the ones that
+    /// applying it performs
+    #[test]
+    fn bridge_actions_match_the_applied_bridge() {
+        run_on_fn("10_terminate_reborrow.rs", "main", |tcx, body| {
+            let x = local_named(&body.body, "x");
+            let location = find_statement(&body.body, |statement| {
+                matches!(
+                    &statement.kind,
+                    StatementKind::Assign(box (_, Rvalue::Ref(_, _, place))) if place.local == x
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let extra = output
+                .get_all_for_bb(location.block)
+                .statements
+                .remove(location.statement_index)
+                .extra;
+            let (from, to) = (extra.after, extra.before_start);
+            let reborrow = from
+                .reborrows()
+                .into_iter()
+                .find(|reborrow| reborrow.value.reserve_location() == location)
+                .unwrap()
+                .value;
+            let terminated: FxHashSet<ReborrowId> = from
+                .bridge_actions(&to, rp)
+                .into_iter()
+                .filter_map(|action| match action {
+                    UnblockAction::TerminateReborrow {
+                        reserve_location,
+                        index,
+                        ..
+                    } => Some(ReborrowId {
+                        reserve_location,
+                        index,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            assert!(terminated.contains(&reborrow.id()));
+            let mut applied = from.clone();
+            let bridge = from.bridge(&to, DebugCtx::Other, rp);
+            assert!(applied.apply_unblock_graph(bridge.ug, rp, location));
+            let ids = |state: &BorrowsState<'_>| {
+                state
+                    .reborrows()
+                    .into_iter()
+                    .map(|reborrow| reborrow.value.id())
+                    .collect::<FxHashSet<_>>()
+            };
+            assert_eq!(
+                ids(&applied),
+                ids(&from).difference(&terminated).copied().collect()
+            );
+        });
+    }
+
+    #[test]
+    fn json_of_states_is_identical_across_runs() {
+        run_on_fn("27_aggregate_of_references.rs", "main", |tcx, body| {
+            let dump = || {
+                let mut output =
+                    run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+                let rp = output.repacker();
+                let mut json = vec![];
+                for block in body.body.basic_blocks.reverse_postorder() {
+                    for statement in output.get_all_for_bb(*block).statements {
+                        json.push(statement.extra.after.to_json(rp).to_string());
+                    }
+                }
+                json
+            };
+            let first = dump();
+            // The order of the edges only matters for states with several
+            // edges of the same kind
+            assert!(first.iter().any(|json| {
+                serde_json::from_str::<Value>(json).unwrap()["reborrows"]
+                    .as_array()
+                    .unwrap()
+                    .len()
+                    > 1
+            }));
+            assert_eq!(first, dump());
+        });
+    }
 }
@@ -1,24 +1,21 @@
-use std::{
-    collections::{BTreeMap, BTreeSet},
-    ops::ControlFlow,
-    rc::Rc,
-};
+use std::{collections::BTreeMap, ops::ControlFlow, rc::Rc};
 
 use rustc_interface::{
     ast::Mutability,
     borrowck::{
         borrow_set::BorrowSet,
-        consumers::{
-            BorrowIndex, LocationTable, PoloniusInput, PoloniusOutput, RegionInferenceContext,
-        },
+        consumers::{BorrowIndex, LocationTable, PoloniusInput, PoloniusOutput},
     },
+    data_structures::fx::{FxHashMap, FxHashSet},
+    hir::def_id::DefId,
     middle::{
         mir::{
-            visit::Visitor, AggregateKind, Body, BorrowKind, Const, Location, Operand, Place,
-            Rvalue, Statement, StatementKind, Terminator, TerminatorKind,
+            visit::Visitor, AggregateKind, Body, BorrowKind, Local, Location, Operand, Place,
+            ProjectionElem, Rvalue, Statement, StatementKind, Terminator, TerminatorKind,
         },
         ty::{
-            self, EarlyBinder, Region, RegionKind, RegionVid, TyCtxt, TypeVisitable, TypeVisitor,
+            self, EarlyBinder, GenericArgsRef, Region, RegionKind, RegionVid, Ty, TyCtxt,
+            TypeVisitable, TypeVisitor,
         },
     },
 };
@@ -26,17 +23,25 @@ use rustc_interface::{
 use crate::{
     borrows::{
         borrows_state::RegionProjectionMember,
-        domain::{AbstractionBlockEdge, AbstractionTarget},
+        domain::{AbstractionBlockEdge, AbstractionTarget, RegionProjection},
         region_abstraction::AbstractionEdge,
     },
     rustc_interface,
-    utils::{self, PlaceRepacker, PlaceSnapshot},
+    utils::{
+        self,
+        assumptions::{check_assumption, record_assumption_violation},
+        PlaceRepacker, PlaceSnapshot, SnapshotLocation,
+    },
 };
 
 use super::{
-    borrows_state::RegionProjectionMemberDirection,
-    domain::{AbstractionOutputTarget, AbstractionType, FunctionCallAbstraction},
+    borrows_state::{AgingReason, RegionProjectionMemberDirection, SharedReborrowPolicy},
+    domain::{
+        AbstractionOutputTarget, AbstractionType, FunctionCallAbstraction, ReborrowBlockedPlace,
+        ReborrowId, ReborrowKind,
+    },
     engine::{BorrowsDomain, BorrowsEngine},
+    outlives::OutlivesRelation,
 };
 use super::{domain::MaybeOldPlace, unblock_graph::UnblockGraph};
 
@@ -59,6 +64,39 @@ impl DebugCtx {
     }
 }
 
+impl From<SnapshotLocation> for DebugCtx {
+    fn from(location: SnapshotLocation) -> Self {
+        match location {
+            SnapshotLocation::Location(location) | SnapshotLocation::BeforeStatement(location) => {
+                DebugCtx::Location(location)
+            }
+            SnapshotLocation::Join(_) => DebugCtx::Other,
+        }
+    }
+}
+
+/// Why the region abstraction of a call could not be built
+#[derive(Debug, Clone)]
+pub enum AbstractionBuildError<'tcx> {
+    /// The signature of the callee (the operand) can't be resolved, and the
+    /// result of the call may borrow from its arguments
+    UnresolvedCallee(Operand<'tcx>),
+}
+
+impl std::fmt::Display for AbstractionBuildError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AbstractionBuildError::UnresolvedCallee(func) => {
+                write!(
+                    f,
+                    "The signature of the callee {:?} can't be resolved",
+                    func
+                )
+            }
+        }
+    }
+}
+
 pub struct BorrowsVisitor<'tcx, 'mir, 'state> {
     tcx: TyCtxt<'tcx>,
     body: &'mir Body<'tcx>,
@@ -68,9 +106,15 @@ pub struct BorrowsVisitor<'tcx, 'mir, 'state> {
     borrow_set: Rc<BorrowSet<'tcx>>,
     before: bool,
     preparing: bool,
-    region_inference_context: Rc<RegionInferenceContext<'tcx>>,
     debug_ctx: Option<DebugCtx>,
-    output_facts: &'mir PoloniusOutput,
+    output_facts: Option<&'mir PoloniusOutput>,
+    loans_in_scope: Rc<FxHashMap<Location, FxHashSet<Location>>>,
+    outlives: Rc<OutlivesRelation>,
+    shared_reborrow_policy: SharedReborrowPolicy,
+    lenient: bool,
+    /// The index of the next reborrow reserved at the visited location, see
+    /// [`ReborrowId`]. A visitor visits a single statement or terminator.
+    next_reborrow_index: usize,
 }
 
 impl<'tcx, 'mir, 'state> BorrowsVisitor<'tcx, 'mir, 'state> {
@@ -108,15 +152,70 @@ impl<'tcx, 'mir, 'state> BorrowsVisitor<'tcx, 'mir, 'state> {
             preparing,
             location_table: engine.location_table,
             borrow_set: engine.borrow_set.clone(),
-            region_inference_context: engine.region_inference_context.clone(),
             debug_ctx: None,
             output_facts: engine.output_facts,
+            loans_in_scope: engine.loans_in_scope.clone(),
+            outlives: engine.outlives.clone(),
+            shared_reborrow_policy: engine.shared_reborrow_policy,
+            lenient: engine.lenient,
+            next_reborrow_index: 0,
         }
     }
-    fn ensure_expansion_to_exactly(&mut self, place: utils::Place<'tcx>, location: Location) {
-        self.state
-            .after
-            .ensure_expansion_to_exactly(self.tcx, self.body, place, location)
+
+    /// Adds a reborrow reserved at `location`, with the next index among the
+    /// reborrows reserved by the visited statement or terminator. Returns its
+    /// id if it was added.
+    fn add_reborrow(
+        &mut self,
+        blocked_place: ReborrowBlockedPlace<'tcx>,
+        assigned_place: utils::Place<'tcx>,
+        mutability: Mutability,
+        kind: ReborrowKind<'tcx>,
+        location: Location,
+    ) -> Option<ReborrowId> {
+        let index = self.next_reborrow_index;
+        if !self.state.after.add_reborrow(
+            blocked_place,
+            assigned_place,
+            mutability,
+            kind,
+            location,
+            index,
+        ) {
+            return None;
+        }
+        self.next_reborrow_index += 1;
+        Some(ReborrowId {
+            reserve_location: location,
+            index,
+        })
+    }
+    /// The snapshot location of the values given to places by the effects of
+    /// this visitor at `location`: the effects applied before the statement
+    /// don't share their snapshots with those of the statement itself
+    fn snapshot_location(&self, location: Location) -> SnapshotLocation {
+        if self.before {
+            SnapshotLocation::BeforeStatement(location)
+        } else {
+            SnapshotLocation::Location(location)
+        }
+    }
+
+    fn ensure_expansion_to_exactly(
+        &mut self,
+        place: utils::Place<'tcx>,
+        access: Mutability,
+        location: Location,
+    ) {
+        let unblocked_at = self.snapshot_location(location);
+        self.state.after.ensure_expansion_to_exactly(
+            self.tcx,
+            self.body,
+            place,
+            access,
+            location,
+            unblocked_at,
+        )
     }
 
     fn loans_invalidated_at(&self, location: Location, start: bool) -> Vec<BorrowIndex> {
@@ -138,27 +237,114 @@ impl<'tcx, 'mir, 'state> BorrowsVisitor<'tcx, 'mir, 'state> {
             .collect()
     }
 
-    fn outlives(&self, sup: RegionVid, sub: RegionVid) -> bool {
-        let mut visited = BTreeSet::default();
-        let mut stack = vec![sup];
-
-        while let Some(current) = stack.pop() {
-            if current == sub {
-                return true;
-            }
-
-            if visited.insert(current) {
-                for o in self
-                    .region_inference_context
-                    .outlives_constraints()
-                    .filter(|c| c.sup == current)
-                {
-                    stack.push(o.sub);
+    /// The signature of the function called by `func`, if it can be resolved
+    fn callee_signature(&self, func: &Operand<'tcx>) -> Option<CalleeSignature<'tcx>> {
+        let caller_def_id = self.body.source.def_id();
+        let func_ty = func.ty(self.body, self.tcx);
+        match *func_ty.kind() {
+            ty::TyKind::FnDef(def_id, substs) => {
+                let is_fn_trait_method = self
+                    .tcx
+                    .trait_of_item(def_id)
+                    .is_some_and(|trait_id| self.tcx.fn_trait_kind_from_def_id(trait_id).is_some());
+                if is_fn_trait_method {
+                    return self.fn_trait_call_signature(def_id, substs);
                 }
+                let sig = EarlyBinder::instantiate_identity(self.tcx.fn_sig(def_id));
+                let sig = self.tcx.liberate_late_bound_regions(def_id, sig);
+                Some(CalleeSignature {
+                    def_id: Some(def_id),
+                    substs,
+                    inputs: sig.inputs().iter().copied().enumerate().collect(),
+                    output: sig.output(),
+                    param_env: self.tcx.param_env(def_id),
+                })
+            }
+            ty::TyKind::FnPtr(..) => {
+                let sig = func_ty.fn_sig(self.tcx);
+                let sig = self.tcx.liberate_late_bound_regions(caller_def_id, sig);
+                Some(CalleeSignature {
+                    def_id: None,
+                    substs: ty::List::empty(),
+                    inputs: sig.inputs().iter().copied().enumerate().collect(),
+                    output: sig.output(),
+                    param_env: self.tcx.param_env(caller_def_id),
+                })
             }
+            _ => None,
         }
+    }
 
-        false
+    /// The signature of a call of a method of the `Fn*` traits (e.g. a call
+    /// of a closure), in which the arguments are passed as a tuple (the
+    /// second argument of the call). The signature is that of the closure,
+    /// function pointer or trait object the method is called on.
+    fn fn_trait_call_signature(
+        &self,
+        def_id: DefId,
+        substs: GenericArgsRef<'tcx>,
+    ) -> Option<CalleeSignature<'tcx>> {
+        let caller_def_id = self.body.source.def_id();
+        let self_ty = substs.type_at(0);
+        let (args, output, scope) = match *self_ty.kind() {
+            ty::TyKind::Closure(closure_def_id, closure_substs) => {
+                let sig = closure_substs.as_closure().sig();
+                let sig = self.tcx.liberate_late_bound_regions(closure_def_id, sig);
+                (sig.inputs()[0], sig.output(), closure_def_id)
+            }
+            ty::TyKind::FnPtr(..) => {
+                let sig = self_ty.fn_sig(self.tcx);
+                let sig = self.tcx.liberate_late_bound_regions(caller_def_id, sig);
+                (
+                    Ty::new_tup(self.tcx, sig.inputs()),
+                    sig.output(),
+                    caller_def_id,
+                )
+            }
+            ty::TyKind::Dynamic(predicates, _, _) => {
+                let fn_once_output = self.tcx.lang_items().fn_once_output()?;
+                let args = predicates.principal()?.map_bound(|p| p.args.type_at(0));
+                let output = predicates
+                    .projection_bounds()
+                    .find(|p| p.item_def_id() == fn_once_output)?
+                    .map_bound(|p| p.term.expect_type());
+                (
+                    self.tcx.liberate_late_bound_regions(caller_def_id, args),
+                    self.tcx.liberate_late_bound_regions(caller_def_id, output),
+                    caller_def_id,
+                )
+            }
+            ty::TyKind::Param(_) => {
+                // A generic `F: Fn*(..) -> ..` of the caller (e.g. an
+                // argument-position `impl Fn*`), whose signature is that of
+                // the bound of `F` in the caller's environment
+                let fn_once_output = self.tcx.lang_items().fn_once_output()?;
+                let bound = self
+                    .tcx
+                    .param_env(caller_def_id)
+                    .caller_bounds()
+                    .iter()
+                    .filter_map(|clause| clause.as_projection_clause())
+                    .find(|p| {
+                        p.projection_def_id() == fn_once_output
+                            && p.skip_binder().self_ty() == self_ty
+                    })?;
+                let bound = self.tcx.liberate_late_bound_regions(caller_def_id, bound);
+                (
+                    bound.projection_term.args.type_at(1),
+                    bound.term.expect_type(),
+                    caller_def_id,
+                )
+            }
+            _ => return None,
+        };
+        Some(CalleeSignature {
+            def_id: Some(def_id),
+            substs,
+            inputs: vec![(1, args)],
+            output,
+            param_env: self.tcx.param_env(scope),
+        })
     }
 
     fn construct_region_abstraction_if_necessary(
@@ -167,31 +353,32 @@ impl<'tcx, 'mir, 'state> BorrowsVisitor<'tcx, 'mir, 'state> {
         args: &[&Operand<'tcx>],
         destination: Place<'tcx>,
         location: Location,
-    ) {
-        let (func_def_id, substs) = match func {
-            Operand::Constant(box c) => match c.const_ {
-                Const::Val(_, ty) => match ty.kind() {
-                    ty::TyKind::FnDef(def_id, substs) => (def_id, substs),
-                    _ => unreachable!(),
-                },
-                _ => unreachable!(),
-            },
-            _ => unreachable!(),
+    ) -> Result<(), AbstractionBuildError<'tcx>> {
+        let Some(callee) = self.callee_signature(func) else {
+            // E.g. a call of a generic `F: FnMut(..)`, whose signature is only
+            // known from the bounds of `F`. The call can only be skipped if
+            // its result doesn't borrow from the arguments.
+            let destination_ty = destination.ty(self.body, self.tcx).ty;
+            if extract_lifetimes(destination_ty).is_empty() {
+                return Ok(());
+            }
+            return Err(AbstractionBuildError::UnresolvedCallee(func.clone()));
         };
-        let sig = EarlyBinder::instantiate_identity(self.tcx.fn_sig(func_def_id));
-        let sig = self.tcx.liberate_late_bound_regions(*func_def_id, sig);
-        let output_lifetimes = extract_lifetimes(sig.output());
+        let output_lifetimes = extract_lifetimes(callee.output);
         if output_lifetimes.is_empty() {
-            return;
+            return Ok(());
         }
-        let param_env = self.tcx.param_env(func_def_id);
+        let substs = callee.substs;
+        let param_env = callee.param_env;
+        let output_ty = callee.output;
         let mut edges = vec![];
 
-        for (idx, ty) in sig.inputs().iter().enumerate() {
+        for (idx, ty) in callee.inputs {
             let input_place: utils::Place<'tcx> = match args[idx].place() {
                 Some(place) => place.into(),
                 None => continue,
             };
+            let remote_input = self.passed_argument(input_place, location);
             let input_place = MaybeOldPlace::OldPlace(PlaceSnapshot::new(
                 input_place,
                 self.state.after.get_latest(&input_place),
@@ -203,7 +390,7 @@ impl<'tcx, 'mir, 'state> BorrowsVisitor<'tcx, 'mir, 'state> {
                             *region,
                             param_env,
                             substs,
-                            sig.output(),
+                            output_ty,
                             destination.into(),
                         ) {
                             let input_place = input_place.project_deref(self.repacker());
@@ -218,22 +405,60 @@ impl<'tcx, 'mir, 'state> BorrowsVisitor<'tcx, 'mir, 'state> {
                     }
                     *ty
                 }
-                _ => *ty,
+                _ => ty,
             };
+            if let ty::TyKind::Param(param) = ty.kind() {
+                // The lifetimes of generic arguments (e.g. argument-position
+                // `impl Trait`) are only visible in the bounds of the type
+                // parameter. Conservatively, the argument as a whole blocks
+                // the outputs with matching lifetimes.
+                for input_lifetime in param_bound_lifetimes(*param, param_env) {
+                    for output in self.matches_for_input_lifetime(
+                        input_lifetime,
+                        param_env,
+                        substs,
+                        output_ty,
+                        destination.into(),
+                    ) {
+                        let edge = (
+                            idx,
+                            AbstractionBlockEdge {
+                                input: AbstractionTarget::Place(input_place.into()),
+                                output,
+                            },
+                        );
+                        if !edges.contains(&edge) {
+                            edges.push(edge);
+                        }
+                    }
+                }
+            }
             for (lifetime_idx, input_lifetime) in extract_lifetimes(ty).into_iter().enumerate() {
+                // The lifetimes of the signature are those of the generic
+                // callee, the substituted type of the argument may have fewer
+                // (e.g. if a lifetime only occurs in an associated type that
+                // is normalized to a type without lifetimes). Such an argument
+                // cannot carry a borrow with that lifetime into the call.
+                let Some(input_projection) =
+                    input_place.region_projection(lifetime_idx, self.repacker())
+                else {
+                    continue;
+                };
+                let input_projection = match remote_input {
+                    Some(local) => RegionProjection::new_remote(input_projection.region, local),
+                    None => input_projection.into(),
+                };
                 for output in self.matches_for_input_lifetime(
                     input_lifetime,
                     param_env,
                     substs,
-                    sig.output(),
+                    output_ty,
                     destination.into(),
                 ) {
                     edges.push((
                         idx,
                         AbstractionBlockEdge {
-                            input: AbstractionTarget::RegionProjection(
-                                input_place.region_projection(lifetime_idx, self.repacker()),
-                            ),
+                            input: AbstractionTarget::RegionProjection(input_projection),
                             output,
                         },
                     ));
@@ -243,17 +468,46 @@ impl<'tcx, 'mir, 'state> BorrowsVisitor<'tcx, 'mir, 'state> {
 
         // No edges may be added e.g. if the inputs do not contain any (possibly
         // nested) mutable references
-        if !edges.is_empty() {
+        if !edges.is_empty()
+            && let Some(abstraction) =
+                FunctionCallAbstraction::new(location, callee.def_id, substs, edges, self.lenient)
+        {
             self.state.after.add_region_abstraction(
-                AbstractionEdge::new(AbstractionType::FunctionCall(FunctionCallAbstraction::new(
-                    location,
-                    *func_def_id,
-                    substs,
-                    edges,
-                ))),
+                AbstractionEdge::new(AbstractionType::FunctionCall(abstraction)),
                 location.block,
             );
         }
+        Ok(())
+    }
+
+    /// The argument of the body passed on in the call operand `place` at
+    /// `location`, either directly or (as in the MIR built for `f(x)`) moved
+    /// into a temporary in the block of the call. Only immutable arguments
+    /// are considered: they still hold the value provided by the caller, so
+    /// their regions are those of the caller.
+    fn passed_argument(&self, place: utils::Place<'tcx>, location: Location) -> Option<Local> {
+        if !place.projection.is_empty() {
+            return None;
+        }
+        let statements = &self.body.basic_blocks[location.block].statements;
+        let local = statements[..location.statement_index]
+            .iter()
+            .rev()
+            .find_map(|statement| match &statement.kind {
+                StatementKind::Assign(box (target, rvalue)) if target.local == place.local => {
+                    Some(match rvalue {
+                        Rvalue::Use(Operand::Move(from)) if target.projection.is_empty() => {
+                            from.as_local()
+                        }
+                        _ => None,
+                    })
+                }
+                _ => None,
+            })
+            .unwrap_or(Some(place.local))?;
+        (self.body.args_iter().any(|arg| arg == local)
+            && self.body.local_decls[local].mutability == Mutability::Not)
+            .then_some(local)
     }
 
     fn matches_for_input_lifetime(
@@ -288,29 +542,103 @@ impl<'tcx, 'mir, 'state> BorrowsVisitor<'tcx, 'mir, 'state> {
         result
     }
 
+    /// The loans whose shared reborrows must be kept at `location`, see
+    /// [`SharedReborrowPolicy`]
+    fn shared_loans_to_keep(&self, location: Location) -> FxHashSet<Location> {
+        match self.shared_reborrow_policy {
+            SharedReborrowPolicy::KeepInScope => self
+                .loans_in_scope
+                .get(&location)
+                .cloned()
+                .unwrap_or_default(),
+            SharedReborrowPolicy::Structural => FxHashSet::default(),
+        }
+    }
+
     fn minimize(&mut self, location: Location) {
         let repacker = PlaceRepacker::new(self.body, self.tcx);
-        self.state.after.minimize(repacker, location);
+        let shared_loans_to_keep = self.shared_loans_to_keep(location);
+        self.state.after.minimize(
+            repacker,
+            self.snapshot_location(location),
+            &shared_loans_to_keep,
+        );
+    }
+
+    fn trim_old_leaves(&mut self, location: Location) {
+        let repacker = PlaceRepacker::new(self.body, self.tcx);
+        let shared_loans_to_keep = self.shared_loans_to_keep(location);
+        self.state.after.trim_old_leaves(
+            repacker,
+            self.snapshot_location(location),
+            &shared_loans_to_keep,
+        );
     }
 }
 
+/// The signature of the callee of a function call
+struct CalleeSignature<'tcx> {
+    /// The function that is called, if it is statically known
+    def_id: Option<DefId>,
+    substs: GenericArgsRef<'tcx>,
+    /// The types of the inputs, with the indices of the corresponding
+    /// arguments of the call
+    inputs: Vec<(usize, ty::Ty<'tcx>)>,
+    output: ty::Ty<'tcx>,
+    /// The environment in which the regions of the signature are related
+    param_env: ty::ParamEnv<'tcx>,
+}
+
+/// The lifetimes mentioned in the bounds of the type parameter `param`, e.g.
+/// `'a` for `F: FnOnce() -> &'a mut T` or `F: 'a`
+fn param_bound_lifetimes(param: ty::ParamTy, param_env: ty::ParamEnv<'_>) -> Vec<ty::Region<'_>> {
+    let mut lifetimes = vec![];
+    for bound in param_env.caller_bounds() {
+        let bounds_param = match bound.kind().skip_binder() {
+            ty::ClauseKind::Trait(predicate) => predicate.self_ty().is_param(param.index),
+            ty::ClauseKind::Projection(predicate) => predicate.self_ty().is_param(param.index),
+            ty::ClauseKind::TypeOutlives(ty::OutlivesPredicate(ty, _)) => ty.is_param(param.index),
+            _ => false,
+        };
+        if bounds_param {
+            let mut visitor = LifetimeExtractor { lifetimes: vec![] };
+            bound.visit_with(&mut visitor);
+            for lifetime in visitor.lifetimes {
+                if !lifetimes.contains(&lifetime) {
+                    lifetimes.push(lifetime);
+                }
+            }
+        }
+    }
+    lifetimes
+}
+
+/// Whether `input_lifetime` outlives `output_lifetime`, either directly or
+/// transitively via the region outlives clauses of `param_env` (e.g. `'a:
+/// 'c` follows from `'a: 'b, 'b: 'c`). Clauses with bound variables (i.e.
+/// higher-ranked ones) do not relate the regions of the signature and are
+/// ignored.
 fn outlives_in_param_env<'tcx>(
     input_lifetime: ty::Region<'tcx>,
     output_lifetime: ty::Region<'tcx>,
     param_env: ty::ParamEnv<'tcx>,
 ) -> bool {
-    if input_lifetime == output_lifetime {
-        return true;
-    }
-    for bound in param_env.caller_bounds() {
-        match bound.as_region_outlives_clause() {
-            Some(outlives) => {
-                let outlives = outlives.no_bound_vars().unwrap();
-                if outlives.0 == input_lifetime && outlives.1 == output_lifetime {
-                    return true;
-                }
+    let clauses: Vec<_> = param_env
+        .caller_bounds()
+        .iter()
+        .filter_map(|bound| bound.as_region_outlives_clause()?.no_bound_vars())
+        .collect();
+    let mut visited = vec![input_lifetime];
+    let mut to_visit = vec![input_lifetime];
+    while let Some(lifetime) = to_visit.pop() {
+        if lifetime == output_lifetime {
+            return true;
+        }
+        for ty::OutlivesPredicate(sup, sub) in clauses.iter() {
+            if *sup == lifetime && !visited.contains(sub) {
+                visited.push(*sub);
+                to_visit.push(*sub);
             }
-            _ => {}
         }
     }
     false
@@ -329,19 +657,34 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
         if self.before && self.preparing {
             match operand {
                 Operand::Move(place) => {
-                    self.state.after.set_latest((*place).into(), location);
-                    self.state.after.make_place_old(
+                    let is_call_argument = location == self.body.terminator_loc(location.block)
+                        && matches!(
+                            self.body[location.block].terminator().kind,
+                            TerminatorKind::Call { .. }
+                        );
+                    let reason = if is_call_argument {
+                        AgingReason::CallArgument
+                    } else {
+                        AgingReason::Move
+                    };
+                    self.state.after.make_moved_place_old(
                         (*place).into(),
+                        self.snapshot_location(location),
                         PlaceRepacker::new(self.body, self.tcx),
                         None,
+                        reason,
                     );
                 }
                 _ => {}
             }
             match operand {
-                Operand::Copy(place) | Operand::Move(place) => {
+                Operand::Copy(place) => {
+                    let place: utils::Place<'tcx> = (*place).into();
+                    self.ensure_expansion_to_exactly(place, Mutability::Not, location);
+                }
+                Operand::Move(place) => {
                     let place: utils::Place<'tcx> = (*place).into();
-                    self.ensure_expansion_to_exactly(place, location);
+                    self.ensure_expansion_to_exactly(place, Mutability::Mut, location);
                 }
                 _ => {}
             }
@@ -362,11 +705,29 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
                     ..
                 } => {
                     self.state.after.set_latest((*destination).into(), location);
-                    self.construct_region_abstraction_if_necessary(
+                    if let Err(err) = self.construct_region_abstraction_if_necessary(
                         func,
                         &args.iter().map(|arg| &arg.node).collect::<Vec<_>>(),
                         (*destination).into(),
                         location,
+                    ) {
+                        // The call is skipped rather than aborting the
+                        // analysis of the whole crate, in every mode
+                        record_assumption_violation(Some(location), err.to_string());
+                    }
+                }
+                TerminatorKind::Yield { resume_arg, .. } => {
+                    // The resume argument is initialized with a fresh value
+                    // when the coroutine is resumed; anything still referring
+                    // to its previous value becomes old.
+                    let repacker = PlaceRepacker::new(self.body, self.tcx);
+                    let shared_loans_to_keep = self.shared_loans_to_keep(location);
+                    self.state.after.resume(
+                        (*resume_arg).into(),
+                        repacker,
+                        location,
+                        self.debug_ctx,
+                        &shared_loans_to_keep,
                     );
                 }
                 _ => {}
@@ -393,7 +754,9 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
             }
 
             let repacker = PlaceRepacker::new(self.body, self.tcx);
-            self.state.after.apply_unblock_graph(g, repacker, location);
+            self.state
+                .after
+                .apply_unblock_graph(g, repacker, self.snapshot_location(location));
         }
 
         // Will be included as start bridge ops
@@ -406,6 +769,7 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
                             target,
                             PlaceRepacker::new(self.body, self.tcx),
                             self.debug_ctx,
+                            AgingReason::Reassign,
                         );
                     }
                 }
@@ -415,10 +779,11 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
                         if place.is_ref(self.body, self.tcx) {
                             self.ensure_expansion_to_exactly(
                                 place.project_deref(self.repacker()),
+                                Mutability::Not,
                                 location,
                             );
                         } else {
-                            self.ensure_expansion_to_exactly(place, location);
+                            self.ensure_expansion_to_exactly(place, Mutability::Not, location);
                         }
                     }
                 }
@@ -434,36 +799,89 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
                     let place: utils::Place<'tcx> = (*local).into();
                     let repacker = PlaceRepacker::new(self.body, self.tcx);
                     // if place.ty(repacker).ty.is_ref() {
-                    self.state
-                        .after
-                        .make_place_old(place, repacker, self.debug_ctx);
-                    self.state.after.trim_old_leaves(repacker, location);
+                    self.state.after.make_place_old(
+                        place,
+                        repacker,
+                        self.debug_ctx,
+                        AgingReason::StorageDead,
+                    );
+                    self.trim_old_leaves(location);
                     // }
                 }
+                StatementKind::Deinit(box place) => {
+                    // The free PCS downgrades the place to `Write`; nothing
+                    // reachable through its former value can be accessed
+                    // anymore, so the borrows behind it are removed as well.
+                    let repacker = PlaceRepacker::new(self.body, self.tcx);
+                    let shared_loans_to_keep = self.shared_loans_to_keep(location);
+                    self.state.after.deinit(
+                        (*place).into(),
+                        repacker,
+                        location,
+                        self.debug_ctx,
+                        &shared_loans_to_keep,
+                    );
+                }
+                StatementKind::SetDiscriminant { box place, .. } => {
+                    // The place now holds another variant: the borrows into
+                    // the fields of the previous one refer to its former value
+                    let place: utils::Place<'tcx> = (*place).into();
+                    let repacker = PlaceRepacker::new(self.body, self.tcx);
+                    self.state.after.make_place_old(
+                        place,
+                        repacker,
+                        self.debug_ctx,
+                        AgingReason::SetDiscriminant,
+                    );
+                }
                 StatementKind::Assign(box (target, _)) => {
                     let target: utils::Place<'tcx> = (*target).into();
                     if !target.is_owned(self.body, self.tcx) {
-                        self.ensure_expansion_to_exactly(target, location);
+                        self.ensure_expansion_to_exactly(target, Mutability::Mut, location);
                     }
                 }
                 _ => {}
             }
         }
 
-
         if !self.preparing && !self.before {
             match &statement.kind {
+                StatementKind::SetDiscriminant { box place, .. } => {
+                    self.state.after.set_latest((*place).into(), location);
+                }
                 StatementKind::Assign(box (target, rvalue)) => {
                     self.state.after.set_latest((*target).into(), location);
                     match rvalue {
                         Rvalue::Aggregate(box kind, fields) => match kind {
-                            AggregateKind::Adt(..) | AggregateKind::Tuple => {
+                            AggregateKind::Adt(..)
+                            | AggregateKind::Tuple
+                            | AggregateKind::Array(..)
+                            | AggregateKind::Closure(..)
+                            | AggregateKind::Coroutine(..)
+                            | AggregateKind::CoroutineClosure(..) => {
                                 let target: utils::Place<'tcx> = (*target).into();
                                 for (_idx, field) in fields.iter_enumerated() {
+                                    // Constant references do not borrow from
+                                    // any place in the body
+                                    if field.place().is_none() {
+                                        continue;
+                                    }
                                     match field.ty(self.body, self.tcx).kind() {
+                                        // The fields of a closure or a
+                                        // coroutine are its captures; only
+                                        // those by mutable reference hold a
+                                        // reborrow of the captured variable
+                                        ty::TyKind::Ref(_, _, mutability)
+                                            if matches!(
+                                                kind,
+                                                AggregateKind::Closure(..)
+                                                    | AggregateKind::Coroutine(..)
+                                                    | AggregateKind::CoroutineClosure(..)
+                                            ) && !mutability.is_mut() => {}
                                         ty::TyKind::Ref(region, _, _) => {
                                             for proj in target.region_projections(self.repacker()) {
                                                 if self
+                                                    .outlives
                                                     .outlives(get_vid(region).unwrap(), proj.region)
                                                 {
                                                     let operand_place: utils::Place<'tcx> =
@@ -471,7 +889,11 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
                                                     let operand_place = MaybeOldPlace::new(
                                                         operand_place
                                                             .project_deref(self.repacker()),
-                                                        Some(location),
+                                                        Some(
+                                                            self.state
+                                                                .after
+                                                                .get_latest(&operand_place),
+                                                        ),
                                                     );
                                                     self.state.after.add_region_projection_member(
                                                         RegionProjectionMember::new(
@@ -510,38 +932,114 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
                                 location,
                             );
                         }
+                        // Copying a shared reference gives a second
+                        // reference to `*from`. As for `Rvalue::Ref`, the
+                        // reborrow is assigned to `*target`, except when the
+                        // copy is stored in a field (`s.f = copy r`): the
+                        // field then holds the reference value itself
                         Rvalue::Use(Operand::Copy(from)) => {
                             match from.ty(self.body, self.tcx).ty.kind() {
                                 ty::TyKind::Ref(region, _, _) => {
                                     let from: utils::Place<'tcx> = (*from).into();
                                     let target: utils::Place<'tcx> = (*target).into();
-                                    self.state.after.add_reborrow(
+                                    let assigned_place = if matches!(
+                                        target.last_projection(),
+                                        Some((_, ProjectionElem::Field(..)))
+                                    ) {
+                                        target
+                                    } else {
+                                        target.project_deref(self.repacker())
+                                    };
+                                    self.add_reborrow(
                                         from.project_deref(self.repacker()).into(),
-                                        target.project_deref(self.repacker()),
+                                        assigned_place,
                                         Mutability::Not,
+                                        ReborrowKind::Ref(*region), // TODO: This is the region for the place, not the loan, does that matter?
                                         location,
-                                        *region, // TODO: This is the region for the place, not the loan, does that matter?
                                     );
                                 }
                                 _ => {}
                             }
                         }
+                        // A reference constant (e.g. a promoted `&[1, 2, 3]`)
+                        // does not borrow from any place in the body, the
+                        // constant itself is the root of the reborrow
+                        Rvalue::Use(Operand::Constant(box c)) => {
+                            if let ty::TyKind::Ref(region, _, mutability) = c.ty().kind() {
+                                let target: utils::Place<'tcx> = (*target).into();
+                                self.add_reborrow(
+                                    ReborrowBlockedPlace::Constant(location),
+                                    target.project_deref(self.repacker()),
+                                    *mutability,
+                                    ReborrowKind::Ref(*region),
+                                    location,
+                                );
+                            }
+                        }
+                        // Fake borrows (e.g. of the scrutinee of a match
+                        // with guards) only exist for the borrow checker,
+                        // they do not transfer any capability
+                        Rvalue::Ref(_, BorrowKind::Fake(_), _) => {}
                         Rvalue::Ref(region, kind, blocked_place) => {
                             let blocked_place: utils::Place<'tcx> = (*blocked_place).into();
                             let target: utils::Place<'tcx> = (*target).into();
                             let assigned_place = target.project_deref(self.repacker());
-                            assert_eq!(
-                                self.tcx
-                                    .erase_regions((*blocked_place).ty(self.body, self.tcx).ty),
-                                self.tcx
-                                    .erase_regions((*assigned_place).ty(self.body, self.tcx).ty)
-                            );
-                            self.state.after.add_reborrow(
+                            let blocked_ty = self
+                                .tcx
+                                .erase_regions((*blocked_place).ty(self.body, self.tcx).ty);
+                            let assigned_ty = self
+                                .tcx
+                                .erase_regions((*assigned_place).ty(self.body, self.tcx).ty);
+                            if !check_assumption(
+                                self.lenient,
+                                blocked_ty == assigned_ty,
+                                Some(location),
+                                || {
+                                    format!(
+                                    "Borrow of {:?} with type {:?} assigned to {:?} with type {:?}",
+                                    blocked_place, blocked_ty, assigned_place, assigned_ty
+                                )
+                                },
+                            ) {
+                                return;
+                            }
+                            // When borrowing behind several references (e.g.
+                            // `&mut **r`), the blocked place is only reachable
+                            // through the intermediate ones, make sure the
+                            // whole chain is in the graph
+                            if blocked_place
+                                .iter_projections()
+                                .filter(|(_, elem)| *elem == ProjectionElem::Deref)
+                                .count()
+                                > 1
+                            {
+                                self.state.after.ensure_deref_expansion_to_at_least(
+                                    self.tcx,
+                                    self.body,
+                                    blocked_place,
+                                    location,
+                                );
+                            }
+                            self.add_reborrow(
                                 blocked_place.into(),
                                 assigned_place,
                                 kind.mutability(),
+                                ReborrowKind::Ref(*region),
+                                location,
+                            );
+                        }
+                        // Raw pointers are not tracked by the borrow checker,
+                        // the reborrow lasts until the blocked place is
+                        // accessed again or the pointer is dead
+                        Rvalue::RawPtr(mutability, blocked_place) => {
+                            let blocked_place: utils::Place<'tcx> = (*blocked_place).into();
+                            let target: utils::Place<'tcx> = (*target).into();
+                            self.add_reborrow(
+                                blocked_place.into(),
+                                target.project_deref(self.repacker()),
+                                *mutability,
+                                ReborrowKind::RawPtr,
                                 location,
-                                *region,
                             );
                         }
                         _ => {}
@@ -549,8 +1047,7 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
                 }
                 _ => {}
             }
-            let repacker = PlaceRepacker::new(self.body, self.tcx);
-            self.state.after.trim_old_leaves(repacker, location);
+            self.trim_old_leaves(location);
         }
     }
 
@@ -566,15 +1063,24 @@ impl<'tcx, 'mir, 'state> Visitor<'tcx> for BorrowsVisitor<'tcx, 'mir, 'state> {
             | NullaryOp(_, _)
             | UnaryOp(_, _)
             | Aggregate(_, _)
-            | ShallowInitBox(_, _) => {}
+            | ShallowInitBox(_, _)
+            | Ref(_, BorrowKind::Fake(_), _) => {}
 
-            &Ref(_, _, place) | &Len(place) | &Discriminant(place) | &CopyForDeref(place) => {
+            &Ref(_, _, place)
+            | &RawPtr(_, place)
+            | &Len(place)
+            | &Discriminant(place)
+            | &CopyForDeref(place) => {
+                let access = match *rvalue {
+                    Ref(_, kind, _) => kind.mutability(),
+                    RawPtr(mutability, _) => mutability,
+                    _ => Mutability::Not,
+                };
                 let place: utils::Place<'tcx> = place.into();
                 if self.before && self.preparing && !place.is_owned(self.body, self.tcx) {
-                    self.ensure_expansion_to_exactly(place, location);
+                    self.ensure_expansion_to_exactly(place, access, location);
                 }
             }
-            _ => todo!(),
         }
     }
 }
@@ -585,7 +1091,11 @@ struct LifetimeExtractor<'tcx> {
 
 impl<'tcx> TypeVisitor<ty::TyCtxt<'tcx>> for LifetimeExtractor<'tcx> {
     fn visit_region(&mut self, rr: ty::Region<'tcx>) {
-        self.lifetimes.push(rr);
+        // Higher-ranked regions (e.g. in `for<'a> fn(&'a T)`) are not region
+        // variables of the body, and cannot have region projections
+        if !matches!(rr.kind(), RegionKind::ReBound(..)) {
+            self.lifetimes.push(rr);
+        }
     }
 }
 
@@ -601,3 +1111,714 @@ pub fn extract_nested_lifetimes<'tcx>(ty: ty::Ty<'tcx>) -> Vec<ty::Region<'tcx>>
         _ => extract_lifetimes(ty),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        borrows::{
+            borrows_graph::BorrowsEdgeKind,
+            borrows_state::{BorrowsState, SharedReborrowPolicy},
+        },
+        combined_pcs::BodyWithBorrowckFacts,
+        free_pcs::CapabilityKind,
+        run_borrows, run_combined_pcs,
+        rustc_interface::{
+            middle::mir::{BasicBlock, START_BLOCK},
+            target::abi::VariantIdx,
+        },
+        test_utils::{find_statement, local_named, run_on_fn},
+        utils::assumptions::take_assumption_violations,
+        PcsOptions,
+    };
+
+    /// The signature of the call `f(x)` of the generic `F` is that of the
+    /// bound of `F`, so the result is known to borrow from `*x`
+    #[test]
+    fn call_of_generic_closure_is_abstracted() {
+        run_on_fn("32_closure_call.rs", "apply", |tcx, body| {
+            let call = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .find(|(_, data)| matches!(data.terminator().kind, TerminatorKind::Call { .. }))
+                .map(|(block, _)| block)
+                .unwrap();
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let state = output
+                .get_all_for_bb(call)
+                .terminator
+                .succs
+                .remove(0)
+                .extra
+                .after;
+            assert!(state.graph_edges().any(|edge| matches!(
+                edge.kind(),
+                BorrowsEdgeKind::RegionAbstraction(AbstractionEdge {
+                    abstraction_type: AbstractionType::FunctionCall(_),
+                })
+            )));
+        });
+    }
+
+    /// The call `f(&mut x)` may return a reborrow of `x`, but the signature
+    /// of the opaque type of `f` is only known from its bounds in `make`
+    #[test]
+    fn call_with_unresolvable_signature_is_reported() {
+        run_on_fn("41_opaque_closure_call.rs", "main", |tcx, body| {
+            run_borrows(
+                body,
+                tcx,
+                PcsOptions {
+                    lenient: true,
+                    ..Default::default()
+                },
+            );
+            let violations = take_assumption_violations();
+            assert_eq!(violations.len(), 1);
+            assert!(violations[0].message.contains("can't be resolved"));
+        });
+    }
+
+    /// Unless the analysis is lenient, the call is skipped as well: the
+    /// analysis finishes, without an abstraction of the call
+    #[test]
+    fn call_with_unresolvable_signature_is_skipped() {
+        run_on_fn("41_opaque_closure_call.rs", "main", |tcx, body| {
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            for block in body.body.basic_blocks.indices() {
+                let states = output.get_all_for_bb(block);
+                let afters = states
+                    .statements
+                    .iter()
+                    .chain(&states.terminator.succs)
+                    .map(|state| &state.extra.after);
+                for state in afters {
+                    assert!(!state
+                        .graph_edges()
+                        .any(|edge| matches!(edge.kind(), BorrowsEdgeKind::RegionAbstraction(_))));
+                }
+            }
+            let violations = take_assumption_violations();
+            assert_eq!(violations.len(), 1);
+            assert!(violations[0].message.contains("can't be resolved"));
+        });
+    }
+
+    /// The signature of a function called through a `const` function pointer
+    /// is that of the pointer, whether the constant is called directly or
+    /// through a copy
+    #[test]
+    fn call_through_const_fn_pointer_is_abstracted() {
+        run_on_fn("51_const_fn_pointer_call.rs", "main", |tcx, body| {
+            let calls: Vec<BasicBlock> = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .filter(|(_, data)| matches!(data.terminator().kind, TerminatorKind::Call { .. }))
+                .map(|(block, _)| block)
+                .take(2)
+                .collect();
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            for call in calls {
+                let state = output
+                    .get_all_for_bb(call)
+                    .terminator
+                    .succs
+                    .remove(0)
+                    .extra
+                    .after;
+                assert!(state.graph_edges().any(|edge| matches!(
+                    edge.kind(),
+                    BorrowsEdgeKind::RegionAbstraction(AbstractionEdge {
+                        abstraction_type: AbstractionType::FunctionCall(abstraction),
+                    }) if abstraction.location().block == call
+                )));
+            }
+            assert!(take_assumption_violations().is_empty());
+        });
+    }
+
+    /// After `p = Pair { owned: b, borrowed: &mut x }`, `b` is moved out, `x`
+    /// is borrowed and `p` holds the borrow
+    #[test]
+    fn struct_with_moved_and_borrowed_fields() {
+        run_on_fn("11_struct_fields.rs", "main", |tcx, body| {
+            let b = local_named(&body.body, "b");
+            let x = local_named(&body.body, "x");
+            let p = local_named(&body.body, "p");
+            let mut borrowed = None;
+            let location = find_statement(&body.body, |statement| {
+                if let StatementKind::Assign(box (target, Rvalue::Aggregate(_, fields))) =
+                    &statement.kind
+                    && target.local == p
+                {
+                    borrowed = fields.iter().nth(1).and_then(|field| field.place());
+                }
+                borrowed.is_some()
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let borrowed = utils::Place::from(borrowed.unwrap()).project_deref(rp);
+            assert_eq!(
+                output.capability_at(b.into(), location.successor_within_block()),
+                Some(CapabilityKind::Write)
+            );
+            let state = output
+                .get_all_for_bb(location.block)
+                .statements
+                .remove(location.statement_index)
+                .extra
+                .after;
+            assert!(state
+                .graph_edges()
+                .any(|edge| edge.blocks_place(utils::Place::from(x).into())));
+            assert!(state.graph_edges().any(|edge| matches!(
+                edge.kind(),
+                BorrowsEdgeKind::RegionProjectionMember(member)
+                    if member.projection.place.place().local == p
+                        && member.place.place() == borrowed
+            )));
+        });
+    }
+
+    /// The region of `for<'a> fn(&'a i32) -> &'a i32` is bound in the type,
+    /// so storing such a function pointer creates no region projection
+    #[test]
+    fn higher_ranked_regions_are_skipped() {
+        run_on_fn("05_higher_ranked.rs", "store", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            for name in ["f", "g", "h"] {
+                let local = local_named(&body.body, name);
+                assert!(extract_lifetimes(body.body.local_decls[local].ty).is_empty());
+                assert!(utils::Place::from(local).region_projections(rp).is_empty());
+            }
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            for block in body.body.basic_blocks.indices() {
+                let states = output.get_all_for_bb(block);
+                let afters = states
+                    .statements
+                    .iter()
+                    .chain(&states.terminator.succs)
+                    .map(|state| &state.extra.after);
+                for state in afters {
+                    assert!(!state.graph_edges().any(|edge| matches!(
+                        edge.kind(),
+                        BorrowsEdgeKind::RegionProjectionMember(_)
+                            | BorrowsEdgeKind::RegionAbstraction(_)
+                    )));
+                }
+            }
+        });
+    }
+
+    /// The edges of the abstraction of the call of `callee` in `body`
+    fn call_abstraction_edges<'tcx>(
+        tcx: TyCtxt<'tcx>,
+        body: &BodyWithBorrowckFacts<'tcx>,
+        callee: &str,
+    ) -> Vec<(usize, AbstractionBlockEdge<'tcx>)> {
+        let call = body
+            .body
+            .basic_blocks
+            .iter_enumerated()
+            .find(|(_, data)| match &data.terminator().kind {
+                TerminatorKind::Call { func, .. } => func
+                    .const_fn_def()
+                    .is_some_and(|(def_id, _)| tcx.item_name(def_id).as_str() == callee),
+                _ => false,
+            })
+            .map(|(block, _)| block)
+            .unwrap();
+        let mut output =
+            run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+        let state = output
+            .get_all_for_bb(call)
+            .terminator
+            .succs
+            .remove(0)
+            .extra
+            .after;
+        state
+            .graph()
+            .abstraction_edges()
+            .into_iter()
+            .find_map(|edge| match edge.value.abstraction_type {
+                AbstractionType::FunctionCall(abstraction)
+                    if abstraction.location().block == call =>
+                {
+                    Some(abstraction.edges().clone())
+                }
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    /// The result of `first(f)` borrows from `f` through the lifetime of the
+    /// bound `impl FnOnce() -> &'a mut i32`, whereas the bound of the `_g`
+    /// argument of `pick` only has a higher-ranked lifetime
+    #[test]
+    fn impl_trait_argument_bounds_are_abstraction_inputs() {
+        run_on_fn("13_impl_trait_arg.rs", "main", |tcx, body| {
+            let edges = call_abstraction_edges(tcx, body, "first");
+            assert!(edges
+                .iter()
+                .any(|(idx, edge)| *idx == 0 && matches!(edge.input, AbstractionTarget::Place(_))));
+            let edges = call_abstraction_edges(tcx, body, "pick");
+            assert!(!edges.is_empty());
+            assert!(edges.iter().all(|(idx, _)| *idx == 0));
+        });
+    }
+
+    #[test]
+    fn closure_capturing_by_mut_ref_is_linked_to_captured_variable() {
+        run_on_fn("16_closure_capture.rs", "main", |tcx, body| {
+            let x = utils::Place::from(local_named(&body.body, "x"));
+            let c = local_named(&body.body, "c");
+            let mut capture = None;
+            let location = find_statement(&body.body, |statement| {
+                if let StatementKind::Assign(box (target, Rvalue::Aggregate(box kind, fields))) =
+                    &statement.kind
+                    && matches!(kind, AggregateKind::Closure(..))
+                    && target.local == c
+                {
+                    capture = fields.iter().next().and_then(|field| field.place());
+                }
+                capture.is_some()
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let captured = utils::Place::from(capture.unwrap()).project_deref(rp);
+            let state = output
+                .get_all_for_bb(location.block)
+                .statements
+                .remove(location.statement_index)
+                .extra
+                .after;
+            // The closure's environment holds the reference to `x`...
+            assert!(state.graph_edges().any(|edge| matches!(
+                edge.kind(),
+                BorrowsEdgeKind::RegionProjectionMember(member)
+                    if member.projection.place.place().local == c
+                        && member.place.place() == captured
+            )));
+            // ...which reborrows `x`
+            assert!(state.reborrows().iter().any(|reborrow| {
+                reborrow.value.blocked_place == x.into()
+                    && reborrow.value.assigned_place.place() == captured
+            }));
+        });
+    }
+
+    #[test]
+    fn reference_copied_into_field_is_assigned_to_the_field() {
+        run_on_fn(
+            "39_copy_reference_into_field.rs",
+            "copy_into_field",
+            |tcx, body| {
+                let mut copy = None;
+                let location = find_statement(&body.body, |statement| {
+                    if let StatementKind::Assign(box (target, Rvalue::Use(Operand::Copy(from)))) =
+                        &statement.kind
+                    {
+                        copy = Some((utils::Place::from(*target), utils::Place::from(*from)));
+                    }
+                    copy.is_some()
+                });
+                let (field, r) = copy.unwrap();
+                let mut output =
+                    run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+                let rp = output.repacker();
+                let state = output
+                    .get_all_for_bb(location.block)
+                    .statements
+                    .remove(location.statement_index)
+                    .extra
+                    .after;
+                assert!(state.reborrows().iter().any(|reborrow| {
+                    reborrow.value.blocked_place == r.project_deref(rp).into()
+                        && reborrow.value.assigned_place == MaybeOldPlace::Current { place: field }
+                }));
+                assert!(!state.reborrows().iter().any(|reborrow| reborrow
+                    .value
+                    .assigned_place
+                    .place()
+                    == field.project_deref(rp)));
+            },
+        );
+    }
+
+    #[test]
+    fn shared_reborrow_in_scope_is_kept_only_by_keep_in_scope_policy() {
+        run_on_fn("21_live_shared_reborrow.rs", "reassign", |tcx, body| {
+            let s = local_named(&body.body, "s");
+            let blocked_place = utils::Place::from(s).into();
+            let r = local_named(&body.body, "r");
+            // `r = &other`: `r` is an old leaf afterwards, but the loan of `s`
+            // is still in scope since `f` is used later
+            let location = find_statement(&body.body, |statement| match &statement.kind {
+                StatementKind::Assign(box (target, rvalue)) => {
+                    target.local == r
+                        && !matches!(rvalue, Rvalue::Ref(_, _, place) if place.local == s)
+                }
+                _ => false,
+            });
+            let reborrows_s = |policy| {
+                let mut output = run_combined_pcs(
+                    body,
+                    tcx,
+                    None,
+                    false,
+                    None,
+                    None,
+                    PcsOptions {
+                        shared_reborrow_policy: policy,
+                        ..Default::default()
+                    },
+                );
+                let state = output
+                    .get_all_for_bb(location.block)
+                    .statements
+                    .remove(location.statement_index)
+                    .extra
+                    .after;
+                state
+                    .reborrows()
+                    .iter()
+                    .any(|reborrow| reborrow.value.blocked_place == blocked_place)
+            };
+            assert!(reborrows_s(SharedReborrowPolicy::KeepInScope));
+            assert!(!reborrows_s(SharedReborrowPolicy::Structural));
+        });
+    }
+
+    #[test]
+    fn reborrows_reserved_by_one_statement_are_killed_independently() {
+        run_on_fn("22_two_reference_args.rs", "main", |tcx, body| {
+            let a = local_named(&body.body, "a");
+            let b = local_named(&body.body, "b");
+            let mut borrows = vec![];
+            for data in body.body.basic_blocks.iter() {
+                for statement in data.statements.iter() {
+                    if let StatementKind::Assign(box (target, Rvalue::Ref(region, kind, place))) =
+                        &statement.kind
+                        && (place.local == a || place.local == b)
+                    {
+                        borrows.push((*target, *region, *kind, *place));
+                    }
+                }
+            }
+            let location = find_statement(&body.body, |statement| {
+                matches!(
+                    &statement.kind,
+                    StatementKind::Assign(box (_, Rvalue::Ref(_, _, place))) if place.local == a
+                )
+            });
+            let engine = BorrowsEngine::new(
+                tcx,
+                &body.body,
+                body.location_table.as_ref().unwrap(),
+                body.input_facts.as_ref().unwrap(),
+                body.borrow_set.clone(),
+                body.region_inference_context.clone(),
+                body.output_facts.as_deref(),
+            );
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let mut state = BorrowsDomain::new(rp, engine.loops.clone(), Some(START_BLOCK));
+            state.initialize_as_start_block();
+            // Both borrows of `main` (`&mut a` and `&mut b`) are reserved by
+            // the statement of the first one
+            let mut visitor = BorrowsVisitor::applying(&engine, &mut state, false);
+            let ids = borrows
+                .into_iter()
+                .map(|(target, region, kind, place)| {
+                    visitor
+                        .add_reborrow(
+                            utils::Place::from(place).into(),
+                            utils::Place::from(target).project_deref(rp),
+                            kind.mutability(),
+                            ReborrowKind::Ref(region),
+                            location,
+                        )
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            let [first, second] = ids[..] else {
+                panic!("Expected two borrows of `a` and `b`, got {:?}", ids);
+            };
+            assert_eq!((first.index, second.index), (0, 1));
+            assert!(state.after.kill_reborrow(first, location, rp));
+            assert!(!state.after.has_reborrow(first));
+            assert!(state.after.has_reborrow(second));
+        });
+    }
+
+    /// `s = "hello"` assigns a reference constant: the reborrow into `*s`
+    /// blocks the constant. So does the one of the promoted `&[1, 2, 3]`.
+    #[test]
+    fn reborrows_of_reference_constants_block_the_constant() {
+        run_on_fn("23_promoted_reference.rs", "main", |tcx, body| {
+            let s = utils::Place::from(local_named(&body.body, "s"));
+            let literal = find_statement(&body.body, |statement| {
+                matches!(
+                    statement.kind.as_assign(),
+                    Some((target, Rvalue::Use(Operand::Constant(_)))) if target.local == s.local
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let state = output
+                .get_all_for_bb(literal.block)
+                .statements
+                .remove(literal.statement_index)
+                .extra
+                .after;
+            assert!(state.reborrows().iter().any(|reborrow| {
+                reborrow.value.blocked_place == ReborrowBlockedPlace::Constant(literal)
+                    && reborrow.value.assigned_place.place() == s.project_deref(rp)
+            }));
+            let constants: FxHashSet<_> = state
+                .reborrows()
+                .into_iter()
+                .filter_map(|reborrow| match reborrow.value.blocked_place {
+                    ReborrowBlockedPlace::Constant(location) => Some(location),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(constants.len(), 2);
+        });
+    }
+
+    /// The mutable references stored in the tuple `t` and in the array `arr`
+    /// are members of the region projections of `t` and `arr`
+    #[test]
+    fn references_in_tuples_and_arrays_are_region_projection_members() {
+        run_on_fn("27_aggregate_of_references.rs", "main", |tcx, body| {
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            for (name, num_references) in [("t", 1), ("arr", 2)] {
+                let local = local_named(&body.body, name);
+                let location = find_statement(&body.body, |statement| {
+                    matches!(
+                        statement.kind.as_assign(),
+                        Some((target, Rvalue::Aggregate(..))) if target.local == local
+                    )
+                });
+                let state = output
+                    .get_all_for_bb(location.block)
+                    .statements
+                    .remove(location.statement_index)
+                    .extra
+                    .after;
+                let members = state
+                    .graph_edges()
+                    .filter(|edge| {
+                        matches!(
+                            edge.kind(),
+                            BorrowsEdgeKind::RegionProjectionMember(member)
+                                if member.projection.place.place().local == local
+                        )
+                    })
+                    .count();
+                assert_eq!(members, num_references, "{name}");
+            }
+        });
+    }
+
+    /// `p = &raw mut x` and `q = &raw const y` add raw pointer reborrows of
+    /// `x` into `*p` and of `y` into `*q`
+    #[test]
+    fn raw_pointers_add_raw_reborrows() {
+        run_on_fn("28_raw_reborrow.rs", "main", |tcx, body| {
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            for (blocked, pointer, mutability) in
+                [("x", "p", Mutability::Mut), ("y", "q", Mutability::Not)]
+            {
+                let blocked = utils::Place::from(local_named(&body.body, blocked));
+                let pointer = utils::Place::from(local_named(&body.body, pointer));
+                let location = find_statement(&body.body, |statement| {
+                    matches!(
+                        statement.kind.as_assign(),
+                        Some((target, Rvalue::RawPtr(..))) if target.local == pointer.local
+                    )
+                });
+                let state = output
+                    .get_all_for_bb(location.block)
+                    .statements
+                    .remove(location.statement_index)
+                    .extra
+                    .after;
+                assert!(state.reborrows().iter().any(|reborrow| {
+                    reborrow.value.blocked_place == blocked.into()
+                        && reborrow.value.assigned_place.place() == pointer.project_deref(rp)
+                        && reborrow.value.kind == ReborrowKind::RawPtr
+                        && reborrow.value.mutability == mutability
+                }));
+            }
+        });
+    }
+
+    /// The fake borrows of the scrutinees `*_3` and `pair` of the matches
+    /// with guards neither add reborrows nor kill those of the match arms
+    #[test]
+    fn fake_borrows_do_not_add_reborrows() {
+        run_on_fn("36_match_guard_fake_borrows.rs", "main", |tcx, body| {
+            let fake_borrows: Vec<_> = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .flat_map(|(block, data)| {
+                    data.statements.iter().enumerate().filter_map(
+                        move |(statement_index, statement)| match statement.kind.as_assign() {
+                            Some((target, Rvalue::Ref(_, BorrowKind::Fake(_), _))) => Some((
+                                Location {
+                                    block,
+                                    statement_index,
+                                },
+                                target.local,
+                            )),
+                            _ => None,
+                        },
+                    )
+                })
+                .collect();
+            assert!(!fake_borrows.is_empty());
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            for (location, target) in fake_borrows {
+                let state = output
+                    .get_all_for_bb(location.block)
+                    .statements
+                    .remove(location.statement_index)
+                    .extra;
+                assert!(state.after.reborrows().iter().all(|reborrow| reborrow
+                    .value
+                    .assigned_place
+                    .place()
+                    .local
+                    != target));
+                assert_eq!(
+                    state.after.reborrows().len(),
+                    state.before_start.reborrows().len(),
+                    "{location:?}"
+                );
+            }
+        });
+    }
+
+    /// The result of `shorten(&mut x, &y)` has the lifetime `'c`, which
+    /// `'a` of the first argument only outlives through `'a: 'b, 'b: 'c`
+    #[test]
+    fn call_abstraction_follows_transitive_outlives() {
+        run_on_fn("43_transitive_outlives.rs", "main", |tcx, body| {
+            let (call, argument, destination) = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .find_map(|(block, data)| match &data.terminator().kind {
+                    TerminatorKind::Call {
+                        args, destination, ..
+                    } => Some((block, args[0].node.place().unwrap(), *destination)),
+                    _ => None,
+                })
+                .unwrap();
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let argument = utils::Place::from(argument).project_deref(rp);
+            let destination = utils::Place::from(destination).project_deref(rp);
+            let state = output
+                .get_all_for_bb(call)
+                .terminator
+                .succs
+                .remove(0)
+                .extra
+                .after;
+            // The input is the argument as it was before the call
+            assert!(state.graph().abstraction_edges().into_iter().any(|edge| {
+                matches!(
+                    edge.value.abstraction_type,
+                    AbstractionType::FunctionCall(_)
+                ) && edge.value.edges().into_iter().any(|edge| {
+                    matches!(
+                        edge.input,
+                        AbstractionTarget::Place(ReborrowBlockedPlace::Local(input))
+                            if input.place() == argument
+                    ) && edge.output == AbstractionTarget::Place(destination.into())
+                })
+            }));
+        });
+    }
+
+    /// The MIR given to the borrow checker has no `SetDiscriminant`, so one
+    /// is applied to `opt` while `x` borrows into its payload: the borrows
+    /// of `opt` then refer to its former value
+    #[test]
+    fn set_discriminant_makes_borrows_of_place_old() {
+        fn blocks<'tcx>(
+            state: &BorrowsState<'tcx>,
+            blocked: utils::Place<'tcx>,
+            old: bool,
+        ) -> bool {
+            state.reborrows().iter().any(|reborrow| {
+                matches!(
+                    reborrow.value.blocked_place,
+                    ReborrowBlockedPlace::Local(place)
+                        if place.place() == blocked && place.is_old() == old
+                )
+            })
+        }
+        run_on_fn(
+            "47_overwrite_enum_with_payload_borrow.rs",
+            "main",
+            |tcx, body| {
+                let opt = utils::Place::from(local_named(&body.body, "opt"));
+                let x = local_named(&body.body, "x");
+                let borrow = find_statement(&body.body, |statement| {
+                    matches!(
+                        statement.kind.as_assign(),
+                        Some((target, Rvalue::Ref(..))) if target.local == x
+                    )
+                });
+                let location = borrow.successor_within_block();
+                let rp = PlaceRepacker::new(&body.body, tcx);
+                let engine = BorrowsEngine::new(
+                    tcx,
+                    &body.body,
+                    body.location_table.as_ref().unwrap(),
+                    body.input_facts.as_ref().unwrap(),
+                    body.borrow_set.clone(),
+                    body.region_inference_context.clone(),
+                    body.output_facts.as_deref(),
+                );
+                let mut cursor = run_borrows(body, tcx, PcsOptions::default());
+                cursor.seek_after_primary_effect(borrow);
+                let mut state = cursor.get().clone();
+                assert!(blocks(&state.after, opt, false));
+                let statement = Statement {
+                    source_info: *body.body.source_info(location),
+                    kind: StatementKind::SetDiscriminant {
+                        place: Box::new(opt.to_rust_place(rp)),
+                        variant_index: VariantIdx::from_u32(0),
+                    },
+                };
+                BorrowsVisitor::preparing(&engine, &mut state, false)
+                    .visit_statement(&statement, location);
+                BorrowsVisitor::applying(&engine, &mut state, false)
+                    .visit_statement(&statement, location);
+                assert!(blocks(&state.after, opt, true));
+                assert!(!blocks(&state.after, opt, false));
+                assert_eq!(state.after.get_latest(&opt), location.into());
+            },
+        );
+    }
+}
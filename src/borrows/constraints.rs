@@ -0,0 +1,90 @@
+use std::collections::BTreeSet;
+
+use crate::rustc_interface::middle::{mir::Location, ty::RegionVid};
+
+/// A single fact of the borrow analysis. Places are identified by their
+/// displayed form (e.g. `(*_1)` or `_2 at bb1[3]`).
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Fact {
+    /// `blocking` blocks `blocked`, i.e. `blocked` cannot be accessed while
+    /// `blocking` is live
+    Blocks { blocked: String, blocking: String },
+    /// The region `sup` outlives the region `sub`
+    Outlives { sup: RegionVid, sub: RegionVid },
+    /// The reborrow created at `location`, blocking `blocked` and assigned to
+    /// `assigned`
+    Reborrow {
+        location: Location,
+        blocked: String,
+        assigned: String,
+    },
+}
+
+impl std::fmt::Display for Fact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fact::Blocks { blocked, blocking } => {
+                write!(f, "blocks({:?}, {:?}).", blocked, blocking)
+            }
+            Fact::Outlives { sup, sub } => {
+                write!(
+                    f,
+                    "outlives({:?}, {:?}).",
+                    format!("{:?}", sup),
+                    format!("{:?}", sub)
+                )
+            }
+            Fact::Reborrow {
+                location,
+                blocked,
+                assigned,
+            } => write!(
+                f,
+                "reborrow({:?}, {:?}, {:?}).",
+                format!("{:?}", location),
+                blocked,
+                assigned
+            ),
+        }
+    }
+}
+
+/// The borrow relationships of a body as a flat set of facts.
+///
+/// The `Display` implementation writes one fact per line, in the form
+/// `name("arg1", "arg2", ...).` with every argument a quoted string, and the
+/// facts in a deterministic order. This is intended to be loaded directly by a
+/// Datalog engine or translated to SMT declarations.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Constraints(BTreeSet<Fact>);
+
+impl Constraints {
+    pub fn new() -> Self {
+        Self(BTreeSet::new())
+    }
+
+    pub fn insert(&mut self, fact: Fact) -> bool {
+        self.0.insert(fact)
+    }
+
+    pub fn facts(&self) -> impl Iterator<Item = &Fact> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::fmt::Display for Constraints {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for fact in self.0.iter() {
+            writeln!(f, "{}", fact)?;
+        }
+        Ok(())
+    }
+}
@@ -28,7 +28,14 @@ impl<'tcx> BorrowDerefExpansion<'tcx> {
 
 #[derive(PartialEq, Eq, Clone, Debug, Hash)]
 pub enum DerefExpansion<'tcx> {
-    OwnedExpansion { base: MaybeOldPlace<'tcx> },
+    /// The expansion of an owned place. Like for a [`BorrowDerefExpansion`],
+    /// the expansion is the last projection element of each place it
+    /// expands to: the dereference of a reference or box, or the fields of
+    /// an owned struct.
+    OwnedExpansion {
+        base: MaybeOldPlace<'tcx>,
+        expansion: Vec<PlaceElem<'tcx>>,
+    },
     BorrowExpansion(BorrowDerefExpansion<'tcx>),
 }
 
@@ -58,6 +65,18 @@ impl<'tcx> DerefExpansion<'tcx> {
         }
     }
 
+    pub fn owned(
+        base: MaybeOldPlace<'tcx>,
+        expansion: Vec<Place<'tcx>>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Self {
+        assert!(base.place().is_owned(repacker.body(), repacker.tcx()));
+        DerefExpansion::OwnedExpansion {
+            base,
+            expansion: expansion_elems(base, expansion),
+        }
+    }
+
     pub fn borrowed(
         base: MaybeOldPlace<'tcx>,
         expansion: Vec<Place<'tcx>>,
@@ -65,15 +84,9 @@ impl<'tcx> DerefExpansion<'tcx> {
         repacker: PlaceRepacker<'_, 'tcx>,
     ) -> Self {
         assert!(!base.place().is_owned(repacker.body(), repacker.tcx()));
-        assert!(expansion.iter().all(|p| base.place().is_prefix(*p)
-            && p.projection.len() == base.place().projection.len() + 1));
         DerefExpansion::BorrowExpansion(BorrowDerefExpansion {
             base,
-            expansion: expansion
-                .into_iter()
-                .map(|p| p.projection.last().unwrap())
-                .copied()
-                .collect(),
+            expansion: expansion_elems(base, expansion),
             location,
         })
     }
@@ -107,19 +120,39 @@ impl<'tcx> DerefExpansion<'tcx> {
 
     pub fn expansion_elems(&self) -> Vec<PlaceElem<'tcx>> {
         match self {
-            DerefExpansion::OwnedExpansion { .. } => vec![PlaceElem::Deref],
+            DerefExpansion::OwnedExpansion { expansion, .. } => expansion.clone(),
             DerefExpansion::BorrowExpansion(e) => e.expansion.clone(),
         }
     }
 
     pub fn expansion(&self, repacker: PlaceRepacker<'_, 'tcx>) -> Vec<MaybeOldPlace<'tcx>> {
         match self {
-            DerefExpansion::OwnedExpansion { base, .. } => vec![base.project_deref(repacker)],
+            DerefExpansion::OwnedExpansion { base, expansion } => expansion
+                .iter()
+                .map(|p| base.project_deeper(repacker.tcx(), *p))
+                .collect(),
             DerefExpansion::BorrowExpansion(e) => e.expansion(repacker),
         }
     }
 }
 
+/// The last projection elements of the places of `expansion`, each of which
+/// must extend `base` by exactly one element
+fn expansion_elems<'tcx>(
+    base: MaybeOldPlace<'tcx>,
+    expansion: Vec<Place<'tcx>>,
+) -> Vec<PlaceElem<'tcx>> {
+    assert!(expansion
+        .iter()
+        .all(|p| base.place().is_prefix(*p)
+            && p.projection.len() == base.place().projection.len() + 1));
+    expansion
+        .into_iter()
+        .map(|p| p.projection.last().unwrap())
+        .copied()
+        .collect()
+}
+
 impl<'tcx> ToJsonWithRepacker<'tcx> for DerefExpansion<'tcx> {
     fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
         json!({
@@ -128,3 +161,58 @@ impl<'tcx> ToJsonWithRepacker<'tcx> for DerefExpansion<'tcx> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        run_combined_pcs,
+        test_utils::{find_statement, local_named, run_on_fn},
+        PcsOptions,
+    };
+
+    /// The owned `p` expands into both of its reference fields, and the
+    /// writes `*p.a = 3` and `*p.b = 4` expand each field into its target
+    #[test]
+    fn owned_struct_expands_into_all_fields() {
+        run_on_fn("48_struct_with_reference_fields.rs", "main", |tcx, body| {
+            let p = Place::from(local_named(&body.body, "p"));
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let fields = p.expand_field(None, rp);
+            assert_eq!(fields.len(), 2);
+            let expansion = DerefExpansion::owned(p.into(), fields.clone(), rp);
+            assert_eq!(expansion.expansion_elems().len(), 2);
+            assert_eq!(
+                expansion.expansion(rp),
+                fields
+                    .iter()
+                    .map(|field| MaybeOldPlace::from(*field))
+                    .collect::<Vec<_>>()
+            );
+            let write_b = find_statement(&body.body, |statement| {
+                matches!(
+                    statement.kind.as_assign(),
+                    Some((target, _)) if Place::from(*target) == fields[1].project_deref(rp)
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let state = output
+                .get_all_for_bb(write_b.block)
+                .statements
+                .remove(write_b.statement_index)
+                .extra
+                .after;
+            for field in fields {
+                assert!(
+                    state.graph().deref_expansions().iter().any(|expansion| {
+                        expansion.value.base().place() == field
+                            && expansion.value.expansion(rp)
+                                == vec![MaybeOldPlace::from(field.project_deref(rp))]
+                    }),
+                    "{field:?}"
+                );
+            }
+        });
+    }
+}
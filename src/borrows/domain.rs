@@ -1,3 +1,5 @@
+use std::marker::PhantomData;
+
 use rustc_interface::{
     ast::Mutability,
     data_structures::{
@@ -11,7 +13,10 @@ use rustc_interface::{
 
 use crate::{
     rustc_interface,
-    utils::{Place, PlaceSnapshot, SnapshotLocation},
+    utils::{
+        assumptions::check_assumption, display::PlaceDisplay, Place, PlaceSnapshot,
+        SnapshotLocation,
+    },
 };
 
 #[derive(PartialEq, Eq, Clone, Debug, Hash)]
@@ -51,7 +56,8 @@ impl<'tcx> LoopAbstraction<'tcx> {
 pub struct FunctionCallAbstraction<'tcx> {
     location: Location,
 
-    def_id: DefId,
+    /// The called function, `None` for calls of function pointers
+    def_id: Option<DefId>,
 
     substs: GenericArgsRef<'tcx>,
 
@@ -66,7 +72,7 @@ impl<'tcx> FunctionCallAbstraction<'tcx> {
             .collect()
     }
 
-    pub fn def_id(&self) -> DefId {
+    pub fn def_id(&self) -> Option<DefId> {
         self.def_id
     }
     pub fn substs(&self) -> GenericArgsRef<'tcx> {
@@ -79,19 +85,46 @@ impl<'tcx> FunctionCallAbstraction<'tcx> {
     pub fn edges(&self) -> &Vec<(usize, AbstractionBlockEdge<'tcx>)> {
         &self.edges
     }
+
+    /// The indices of the arguments that `output` may derive from
+    pub fn inputs_of(&self, output: AbstractionOutputTarget<'tcx>) -> FxHashSet<usize> {
+        self.edges
+            .iter()
+            .filter(|(_, edge)| edge.output == output)
+            .map(|(idx, _)| *idx)
+            .collect()
+    }
+
+    /// Returns true iff no argument flows into both `output1` and `output2`,
+    /// i.e. the two outputs cannot alias through this call.
+    pub fn outputs_disjoint(
+        &self,
+        output1: AbstractionOutputTarget<'tcx>,
+        output2: AbstractionOutputTarget<'tcx>,
+    ) -> bool {
+        self.inputs_of(output1).is_disjoint(&self.inputs_of(output2))
+    }
+
+    /// Returns `None` if `edges` is empty (which is only allowed if
+    /// `lenient`, see [`check_assumption`])
     pub fn new(
         location: Location,
-        def_id: DefId,
+        def_id: Option<DefId>,
         substs: GenericArgsRef<'tcx>,
         edges: Vec<(usize, AbstractionBlockEdge<'tcx>)>,
-    ) -> Self {
-        assert!(edges.len() > 0);
-        Self {
+        lenient: bool,
+    ) -> Option<Self> {
+        if !check_assumption(lenient, !edges.is_empty(), Some(location), || {
+            "Function call abstraction without edges".to_string()
+        }) {
+            return None;
+        }
+        Some(Self {
             location,
             def_id,
             substs,
             edges,
-        }
+        })
     }
 }
 
@@ -132,10 +165,26 @@ impl<'tcx> AbstractionBlockEdge<'tcx> {
     }
 }
 
+impl<'tcx> ToJsonWithRepacker<'tcx> for AbstractionBlockEdge<'tcx> {
+    fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
+        json!({
+            "input": match self.input {
+                AbstractionTarget::Place(p) => p.to_json(repacker),
+                AbstractionTarget::RegionProjection(p) => p.to_json(repacker),
+            },
+            "input_region": self.input.region(repacker).map(|region| format!("{:?}", region)),
+            "output": match self.output {
+                AbstractionTarget::Place(p) => p.to_json(repacker),
+                AbstractionTarget::RegionProjection(p) => p.to_json(repacker),
+            },
+        })
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Debug, Hash, Copy)]
 pub enum AbstractionTarget<'tcx, T> {
     Place(T),
-    RegionProjection(RegionProjection<'tcx>),
+    RegionProjection(RegionProjection<'tcx, T>),
 }
 
 pub type AbstractionInputTarget<'tcx> = AbstractionTarget<'tcx, ReborrowBlockedPlace<'tcx>>;
@@ -147,18 +196,44 @@ impl<'tcx> AbstractionInputTarget<'tcx> {
             AbstractionTarget::Place(p) => match p {
                 ReborrowBlockedPlace::Local(maybe_old_place) => maybe_old_place == place,
                 ReborrowBlockedPlace::Remote(local) => false,
+                ReborrowBlockedPlace::Constant(_) => false,
             },
             AbstractionTarget::RegionProjection(_p) => false,
         }
     }
 
+    /// The region of the input, i.e. the region of the reference the blocked
+    /// place is accessed through (e.g. `'a` for `*x` where `x: &'a mut T`,
+    /// or for `Remote(x)` where the argument `x` has that type), or the
+    /// region of a region projection. `None` if the input is not behind a
+    /// reference with a region variable, e.g. for constants.
+    pub fn region(&self, repacker: PlaceRepacker<'_, 'tcx>) -> Option<RegionVid> {
+        let reference_ty = match self {
+            AbstractionTarget::Place(ReborrowBlockedPlace::Local(p)) => {
+                p.place().prefix_place(repacker)?.ty(repacker).ty
+            }
+            AbstractionTarget::Place(ReborrowBlockedPlace::Remote(local)) => {
+                repacker.body().local_decls[*local].ty
+            }
+            AbstractionTarget::Place(ReborrowBlockedPlace::Constant(_)) => return None,
+            AbstractionTarget::RegionProjection(p) => return Some(p.region),
+        };
+        match reference_ty.kind() {
+            ty::TyKind::Ref(region, _, _) => get_vid(region),
+            _ => None,
+        }
+    }
+
     pub fn mut_place(&mut self) -> Option<&mut MaybeOldPlace<'tcx>> {
         match self {
             AbstractionTarget::Place(bp) => match bp {
                 ReborrowBlockedPlace::Local(ref mut maybe_old_place) => Some(maybe_old_place),
-                ReborrowBlockedPlace::Remote(_) => None,
+                ReborrowBlockedPlace::Remote(_) | ReborrowBlockedPlace::Constant(_) => None,
+            },
+            AbstractionTarget::RegionProjection(p) => match p.place {
+                ReborrowBlockedPlace::Local(ref mut maybe_old_place) => Some(maybe_old_place),
+                ReborrowBlockedPlace::Remote(_) | ReborrowBlockedPlace::Constant(_) => None,
             },
-            AbstractionTarget::RegionProjection(p) => Some(&mut p.place),
         }
     }
 }
@@ -179,22 +254,39 @@ impl<'tcx, T: HasPlaces<'tcx>> AbstractionTarget<'tcx, T> {
             AbstractionTarget::RegionProjection(p) => p.make_place_old(place, latest),
         }
     }
+}
 
-    // pub fn region(&self, repacker: PlaceRepacker<'_, 'tcx>) -> RegionVid {
-    //     match self {
-    //         AbstractionTarget::Place(p) => {
-    //             let prefix = p.place().prefix_place(repacker);
-    //             match prefix.unwrap().ty(repacker).ty.kind() {
-    //                 ty::Ref(region, _, _) => match region.kind() {
-    //                     ty::RegionKind::ReVar(v) => v,
-    //                     _ => unreachable!(),
-    //                 },
-    //                 _ => unreachable!(),
-    //             }
-    //         }
-    //         AbstractionTarget::RegionProjection(p) => p.region,
-    //     }
-    // }
+impl<'tcx> ToJsonWithRepacker<'tcx> for AbstractionType<'tcx> {
+    fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
+        match self {
+            AbstractionType::FunctionCall(c) => json!({
+                "type": "FunctionCall",
+                "location": format!("{:?}", c.location),
+                "def_id": c.def_id.map(|def_id| format!("{:?}", def_id)),
+                "function": c.def_id.map(|def_id| repacker.tcx().def_path_str(def_id)),
+                "substs": format!("{:?}", c.substs),
+                "edges": c
+                    .edges
+                    .iter()
+                    .map(|(arg, edge)| {
+                        let mut json = edge.to_json(repacker);
+                        json["arg"] = json!(arg);
+                        json
+                    })
+                    .collect::<Vec<_>>(),
+            }),
+            AbstractionType::Loop(c) => json!({
+                "type": "Loop",
+                "location": format!("{:?}", c.location()),
+                "block": format!("{:?}", c.block),
+                "edges": c
+                    .edges
+                    .iter()
+                    .map(|edge| edge.to_json(repacker))
+                    .collect::<Vec<_>>(),
+            }),
+        }
+    }
 }
 
 impl<'tcx> AbstractionType<'tcx> {
@@ -283,12 +375,18 @@ impl<'tcx> std::fmt::Display for MaybeOldPlace<'tcx> {
 }
 
 impl<'tcx> MaybeOldPlace<'tcx> {
+    /// The `idx`-th region projection of this place, or `None` if the type of
+    /// the place has at most `idx` (possibly nested) lifetimes. This can
+    /// happen when an index computed for one type is applied to another one
+    /// whose lifetime count differs, e.g. after substituting generics.
     pub fn region_projection(
         &self,
         idx: usize,
         repacker: PlaceRepacker<'_, 'tcx>,
-    ) -> RegionProjection<'tcx> {
-        RegionProjection::new(self.region_projections(repacker)[idx].region, self.clone())
+    ) -> Option<RegionProjection<'tcx>> {
+        self.region_projections(repacker)
+            .get(idx)
+            .map(|projection| RegionProjection::new(projection.region, self.clone()))
     }
 
     pub fn region_projections(
@@ -369,7 +467,8 @@ impl<'tcx> MaybeOldPlace<'tcx> {
 
     pub fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
         json!({
-            "place": self.place().to_json(repacker),
+            "place": self.place().to_encoded_json(),
+            "display": self.place().to_json(repacker),
             "at": self.location().map(|loc| format!("{:?}", loc)),
         })
     }
@@ -413,6 +512,11 @@ pub enum ReborrowBlockedPlace<'tcx> {
     /// The blocked place that a borrows in function inputs; e.g for a function
     /// `f(&mut x)` the blocked place is `Remote(x)`
     Remote(mir::Local),
+
+    /// The data of a constant of reference type used at the location, e.g for
+    /// `x = const main::promoted[0]` (from `x = &[1, 2, 3]`), the blocked place
+    /// is `Constant(l)` where `l` is the location of the assignment
+    Constant(Location),
 }
 
 impl<'tcx> std::fmt::Display for ReborrowBlockedPlace<'tcx> {
@@ -420,6 +524,7 @@ impl<'tcx> std::fmt::Display for ReborrowBlockedPlace<'tcx> {
         match self {
             ReborrowBlockedPlace::Local(p) => write!(f, "{}", p),
             ReborrowBlockedPlace::Remote(l) => write!(f, "Remote({:?})", l),
+            ReborrowBlockedPlace::Constant(l) => write!(f, "Constant({:?})", l),
         }
     }
 }
@@ -432,21 +537,36 @@ impl<'tcx> ReborrowBlockedPlace<'tcx> {
     pub fn make_place_old(&mut self, place: Place<'tcx>, latest: &Latest) {
         match self {
             ReborrowBlockedPlace::Local(p) => p.make_place_old(place, latest),
-            ReborrowBlockedPlace::Remote(_) => {}
+            ReborrowBlockedPlace::Remote(_) | ReborrowBlockedPlace::Constant(_) => {}
         }
     }
 
     pub fn as_local(&self) -> Option<MaybeOldPlace<'tcx>> {
         match self {
             ReborrowBlockedPlace::Local(p) => Some(*p),
-            ReborrowBlockedPlace::Remote(_) => None,
+            ReborrowBlockedPlace::Remote(_) | ReborrowBlockedPlace::Constant(_) => None,
         }
     }
 
     pub fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
         match self {
             ReborrowBlockedPlace::Local(p) => p.to_json(repacker),
-            ReborrowBlockedPlace::Remote(_) => todo!(),
+            ReborrowBlockedPlace::Remote(local) => {
+                // The name of the argument, if it has one
+                let name = match Place::from(*local).to_string(repacker) {
+                    PlaceDisplay::User(_, name) => Some(name),
+                    PlaceDisplay::Temporary(_) => None,
+                };
+                json!({
+                    "type": "remote",
+                    "local": format!("{:?}", local),
+                    "name": name,
+                })
+            }
+            ReborrowBlockedPlace::Constant(location) => json!({
+                "type": "constant",
+                "location": format!("{:?}", location),
+            }),
         }
     }
 }
@@ -472,16 +592,40 @@ impl<'tcx> std::fmt::Display for Reborrow<'tcx> {
         )
     }
 }
+/// Identifies a reborrow.
+///
+/// Several reborrows can be reserved at the same location (e.g. the
+/// references in the arguments of the body all borrow from the caller at the
+/// start of the body), they are then distinguished by their index.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash, PartialOrd, Ord)]
+pub struct ReborrowId {
+    pub reserve_location: Location,
+    /// The index of the reborrow among those reserved at `reserve_location`
+    pub index: usize,
+}
+
+/// How the assigned place of a reborrow refers to the blocked place
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum ReborrowKind<'tcx> {
+    /// A reference with the given region, e.g. `&mut x`
+    Ref(ty::Region<'tcx>),
+    /// A raw pointer, e.g. `&raw mut x`. Raw pointers have no region.
+    RawPtr,
+}
+
 #[derive(PartialEq, Eq, Clone, Debug, Hash)]
 pub struct Reborrow<'tcx> {
     pub blocked_place: ReborrowBlockedPlace<'tcx>,
     pub assigned_place: MaybeOldPlace<'tcx>,
     pub mutability: Mutability,
+    pub kind: ReborrowKind<'tcx>,
 
     /// The location when the reborrow was created
     reserve_location: Location,
 
-    pub region: ty::Region<'tcx>,
+    /// Distinguishes the reborrows reserved at the same location, see
+    /// [`ReborrowId`]
+    index: usize,
 }
 
 impl<'tcx> Reborrow<'tcx> {
@@ -489,15 +633,17 @@ impl<'tcx> Reborrow<'tcx> {
         blocked_place: ReborrowBlockedPlace<'tcx>,
         assigned_place: MaybeOldPlace<'tcx>,
         mutability: Mutability,
+        kind: ReborrowKind<'tcx>,
         reservation_location: Location,
-        region: ty::Region<'tcx>,
+        index: usize,
     ) -> Self {
         Self {
             blocked_place,
             assigned_place,
             mutability,
+            kind,
             reserve_location: reservation_location,
-            region,
+            index,
         }
     }
 
@@ -505,21 +651,35 @@ impl<'tcx> Reborrow<'tcx> {
         self.reserve_location
     }
 
+    pub fn id(&self) -> ReborrowId {
+        ReborrowId {
+            reserve_location: self.reserve_location,
+            index: self.index,
+        }
+    }
+
     pub fn make_place_old(&mut self, place: Place<'tcx>, latest: &Latest) {
         self.blocked_place.make_place_old(place, latest);
         self.assigned_place.make_place_old(place, latest);
     }
 
+    /// Whether a reborrow with the given mutability may be assigned to
+    /// `place`: either the dereference of the reference storing it, or, for a
+    /// copied shared reference, the field the copy is stored in
+    pub fn is_valid_assigned_place(place: Place<'tcx>, mutability: Mutability) -> bool {
+        place.is_deref()
+            || mutability == Mutability::Not
+                && matches!(place.last_projection(), Some((_, PlaceElem::Field(..))))
+    }
+
     pub fn assiged_place_region_vid(&self, repacker: PlaceRepacker<'_, 'tcx>) -> Option<RegionVid> {
-        match self
-            .assigned_place
-            .place()
-            .prefix_place(repacker)
-            .unwrap()
-            .ty(repacker)
-            .ty
-            .kind()
-        {
+        let place = self.assigned_place.place();
+        let reference = if place.is_deref() {
+            place.prefix_place(repacker).unwrap()
+        } else {
+            place
+        };
+        match reference.ty(repacker).ty.kind() {
             ty::Ref(region, _, _) => match region.kind() {
                 ty::RegionKind::ReVar(v) => Some(v),
                 _ => None,
@@ -529,9 +689,12 @@ impl<'tcx> Reborrow<'tcx> {
     }
 
     pub fn region_vid(&self) -> Option<RegionVid> {
-        match self.region.kind() {
-            ty::RegionKind::ReVar(v) => Some(v),
-            _ => None,
+        match self.kind {
+            ReborrowKind::Ref(region) => match region.kind() {
+                ty::RegionKind::ReVar(v) => Some(v),
+                _ => None,
+            },
+            ReborrowKind::RawPtr => None,
         }
     }
 }
@@ -545,20 +708,81 @@ impl<'tcx> ToJsonWithRepacker<'tcx> for Reborrow<'tcx> {
         json!({
             "blocked_place": self.blocked_place.to_json(repacker),
             "assigned_place": self.assigned_place.to_json(repacker),
-            "is_mut": self.mutability == Mutability::Mut
+            "is_mut": self.mutability == Mutability::Mut,
+            "is_raw_pointer": self.kind == ReborrowKind::RawPtr,
+            "reserve_location": format!("{:?}", self.reserve_location),
+        })
+    }
+}
+
+impl<'tcx> ToJsonWithRepacker<'tcx> for RegionProjection<'tcx> {
+    fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
+        json!({
+            "place": self.place.to_json(repacker),
+            "region": format!("{:?}", self.region),
+        })
+    }
+}
+
+impl<'tcx> ToJsonWithRepacker<'tcx> for RegionProjection<'tcx, ReborrowBlockedPlace<'tcx>> {
+    fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
+        json!({
+            "place": self.place.to_json(repacker),
+            "region": format!("{:?}", self.region),
         })
     }
 }
 
+/// The region `region` of the type of `place`. The inputs of abstractions may
+/// also project the regions of a [`ReborrowBlockedPlace`], e.g. a region of an
+/// input of the function (which is a remote place).
 #[derive(PartialEq, Eq, Clone, Debug, Hash, Copy)]
-pub struct RegionProjection<'tcx> {
-    pub place: MaybeOldPlace<'tcx>,
+pub struct RegionProjection<'tcx, P = MaybeOldPlace<'tcx>> {
+    pub place: P,
     pub region: RegionVid,
+    _marker: PhantomData<&'tcx ()>,
+}
+
+impl<'tcx> From<RegionProjection<'tcx>> for RegionProjection<'tcx, ReborrowBlockedPlace<'tcx>> {
+    fn from(projection: RegionProjection<'tcx>) -> Self {
+        Self {
+            place: projection.place.into(),
+            region: projection.region,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'tcx> RegionProjection<'tcx, ReborrowBlockedPlace<'tcx>> {
+    pub fn new_remote(region: RegionVid, local: Local) -> Self {
+        Self {
+            place: ReborrowBlockedPlace::Remote(local),
+            region,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The projection of the region of a local place, if the place is local
+    pub fn as_local(&self) -> Option<RegionProjection<'tcx>> {
+        self.place
+            .as_local()
+            .map(|place| RegionProjection::new(self.region, place))
+    }
+}
+
+impl<'tcx, P: HasPlaces<'tcx>> HasPlaces<'tcx> for RegionProjection<'tcx, P> {
+    fn places_mut(&mut self) -> Vec<&mut MaybeOldPlace<'tcx>> {
+        self.place.places_mut()
+    }
 }
 
 impl<'tcx> RegionProjection<'tcx> {
     pub fn new(region: RegionVid, place: MaybeOldPlace<'tcx>) -> Self {
-        Self { place, region }
+        Self {
+            place,
+            region,
+            _marker: PhantomData,
+        }
     }
     pub fn make_place_old(&mut self, place: Place<'tcx>, latest: &Latest) {
         self.place.make_place_old(place, latest);
@@ -570,3 +794,189 @@ impl<'tcx> RegionProjection<'tcx> {
             .unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        run_combined_pcs,
+        rustc_interface::middle::mir::TerminatorKind,
+        test_utils::{local_named, run_on_fn},
+        PcsOptions,
+    };
+
+    /// The distinct outputs of the edges of `abstraction`
+    fn outputs<'tcx>(
+        abstraction: &FunctionCallAbstraction<'tcx>,
+    ) -> Vec<AbstractionOutputTarget<'tcx>> {
+        let mut outputs = vec![];
+        for (_, edge) in abstraction.edges() {
+            if !outputs.contains(&edge.output) {
+                outputs.push(edge.output);
+            }
+        }
+        outputs
+    }
+
+    /// The outputs of `pair(&mut a, &mut b)` each derive from one of its
+    /// arguments, those of `same(&mut a, &mut b)` from both
+    #[test]
+    fn outputs_of_calls_are_disjoint_iff_their_inputs_are() {
+        run_on_fn("06_two_outputs.rs", "main", |tcx, body| {
+            let calls: Vec<BasicBlock> = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .filter(|(_, data)| matches!(data.terminator().kind, TerminatorKind::Call { .. }))
+                .map(|(block, _)| block)
+                .take(2)
+                .collect();
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let abstractions: Vec<FunctionCallAbstraction<'_>> = calls
+                .into_iter()
+                .map(|call| {
+                    let state = output
+                        .get_all_for_bb(call)
+                        .terminator
+                        .succs
+                        .remove(0)
+                        .extra
+                        .after;
+                    state
+                        .graph()
+                        .abstraction_edges()
+                        .into_iter()
+                        .find_map(|edge| match edge.value.abstraction_type {
+                            AbstractionType::FunctionCall(abstraction)
+                                if abstraction.location().block == call =>
+                            {
+                                Some(abstraction)
+                            }
+                            _ => None,
+                        })
+                        .unwrap()
+                })
+                .collect();
+            let [pair, same] = &abstractions[..] else {
+                panic!("Expected two calls, got {:?}", abstractions);
+            };
+
+            let [first, second] = outputs(pair)[..] else {
+                panic!("Expected two outputs of `pair`, got {:?}", outputs(pair));
+            };
+            let mut inputs = [pair.inputs_of(first), pair.inputs_of(second)];
+            inputs.sort_by_key(|inputs| inputs.iter().copied().collect::<Vec<_>>());
+            assert_eq!(
+                inputs,
+                [FxHashSet::from_iter([0]), FxHashSet::from_iter([1])]
+            );
+            assert!(pair.outputs_disjoint(first, second));
+
+            let [first, second] = outputs(same)[..] else {
+                panic!("Expected two outputs of `same`, got {:?}", outputs(same));
+            };
+            for output in [first, second] {
+                assert_eq!(same.inputs_of(output), FxHashSet::from_iter([0, 1]));
+            }
+            assert!(!same.outputs_disjoint(first, second));
+        });
+    }
+
+    /// The lifetime `'a` of `f` appears in the type of its first argument
+    /// only through `<() as Tr<'a>>::Out`, which is `u32` at the call
+    #[test]
+    fn region_projection_out_of_range_is_none() {
+        run_on_fn("42_associated_type_argument.rs", "main", |tcx, body| {
+            let n = MaybeOldPlace::from(Place::from(local_named(&body.body, "n")));
+            let call = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .find(|(_, data)| matches!(data.terminator().kind, TerminatorKind::Call { .. }))
+                .map(|(block, _)| block)
+                .unwrap();
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            assert!(n.region_projection(0, rp).is_none());
+            let state = output
+                .get_all_for_bb(call)
+                .terminator
+                .succs
+                .remove(0)
+                .extra
+                .after;
+            let inputs: Vec<_> = state
+                .graph()
+                .abstraction_edges()
+                .into_iter()
+                .flat_map(|edge| edge.value.inputs())
+                .collect();
+            assert!(!inputs.is_empty());
+            assert!(inputs.iter().all(|input| match input {
+                AbstractionTarget::Place(place) => place.as_local(),
+                AbstractionTarget::RegionProjection(projection) => projection.place.as_local(),
+            }
+            .map_or(true, |place| place.place().local != n.place().local)));
+        });
+    }
+
+    /// The input of the call `first(v)` is the dereference of the argument
+    /// of the call, which has the region of the argument's reference type
+    #[test]
+    fn abstraction_input_is_mapped_to_its_region() {
+        run_on_fn(
+            "45_abstraction_input_region.rs",
+            "first_of_arg",
+            |tcx, body| {
+                let v = local_named(&body.body, "v");
+                let (call, argument) = body
+                    .body
+                    .basic_blocks
+                    .iter_enumerated()
+                    .find_map(|(block, data)| match &data.terminator().kind {
+                        TerminatorKind::Call { args, .. } => {
+                            Some((block, args[0].node.place().unwrap().local))
+                        }
+                        _ => None,
+                    })
+                    .unwrap();
+                let local_decls = &body.body.local_decls;
+                let region_of = |local: Local| match local_decls[local].ty.kind() {
+                    ty::TyKind::Ref(region, _, _) => get_vid(region),
+                    _ => None,
+                };
+                let mut output =
+                    run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+                let rp = output.repacker();
+                assert_eq!(
+                    AbstractionTarget::Place(ReborrowBlockedPlace::Remote(v)).region(rp),
+                    region_of(v)
+                );
+                let state = output
+                    .get_all_for_bb(call)
+                    .terminator
+                    .succs
+                    .remove(0)
+                    .extra
+                    .after;
+                let input = state
+                    .graph()
+                    .abstraction_edges()
+                    .into_iter()
+                    .flat_map(|edge| edge.value.inputs())
+                    .find(|input| {
+                        matches!(
+                            input,
+                            AbstractionTarget::Place(ReborrowBlockedPlace::Local(place))
+                                if place.place().local == argument
+                        )
+                    })
+                    .unwrap();
+                assert!(region_of(argument).is_some());
+                assert_eq!(input.region(rp), region_of(argument));
+            },
+        );
+    }
+}
@@ -1,15 +1,20 @@
-use std::rc::Rc;
+use std::{cell::Cell, fmt::Debug, rc::Rc};
 
 use rustc_interface::{
     borrowck::{
         borrow_set::BorrowSet,
-        consumers::{LocationTable, PoloniusInput, PoloniusOutput, RegionInferenceContext},
+        consumers::{
+            calculate_borrows_out_of_scope_at_location, BorrowIndex, LocationTable, PoloniusInput,
+            PoloniusOutput, RegionInferenceContext,
+        },
     },
-    dataflow::{Analysis, AnalysisDomain, JoinSemiLattice},
+    data_structures::fx::{FxHashMap, FxHashSet},
+    dataflow::{fmt::DebugWithContext, Analysis, AnalysisDomain, JoinSemiLattice},
+    index::{bit_set::BitSet, Idx, IndexVec},
     middle::{
         mir::{
             visit::Visitor, BasicBlock, Body, CallReturnPlaces, Location, Statement, Terminator,
-            TerminatorEdges,
+            TerminatorEdges, START_BLOCK,
         },
         ty::{self, TyCtxt},
     },
@@ -18,12 +23,16 @@ use serde_json::{json, Value};
 
 use crate::{
     borrows::domain::ToJsonWithRepacker,
+    r#loop::LoopAnalysis,
     rustc_interface,
     utils::{self, Place, PlaceRepacker},
 };
 
 use super::{
-    borrows_state::BorrowsState, borrows_visitor::BorrowsVisitor, domain::ReborrowBlockedPlace,
+    borrows_state::{AgingPolicy, BorrowsState, SharedReborrowPolicy},
+    borrows_visitor::BorrowsVisitor,
+    domain::{ReborrowBlockedPlace, ReborrowKind},
+    outlives::OutlivesRelation,
     path_condition::PathCondition,
 };
 use super::{
@@ -38,7 +47,24 @@ pub struct BorrowsEngine<'mir, 'tcx> {
     pub input_facts: &'mir PoloniusInput,
     pub borrow_set: Rc<BorrowSet<'tcx>>,
     pub region_inference_context: Rc<RegionInferenceContext<'tcx>>,
-    pub output_facts: &'mir PoloniusOutput,
+    /// `None` if the borrow checker was run without Polonius, nothing in
+    /// the analysis depends on them
+    pub output_facts: Option<&'mir PoloniusOutput>,
+    /// For each location, the reservation locations of the loans in scope at
+    /// its start, see [`loans_in_scope`]
+    pub loans_in_scope: Rc<FxHashMap<Location, FxHashSet<Location>>>,
+    /// The transitive closure of the outlives constraints of
+    /// `region_inference_context`
+    pub outlives: Rc<OutlivesRelation>,
+    /// The loops of `body`, used when joining the states of the blocks
+    pub loops: Rc<LoopAnalysis>,
+    pub shared_reborrow_policy: SharedReborrowPolicy,
+    pub aging_policy: AgingPolicy,
+    /// See [`crate::PcsOptions::lenient`]
+    pub lenient: bool,
+    /// The block of the next domain created by `bottom_value`, when the
+    /// analysis is run on its own (see [`crate::run_borrows`])
+    curr_block: Cell<BasicBlock>,
 }
 
 impl<'mir, 'tcx> BorrowsEngine<'mir, 'tcx> {
@@ -49,8 +75,16 @@ impl<'mir, 'tcx> BorrowsEngine<'mir, 'tcx> {
         input_facts: &'mir PoloniusInput,
         borrow_set: Rc<BorrowSet<'tcx>>,
         region_inference_context: Rc<RegionInferenceContext<'tcx>>,
-        output_facts: &'mir PoloniusOutput,
+        output_facts: Option<&'mir PoloniusOutput>,
     ) -> Self {
+        let loans_in_scope = Rc::new(loans_in_scope(
+            body,
+            input_facts,
+            &borrow_set,
+            &region_inference_context,
+        ));
+        let outlives = Rc::new(OutlivesRelation::new(&region_inference_context));
+        let loops = Rc::new(LoopAnalysis::find_loops(body));
         BorrowsEngine {
             tcx,
             body,
@@ -59,8 +93,111 @@ impl<'mir, 'tcx> BorrowsEngine<'mir, 'tcx> {
             borrow_set,
             region_inference_context,
             output_facts,
+            loans_in_scope,
+            outlives,
+            loops,
+            shared_reborrow_policy: SharedReborrowPolicy::default(),
+            aging_policy: AgingPolicy::default(),
+            lenient: false,
+            curr_block: Cell::new(START_BLOCK),
+        }
+    }
+}
+
+/// The locations at which each loan goes out of scope, according to the
+/// borrow checker.
+pub(crate) fn loans_out_of_scope<'tcx>(
+    body: &Body<'tcx>,
+    borrow_set: &BorrowSet<'tcx>,
+    region_inference_context: &RegionInferenceContext<'tcx>,
+) -> FxHashMap<BorrowIndex, FxHashSet<Location>> {
+    let mut out_of_scope: FxHashMap<_, FxHashSet<Location>> = FxHashMap::default();
+    for (location, loans) in
+        calculate_borrows_out_of_scope_at_location(body, region_inference_context, borrow_set)
+    {
+        for loan in loans {
+            out_of_scope.entry(loan).or_default().insert(location);
+        }
+    }
+    out_of_scope
+}
+
+/// The loans in scope at the start of each location, identified by their
+/// reservation location. A loan is in scope from its reservation until the
+/// borrow checker considers it out of scope, i.e. until its region no longer
+/// contains the location. The polonius output facts can't be used for this,
+/// they don't include the liveness of the loans unless polonius is run in
+/// its debugging mode.
+///
+/// The scopes of all loans are computed at once, as a forward dataflow
+/// problem over the blocks of the body: a loan is in scope at a location if
+/// it is in scope at (or reserved at) one of its predecessors, and does not
+/// go out of scope at the location itself.
+pub(crate) fn loans_in_scope<'tcx>(
+    body: &Body<'tcx>,
+    input_facts: &PoloniusInput,
+    borrow_set: &BorrowSet<'tcx>,
+    region_inference_context: &RegionInferenceContext<'tcx>,
+) -> FxHashMap<Location, FxHashSet<Location>> {
+    let out_of_scope =
+        calculate_borrows_out_of_scope_at_location(body, region_inference_context, borrow_set);
+    let mut reserved: FxHashMap<Location, Vec<BorrowIndex>> = FxHashMap::default();
+    for (_, loan, _) in input_facts.loan_issued_at.iter() {
+        reserved
+            .entry(borrow_set[*loan].reserve_location)
+            .or_default()
+            .push(*loan);
+    }
+    // Applies the locations of `block` to `state`, the loans in scope at the
+    // start of the block, calling `visit` with the loans in scope at the start
+    // of each location
+    let apply_block =
+        |block: BasicBlock,
+         state: &mut BitSet<BorrowIndex>,
+         visit: &mut dyn FnMut(Location, &BitSet<BorrowIndex>)| {
+            for statement_index in 0..=body[block].statements.len() {
+                let location = Location {
+                    block,
+                    statement_index,
+                };
+                for loan in out_of_scope.get(&location).into_iter().flatten() {
+                    state.remove(*loan);
+                }
+                visit(location, state);
+                for loan in reserved.get(&location).into_iter().flatten() {
+                    state.insert(*loan);
+                }
+            }
+        };
+    let mut entry_sets: IndexVec<BasicBlock, BitSet<BorrowIndex>> =
+        IndexVec::from_elem_n(BitSet::new_empty(borrow_set.len()), body.basic_blocks.len());
+    let mut worklist = body.basic_blocks.indices().rev().collect::<Vec<_>>();
+    let mut in_worklist = BitSet::new_filled(body.basic_blocks.len());
+    while let Some(block) = worklist.pop() {
+        in_worklist.remove(block);
+        let mut state = entry_sets[block].clone();
+        apply_block(block, &mut state, &mut |_, _| {});
+        for successor in body[block].terminator().successors() {
+            if entry_sets[successor].union(&state) && in_worklist.insert(successor) {
+                worklist.push(successor);
+            }
         }
     }
+    let mut in_scope = FxHashMap::default();
+    for (block, mut state) in entry_sets.into_iter_enumerated() {
+        apply_block(block, &mut state, &mut |location, loans| {
+            if !loans.is_empty() {
+                in_scope.insert(
+                    location,
+                    loans
+                        .iter()
+                        .map(|loan| borrow_set[loan].reserve_location)
+                        .collect(),
+                );
+            }
+        });
+    }
+    in_scope
 }
 
 #[derive(Clone, Debug)]
@@ -107,8 +244,26 @@ impl<'mir, 'tcx> JoinSemiLattice for BorrowsDomain<'mir, 'tcx> {
             let pc = PathCondition::new(other.block(), self.block());
             other_after.add_path_condition(pc);
 
-            // Overlay both graphs
-            self.after.join(&other_after, self.block(), self.repacker)
+            // Widen both graphs, then overlay them
+            let mut changed = self
+                .after
+                .collapse_divergent_expansions(&mut other_after, self.repacker);
+            let widened = cfg!(debug_assertions).then(|| self.after.clone());
+            if self
+                .after
+                .join(&other_after, self.block(), &self.loops, self.repacker)
+            {
+                changed = true;
+            }
+            if let Some(widened) = widened {
+                assert!(
+                    widened.leq(&self.after, self.repacker)
+                        && other_after.leq(&self.after, self.repacker),
+                    "Join of the borrows at {:?} is not above its widened inputs",
+                    self.block()
+                );
+            }
+            changed
         }
     }
 }
@@ -117,12 +272,29 @@ impl<'tcx, 'a> AnalysisDomain<'tcx> for BorrowsEngine<'a, 'tcx> {
     type Domain = BorrowsDomain<'a, 'tcx>;
     const NAME: &'static str = "borrows";
 
-    fn bottom_value(&self, _body: &Body<'tcx>) -> Self::Domain {
-        todo!()
+    fn bottom_value(&self, body: &Body<'tcx>) -> Self::Domain {
+        // The entry states of the blocks are created in order, the other
+        // states (e.g. for results cursors) are not associated with a block
+        let block = self.curr_block.get();
+        let block = if block.as_usize() < body.basic_blocks.len() {
+            self.curr_block.set(block.plus(1));
+            Some(block)
+        } else {
+            None
+        };
+        let mut state = BorrowsDomain::new(
+            PlaceRepacker::new(self.body, self.tcx),
+            self.loops.clone(),
+            block,
+        );
+        state.set_aging_policy(self.aging_policy);
+        state.set_lenient(self.lenient);
+        state
     }
 
-    fn initialize_start_block(&self, _body: &Body<'tcx>, _state: &mut Self::Domain) {
-        todo!()
+    fn initialize_start_block(&self, _body: &Body<'tcx>, state: &mut Self::Domain) {
+        self.curr_block.set(START_BLOCK);
+        state.initialize_as_start_block();
     }
 }
 
@@ -181,7 +353,8 @@ impl<'a, 'tcx> Analysis<'tcx> for BorrowsEngine<'a, 'tcx> {
         _block: BasicBlock,
         _return_places: CallReturnPlaces<'_, 'tcx>,
     ) {
-        todo!()
+        // The effect of the call on the destination is applied by the
+        // terminator effect
     }
 }
 #[derive(Clone)]
@@ -192,6 +365,7 @@ pub struct BorrowsDomain<'mir, 'tcx> {
     pub after: BorrowsState<'tcx>,
     pub block: Option<BasicBlock>,
     pub repacker: PlaceRepacker<'mir, 'tcx>,
+    loops: Rc<LoopAnalysis>,
 }
 
 impl<'mir, 'tcx> PartialEq for BorrowsDomain<'mir, 'tcx> {
@@ -218,6 +392,64 @@ impl<'mir, 'tcx> std::fmt::Debug for BorrowsDomain<'mir, 'tcx> {
     }
 }
 
+impl<'mir, 'tcx> DebugWithContext<BorrowsEngine<'mir, 'tcx>> for BorrowsDomain<'mir, 'tcx> {
+    fn fmt_diff_with(
+        &self,
+        old: &Self,
+        _ctxt: &BorrowsEngine<'mir, 'tcx>,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        let repacker = self.repacker;
+        BorrowsStateCompare(&self.before_start, &old.after, "", repacker).fmt(f)?;
+        BorrowsStateCompare(
+            &self.before_after,
+            &self.before_start,
+            "ARGUMENTS:\n",
+            repacker,
+        )
+        .fmt(f)?;
+        BorrowsStateCompare(&self.start, &self.before_after, "", repacker).fmt(f)?;
+        BorrowsStateCompare(&self.after, &self.start, "STATEMENT:\n", repacker).fmt(f)?;
+        Ok(())
+    }
+}
+
+/// The edges removed from (`-`) and added to (`+`) the graph of the second
+/// state to get that of the first, in the order of
+/// [`BorrowsGraph::ordered_edges`](super::borrows_graph::BorrowsGraph::ordered_edges).
+/// Nothing is printed if the graphs are the same.
+struct BorrowsStateCompare<'a, 'mir, 'tcx>(
+    &'a BorrowsState<'tcx>,
+    &'a BorrowsState<'tcx>,
+    &'a str,
+    PlaceRepacker<'mir, 'tcx>,
+);
+impl Debug for BorrowsStateCompare<'_, '_, '_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let new_edges = self.0.ordered_graph_edges(self.3);
+        let old_edges = self.1.ordered_graph_edges(self.3);
+        let removed: Vec<_> = old_edges
+            .iter()
+            .filter(|e| !new_edges.contains(e))
+            .collect();
+        let added: Vec<_> = new_edges
+            .iter()
+            .filter(|e| !old_edges.contains(e))
+            .collect();
+        if removed.is_empty() && added.is_empty() {
+            return Ok(());
+        }
+        write!(f, "{}", self.2)?;
+        for edge in removed {
+            write!(f, "\u{001f}-{:?}", edge)?;
+        }
+        for edge in added {
+            write!(f, "\u{001f}+{:?}", edge)?;
+        }
+        writeln!(f)
+    }
+}
+
 impl<'mir, 'tcx> BorrowsDomain<'mir, 'tcx> {
     pub fn is_initialized(&self) -> bool {
         self.block.is_some()
@@ -231,6 +463,22 @@ impl<'mir, 'tcx> BorrowsDomain<'mir, 'tcx> {
         self.block.unwrap()
     }
 
+    /// Whether `self` is below `other` in the lattice. An uninitialized
+    /// domain is the bottom element.
+    pub fn leq(&self, other: &Self) -> bool {
+        !self.is_initialized()
+            || (other.is_initialized() && self.after.leq(&other.after, self.repacker))
+    }
+
+    /// Canonicalizes the graphs of all the states, see
+    /// [`super::borrows_graph::BorrowsGraph::canonicalize`]
+    pub fn canonicalize(&mut self) -> bool {
+        self.before_start.canonicalize()
+            | self.before_after.canonicalize()
+            | self.start.canonicalize()
+            | self.after.canonicalize()
+    }
+
     pub fn to_json(&self, repacker: PlaceRepacker<'mir, 'tcx>) -> Value {
         json!({
             "before_start": self.before_start.to_json(repacker),
@@ -240,7 +488,11 @@ impl<'mir, 'tcx> BorrowsDomain<'mir, 'tcx> {
         })
     }
 
-    pub fn new(repacker: PlaceRepacker<'mir, 'tcx>, block: Option<BasicBlock>) -> Self {
+    pub fn new(
+        repacker: PlaceRepacker<'mir, 'tcx>,
+        loops: Rc<LoopAnalysis>,
+        block: Option<BasicBlock>,
+    ) -> Self {
         Self {
             before_start: BorrowsState::new(),
             before_after: BorrowsState::new(),
@@ -248,21 +500,39 @@ impl<'mir, 'tcx> BorrowsDomain<'mir, 'tcx> {
             after: BorrowsState::new(),
             block,
             repacker,
+            loops,
         }
     }
 
+    pub fn set_aging_policy(&mut self, aging_policy: AgingPolicy) {
+        self.before_start.set_aging_policy(aging_policy);
+        self.before_after.set_aging_policy(aging_policy);
+        self.start.set_aging_policy(aging_policy);
+        self.after.set_aging_policy(aging_policy);
+    }
+
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.before_start.set_lenient(lenient);
+        self.before_after.set_lenient(lenient);
+        self.start.set_lenient(lenient);
+        self.after.set_lenient(lenient);
+    }
+
     pub fn initialize_as_start_block(&mut self) {
-        for arg in self.repacker.body().args_iter() {
+        for (index, arg) in self.repacker.body().args_iter().enumerate() {
             if let ty::TyKind::Ref(region, _, mutability) =
                 self.repacker.body().local_decls[arg].ty.kind()
             {
                 let arg_place: Place<'tcx> = arg.into();
+                // All the arguments borrow from the caller at the start of
+                // the body, they are distinguished by their position
                 self.after.add_reborrow(
                     ReborrowBlockedPlace::Remote(arg),
                     arg_place.project_deref(self.repacker),
                     *mutability,
+                    ReborrowKind::Ref(*region),
                     Location::START,
-                    *region,
+                    index,
                 );
             }
         }
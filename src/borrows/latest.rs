@@ -20,19 +20,97 @@ impl Latest {
         self.0.insert(local, location)
     }
 
+    /// The latest snapshot of `local`, if it has one
+    pub fn snapshot_of(&self, local: Local) -> Option<SnapshotLocation> {
+        self.0.get(&local).copied()
+    }
+
+    /// The locals that have a latest snapshot, with that snapshot
+    pub fn iter(&self) -> impl Iterator<Item = (Local, SnapshotLocation)> + '_ {
+        self.0.iter().map(|(local, location)| (*local, *location))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::Value::Object(
+            self.0
+                .iter()
+                .map(|(local, location)| (format!("{:?}", local), format!("{:?}", location).into()))
+                .collect(),
+        )
+    }
+
+    /// Joins the latest snapshots of `other` into `self`, for the join at
+    /// the start of `block`. A local whose latest snapshot differs between
+    /// the two (e.g. a local assigned in both arms of an `if`) gets the
+    /// snapshot [`SnapshotLocation::Join`] of `block`; the old places of the
+    /// graphs that have the snapshot of either side are renamed accordingly
+    /// by [`super::borrows_state::BorrowsState::join`].
+    ///
+    /// A snapshot taken by the join itself (the `Join` snapshot of `block`,
+    /// or the snapshot at the start of `block` given by the unblocking after
+    /// the join) is more recent than those of the predecessors and is kept.
+    /// In particular, in a loop, a local assigned in the body already has the
+    /// `Join` snapshot of the loop head when the end of the body is joined
+    /// into it again, so this reaches a fixpoint.
+    ///
+    /// Returns `true` iff `self` changed.
     pub fn join(&mut self, other: &Self, block: BasicBlock) -> bool {
+        let start = SnapshotLocation::Location(block.start_location());
         let mut changed = false;
-        for (place, other_loc) in other.0.iter() {
-            if let Some(self_loc) = self.0.get(place) {
-                if *self_loc != *other_loc {
-                    self.insert(*place, SnapshotLocation::Join(block));
-                    changed = true;
+        for (local, other_loc) in other.0.iter() {
+            let joined = match self.0.get(local) {
+                Some(self_loc)
+                    if self_loc == other_loc
+                        || *self_loc == SnapshotLocation::Join(block)
+                        || *self_loc == start =>
+                {
+                    continue
                 }
-            } else {
-                self.insert(*place, *other_loc);
+                Some(_) => SnapshotLocation::Join(block),
+                None => *other_loc,
+            };
+            if self.insert(*local, joined) != Some(joined) {
                 changed = true;
             }
         }
         changed
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(block: usize, statement_index: usize) -> SnapshotLocation {
+        SnapshotLocation::Location(Location {
+            block: BasicBlock::from_usize(block),
+            statement_index,
+        })
+    }
+
+    #[test]
+    fn join_of_loop_back_edge_reaches_fixpoint() {
+        let head = BasicBlock::from_usize(1);
+        let local = Local::from_usize(1);
+        let mut entry = Latest::new();
+        entry.insert(local, at(0, 1));
+        let mut end_of_body = Latest::new();
+        end_of_body.insert(local, at(2, 3));
+        assert!(entry.join(&end_of_body, head));
+        assert_eq!(entry.0[&local], SnapshotLocation::Join(head));
+        // The next iteration assigns the local again
+        end_of_body.insert(local, at(2, 4));
+        assert!(!entry.join(&end_of_body, head));
+        assert_eq!(entry.0[&local], SnapshotLocation::Join(head));
+    }
+
+    #[test]
+    fn join_reports_new_locals() {
+        let local = Local::from_usize(1);
+        let mut latest = Latest::new();
+        let mut other = Latest::new();
+        other.insert(local, at(0, 1));
+        assert!(latest.join(&other, BasicBlock::from_usize(1)));
+        assert!(!latest.join(&other, BasicBlock::from_usize(1)));
+    }
+}
@@ -0,0 +1,104 @@
+use rustc_interface::{
+    borrowck::consumers::BorrowIndex,
+    data_structures::fx::FxHashMap,
+    middle::mir::{BorrowKind, Location},
+};
+use serde_json::json;
+
+use crate::{
+    combined_pcs::BodyWithBorrowckFacts,
+    rustc_interface,
+    utils::{Place, PlaceRepacker},
+};
+
+use super::engine::{loans_in_scope, loans_out_of_scope};
+
+/// The lifetimes of the borrows of the body, in a form suitable for a
+/// timeline chart.
+///
+/// The locations of the body are laid out in a single order (block by block,
+/// statement by statement), listed in `locations`. Each borrow has its
+/// reservation and kill locations, and the `intervals` of positions in that
+/// order where it is in scope (inclusive at both ends). A borrow in scope on
+/// several branches or around a loop has several intervals.
+pub fn borrow_lifetimes_json<'tcx>(
+    mir: &BodyWithBorrowckFacts<'tcx>,
+    repacker: PlaceRepacker<'_, 'tcx>,
+) -> serde_json::Value {
+    let body = &mir.body;
+    let locations = body
+        .basic_blocks
+        .iter_enumerated()
+        .flat_map(|(block, data)| {
+            (0..=data.statements.len()).map(move |statement_index| Location {
+                block,
+                statement_index,
+            })
+        })
+        .collect::<Vec<_>>();
+    let positions = locations
+        .iter()
+        .enumerate()
+        .map(|(position, location)| (*location, position))
+        .collect::<FxHashMap<_, _>>();
+    let in_scope = loans_in_scope(
+        body,
+        mir.input_facts.as_ref().unwrap(),
+        &mir.borrow_set,
+        &mir.region_inference_context,
+    );
+    let mut out_of_scope = loans_out_of_scope(body, &mir.borrow_set, &mir.region_inference_context);
+    let borrows = mir
+        .borrow_set
+        .location_map
+        .iter()
+        .enumerate()
+        .map(|(index, (reserve_location, borrow))| {
+            let index = BorrowIndex::from_usize(index);
+            let mut kill_locations = out_of_scope
+                .remove(&index)
+                .unwrap_or_default()
+                .into_iter()
+                .collect::<Vec<_>>();
+            kill_locations.sort();
+            let mut in_scope_positions = in_scope
+                .iter()
+                .filter(|(_, loans)| loans.contains(reserve_location))
+                .map(|(location, _)| positions[location])
+                .chain(std::iter::once(positions[reserve_location]))
+                .collect::<Vec<_>>();
+            in_scope_positions.sort();
+            let mut intervals: Vec<(usize, usize)> = vec![];
+            for position in in_scope_positions {
+                match intervals.last_mut() {
+                    Some((_, end)) if *end + 1 == position => *end = position,
+                    _ => intervals.push((position, position)),
+                }
+            }
+            let kind = match borrow.kind {
+                BorrowKind::Shared => "shared",
+                BorrowKind::Fake(_) => "fake",
+                BorrowKind::Mut { .. } => "mut",
+            };
+            json!({
+                "borrow": format!("{:?}", index),
+                "kind": kind,
+                "borrowed_place": Place::from(borrow.borrowed_place).to_short_string(repacker),
+                "assigned_place": Place::from(borrow.assigned_place).to_short_string(repacker),
+                "reserve_location": format!("{:?}", reserve_location),
+                "kill_locations": kill_locations
+                    .iter()
+                    .map(|location| format!("{:?}", location))
+                    .collect::<Vec<_>>(),
+                "intervals": intervals,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "locations": locations
+            .iter()
+            .map(|location| format!("{:?}", location))
+            .collect::<Vec<_>>(),
+        "borrows": borrows,
+    })
+}
@@ -1,10 +1,13 @@
 pub mod borrows_graph;
 pub mod borrows_state;
 pub mod borrows_visitor;
+pub mod constraints;
 pub mod deref_expansion;
 pub mod domain;
 pub mod engine;
 pub mod latest;
+pub mod lifetimes;
+pub mod outlives;
 pub mod path_condition;
 pub mod region_abstraction;
 pub mod unblock_graph;
@@ -0,0 +1,42 @@
+use crate::rustc_interface::{
+    borrowck::consumers::RegionInferenceContext,
+    index::{bit_set::BitMatrix, IndexVec},
+    middle::ty::RegionVid,
+};
+
+/// The transitive closure of the outlives constraints of the region inference
+/// context of a body.
+///
+/// Computed once per body (see [`super::engine::BorrowsEngine::new`]) so that
+/// querying whether one region outlives another does not require traversing
+/// the constraint graph.
+pub struct OutlivesRelation {
+    /// Row `sup` contains `sub` iff `sup: sub`
+    closure: BitMatrix<RegionVid, RegionVid>,
+}
+
+impl OutlivesRelation {
+    pub fn new(region_inference_context: &RegionInferenceContext<'_>) -> Self {
+        let num_regions = region_inference_context.regions().count();
+        let mut successors: IndexVec<RegionVid, Vec<RegionVid>> =
+            IndexVec::from_elem_n(vec![], num_regions);
+        for constraint in region_inference_context.outlives_constraints() {
+            successors[constraint.sup].push(constraint.sub);
+        }
+        let mut closure = BitMatrix::new(num_regions, num_regions);
+        for sup in successors.indices() {
+            let mut stack = vec![sup];
+            while let Some(current) = stack.pop() {
+                if closure.insert(sup, current) {
+                    stack.extend(successors[current].iter().copied());
+                }
+            }
+        }
+        Self { closure }
+    }
+
+    /// Whether `sup` outlives `sub`. Every region outlives itself.
+    pub fn outlives(&self, sup: RegionVid, sub: RegionVid) -> bool {
+        self.closure.contains(sup, sub)
+    }
+}
@@ -3,7 +3,10 @@ use std::collections::BTreeSet;
 use serde_json::json;
 
 use crate::{
-    rustc_interface::middle::mir::{BasicBlock, BasicBlocks},
+    rustc_interface::{
+        data_structures::fx::FxHashSet,
+        middle::mir::{BasicBlock, BasicBlocks, START_BLOCK},
+    },
     utils::PlaceRepacker,
 };
 
@@ -98,21 +101,27 @@ impl PCGraph {
         } else {
             path
         };
-        let mut i = 0;
-        while i < path.len() - 1 {
-            let f = path[i];
-            let t = path[i + 1];
-            if !self.0.contains(&PathCondition::new(f, t)) {
-                return false;
-            }
-            i += 1
-        }
-        true
+        path.windows(2)
+            .all(|w| self.0.contains(&PathCondition::new(w[0], w[1])))
     }
 
     pub fn insert(&mut self, pc: PathCondition) -> bool {
         self.0.insert(pc)
     }
+
+    pub fn edges(&self) -> impl Iterator<Item = &PathCondition> {
+        self.0.iter()
+    }
+}
+
+/// How far a path is in satisfying [`PathConditions::Paths`], see
+/// [`PathConditions::mutually_exclusive`]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+enum PathProgress {
+    BeforeRoot,
+    AtRoot,
+    /// At least one edge of the graph was taken since the root
+    InGraph,
 }
 
 #[derive(PartialEq, Eq, Clone, Hash, PartialOrd, Ord, Debug)]
@@ -164,29 +173,95 @@ impl PathConditions {
         }
     }
 
-    /// Returns true if no path through the program would satisfy both conditions.
+    /// Returns true if no execution path (starting from the start block)
+    /// satisfies both conditions. A path satisfies
+    /// - `AtBlock(b)` if it ends at `b`, and
+    /// - `Paths(g)` if, from its first visit of the root of `g`, it takes at
+    ///   least one edge and only edges of `g` (e.g. `{bb1 -> bb3}` and
+    ///   `{bb2 -> bb3}` are exclusive, as are the two branches `{bb2 -> bb3}`
+    ///   and `{bb2 -> bb4}` of a switch, even in a loop).
+    ///
+    /// Conditions without a root are conservatively assumed not to be
+    /// exclusive with anything.
     pub fn mutually_exclusive(&self, other: &Self, blocks: &BasicBlocks<'_>) -> bool {
         if self == other {
             return false;
         }
-        match (self.root(), other.root(), self.end(), other.end()) {
-            (Some(r1), Some(r2), Some(e1), Some(e2)) => {
-                let preds = blocks.predecessors();
-                !preds[r1].contains(&e2) && !preds[r2].contains(&e1)
+        if self.root().is_none() || other.root().is_none() {
+            return false;
+        }
+        let start = (
+            START_BLOCK,
+            self.enter(START_BLOCK),
+            other.enter(START_BLOCK),
+        );
+        let mut visited = FxHashSet::default();
+        let mut worklist = vec![start];
+        while let Some((block, p1, p2)) = worklist.pop() {
+            if !visited.insert((block, p1, p2)) {
+                continue;
+            }
+            if self.satisfied(block, p1) && other.satisfied(block, p2) {
+                return false;
+            }
+            for succ in blocks[block].terminator().successors() {
+                if let (Some(s1), Some(s2)) =
+                    (self.step(block, p1, succ), other.step(block, p2, succ))
+                {
+                    worklist.push((succ, s1, s2));
+                }
             }
-            _ => false,
+        }
+        true
+    }
+
+    /// The progress of a path towards satisfying `self` when it starts at
+    /// `block`
+    fn enter(&self, block: BasicBlock) -> PathProgress {
+        match self {
+            PathConditions::Paths(graph) if graph.root() == Some(block) => PathProgress::AtRoot,
+            _ => PathProgress::BeforeRoot,
+        }
+    }
+
+    /// The progress of a path towards satisfying `self` after taking the edge
+    /// `from -> to`, or `None` if the path can no longer satisfy `self`
+    fn step(
+        &self,
+        from: BasicBlock,
+        progress: PathProgress,
+        to: BasicBlock,
+    ) -> Option<PathProgress> {
+        match (self, progress) {
+            (PathConditions::Paths(graph), PathProgress::AtRoot | PathProgress::InGraph) => graph
+                .0
+                .contains(&PathCondition::new(from, to))
+                .then_some(PathProgress::InGraph),
+            _ => Some(self.enter(to)),
+        }
+    }
+
+    fn satisfied(&self, block: BasicBlock, progress: PathProgress) -> bool {
+        match self {
+            PathConditions::AtBlock(b) => *b == block,
+            PathConditions::Paths(_) => progress == PathProgress::InGraph,
         }
     }
 
     pub fn join(&mut self, other: &Self) -> bool {
-        match (self, other) {
+        match (&mut *self, other) {
             (PathConditions::AtBlock(b1), PathConditions::AtBlock(b2)) => {
                 assert!(*b1 == *b2);
                 false
             }
             (PathConditions::Paths(p1), PathConditions::Paths(p2)) => p1.join(p2),
-            (PathConditions::AtBlock(b), PathConditions::Paths(p)) => false, // TODO: check
-            (PathConditions::Paths(p), PathConditions::AtBlock(b)) => false, // TODO: check
+            // An edge that holds at `b` regardless of the path taken already
+            // subsumes any path-specific condition
+            (PathConditions::AtBlock(_), PathConditions::Paths(_)) => false,
+            (PathConditions::Paths(_), PathConditions::AtBlock(b)) => {
+                *self = PathConditions::AtBlock(*b);
+                true
+            }
         }
     }
 
@@ -208,3 +283,158 @@ impl PathConditions {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        borrows::borrows_graph::BorrowsEdgeKind,
+        run_combined_pcs,
+        rustc_interface::middle::mir::{Rvalue, StatementKind, TerminatorKind},
+        test_utils::{find_statement, local_named, run_on_fn},
+        PcsOptions,
+    };
+
+    #[test]
+    fn mutually_exclusive_conditions_of_branches() {
+        run_on_fn(
+            "40_assign_in_both_branches.rs",
+            "assign_in_both_branches",
+            |_tcx, body| {
+                let blocks = &body.body.basic_blocks;
+                let [then_branch, else_branch] = blocks[START_BLOCK]
+                    .terminator()
+                    .successors()
+                    .collect::<Vec<_>>()[..]
+                else {
+                    panic!("Expected a switch with two targets");
+                };
+                let join = blocks[then_branch]
+                    .terminator()
+                    .successors()
+                    .next()
+                    .unwrap();
+                let paths = |edges: &[(BasicBlock, BasicBlock)]| {
+                    PathConditions::Paths(PCGraph(
+                        edges
+                            .iter()
+                            .map(|(from, to)| PathCondition::new(*from, *to))
+                            .collect(),
+                    ))
+                };
+                let exclusive =
+                    |c1: &PathConditions, c2: &PathConditions| c1.mutually_exclusive(c2, blocks);
+
+                // `AtBlock` and `AtBlock`
+                let at_join = PathConditions::AtBlock(join);
+                assert!(!exclusive(&at_join, &at_join));
+                assert!(exclusive(
+                    &PathConditions::AtBlock(then_branch),
+                    &PathConditions::AtBlock(else_branch)
+                ));
+
+                // `AtBlock` and `Paths`
+                let to_then = paths(&[(START_BLOCK, then_branch)]);
+                let then_to_join = paths(&[(then_branch, join)]);
+                assert!(!exclusive(&PathConditions::AtBlock(then_branch), &to_then));
+                assert!(!exclusive(&to_then, &PathConditions::AtBlock(then_branch)));
+                assert!(exclusive(&at_join, &to_then));
+                assert!(exclusive(&to_then, &at_join));
+                assert!(!exclusive(&at_join, &then_to_join));
+
+                // `Paths` and `Paths`
+                let to_else = paths(&[(START_BLOCK, else_branch)]);
+                let else_to_join = paths(&[(else_branch, join)]);
+                assert!(exclusive(&to_then, &to_else));
+                assert!(exclusive(&then_to_join, &else_to_join));
+                assert!(!exclusive(
+                    &paths(&[(START_BLOCK, then_branch), (then_branch, join)]),
+                    &then_to_join
+                ));
+
+                // Conditions without a root
+                let cycle = paths(&[(START_BLOCK, then_branch), (then_branch, START_BLOCK)]);
+                assert!(cycle.root().is_none());
+                assert!(!exclusive(&cycle, &to_else));
+            },
+        );
+    }
+
+    /// In `flag && *r > 0`, `*r` is only reborrowed if the right operand is
+    /// evaluated: at the block where both operands are merged, the reborrow
+    /// only holds on the path through the right operand
+    #[test]
+    fn reborrow_in_right_operand_is_conditional() {
+        run_on_fn(
+            "04_short_circuit.rs",
+            "conditional_reborrow",
+            |tcx, body| {
+                let blocks = &body.body.basic_blocks;
+                let r = local_named(&body.body, "r");
+                let reborrow = find_statement(&body.body, |statement| {
+                    matches!(
+                        &statement.kind,
+                        StatementKind::Assign(box (_, Rvalue::Ref(_, _, place)))
+                            if place.local == r
+                    )
+                });
+                let right_operand = reborrow.block;
+                let merge = blocks[right_operand]
+                    .terminator()
+                    .successors()
+                    .next()
+                    .unwrap();
+                let TerminatorKind::SwitchInt { targets, .. } =
+                    &blocks[START_BLOCK].terminator().kind
+                else {
+                    panic!("Expected a switch on `flag`");
+                };
+                let (flag_false, flag_true) = (targets.target_for_value(0), targets.otherwise());
+                let mut output =
+                    run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+                let state = output
+                    .get_all_for_bb(merge)
+                    .statements
+                    .remove(0)
+                    .extra
+                    .before_start;
+                let conditions = state
+                    .graph_edges()
+                    .find_map(|edge| match edge.kind() {
+                        BorrowsEdgeKind::Reborrow(reborrow)
+                            if reborrow
+                                .blocked_place
+                                .as_local()
+                                .is_some_and(|place| place.place().local == r) =>
+                        {
+                            Some(edge.conditions().clone())
+                        }
+                        _ => None,
+                    })
+                    .unwrap();
+                assert_eq!(
+                    conditions,
+                    PathConditions::Paths(PCGraph::singleton(PathCondition::new(
+                        right_operand,
+                        merge
+                    )))
+                );
+                assert!(conditions.valid_for_path(&[START_BLOCK, flag_true, right_operand, merge]));
+                // Both paths on which the right operand is short-circuited
+                assert!(!conditions.valid_for_path(&[START_BLOCK, flag_false, merge]));
+                let right_false = blocks[flag_true]
+                    .terminator()
+                    .successors()
+                    .find(|block| *block != right_operand)
+                    .unwrap();
+                assert!(!conditions.valid_for_path(&[
+                    START_BLOCK,
+                    flag_true,
+                    right_false,
+                    flag_false,
+                    merge
+                ]));
+            },
+        );
+    }
+}
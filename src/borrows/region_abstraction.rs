@@ -1,11 +1,14 @@
 use rustc_interface::{data_structures::fx::FxHashSet, middle::mir::Location};
 
-use crate::{rustc_interface, utils::Place};
+use crate::{
+    rustc_interface,
+    utils::{Place, PlaceRepacker},
+};
 
 use super::{
     domain::{
-        AbstractionBlockEdge, AbstractionInputTarget, AbstractionOutputTarget, AbstractionTarget,
-        AbstractionType, MaybeOldPlace, ReborrowBlockedPlace,
+        AbstractionBlockEdge, AbstractionInputTarget, AbstractionOutputTarget, AbstractionType,
+        MaybeOldPlace, ReborrowBlockedPlace, ToJsonWithRepacker,
     },
     latest::Latest,
 };
@@ -56,3 +59,9 @@ impl<'tcx> AbstractionEdge<'tcx> {
         self.abstraction_type.edges()
     }
 }
+
+impl<'tcx> ToJsonWithRepacker<'tcx> for AbstractionEdge<'tcx> {
+    fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
+        self.abstraction_type.to_json(repacker)
+    }
+}
@@ -3,25 +3,29 @@ use std::collections::HashSet;
 use rustc_interface::{
     ast::Mutability,
     middle::{
-        mir::{BasicBlock, Location},
-        ty::TyCtxt,
+        mir::{BasicBlock, Local, Location},
+        ty::{self, RegionVid, TyCtxt},
     },
 };
+use serde_json::json;
 
 use crate::{
     borrows::{
         borrows_state::BorrowsState,
         domain::{MaybeOldPlace, Reborrow},
     },
-    combined_pcs::{ProjectionEdge, UnblockAction},
+    combined_pcs::{place_from_json, place_to_json, UnblockAction},
     rustc_interface,
-    utils::{Place, PlaceRepacker, PlaceSnapshot},
+    utils::{assumptions::check_assumption, PlaceRepacker, PlaceSnapshot, SnapshotLocation},
     visualization::generate_unblock_dot_graph,
 };
 
 use super::{
     borrows_graph::{BorrowsEdge, BorrowsEdgeKind, Conditioned},
-    domain::{AbstractionType, ReborrowBlockedPlace},
+    borrows_visitor::get_vid,
+    deref_expansion::DerefExpansion,
+    domain::{AbstractionType, ReborrowBlockedPlace, ReborrowKind},
+    path_condition::{PathCondition, PathConditions},
     region_abstraction::AbstractionEdge,
 };
 
@@ -33,6 +37,67 @@ pub struct UnblockGraph<'tcx> {
 
     /// For debugging only, record whether an error occurred
     error: bool,
+
+    /// Cycles in the borrows graph encountered while constructing this graph.
+    /// If this is non-empty the graph is only an approximation: each cycle is
+    /// broken at the edge that would have closed it.
+    cycles: Vec<BorrowCycle<'tcx>>,
+
+    /// Whether the assumptions checked when computing the actions of the
+    /// graph may be violated, see [`crate::PcsOptions::lenient`]
+    lenient: bool,
+}
+
+/// A place that (transitively) blocks itself. This indicates either a bug in
+/// the analysis or self-referential data constructed via unsafe code.
+#[derive(Clone, Debug)]
+pub struct BorrowCycle<'tcx> {
+    pub places: Vec<ReborrowBlockedPlace<'tcx>>,
+    pub reborrows: Vec<Reborrow<'tcx>>,
+}
+
+impl<'tcx> std::fmt::Display for BorrowCycle<'tcx> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cycle through places [")?;
+        for (i, place) in self.places.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", place)?;
+        }
+        write!(f, "] via reborrows [")?;
+        for (i, reborrow) in self.reborrows.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", reborrow)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// The error returned by [`UnblockGraph::actions`] when constructing the graph
+/// required unblocking the same place (or killing the same reborrow) twice.
+#[derive(Debug)]
+pub struct UnblockError<'tcx> {
+    /// The cycles in the unblock history that triggered the error
+    pub cycles: Vec<BorrowCycle<'tcx>>,
+    /// The actions obtained by breaking each cycle at one of its edges. These
+    /// only approximate the unblocking that is actually required.
+    pub approximate_actions: Vec<UnblockAction<'tcx>>,
+}
+
+impl<'tcx> std::fmt::Display for UnblockError<'tcx> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unblocking the same place twice: ")?;
+        for (i, cycle) in self.cycles.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", cycle)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -65,6 +130,25 @@ impl<'tcx> UnblockHistory<'tcx> {
         Self(vec![])
     }
 
+    /// The cycle formed by the actions since the (earlier) occurrence of
+    /// `action` in the history
+    fn cycle_from(&self, action: &UnblockHistoryAction<'tcx>) -> BorrowCycle<'tcx> {
+        let start = self.0.iter().position(|a| a == action).unwrap_or(0);
+        let mut cycle = BorrowCycle {
+            places: vec![],
+            reborrows: vec![],
+        };
+        for action in self.0[start..].iter() {
+            match action {
+                UnblockHistoryAction::UnblockPlace(place) => cycle.places.push(*place),
+                UnblockHistoryAction::KillReborrow(reborrow) => {
+                    cycle.reborrows.push(reborrow.clone())
+                }
+            }
+        }
+        cycle
+    }
+
     // Adds an element to the end of the history if it is not already present
     // Returns false iff the element was already present
     pub fn record(&mut self, action: UnblockHistoryAction<'tcx>) -> bool {
@@ -82,21 +166,77 @@ impl<'tcx> UnblockGraph<'tcx> {
     pub fn has_error(&self) -> bool {
         self.error
     }
+
+    /// The cycles that were broken while constructing this graph
+    pub fn cycles(&self) -> &[BorrowCycle<'tcx>] {
+        &self.cycles
+    }
+
+    pub fn is_lenient(&self) -> bool {
+        self.lenient
+    }
+
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Whether the graph had to break a cycle in the borrows graph, and
+    /// therefore only approximates the required unblocking
+    pub fn is_approximate(&self) -> bool {
+        !self.cycles.is_empty()
+    }
     pub fn edges(&self) -> impl Iterator<Item = &UnblockEdge<'tcx>> {
         self.edges.iter()
     }
+    /// The graph for display, and its edges (which [`UnblockGraph::from_json`]
+    /// loads back). The edges are `null` if the graph contains an edge that
+    /// can't be saved, i.e. a region abstraction or a region projection
+    /// member.
     pub fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
         let dot_graph = generate_unblock_dot_graph(&repacker, self).unwrap();
+        let edges = self
+            .edges
+            .iter()
+            .map(|edge| edge_to_json(edge, repacker))
+            .collect::<Option<Vec<_>>>();
         serde_json::json!({
             "empty": self.is_empty(),
-            "dot_graph": dot_graph
+            "dot_graph": dot_graph,
+            "edges": edges,
         })
     }
 
+    /// Loads the edges of a graph saved with [`UnblockGraph::to_json`] in a
+    /// graph for the body of `repacker` (see [`UnblockGraph::from_edges`]),
+    /// e.g. to replay its actions with [`UnblockGraph::actions`]. Returns
+    /// `None` if the edges weren't saved or don't match the body.
+    pub fn from_json(json: &serde_json::Value, repacker: PlaceRepacker<'_, 'tcx>) -> Option<Self> {
+        let edges = json["edges"]
+            .as_array()?
+            .iter()
+            .map(|edge| edge_from_json(edge, repacker))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self::from_edges(edges))
+    }
+
     pub fn new() -> Self {
         Self {
             edges: HashSet::new(),
             error: false,
+            cycles: vec![],
+            lenient: false,
+        }
+    }
+
+    /// A graph consisting of the given edges, e.g. those of a graph that was
+    /// computed (and saved) earlier. The edges should not form a cycle, as
+    /// the cycles of the original graph are not known.
+    pub fn from_edges(edges: impl IntoIterator<Item = UnblockEdge<'tcx>>) -> Self {
+        Self {
+            edges: edges.into_iter().collect(),
+            error: false,
+            cycles: vec![],
+            lenient: false,
         }
     }
 
@@ -106,6 +246,7 @@ impl<'tcx> UnblockGraph<'tcx> {
         repacker: PlaceRepacker<'_, 'tcx>,
     ) -> Self {
         let mut ug = Self::new();
+        ug.set_lenient(state.is_lenient());
         ug.unblock_place(place, state, repacker);
         ug
     }
@@ -118,8 +259,53 @@ impl<'tcx> UnblockGraph<'tcx> {
         self.edges.retain(|edge| edge.valid_for_path(path));
     }
 
-    pub fn actions(self, repacker: PlaceRepacker<'_, 'tcx>) -> Vec<UnblockAction<'tcx>> {
-        let mut edges = self.edges;
+    fn unblock_action(
+        edge: &UnblockEdge<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Option<UnblockAction<'tcx>> {
+        match edge.kind() {
+            UnblockEdgeType::Reborrow(reborrow) => Some(UnblockAction::TerminateReborrow {
+                blocked_place: reborrow.blocked_place,
+                assigned_place: reborrow.assigned_place,
+                reserve_location: reborrow.reserve_location(),
+                index: reborrow.id().index,
+                is_mut: reborrow.mutability == Mutability::Mut,
+            }),
+            UnblockEdgeType::DerefExpansion(deref_edge) => Some(UnblockAction::Collapse(
+                deref_edge.base(),
+                deref_edge.expansion(repacker),
+            )),
+            UnblockEdgeType::RegionAbstraction(abstraction_edge) => {
+                Some(UnblockAction::TerminateAbstraction(
+                    abstraction_edge.location(),
+                    abstraction_edge.abstraction_type.clone(),
+                ))
+            }
+            UnblockEdgeType::RegionProjectionMember(_) => None,
+        }
+    }
+
+    /// The actions required to unblock the places of the graph, in the order in
+    /// which they should be performed. Returns an error if the graph contains
+    /// a cycle (see [`UnblockGraph::cycles`]).
+    pub fn actions(
+        self,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Result<Vec<UnblockAction<'tcx>>, UnblockError<'tcx>> {
+        self.simulate(repacker)
+    }
+
+    /// Computes the actions of [`UnblockGraph::actions`] without consuming
+    /// the graph. The order is determined by the edges of the graph alone (the
+    /// repacker is only used to compute the expansions of deref edges), so the
+    /// actions of a graph obtained with [`UnblockGraph::from_edges`] can be
+    /// replayed without the state it was constructed from.
+    pub fn simulate(
+        &self,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Result<Vec<UnblockAction<'tcx>>, UnblockError<'tcx>> {
+        let mut is_approximate = self.is_approximate();
+        let mut edges = self.edges.clone();
         let mut actions = vec![];
 
         // There might be duplicates because the same action may be required by
@@ -154,45 +340,52 @@ impl<'tcx> UnblockGraph<'tcx> {
                 // })
             };
             for edge in edges.iter() {
-                match edge.kind() {
-                    UnblockEdgeType::Reborrow(reborrow) => {
-                        if is_leaf(reborrow.assigned_place) {
-                            push_action(UnblockAction::TerminateReborrow {
-                                blocked_place: reborrow.blocked_place,
-                                assigned_place: reborrow.assigned_place,
-                                reserve_location: reborrow.reserve_location(),
-                                is_mut: reborrow.mutability == Mutability::Mut,
-                            });
-                            to_keep.remove(edge);
-                        }
-                    }
-                    UnblockEdgeType::DerefExpansion(deref_edge) => {
-                        let expansion = deref_edge.expansion(repacker);
-                        if expansion.iter().all(|p| is_leaf(*p)) {
-                            push_action(UnblockAction::Collapse(deref_edge.base(), expansion));
-                            to_keep.remove(edge);
-                        }
-                    }
+                let is_leaf_edge = match edge.kind() {
+                    UnblockEdgeType::Reborrow(reborrow) => is_leaf(reborrow.assigned_place),
+                    UnblockEdgeType::DerefExpansion(deref_edge) => deref_edge
+                        .expansion(repacker)
+                        .iter()
+                        .all(|p| is_leaf(*p)),
                     UnblockEdgeType::RegionAbstraction(abstraction_edge) => {
-                        if is_leaf_abstraction(&abstraction_edge.abstraction_type) {
-                            push_action(UnblockAction::TerminateAbstraction(
-                                abstraction_edge.location(),
-                                abstraction_edge.abstraction_type.clone(),
-                            ));
-                            to_keep.remove(edge);
-                        }
+                        is_leaf_abstraction(&abstraction_edge.abstraction_type)
                     }
-                    _ => {}
+                    _ => false,
+                };
+                if is_leaf_edge {
+                    if let Some(action) = Self::unblock_action(edge, repacker) {
+                        push_action(action);
+                    }
+                    to_keep.remove(edge);
                 }
             }
-            assert!(
-                to_keep.len() < edges.len(),
-                "Didn't remove any leaves! {:#?}",
-                edges
-            );
+            if to_keep.len() == edges.len() {
+                // The remaining edges form a cycle, normally one of those
+                // reported when constructing the graph. Break it at a reborrow
+                // (if any) and continue, the actions are then approximate.
+                if !check_assumption(self.lenient, is_approximate, None, || {
+                    format!("Didn't remove any leaves! {:#?}", edges)
+                }) {
+                    is_approximate = true;
+                }
+                let edge = edges
+                    .iter()
+                    .find(|edge| matches!(edge.kind(), UnblockEdgeType::Reborrow(_)))
+                    .unwrap_or_else(|| edges.iter().next().unwrap());
+                if let Some(action) = Self::unblock_action(edge, repacker) {
+                    push_action(action);
+                }
+                to_keep.remove(edge);
+            }
             edges = to_keep;
         }
-        actions
+        if is_approximate {
+            Err(UnblockError {
+                cycles: self.cycles.clone(),
+                approximate_actions: actions,
+            })
+        } else {
+            Ok(actions)
+        }
     }
 
     fn add_dependency(&mut self, unblock_edge: UnblockEdge<'tcx>) {
@@ -221,48 +414,92 @@ impl<'tcx> UnblockGraph<'tcx> {
         borrows: &BorrowsState<'tcx>,
         repacker: PlaceRepacker<'_, 'tcx>,
     ) {
-        self.unblock_place_internal(place, borrows, repacker, UnblockHistory::new());
+        self.unblock_place_internal(
+            place,
+            Mutability::Mut,
+            borrows,
+            repacker,
+            UnblockHistory::new(),
+        );
+    }
+
+    /// Like [`Self::unblock_place`], but only makes `place` readable: the
+    /// shared reborrows of `place` (or of the places it is expanded to) are
+    /// kept, since reading a place doesn't end its shared borrows.
+    pub fn unblock_place_for_read(
+        &mut self,
+        place: ReborrowBlockedPlace<'tcx>,
+        borrows: &BorrowsState<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) {
+        self.unblock_place_internal(
+            place,
+            Mutability::Not,
+            borrows,
+            repacker,
+            UnblockHistory::new(),
+        );
     }
 
-    fn report_error(&mut self) {
-        panic!("Error in unblock graph");
-        // self.error = true;
+    fn report_cycle(&mut self, cycle: BorrowCycle<'tcx>) {
+        self.error = true;
+        self.cycles.push(cycle);
     }
 
+    /// Returns whether `place` is fully unblocked, i.e. no shared reborrow of
+    /// it was kept because of a read `access`
     fn unblock_place_internal(
         &mut self,
         place: ReborrowBlockedPlace<'tcx>,
+        access: Mutability,
         borrows: &BorrowsState<'tcx>,
         repacker: PlaceRepacker<'_, 'tcx>,
         mut history: UnblockHistory<'tcx>,
-    ) {
-        if !history.record(UnblockHistoryAction::UnblockPlace(place)) {
-            self.report_error();
-            return;
+    ) -> bool {
+        let action = UnblockHistoryAction::UnblockPlace(place);
+        if !history.record(action.clone()) {
+            self.report_cycle(history.cycle_from(&action));
+            return true;
         }
+        let mut unblocked = true;
         for edge in borrows.edges_blocking(place) {
             match edge.kind() {
-                BorrowsEdgeKind::Reborrow(reborrow) => self.kill_reborrow_internal(
-                    Conditioned::new(reborrow.clone(), edge.conditions().clone()),
-                    borrows,
-                    repacker,
-                    history.clone(),
-                ),
+                BorrowsEdgeKind::Reborrow(reborrow) => {
+                    if access == Mutability::Not && reborrow.mutability == Mutability::Not {
+                        unblocked = false;
+                        continue;
+                    }
+                    self.kill_reborrow_internal(
+                        Conditioned::new(reborrow.clone(), edge.conditions().clone()),
+                        borrows,
+                        repacker,
+                        history.clone(),
+                    )
+                }
                 BorrowsEdgeKind::DerefExpansion(expansion) => {
-                    self.add_dependency(edge.clone());
+                    let mut expansion_unblocked = true;
                     for place in expansion.expansion(repacker) {
-                        self.unblock_place_internal(
+                        expansion_unblocked &= self.unblock_place_internal(
                             place.into(),
+                            access,
                             borrows,
                             repacker,
                             history.clone(),
                         );
                     }
+                    // The expansion can't be collapsed while some of its
+                    // places are still borrowed
+                    if expansion_unblocked {
+                        self.add_dependency(edge.clone());
+                    } else {
+                        unblocked = false;
+                    }
                 }
                 BorrowsEdgeKind::RegionAbstraction(abstraction) => {
                     for place in abstraction.abstraction_type.blocker_places() {
                         self.unblock_place_internal(
                             place.into(),
+                            Mutability::Mut,
                             borrows,
                             repacker,
                             history.clone(),
@@ -275,6 +512,7 @@ impl<'tcx> UnblockGraph<'tcx> {
                 }
             }
         }
+        unblocked
     }
 
     pub fn kill_reborrows_reserved_at(
@@ -296,12 +534,14 @@ impl<'tcx> UnblockGraph<'tcx> {
         repacker: PlaceRepacker<'_, 'tcx>,
         mut history: UnblockHistory<'tcx>,
     ) {
-        if !history.record(UnblockHistoryAction::KillReborrow(reborrow.value.clone())) {
-            self.error = true;
+        let action = UnblockHistoryAction::KillReborrow(reborrow.value.clone());
+        if !history.record(action.clone()) {
+            self.report_cycle(history.cycle_from(&action));
             return;
         }
         self.unblock_place_internal(
             reborrow.value.assigned_place.into(),
+            Mutability::Mut,
             borrows,
             repacker,
             history,
@@ -335,3 +575,305 @@ impl<'tcx> UnblockGraph<'tcx> {
         }
     }
 }
+
+fn location_to_json(location: Location) -> serde_json::Value {
+    json!([location.block.as_usize(), location.statement_index])
+}
+
+fn location_from_json(json: &serde_json::Value) -> Option<Location> {
+    let [block, statement_index] = json.as_array()?.as_slice() else {
+        return None;
+    };
+    Some(Location {
+        block: BasicBlock::from_usize(block.as_u64()? as usize),
+        statement_index: statement_index.as_u64()? as usize,
+    })
+}
+
+fn maybe_old_place_to_json(place: MaybeOldPlace<'_>) -> Option<serde_json::Value> {
+    let at = match place {
+        MaybeOldPlace::Current { .. } => serde_json::Value::Null,
+        MaybeOldPlace::OldPlace(snapshot) => match snapshot.at {
+            SnapshotLocation::Location(location) => {
+                json!({ "location": location_to_json(location) })
+            }
+            SnapshotLocation::BeforeStatement(location) => {
+                json!({ "before_statement": location_to_json(location) })
+            }
+            SnapshotLocation::Join(block) => json!({ "join": block.as_usize() }),
+        },
+    };
+    Some(json!({
+        "place": place_to_json(place.place())?,
+        "at": at,
+    }))
+}
+
+fn maybe_old_place_from_json<'tcx>(
+    json: &serde_json::Value,
+    repacker: PlaceRepacker<'_, 'tcx>,
+) -> Option<MaybeOldPlace<'tcx>> {
+    let place = place_from_json(&json["place"], repacker)?;
+    let at = &json["at"];
+    let at = if at.is_null() {
+        None
+    } else if let Some(block) = at["join"].as_u64() {
+        Some(SnapshotLocation::Join(BasicBlock::from_usize(
+            block as usize,
+        )))
+    } else if !at["before_statement"].is_null() {
+        Some(SnapshotLocation::BeforeStatement(location_from_json(
+            &at["before_statement"],
+        )?))
+    } else {
+        Some(SnapshotLocation::Location(location_from_json(
+            &at["location"],
+        )?))
+    };
+    Some(MaybeOldPlace::new(place, at))
+}
+
+fn edge_to_json<'tcx>(
+    edge: &UnblockEdge<'tcx>,
+    repacker: PlaceRepacker<'_, 'tcx>,
+) -> Option<serde_json::Value> {
+    let conditions = match edge.conditions() {
+        PathConditions::AtBlock(block) => json!({ "at_block": block.as_usize() }),
+        PathConditions::Paths(paths) => json!({
+            "paths": paths
+                .edges()
+                .map(|pc| [pc.from.as_usize(), pc.to.as_usize()])
+                .collect::<Vec<_>>()
+        }),
+    };
+    let kind = match edge.kind() {
+        UnblockEdgeType::Reborrow(reborrow) => {
+            let blocked_place = match reborrow.blocked_place {
+                ReborrowBlockedPlace::Local(place) => {
+                    json!({ "local": maybe_old_place_to_json(place)? })
+                }
+                ReborrowBlockedPlace::Remote(local) => json!({ "remote": local.as_usize() }),
+                ReborrowBlockedPlace::Constant(location) => {
+                    json!({ "constant": location_to_json(location) })
+                }
+            };
+            let region = match reborrow.kind {
+                ReborrowKind::Ref(region) => json!(get_vid(&region)?.as_usize()),
+                ReborrowKind::RawPtr => serde_json::Value::Null,
+            };
+            json!({ "reborrow": {
+                "blocked_place": blocked_place,
+                "assigned_place": maybe_old_place_to_json(reborrow.assigned_place)?,
+                "is_mut": reborrow.mutability == Mutability::Mut,
+                "region": region,
+                "reserve_location": location_to_json(reborrow.reserve_location()),
+                "index": reborrow.id().index,
+            }})
+        }
+        UnblockEdgeType::DerefExpansion(expansion) => {
+            // The expansion is saved as the places it expands to, as given to
+            // the constructors of `DerefExpansion`
+            let base = expansion.base();
+            let places = expansion
+                .expansion(repacker)
+                .into_iter()
+                .map(|place| place_to_json(place.place()))
+                .collect::<Option<Vec<_>>>()?;
+            json!({ "deref_expansion": {
+                "base": maybe_old_place_to_json(base)?,
+                "expansion": places,
+                "location": expansion
+                    .borrow_expansion()
+                    .map(|expansion| location_to_json(expansion.location)),
+            }})
+        }
+        UnblockEdgeType::RegionAbstraction(_) | UnblockEdgeType::RegionProjectionMember(_) => {
+            return None
+        }
+    };
+    Some(json!({
+        "conditions": conditions,
+        "kind": kind,
+    }))
+}
+
+fn edge_from_json<'tcx>(
+    json: &serde_json::Value,
+    repacker: PlaceRepacker<'_, 'tcx>,
+) -> Option<UnblockEdge<'tcx>> {
+    let conditions = &json["conditions"];
+    let conditions = if let Some(block) = conditions["at_block"].as_u64() {
+        PathConditions::new(BasicBlock::from_usize(block as usize))
+    } else {
+        let paths = conditions["paths"]
+            .as_array()?
+            .iter()
+            .map(|pc| {
+                let [from, to] = pc.as_array()?.as_slice() else {
+                    return None;
+                };
+                Some(PathCondition::new(
+                    BasicBlock::from_usize(from.as_u64()? as usize),
+                    BasicBlock::from_usize(to.as_u64()? as usize),
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let mut conditions = PathConditions::new(paths.first()?.from);
+        for pc in paths {
+            conditions.insert(pc);
+        }
+        conditions
+    };
+    let kind = &json["kind"];
+    let kind = if !kind["reborrow"].is_null() {
+        let reborrow = &kind["reborrow"];
+        let blocked_place = &reborrow["blocked_place"];
+        let blocked_place = if let Some(local) = blocked_place["remote"].as_u64() {
+            ReborrowBlockedPlace::Remote(Local::from_usize(local as usize))
+        } else if !blocked_place["constant"].is_null() {
+            ReborrowBlockedPlace::Constant(location_from_json(&blocked_place["constant"])?)
+        } else {
+            ReborrowBlockedPlace::Local(maybe_old_place_from_json(
+                &blocked_place["local"],
+                repacker,
+            )?)
+        };
+        let kind = match reborrow["region"].as_u64() {
+            Some(vid) => ReborrowKind::Ref(ty::Region::new_var(
+                repacker.tcx(),
+                RegionVid::from_usize(vid as usize),
+            )),
+            None => ReborrowKind::RawPtr,
+        };
+        let mutability = if reborrow["is_mut"].as_bool()? {
+            Mutability::Mut
+        } else {
+            Mutability::Not
+        };
+        UnblockEdgeType::Reborrow(Reborrow::new(
+            blocked_place,
+            maybe_old_place_from_json(&reborrow["assigned_place"], repacker)?,
+            mutability,
+            kind,
+            location_from_json(&reborrow["reserve_location"])?,
+            reborrow["index"].as_u64()? as usize,
+        ))
+    } else {
+        let expansion = &kind["deref_expansion"];
+        let base = maybe_old_place_from_json(&expansion["base"], repacker)?;
+        let places = expansion["expansion"]
+            .as_array()?
+            .iter()
+            .map(|place| place_from_json(place, repacker))
+            .collect::<Option<Vec<_>>>()?;
+        let is_owned = base.place().is_owned(repacker.body(), repacker.tcx());
+        if is_owned != expansion["location"].is_null() {
+            return None;
+        }
+        let expansion = if is_owned {
+            DerefExpansion::owned(base, places, repacker)
+        } else {
+            let location = location_from_json(&expansion["location"])?;
+            DerefExpansion::borrowed(base, places, location, repacker)
+        };
+        UnblockEdgeType::DerefExpansion(expansion)
+    };
+    Some(UnblockEdge::new(kind, conditions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        run_combined_pcs,
+        rustc_interface::middle::mir,
+        test_utils::{find_statement, local_named, run_on_fn},
+        utils::Place,
+        PcsOptions,
+    };
+
+    #[test]
+    fn reborrows_blocking_each_other_are_reported_as_cycle() {
+        run_on_fn("22_two_reference_args.rs", "pick", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = Place::from(local_named(&body.body, "x")).project_deref(rp);
+            let y = Place::from(local_named(&body.body, "y")).project_deref(rp);
+            let mut state = BorrowsState::new();
+            for (index, (blocked, assigned)) in [(x, y), (y, x)].into_iter().enumerate() {
+                state.add_reborrow(
+                    blocked.into(),
+                    assigned,
+                    Mutability::Mut,
+                    ReborrowKind::Ref(tcx.lifetimes.re_erased),
+                    Location::START,
+                    index,
+                );
+            }
+            let ug = UnblockGraph::for_place(x.into(), &state, rp);
+            assert_eq!(ug.cycles().len(), 1);
+            let err = ug.actions(rp).unwrap_err();
+            let places = &err.cycles[0].places;
+            assert!(places.contains(&x.into()) && places.contains(&y.into()));
+            assert_eq!(err.cycles[0].reborrows.len(), 2);
+        });
+    }
+
+    /// The graph unblocking `x` and `r` after `*r = 2` in
+    /// `tests/10_terminate_reborrow.rs` kills the reborrow of `x` and collapses
+    /// the expansion of `r`: loaded back from its JSON, it gives the same
+    /// actions, with the same effect on the state
+    #[test]
+    fn saved_graph_is_replayed_to_the_same_state() {
+        run_on_fn("10_terminate_reborrow.rs", "main", |tcx, body| {
+            let x = local_named(&body.body, "x");
+            let r = local_named(&body.body, "r");
+            let location = find_statement(&body.body, |statement| {
+                statement
+                    .kind
+                    .as_assign()
+                    .is_some_and(|(target, _)| target.local == r && !target.projection.is_empty())
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let state = output
+                .get_all_for_bb(location.block)
+                .statements
+                .remove(location.statement_index)
+                .extra
+                .after;
+            let mut graph = UnblockGraph::new();
+            for local in [x, r] {
+                graph.unblock_place(
+                    MaybeOldPlace::from(mir::Place::from(local)).into(),
+                    &state,
+                    rp,
+                );
+            }
+            assert!(graph
+                .edges()
+                .any(|edge| matches!(edge.kind(), UnblockEdgeType::Reborrow(_))));
+            assert!(graph
+                .edges()
+                .any(|edge| matches!(edge.kind(), UnblockEdgeType::DerefExpansion(_))));
+
+            let saved = graph.to_json(rp).to_string();
+            let loaded =
+                UnblockGraph::from_json(&serde_json::from_str(&saved).unwrap(), rp).unwrap();
+            assert_eq!(
+                loaded.edges().collect::<HashSet<_>>(),
+                graph.edges().collect::<HashSet<_>>()
+            );
+            let actions = graph.simulate(rp).unwrap();
+            let replayed = loaded.simulate(rp).unwrap();
+            assert_eq!(actions.len(), replayed.len());
+            assert!(replayed.iter().all(|action| actions.contains(action)));
+
+            let mut expected = state.clone();
+            assert!(expected.apply_unblock_graph(graph, rp, location));
+            let mut result = state.clone();
+            assert!(result.apply_unblock_graph(loaded, rp, location));
+            assert!(result == expected);
+        });
+    }
+}
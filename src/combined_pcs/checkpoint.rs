@@ -0,0 +1,338 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use serde_json::json;
+
+use crate::{
+    free_pcs::{CapabilityKind, CapabilityLocal, CapabilitySummary},
+    rustc_interface::{
+        index::IndexVec,
+        middle::{
+            mir::{self, BasicBlock, Local, ProjectionElem},
+            ty::{self, TyCtxt},
+        },
+        target::abi::{FieldIdx, VariantIdx},
+    },
+    utils::{Place, PlaceRepacker},
+};
+
+/// The free PCS at the entry of each block, saved after a run of the analysis.
+///
+/// A later run on an edited version of the body can use the entry states of
+/// the unchanged blocks as the starting point of its fixpoint (see
+/// [`super::PcsEngine::set_checkpoint`]) rather than starting from bottom.
+///
+/// A checkpoint is saved with [`Checkpoint::to_json`] and loaded with
+/// [`Checkpoint::from_json`], which discards the entry states that may have
+/// been invalidated by the edit:
+/// - If the signature or the locals of the body changed, every entry state is
+///   discarded.
+/// - Otherwise, the entry state of a block is discarded if the block changed,
+///   if it was a successor of a block that changed (it may have lost a
+///   predecessor, whose exit state is still joined into the saved state), or
+///   if it is reachable from such a block (the block may now leave a
+///   different state to its successors).
+///
+/// Only the free PCS is saved, the borrows state of each block is recomputed.
+pub struct Checkpoint<'tcx> {
+    entry_states: IndexVec<BasicBlock, Option<CapabilitySummary<'tcx>>>,
+}
+
+impl<'tcx> Checkpoint<'tcx> {
+    pub fn new(entry_states: IndexVec<BasicBlock, Option<CapabilitySummary<'tcx>>>) -> Self {
+        Self { entry_states }
+    }
+
+    /// The saved entry state of `block`, if any
+    pub fn entry_state(&self, block: BasicBlock) -> Option<&CapabilitySummary<'tcx>> {
+        self.entry_states.get(block)?.as_ref()
+    }
+
+    /// The number of blocks with a saved entry state
+    pub fn num_entry_states(&self) -> usize {
+        self.entry_states.iter().flatten().count()
+    }
+
+    pub fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
+        let body = repacker.body();
+        let blocks = body
+            .basic_blocks
+            .iter_enumerated()
+            .map(|(block, data)| {
+                let state = self.entry_state(block).and_then(|state| {
+                    state
+                        .iter()
+                        .map(|local| match local {
+                            CapabilityLocal::Unallocated => Some(serde_json::Value::Null),
+                            CapabilityLocal::Allocated(projections) => projections
+                                .iter()
+                                .map(|(place, kind)| {
+                                    Some(json!([place_to_json(*place)?, format!("{:?}", kind)]))
+                                })
+                                .collect::<Option<Vec<_>>>()
+                                .map(serde_json::Value::Array),
+                        })
+                        .collect::<Option<Vec<_>>>()
+                });
+                json!({
+                    "fingerprint": fingerprint(data),
+                    "successors": data
+                        .terminator()
+                        .successors()
+                        .map(|successor| successor.as_usize())
+                        .collect::<Vec<_>>(),
+                    "state": state,
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "signature": signature_fingerprint(body),
+            "blocks": blocks,
+        })
+    }
+
+    /// Loads a checkpoint saved by [`Checkpoint::to_json`], keeping only the
+    /// entry states that remain valid for the body of `repacker`.
+    pub fn from_json(json: &serde_json::Value, repacker: PlaceRepacker<'_, 'tcx>) -> Self {
+        let body = repacker.body();
+        let mut entry_states = IndexVec::from_elem_n(None, body.basic_blocks.len());
+        if json["signature"].as_u64() != Some(signature_fingerprint(body)) {
+            return Self { entry_states };
+        }
+        let saved = json["blocks"].as_array().map_or(&[][..], |b| b.as_slice());
+        let num_blocks = body.basic_blocks.len();
+        let changed = (0..saved.len().max(num_blocks)).filter(|&block| {
+            let data = body.basic_blocks.get(BasicBlock::from_usize(block));
+            saved
+                .get(block)
+                .and_then(|saved| saved["fingerprint"].as_u64())
+                != data.map(fingerprint)
+        });
+        // A changed block may have had successors in the saved body that it
+        // no longer has: their saved state joins its old exit state
+        let old_successors = changed.clone().flat_map(|block| {
+            saved
+                .get(block)
+                .and_then(|saved| saved["successors"].as_array())
+                .map_or(&[][..], |successors| successors.as_slice())
+                .iter()
+                .filter_map(|successor| successor.as_u64())
+                .map(|successor| successor as usize)
+        });
+        let mut invalidated = IndexVec::from_elem_n(false, num_blocks);
+        let mut worklist = changed
+            .chain(old_successors)
+            .filter(|&block| block < num_blocks)
+            .map(BasicBlock::from_usize)
+            .collect::<Vec<_>>();
+        while let Some(block) = worklist.pop() {
+            if !invalidated[block] {
+                invalidated[block] = true;
+                worklist.extend(body[block].terminator().successors());
+            }
+        }
+        for (block, state) in entry_states.iter_enumerated_mut() {
+            if !invalidated[block] {
+                *state = summary_from_json(&saved[block.as_usize()]["state"], repacker);
+            }
+        }
+        Self { entry_states }
+    }
+}
+
+fn hash_debug(value: &impl std::fmt::Debug) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", value).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies the contents of a block, ignoring spans
+fn fingerprint(data: &mir::BasicBlockData<'_>) -> u64 {
+    let statements = data.statements.iter().map(|s| &s.kind).collect::<Vec<_>>();
+    hash_debug(&(statements, &data.terminator().kind, data.is_cleanup))
+}
+
+/// Identifies the signature and the locals of a body
+fn signature_fingerprint(body: &mir::Body<'_>) -> u64 {
+    let locals = body
+        .local_decls
+        .iter()
+        .map(|decl| (decl.mutability, decl.ty))
+        .collect::<Vec<_>>();
+    hash_debug(&(body.arg_count, locals))
+}
+
+pub(crate) fn place_to_json(place: Place<'_>) -> Option<serde_json::Value> {
+    let projection = place
+        .projection
+        .iter()
+        .map(|elem| match *elem {
+            ProjectionElem::Deref => Some(json!("deref")),
+            ProjectionElem::Field(field, _) => Some(json!({ "field": field.as_usize() })),
+            ProjectionElem::Downcast(_, variant) => Some(json!({ "downcast": variant.as_usize() })),
+            ProjectionElem::Index(local) => Some(json!({ "index": local.as_usize() })),
+            ProjectionElem::ConstantIndex {
+                offset,
+                min_length,
+                from_end,
+            } => Some(json!({ "constant_index": [offset, min_length, from_end] })),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(json!({
+        "local": place.local.as_usize(),
+        "projection": projection,
+    }))
+}
+
+pub(crate) fn place_from_json<'tcx>(
+    json: &serde_json::Value,
+    repacker: PlaceRepacker<'_, 'tcx>,
+) -> Option<Place<'tcx>> {
+    let tcx: TyCtxt<'tcx> = repacker.tcx();
+    let local = json["local"].as_u64()? as usize;
+    if local >= repacker.local_count() {
+        return None;
+    }
+    let mut place: mir::Place<'tcx> = Local::from_usize(local).into();
+    for elem in json["projection"].as_array()? {
+        let place_ty = place.ty(repacker.body(), tcx);
+        let elem = if elem.as_str() == Some("deref") {
+            ProjectionElem::Deref
+        } else if let Some(field) = elem["field"].as_u64() {
+            let field = FieldIdx::from_usize(field as usize);
+            ProjectionElem::Field(field, place_ty.field_ty(tcx, field))
+        } else if let Some(variant) = elem["downcast"].as_u64() {
+            let variant = VariantIdx::from_usize(variant as usize);
+            let ty::TyKind::Adt(adt, _) = place_ty.ty.kind() else {
+                return None;
+            };
+            ProjectionElem::Downcast(Some(adt.variant(variant).name), variant)
+        } else if let Some(index) = elem["index"].as_u64() {
+            ProjectionElem::Index(Local::from_usize(index as usize))
+        } else if let Some([offset, min_length, from_end]) =
+            elem["constant_index"].as_array().map(|a| a.as_slice())
+        {
+            ProjectionElem::ConstantIndex {
+                offset: offset.as_u64()?,
+                min_length: min_length.as_u64()?,
+                from_end: from_end.as_bool()?,
+            }
+        } else {
+            return None;
+        };
+        place = tcx.mk_place_elem(place, elem);
+    }
+    Some(place.into())
+}
+
+fn summary_from_json<'tcx>(
+    json: &serde_json::Value,
+    repacker: PlaceRepacker<'_, 'tcx>,
+) -> Option<CapabilitySummary<'tcx>> {
+    let locals = json.as_array()?;
+    if locals.len() != repacker.local_count() {
+        return None;
+    }
+    let mut summary = CapabilitySummary::default(repacker.local_count());
+    for (local, projections) in summary.iter_mut().zip(locals) {
+        if projections.is_null() {
+            *local = CapabilityLocal::Unallocated;
+            continue;
+        }
+        let CapabilityLocal::Allocated(capabilities) = local else {
+            unreachable!()
+        };
+        for entry in projections.as_array()? {
+            let place = place_from_json(&entry[0], repacker)?;
+            let kind = match entry[1].as_str()? {
+                "W" => CapabilityKind::Write,
+                "E" => CapabilityKind::Exclusive,
+                "e" => CapabilityKind::ShallowExclusive,
+                "A" => CapabilityKind::Aliased,
+                "R" => CapabilityKind::Read,
+                _ => return None,
+            };
+            capabilities.insert(place, kind);
+        }
+    }
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_combined_pcs, test_utils::run_on_fn, PcsOptions};
+
+    #[test]
+    fn checkpoint_round_trips_and_resumes_to_same_entry_states() {
+        run_on_fn("02_list_zero.rs", "all_zero", |tcx, body| {
+            let output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let saved = output.checkpoint();
+            let json = saved.to_json(output.repacker());
+            let loaded = Checkpoint::from_json(&json, output.repacker());
+            assert_eq!(loaded.num_entry_states(), body.body.basic_blocks.len());
+            for block in body.body.basic_blocks.indices() {
+                assert!(loaded.entry_state(block) == saved.entry_state(block));
+            }
+            let resumed = run_combined_pcs(
+                body,
+                tcx,
+                None,
+                false,
+                None,
+                Some(loaded),
+                PcsOptions::default(),
+            );
+            for block in body.body.basic_blocks.indices() {
+                assert!(resumed.checkpoint().entry_state(block) == saved.entry_state(block));
+            }
+        });
+    }
+
+    #[test]
+    fn predecessor_removed_by_edit_invalidates_entry_state() {
+        let mut saved = None;
+        run_on_fn("49_edited_body.rs", "before_edit", |tcx, body| {
+            let output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            saved = Some(output.checkpoint().to_json(output.repacker()));
+        });
+        let saved = saved.unwrap();
+        run_on_fn("49_edited_body.rs", "after_edit", |tcx, body| {
+            let fresh = run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let loaded = Checkpoint::from_json(&saved, fresh.repacker());
+            // Only the changed block has a different fingerprint, but the
+            // block it used to go to has lost a predecessor
+            let join = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .find(|(_, data)| {
+                    data.statements.len() == 1 && data.terminator().successors().count() == 0
+                })
+                .map(|(block, _)| block)
+                .unwrap();
+            assert!(loaded.entry_state(join).is_none());
+            let resumed = run_combined_pcs(
+                body,
+                tcx,
+                None,
+                false,
+                None,
+                Some(loaded),
+                PcsOptions::default(),
+            );
+            for block in body.body.basic_blocks.indices() {
+                assert!(
+                    resumed.checkpoint().entry_state(block)
+                        == fresh.checkpoint().entry_state(block),
+                    "{block:?}"
+                );
+            }
+        });
+    }
+}
@@ -21,14 +21,16 @@ use rustc_interface::{
 
 use crate::{
     borrows::{
+        borrows_state::BorrowsState,
         borrows_visitor::DebugCtx,
         domain::{MaybeOldPlace, ReborrowBlockedPlace},
         engine::BorrowsDomain,
         unblock_graph::UnblockGraph,
     },
-    free_pcs::{CapabilityLocal, FreePlaceCapabilitySummary, HasPrepare},
+    free_pcs::{CapabilityLocal, CapabilitySummary, FreePlaceCapabilitySummary, HasPrepare},
+    r#loop::LoopAnalysis,
     rustc_interface,
-    utils::SnapshotLocation,
+    utils::{PlaceRepacker, SnapshotLocation},
     visualization::generate_dot_graph,
     RECORD_PCS,
 };
@@ -57,6 +59,21 @@ impl DataflowStmtPhase {
             _ => format!("{:?}", self),
         }
     }
+
+    pub fn transition_label(&self, statement_index: usize) -> String {
+        match self {
+            DataflowStmtPhase::Initial => format!("Enter statement {}", statement_index),
+            DataflowStmtPhase::Join(block) => format!("Join with {:?}", block),
+            DataflowStmtPhase::BeforeStart => {
+                format!("Before start of statement {}", statement_index)
+            }
+            DataflowStmtPhase::BeforeAfter => {
+                format!("Before effect of statement {}", statement_index)
+            }
+            DataflowStmtPhase::Start => format!("Start of statement {}", statement_index),
+            DataflowStmtPhase::After => format!("After statement {}", statement_index),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -118,6 +135,18 @@ impl DotGraphs {
         self.0[statement_index].len()
     }
 
+    /// The number of times a predecessor state was joined into the entry state
+    /// of the block. Iterations are registered for the first statement on each
+    /// join, and (when recording) before its `Initial` phase.
+    pub fn num_joins(&self) -> usize {
+        self.0.first().map_or(0, |iterations| {
+            iterations
+                .iter()
+                .filter(|phases| !phases.contains_key(&DataflowStmtPhase::Initial))
+                .count()
+        })
+    }
+
     pub fn insert(
         &mut self,
         statement_index: usize,
@@ -128,6 +157,37 @@ impl DotGraphs {
         top.insert(phase, filename).is_none()
     }
 
+    /// The recorded graphs as a flat sequence of steps, ordered by statement,
+    /// then iteration (numbered from 1, as in the filenames), then phase, each
+    /// labelled with the transition that produced it. Intended for frontends
+    /// that animate the computation.
+    pub fn timeline_json(&self) -> serde_json::Value {
+        let steps = self
+            .0
+            .iter()
+            .enumerate()
+            .flat_map(|(statement_index, iterations)| {
+                iterations
+                    .iter()
+                    .enumerate()
+                    .flat_map(move |(iteration, map)| {
+                        map.iter()
+                            .sorted_by_key(|x| x.0)
+                            .map(move |(phase, filename)| {
+                                serde_json::json!({
+                                    "statement_index": statement_index,
+                                    "iteration": iteration + 1,
+                                    "phase": format!("{:?}", phase),
+                                    "label": phase.transition_label(statement_index),
+                                    "filename": filename,
+                                })
+                            })
+                    })
+            })
+            .collect::<Vec<_>>();
+        serde_json::Value::Array(steps)
+    }
+
     pub fn write_json_file(&self, filename: &str) {
         let iterations_json = self
             .0
@@ -240,12 +300,13 @@ impl<'a, 'tcx> PlaceCapabilitySummary<'a, 'tcx> {
 
     pub fn new(
         cgx: Rc<PcsContext<'a, 'tcx>>,
+        loops: Rc<LoopAnalysis>,
         block: Option<BasicBlock>,
         dot_output_dir: Option<String>,
         dot_graphs: Option<Rc<RefCell<DotGraphs>>>,
     ) -> Self {
         let fpcs = FreePlaceCapabilitySummary::new(cgx.rp);
-        let borrows = BorrowsDomain::new(cgx.rp, block);
+        let borrows = BorrowsDomain::new(cgx.rp, loops, block);
         Self {
             cgx,
             block,
@@ -276,37 +337,91 @@ impl JoinSemiLattice for PlaceCapabilitySummary<'_, '_> {
         if self.block().as_usize() == 0 {
             panic!("{:?}", other.block());
         }
+        let fpcs_old = cfg!(debug_assertions).then(|| self.fpcs.clone());
         let fpcs = self.fpcs.join(&other.fpcs);
-        let borrows = self.borrows.join(&other.borrows);
-        let mut g = UnblockGraph::new();
-        for root in self.borrows.after.roots(self.cgx.rp) {
-            if let ReborrowBlockedPlace::Local(MaybeOldPlace::Current { place: root }) = root {
-                match &self.fpcs.after[root.local] {
-                    CapabilityLocal::Unallocated => {
-                        g.unblock_place(root.into(), &self.borrows.after, self.cgx.rp);
-                    }
-                    CapabilityLocal::Allocated(projs) => {
-                        if !(*projs).contains_key(&root) {
-                            g.unblock_place(root.into(), &self.borrows.after, self.cgx.rp);
-                        }
-                    }
-                }
-            }
+        if let Some(fpcs_old) = fpcs_old {
+            assert!(
+                fpcs_old.leq(&self.fpcs) && other.fpcs.leq(&self.fpcs),
+                "Join of the free PCS at {:?} is not above its inputs",
+                self.block()
+            );
         }
-        let ub = self.borrows.after.apply_unblock_graph(
-            g,
+        // The edges of places that no longer hold a value are removed from
+        // both sides before their borrows are joined, rather than from the
+        // result: otherwise the result would depend on the order in which the
+        // predecessors are joined, and joining a predecessor whose state did
+        // not change could change the state of the block again
+        let location = mir::Location {
+            block: self.block(),
+            statement_index: 0,
+        };
+        let ub = if self.borrows.is_initialized() {
+            unblock_unallocated_roots(
+                &mut self.borrows.after,
+                &self.fpcs.after,
+                self.cgx.rp,
+                location,
+            )
+        } else {
+            false
+        };
+        let mut other_borrows = other.borrows.clone();
+        unblock_unallocated_roots(
+            &mut other_borrows.after,
+            &self.fpcs.after,
             self.cgx.rp,
-            mir::Location {
-                block: self.block(),
-                statement_index: 0,
-            },
+            location,
         );
+        let borrows = self.borrows.join(&other_borrows);
         self.dot_graphs().borrow_mut().register_new_iteration(0);
         self.generate_dot_graph(DataflowStmtPhase::Join(other.block()), 0);
         fpcs || borrows || ub
     }
 }
 
+/// Removes the edges of the roots of `borrows` that are current places which
+/// no longer hold a value in `fpcs`, i.e. are unallocated, not in `fpcs` or
+/// only writable. Returns whether `borrows` changed.
+fn unblock_unallocated_roots<'tcx>(
+    borrows: &mut BorrowsState<'tcx>,
+    fpcs: &CapabilitySummary<'tcx>,
+    rp: PlaceRepacker<'_, 'tcx>,
+    location: Location,
+) -> bool {
+    let mut g = UnblockGraph::new();
+    let roots = borrows
+        .roots(rp)
+        .into_iter()
+        .sorted_by_cached_key(|root| format!("{:?}", root));
+    for root in roots {
+        if let ReborrowBlockedPlace::Local(MaybeOldPlace::Current { place: root }) = root {
+            match &fpcs[root.local] {
+                CapabilityLocal::Unallocated => {
+                    g.unblock_place(root.into(), borrows, rp);
+                }
+                CapabilityLocal::Allocated(projs) => {
+                    // A place with only write capability holds no value, so
+                    // nothing can still be borrowed through it
+                    if (*projs).get(&root).is_some_and(|cap| !cap.is_write()) {
+                        continue;
+                    }
+                    // A place that is only borrowed by shared reborrows
+                    // remains readable, so they are kept
+                    let only_shared = borrows
+                        .edges_blocking(root.into())
+                        .all(|edge| edge.is_shared_borrow());
+                    if only_shared {
+                        g.unblock_place_for_read(root.into(), borrows, rp);
+                    } else {
+                        g.unblock_place(root.into(), borrows, rp);
+                    }
+                }
+            }
+        }
+    }
+    borrows.apply_unblock_graph(g, rp, location)
+}
+
 impl<'a, 'tcx> DebugWithContext<PcsEngine<'a, 'tcx>> for PlaceCapabilitySummary<'a, 'tcx> {
     fn fmt_diff_with(
         &self,
@@ -314,6 +429,7 @@ impl<'a, 'tcx> DebugWithContext<PcsEngine<'a, 'tcx>> for PlaceCapabilitySummary<
         ctxt: &PcsEngine<'a, 'tcx>,
         f: &mut Formatter<'_>,
     ) -> Result {
-        self.fpcs.fmt_diff_with(&old.fpcs, &ctxt.fpcs, f)
+        self.fpcs.fmt_diff_with(&old.fpcs, &ctxt.fpcs, f)?;
+        self.borrows.fmt_diff_with(&old.borrows, &ctxt.borrows, f)
     }
 }
@@ -8,6 +8,7 @@ use std::{
     cell::{Cell, RefCell},
     fs::create_dir_all,
     rc::Rc,
+    time::Instant,
 };
 
 use itertools::Itertools;
@@ -30,6 +31,7 @@ use rustc_interface::{
 
 use crate::{
     borrows::{
+        borrows_state::{AgingPolicy, SharedReborrowPolicy},
         domain::{AbstractionType, MaybeOldPlace, ReborrowBlockedPlace},
         engine::BorrowsEngine,
     },
@@ -37,9 +39,12 @@ use crate::{
     rustc_interface,
     utils::PlaceRepacker,
     visualization::generate_dot_graph,
+    RECORD_PCS,
 };
 
-use super::{domain::PlaceCapabilitySummary, DataflowStmtPhase, DotGraphs};
+use super::{
+    domain::PlaceCapabilitySummary, Checkpoint, DataflowStmtPhase, DotGraphs, PcsStats, SubAnalysis,
+};
 
 #[derive(Clone)]
 
@@ -111,6 +116,11 @@ pub struct PcsEngine<'a, 'tcx> {
     debug_output_dir: Option<String>,
     dot_graphs: IndexVec<BasicBlock, Rc<RefCell<DotGraphs>>>,
     curr_block: Cell<BasicBlock>,
+    initial_state: Option<PlaceCapabilitySummary<'a, 'tcx>>,
+    checkpoint: Option<Checkpoint<'tcx>>,
+    stats: Option<PcsStats>,
+    record_capability_changes: bool,
+    fixpoint_reached: bool,
 }
 impl<'a, 'tcx> PcsEngine<'a, 'tcx> {
     fn initialize(&self, state: &mut PlaceCapabilitySummary<'a, 'tcx>, block: BasicBlock) {
@@ -143,7 +153,7 @@ impl<'a, 'tcx> PcsEngine<'a, 'tcx> {
             cgx.mir.input_facts.as_ref().unwrap(),
             cgx.mir.borrow_set.clone(),
             cgx.mir.region_inference_context.clone(),
-            cgx.mir.output_facts.as_ref().unwrap(),
+            cgx.mir.output_facts.as_deref(),
         );
         Self {
             cgx,
@@ -152,6 +162,118 @@ impl<'a, 'tcx> PcsEngine<'a, 'tcx> {
             borrows,
             debug_output_dir,
             curr_block: Cell::new(START_BLOCK),
+            initial_state: None,
+            checkpoint: None,
+            stats: None,
+            record_capability_changes: false,
+            fixpoint_reached: false,
+        }
+    }
+
+    /// Sets the policy used by the borrow states of this analysis to decide
+    /// which places become old when a place is moved out of.
+    pub fn set_aging_policy(&mut self, aging_policy: AgingPolicy) {
+        self.borrows.aging_policy = aging_policy;
+    }
+
+    /// Sets the policy used when minimizing the borrow states to decide which
+    /// shared reborrows may be removed.
+    pub fn set_shared_reborrow_policy(&mut self, policy: SharedReborrowPolicy) {
+        self.borrows.shared_reborrow_policy = policy;
+    }
+
+    /// See [`crate::PcsOptions::lenient`]
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.borrows.lenient = lenient;
+    }
+
+    /// Sets the state at the start of the body, replacing the default one in
+    /// which the arguments are initialized and their references borrow from
+    /// the caller. Only its `after` free PCS and borrows states are used.
+    ///
+    /// The analysis is only sound if this state is consistent with the
+    /// signature of the function: it must not grant capabilities to places
+    /// that a caller cannot have initialized, and every reference reachable
+    /// from the arguments must still be blocked by a borrow of the caller
+    /// (or of an edge implied by it), otherwise the places it refers to may
+    /// be considered accessible while still borrowed.
+    pub fn set_initial_state(&mut self, initial_state: PlaceCapabilitySummary<'a, 'tcx>) {
+        self.initial_state = Some(initial_state);
+    }
+
+    /// Starts the fixpoint computation of each block with a saved entry state
+    /// in `checkpoint` from that state rather than from bottom. The checkpoint
+    /// must have been loaded for the body being analyzed, see
+    /// [`Checkpoint::from_json`] for when saved states are discarded.
+    pub fn set_checkpoint(&mut self, checkpoint: Checkpoint<'tcx>) {
+        self.checkpoint = Some(checkpoint);
+    }
+
+    /// Collects statistics on the fixpoint computation, see [`PcsStats`].
+    /// Applying the effects again afterwards, e.g. when seeking with a results
+    /// cursor, is not counted.
+    pub fn enable_stats(&mut self) {
+        self.stats = Some(PcsStats::new(self.cgx.mir.body.basic_blocks.len()));
+    }
+
+    /// Records the location of the last capability change of each place,
+    /// see [`crate::free_pcs::FreePlaceCapabilitySummary::last_capability_change`]
+    pub fn enable_capability_changes(&mut self) {
+        self.record_capability_changes = true;
+    }
+
+    /// Marks the fixpoint computation as done: the effects applied from now
+    /// on (e.g. when seeking with a results cursor) merge the borrows edges
+    /// that only differ by their conditions (see
+    /// [`crate::borrows::borrows_graph::BorrowsGraph::canonicalize`]), as do
+    /// the entry states of the results.
+    pub fn set_fixpoint_reached(&mut self) {
+        self.fixpoint_reached = true;
+    }
+
+    /// Canonicalizes the borrows of `state` if the fixpoint is reached, see
+    /// [`Self::set_fixpoint_reached`]
+    fn canonicalize_after_fixpoint(&self, state: &mut PlaceCapabilitySummary<'a, 'tcx>) {
+        if self.fixpoint_reached {
+            state.borrows.canonicalize();
+        }
+    }
+
+    pub fn stats(&self) -> Option<&PcsStats> {
+        self.stats.as_ref()
+    }
+
+    /// The statistics to update, if they are collected and the fixpoint
+    /// computation is running
+    fn recording_stats(&mut self) -> Option<&mut PcsStats> {
+        if self.stats.is_none() || !*RECORD_PCS.lock().unwrap() {
+            return None;
+        }
+        self.stats.as_mut()
+    }
+
+    /// The start of an effect to time with [`Self::record_time`], if the
+    /// statistics are collected
+    fn start_timer(&self) -> Option<Instant> {
+        self.stats.is_some().then(Instant::now)
+    }
+
+    fn record_time(&mut self, block: BasicBlock, analysis: SubAnalysis, start: Option<Instant>) {
+        let Some(start) = start else {
+            return;
+        };
+        if let Some(stats) = self.recording_stats() {
+            stats.record_time(block, analysis, start);
+        }
+    }
+
+    fn start_visit(&mut self, state: &PlaceCapabilitySummary<'a, 'tcx>, location: Location) {
+        if location.statement_index != 0 {
+            return;
+        }
+        let edge_count = state.borrows.after.edge_count();
+        if let Some(stats) = self.recording_stats() {
+            stats.start_visit(location.block, edge_count);
         }
     }
 
@@ -178,18 +300,39 @@ impl<'a, 'tcx> AnalysisDomain<'tcx> for PcsEngine<'a, 'tcx> {
             // For results cursor, don't set block
             (None, None)
         };
-        PlaceCapabilitySummary::new(
+        let mut state = PlaceCapabilitySummary::new(
             self.cgx.clone(),
+            self.borrows.loops.clone(),
             block,
             self.debug_output_dir.clone(),
             dot_graphs,
-        )
+        );
+        state.borrows.set_aging_policy(self.borrows.aging_policy);
+        state.borrows.set_lenient(self.borrows.lenient);
+        if self.record_capability_changes {
+            state.fpcs.enable_capability_changes();
+        }
+        if let Some(entry_state) = block.and_then(|block| {
+            self.checkpoint
+                .as_ref()
+                .and_then(|checkpoint| checkpoint.entry_state(block))
+        }) {
+            state.fpcs.after = entry_state.clone();
+        }
+        state
     }
 
     fn initialize_start_block(&self, _body: &Body<'tcx>, state: &mut Self::Domain) {
         self.curr_block.set(START_BLOCK);
-        state.fpcs.initialize_as_start_block();
-        state.borrows.initialize_as_start_block();
+        if let Some(initial_state) = &self.initial_state {
+            state.fpcs.after = initial_state.fpcs.after.clone();
+            state.borrows.after = initial_state.borrows.after.clone();
+            state.borrows.set_aging_policy(self.borrows.aging_policy);
+            state.borrows.set_lenient(self.borrows.lenient);
+        } else {
+            state.fpcs.initialize_as_start_block();
+            state.borrows.initialize_as_start_block();
+        }
     }
 }
 
@@ -216,6 +359,9 @@ pub enum UnblockAction<'tcx> {
     TerminateAbstraction(Location, AbstractionType<'tcx>),
     TerminateReborrow {
         reserve_location: Location,
+        /// Distinguishes the reborrows reserved at `reserve_location`, see
+        /// [`crate::borrows::domain::ReborrowId`]
+        index: usize,
         blocked_place: ReborrowBlockedPlace<'tcx>,
         assigned_place: MaybeOldPlace<'tcx>,
         is_mut: bool,
@@ -231,9 +377,17 @@ impl<'a, 'tcx> Analysis<'tcx> for PcsEngine<'a, 'tcx> {
         location: Location,
     ) {
         self.initialize(state, location.block);
+        if location.statement_index == 0 {
+            // The entry state of the block
+            self.canonicalize_after_fixpoint(state);
+        }
+        self.start_visit(state, location);
         self.generate_dot_graph(state, DataflowStmtPhase::Initial, location.statement_index);
+        let start = self.start_timer();
         self.fpcs
             .apply_before_statement_effect(&mut state.fpcs, statement, location);
+        self.record_time(location.block, SubAnalysis::Fpcs, start);
+        let start = self.start_timer();
         state.borrows.after.ensure_deref_expansions_to_fpcs(
             self.cgx.rp.tcx(),
             self.cgx.rp.body(),
@@ -242,6 +396,8 @@ impl<'a, 'tcx> Analysis<'tcx> for PcsEngine<'a, 'tcx> {
         );
         self.borrows
             .apply_before_statement_effect(&mut state.borrows, statement, location);
+        self.record_time(location.block, SubAnalysis::Borrows, start);
+        self.canonicalize_after_fixpoint(state);
         self.generate_dot_graph(
             state,
             DataflowStmtPhase::BeforeStart,
@@ -259,8 +415,11 @@ impl<'a, 'tcx> Analysis<'tcx> for PcsEngine<'a, 'tcx> {
         statement: &Statement<'tcx>,
         location: Location,
     ) {
+        let start = self.start_timer();
         self.fpcs
             .apply_statement_effect(&mut state.fpcs, statement, location);
+        self.record_time(location.block, SubAnalysis::Fpcs, start);
+        let start = self.start_timer();
         state.borrows.after.ensure_deref_expansions_to_fpcs(
             self.cgx.rp.tcx(),
             self.cgx.rp.body(),
@@ -269,6 +428,8 @@ impl<'a, 'tcx> Analysis<'tcx> for PcsEngine<'a, 'tcx> {
         );
         self.borrows
             .apply_statement_effect(&mut state.borrows, statement, location);
+        self.record_time(location.block, SubAnalysis::Borrows, start);
+        self.canonicalize_after_fixpoint(state);
         self.generate_dot_graph(state, DataflowStmtPhase::Start, location.statement_index);
         self.generate_dot_graph(state, DataflowStmtPhase::After, location.statement_index);
     }
@@ -279,11 +440,21 @@ impl<'a, 'tcx> Analysis<'tcx> for PcsEngine<'a, 'tcx> {
         location: Location,
     ) {
         self.initialize(state, location.block);
+        if location.statement_index == 0 {
+            // The entry state of a block without statements
+            self.canonicalize_after_fixpoint(state);
+        }
+        self.start_visit(state, location);
         self.generate_dot_graph(state, DataflowStmtPhase::Initial, location.statement_index);
+        let start = self.start_timer();
         self.borrows
             .apply_before_terminator_effect(&mut state.borrows, terminator, location);
+        self.record_time(location.block, SubAnalysis::Borrows, start);
+        let start = self.start_timer();
         self.fpcs
             .apply_before_terminator_effect(&mut state.fpcs, terminator, location);
+        self.record_time(location.block, SubAnalysis::Fpcs, start);
+        self.canonicalize_after_fixpoint(state);
         self.generate_dot_graph(
             state,
             DataflowStmtPhase::BeforeStart,
@@ -301,10 +472,19 @@ impl<'a, 'tcx> Analysis<'tcx> for PcsEngine<'a, 'tcx> {
         terminator: &'mir Terminator<'tcx>,
         location: Location,
     ) -> TerminatorEdges<'mir, 'tcx> {
+        let start = self.start_timer();
         self.borrows
             .apply_terminator_effect(&mut state.borrows, terminator, location);
+        self.record_time(location.block, SubAnalysis::Borrows, start);
+        let start = self.start_timer();
         self.fpcs
             .apply_terminator_effect(&mut state.fpcs, terminator, location);
+        self.record_time(location.block, SubAnalysis::Fpcs, start);
+        self.canonicalize_after_fixpoint(state);
+        let edge_count = state.borrows.after.edge_count();
+        if let Some(stats) = self.recording_stats() {
+            stats.end_visit(location.block, edge_count);
+        }
         self.generate_dot_graph(state, DataflowStmtPhase::Start, location.statement_index);
         self.generate_dot_graph(state, DataflowStmtPhase::After, location.statement_index);
         terminator.edges()
@@ -312,10 +492,17 @@ impl<'a, 'tcx> Analysis<'tcx> for PcsEngine<'a, 'tcx> {
 
     fn apply_call_return_effect(
         &mut self,
-        _state: &mut Self::Domain,
-        _block: BasicBlock,
-        _return_places: CallReturnPlaces<'_, 'tcx>,
+        state: &mut Self::Domain,
+        block: BasicBlock,
+        return_places: CallReturnPlaces<'_, 'tcx>,
     ) {
-        // Nothing to do here
+        let start = self.start_timer();
+        self.borrows
+            .apply_call_return_effect(&mut state.borrows, block, return_places);
+        self.record_time(block, SubAnalysis::Borrows, start);
+        let start = self.start_timer();
+        self.fpcs
+            .apply_call_return_effect(&mut state.fpcs, block, return_places);
+        self.record_time(block, SubAnalysis::Fpcs, start);
     }
 }
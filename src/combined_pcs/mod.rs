@@ -7,7 +7,11 @@
 mod engine;
 mod domain;
 mod remove;
+mod checkpoint;
+mod stats;
 
 pub use engine::*;
 pub use domain::*;
 pub use remove::*;
+pub use checkpoint::*;
+pub use stats::*;
@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::rustc_interface::{index::IndexVec, middle::mir::BasicBlock};
+
+/// The sub-analyses of the combined PCS whose effects are timed separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubAnalysis {
+    Fpcs,
+    Borrows,
+}
+
+/// Statistics on the analysis of a single block, see [`PcsStats`]
+#[derive(Clone, Debug, Default)]
+pub struct BlockStats {
+    /// The number of times the effects of the block were applied
+    pub num_visits: usize,
+    /// The number of times the fixpoint computation joined into the block.
+    /// Only set in the statistics returned by [`crate::FpcsOutput::stats`].
+    pub num_joins: usize,
+    /// For each visit, the number of edges of the borrows graph at the end of
+    /// the block minus that at its start
+    pub borrows_edge_deltas: Vec<isize>,
+    /// The time spent applying the effects of the free PCS
+    pub fpcs_time: Duration,
+    /// The time spent applying the effects of the borrows analysis (including
+    /// the deref expansions required by the free PCS)
+    pub borrows_time: Duration,
+}
+
+/// Per-block statistics of the fixpoint computation of the combined PCS.
+///
+/// Collected when enabled with [`super::PcsEngine::enable_stats`]. Like the
+/// iterations recorded in [`super::DotGraphs`], but for performance
+/// investigations: it shows which blocks, and which of the two sub-analyses,
+/// dominate the time of the computation.
+#[derive(Clone, Debug)]
+pub struct PcsStats {
+    blocks: IndexVec<BasicBlock, BlockStats>,
+    /// The number of edges of the borrows graph at the start of the block
+    /// currently being visited
+    visit_start_edge_count: usize,
+}
+
+impl PcsStats {
+    pub fn new(num_blocks: usize) -> Self {
+        Self {
+            blocks: IndexVec::from_elem_n(BlockStats::default(), num_blocks),
+            visit_start_edge_count: 0,
+        }
+    }
+
+    pub fn block(&self, block: BasicBlock) -> &BlockStats {
+        &self.blocks[block]
+    }
+
+    pub fn block_mut(&mut self, block: BasicBlock) -> &mut BlockStats {
+        &mut self.blocks[block]
+    }
+
+    pub(crate) fn start_visit(&mut self, block: BasicBlock, edge_count: usize) {
+        self.blocks[block].num_visits += 1;
+        self.visit_start_edge_count = edge_count;
+    }
+
+    pub(crate) fn end_visit(&mut self, block: BasicBlock, edge_count: usize) {
+        self.blocks[block]
+            .borrows_edge_deltas
+            .push(edge_count as isize - self.visit_start_edge_count as isize);
+    }
+
+    pub(crate) fn record_time(&mut self, block: BasicBlock, analysis: SubAnalysis, start: Instant) {
+        let stats = &mut self.blocks[block];
+        match analysis {
+            SubAnalysis::Fpcs => stats.fpcs_time += start.elapsed(),
+            SubAnalysis::Borrows => stats.borrows_time += start.elapsed(),
+        }
+    }
+
+    pub fn total_time(&self, analysis: SubAnalysis) -> Duration {
+        self.blocks
+            .iter()
+            .map(|stats| match analysis {
+                SubAnalysis::Fpcs => stats.fpcs_time,
+                SubAnalysis::Borrows => stats.borrows_time,
+            })
+            .sum()
+    }
+
+    /// Times are in microseconds
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "fpcs_time_us": self.total_time(SubAnalysis::Fpcs).as_micros(),
+            "borrows_time_us": self.total_time(SubAnalysis::Borrows).as_micros(),
+            "blocks": self
+                .blocks
+                .iter_enumerated()
+                .map(|(block, stats)| json!({
+                    "block": format!("{:?}", block),
+                    "num_visits": stats.num_visits,
+                    "num_joins": stats.num_joins,
+                    "borrows_edge_deltas": stats.borrows_edge_deltas,
+                    "fpcs_time_us": stats.fpcs_time.as_micros(),
+                    "borrows_time_us": stats.borrows_time.as_micros(),
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
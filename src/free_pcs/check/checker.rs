@@ -23,7 +23,7 @@ use crate::{
 pub(crate) fn check<
     'mir,
     'tcx,
-    T,
+    T: Clone,
     D: HasFpcs<'mir, 'tcx> + HasExtra<T, BridgeCtx = TyCtxt<'tcx>> + HasPrepare,
     E: Analysis<'tcx, Domain = D>,
 >(
@@ -137,7 +137,7 @@ impl<'tcx> RepackOp<'tcx> {
                 assert_eq!(old, Some(from), "{self:?}, {curr_state:?}");
             }
             RepackOp::Expand(place, guide, kind) => {
-                assert_eq!(kind, CapabilityKind::Exclusive, "{self:?}");
+                assert!(kind.is_exclusive() || kind.is_aliased(), "{self:?}");
                 assert!(place.is_prefix_exact(guide), "{self:?}");
                 assert!(
                     can_downcast
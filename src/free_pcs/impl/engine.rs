@@ -42,10 +42,13 @@ impl<'a, 'tcx> Analysis<'tcx> for FpcsEngine<'a, 'tcx> {
         statement: &Statement<'tcx>,
         location: Location,
     ) {
-        TripleWalker::prepare(&mut state.after, self.0, Stage::Before).visit_statement(statement, location);
+        let mut walker = TripleWalker::prepare(&mut state.after, self.0, Stage::Before);
+        walker.visit_statement(statement, location);
+        state.capability_errors = walker.errors;
         state.before_start = state.after.clone();
         TripleWalker::apply(&mut state.after, self.0, Stage::Before).visit_statement(statement, location);
         state.before_after = state.after.clone();
+        state.record_capability_changes(Stage::Before, location);
     }
     fn apply_statement_effect(
         &mut self,
@@ -53,9 +56,12 @@ impl<'a, 'tcx> Analysis<'tcx> for FpcsEngine<'a, 'tcx> {
         statement: &Statement<'tcx>,
         location: Location,
     ) {
-        TripleWalker::prepare(&mut state.after, self.0, Stage::Main).visit_statement(statement, location);
+        let mut walker = TripleWalker::prepare(&mut state.after, self.0, Stage::Main);
+        walker.visit_statement(statement, location);
+        state.capability_errors.extend(walker.errors);
         state.start = state.after.clone();
         TripleWalker::apply(&mut state.after, self.0, Stage::Main).visit_statement(statement, location);
+        state.record_capability_changes(Stage::Main, location);
     }
 
     fn apply_before_terminator_effect(
@@ -64,10 +70,13 @@ impl<'a, 'tcx> Analysis<'tcx> for FpcsEngine<'a, 'tcx> {
         terminator: &Terminator<'tcx>,
         location: Location,
     ) {
-        TripleWalker::prepare(&mut state.after, self.0, Stage::Before).visit_terminator(terminator, location);
+        let mut walker = TripleWalker::prepare(&mut state.after, self.0, Stage::Before);
+        walker.visit_terminator(terminator, location);
+        state.capability_errors = walker.errors;
         state.before_start = state.after.clone();
         TripleWalker::apply(&mut state.after, self.0, Stage::Before).visit_terminator(terminator, location);
         state.before_after = state.after.clone();
+        state.record_capability_changes(Stage::Before, location);
     }
     fn apply_terminator_effect<'mir>(
         &mut self,
@@ -75,18 +84,64 @@ impl<'a, 'tcx> Analysis<'tcx> for FpcsEngine<'a, 'tcx> {
         terminator: &'mir Terminator<'tcx>,
         location: Location,
     ) -> TerminatorEdges<'mir, 'tcx> {
-        TripleWalker::prepare(&mut state.after, self.0, Stage::Main).visit_terminator(terminator, location);
+        let mut walker = TripleWalker::prepare(&mut state.after, self.0, Stage::Main);
+        walker.visit_terminator(terminator, location);
+        state.capability_errors.extend(walker.errors);
         state.start = state.after.clone();
         TripleWalker::apply(&mut state.after, self.0, Stage::Main).visit_terminator(terminator, location);
+        state.record_capability_changes(Stage::Main, location);
         terminator.edges()
     }
 
     fn apply_call_return_effect(
         &mut self,
-        _state: &mut Self::Domain,
+        state: &mut Self::Domain,
         _block: BasicBlock,
-        _return_places: CallReturnPlaces<'_, 'tcx>,
+        return_places: CallReturnPlaces<'_, 'tcx>,
     ) {
-        // Nothing to do here
+        return_places.for_each(|place| state.after.call_return(place.into(), self.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        free_pcs::CapabilityKind,
+        run_combined_pcs,
+        rustc_interface::middle::mir::{TerminatorKind, UnwindAction},
+        test_utils::{local_named, run_on_fn},
+        PcsOptions,
+    };
+
+    /// `t = make()` may unwind: `t` is only initialized when `make` returns
+    #[test]
+    fn call_destination_is_exclusive_on_return_edge_only() {
+        run_on_fn("46_call_with_cleanup.rs", "main", |tcx, body| {
+            let t = local_named(&body.body, "t");
+            let (target, cleanup) = body
+                .body
+                .basic_blocks
+                .iter()
+                .find_map(|data| match data.terminator().kind {
+                    TerminatorKind::Call {
+                        destination,
+                        target: Some(target),
+                        unwind: UnwindAction::Cleanup(cleanup),
+                        ..
+                    } if destination.local == t => Some((target, cleanup)),
+                    _ => None,
+                })
+                .unwrap();
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            assert_eq!(
+                output.capability_at(t.into(), target.start_location()),
+                Some(CapabilityKind::Exclusive)
+            );
+            assert_eq!(
+                output.capability_at(t.into(), cleanup.start_location()),
+                Some(CapabilityKind::Write)
+            );
+        });
     }
 }
@@ -9,16 +9,20 @@ use std::fmt::{Debug, Formatter, Result};
 use derive_more::{Deref, DerefMut};
 use rustc_interface::{
     index::Idx,
-    dataflow::fmt::DebugWithContext, index::IndexVec, middle::mir::{Local, RETURN_PLACE},
+    data_structures::fx::FxHashMap,
+    dataflow::fmt::DebugWithContext, index::IndexVec, middle::mir::{BasicBlock, Local, Location, RETURN_PLACE},
+    middle::ty::TyCtxt,
 };
+use serde_json::json;
 
 use crate::{
+    borrows::borrows_visitor::DebugCtx,
     free_pcs::{
-        CapabilityLocal, CapabilityProjections, RepackOp,
-    }, rustc_interface, utils::PlaceRepacker
+        CapabilityLocal, CapabilityProjections, HasExtra, HasPrepare, RepackOp,
+    }, rustc_interface, utils::{Place, PlaceRepacker}
 };
 
-use super::{CapabilityKind, RepackingBridgeSemiLattice, engine::FpcsEngine};
+use super::{CapabilityKind, RepackingBridgeSemiLattice, engine::FpcsEngine, triple::Stage};
 
 pub struct FreePlaceCapabilitySummary<'a, 'tcx> {
     pub(crate) repacker: PlaceRepacker<'a, 'tcx>,
@@ -26,6 +30,34 @@ pub struct FreePlaceCapabilitySummary<'a, 'tcx> {
     pub before_after: CapabilitySummary<'tcx>,
     pub start: CapabilitySummary<'tcx>,
     pub after: CapabilitySummary<'tcx>,
+    /// The location of the last statement that changed the capability of
+    /// each place, see [`Self::last_capability_change`]. `None` unless
+    /// enabled with [`Self::enable_capability_changes`].
+    capability_changes: Option<FxHashMap<Place<'tcx>, Location>>,
+    /// The preconditions of the last applied statement or terminator that
+    /// could not be satisfied
+    pub(crate) capability_errors: Vec<CapabilityError<'tcx>>,
+}
+
+/// A place that lacks the capability required by a statement or terminator,
+/// e.g. a moved-out place (with [`CapabilityKind::Write`]) that is read
+/// (requiring [`CapabilityKind::Exclusive`]).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapabilityError<'tcx> {
+    pub place: Place<'tcx>,
+    pub required: CapabilityKind,
+    pub available: CapabilityKind,
+    pub location: Location,
+}
+
+impl std::fmt::Display for CapabilityError<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "at {:?}: {:?} requires {:?} but only has {:?}",
+            self.location, self.place, self.required, self.available
+        )
+    }
 }
 impl<'a, 'tcx> FreePlaceCapabilitySummary<'a, 'tcx> {
     pub(crate) fn new(repacker: PlaceRepacker<'a, 'tcx>) -> Self {
@@ -36,6 +68,8 @@ impl<'a, 'tcx> FreePlaceCapabilitySummary<'a, 'tcx> {
             before_after: CapabilitySummary::empty(),
             start: CapabilitySummary::empty(),
             after,
+            capability_changes: None,
+            capability_errors: Vec::new(),
         }
     }
     pub fn initialize_as_start_block(&mut self) {
@@ -60,6 +94,69 @@ impl<'a, 'tcx> FreePlaceCapabilitySummary<'a, 'tcx> {
         }
     }
 
+    /// Records the capability changes of the places from now on, see
+    /// [`Self::last_capability_change`]
+    pub(crate) fn enable_capability_changes(&mut self) {
+        self.capability_changes.get_or_insert_with(FxHashMap::default);
+    }
+
+    /// The location of the statement that last changed the capability of
+    /// `place` (e.g. by moving out of it or borrowing it), or of its closest
+    /// prefix whose capability changed as a whole. Repacks are not
+    /// considered changes. On paths that join, the greatest of the locations
+    /// is kept. `None` if the changes are not recorded.
+    pub fn last_capability_change(&self, place: Place<'tcx>) -> Option<Location> {
+        self.capability_changes
+            .as_ref()?
+            .iter()
+            .filter(|(changed, _)| changed.is_prefix(place))
+            .max_by_key(|(changed, _)| changed.projection.len())
+            .map(|(_, location)| *location)
+    }
+
+    /// Stamps `location` on the places whose capability was changed by the
+    /// application of the statement or terminator in `stage`
+    /// A change of a place supersedes the changes of the places it is a
+    /// prefix of, which are removed.
+    pub(crate) fn record_capability_changes(&mut self, stage: Stage, location: Location) {
+        let Some(changes) = self.capability_changes.as_mut() else {
+            return;
+        };
+        let (from, to) = match stage {
+            Stage::Before => (&self.before_start, &self.before_after),
+            Stage::Main => (&self.start, &self.after),
+        };
+        let (from, to) = (from.flatten(), to.flatten());
+        let changed = from
+            .iter()
+            .filter(|(place, kind)| to.get(place) != Some(kind))
+            .map(|(place, _)| *place)
+            .chain(to.keys().filter(|place| !from.contains_key(place)).copied());
+        for place in changed {
+            changes.retain(|other, _| !place.is_prefix(*other));
+            changes.insert(place, location);
+        }
+    }
+
+    /// Joins the capability changes of `other` into those of `self`, keeping
+    /// the greatest location of a place changed in both. Returns whether the
+    /// changes of `self` were modified.
+    pub(crate) fn join_capability_changes(&mut self, other: &Self) -> bool {
+        let (Some(changes), Some(other_changes)) =
+            (self.capability_changes.as_mut(), other.capability_changes.as_ref())
+        else {
+            return false;
+        };
+        let mut changed = false;
+        for (place, location) in other_changes.iter() {
+            if changes.get(place).map_or(true, |existing| existing < location) {
+                changes.insert(*place, *location);
+                changed = true;
+            }
+        }
+        changed
+    }
+
     pub fn repack_ops(&self, previous: &CapabilitySummary<'tcx>) -> (Vec<RepackOp<'tcx>>, Vec<RepackOp<'tcx>>) {
         let from_prev = previous.bridge(&self.before_start, self.repacker);
         let middle = self.before_after.bridge(&self.start, self.repacker);
@@ -75,12 +172,14 @@ impl Clone for FreePlaceCapabilitySummary<'_, '_> {
             before_after: self.before_after.clone(),
             start: self.start.clone(),
             after: self.after.clone(),
+            capability_changes: self.capability_changes.clone(),
+            capability_errors: self.capability_errors.clone(),
         }
     }
 }
 impl PartialEq for FreePlaceCapabilitySummary<'_, '_> {
     fn eq(&self, other: &Self) -> bool {
-        self.after == other.after
+        self.after == other.after && self.capability_changes == other.capability_changes
     }
 }
 impl Eq for FreePlaceCapabilitySummary<'_, '_> {}
@@ -112,6 +211,23 @@ impl<'a, 'tcx> DebugWithContext<FpcsEngine<'a, 'tcx>> for FreePlaceCapabilitySum
     }
 }
 
+/// The free PCS on its own carries no extra state, this is what allows
+/// running [`FpcsEngine`] without the borrows analysis (see
+/// [`crate::run_free_pcs`]).
+impl<'a, 'tcx> HasExtra<()> for FreePlaceCapabilitySummary<'a, 'tcx> {
+    type ExtraBridge = ();
+    type BridgeCtx = TyCtxt<'tcx>;
+    fn get_extra(&self) {}
+    fn bridge_between_stmts(_lhs: (), _rhs: (), _debug_ctx: DebugCtx) -> ((), ()) {
+        ((), ())
+    }
+    fn bridge_terminator(_lhs: &(), _rhs: (), _block: BasicBlock, _args: TyCtxt<'tcx>) {}
+}
+
+impl<'a, 'tcx> HasPrepare for FreePlaceCapabilitySummary<'a, 'tcx> {
+    fn prepare(&self) {}
+}
+
 #[derive(Clone, PartialEq, Eq, Deref, DerefMut)]
 /// The free pcs of all locals
 pub struct CapabilitySummary<'tcx>(IndexVec<Local, CapabilityLocal<'tcx>>);
@@ -130,6 +246,86 @@ impl<'tcx> CapabilitySummary<'tcx> {
     pub fn empty() -> Self {
         Self(IndexVec::new())
     }
+
+    /// The capabilities of each local, in order: `"unallocated"`, or the
+    /// places of the local with their capability, ordered by projection.
+    pub fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
+        let locals = self
+            .0
+            .iter_enumerated()
+            .map(|(local, c)| {
+                let capabilities = match c {
+                    CapabilityLocal::Unallocated => json!("unallocated"),
+                    CapabilityLocal::Allocated(projections) => {
+                        let mut places = projections.iter().collect::<Vec<_>>();
+                        places.sort_by_key(|(place, _)| place.projection_codes());
+                        places
+                            .into_iter()
+                            .map(|(place, kind)| {
+                                json!({
+                                    "place": place.to_json(repacker),
+                                    "capability": format!("{:?}", kind),
+                                })
+                            })
+                            .collect()
+                    }
+                };
+                json!({
+                    "local": Place::from(local).to_json(repacker),
+                    "capabilities": capabilities,
+                })
+            })
+            .collect();
+        serde_json::Value::Array(locals)
+    }
+
+    /// The capabilities of all places tracked in this summary, across all
+    /// locals. Unallocated locals are omitted.
+    pub fn flatten(&self) -> FxHashMap<Place<'tcx>, CapabilityKind> {
+        self.0
+            .iter()
+            .flat_map(|c| match c {
+                CapabilityLocal::Unallocated => None,
+                CapabilityLocal::Allocated(projections) => Some(projections.iter()),
+            })
+            .flatten()
+            .map(|(place, kind)| (*place, *kind))
+            .collect()
+    }
+
+    /// The capability of `place`, as it would be after repacking the summary
+    /// to contain it (e.g. expanding a prefix of `place`, or collapsing the
+    /// places it is a prefix of). Returns `None` if the local of `place` is
+    /// unallocated or if naming `place` would require expanding through a
+    /// reference: the places behind references are tracked by the borrows
+    /// graph instead.
+    pub fn capability_of(
+        &self,
+        place: Place<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Option<CapabilityKind> {
+        let CapabilityLocal::Allocated(projections) = &self[place.local] else {
+            return None;
+        };
+        if let Some(cap) = projections.get(&place) {
+            return Some(*cap);
+        }
+        let expand_from = projections
+            .keys()
+            .filter(|p| p.related_to(place))
+            .map(|p| p.common_prefix(place))
+            .min_by_key(|p| p.projection.len())?;
+        if place
+            .iter_projections()
+            .skip(expand_from.projection.len())
+            .any(|(base, _)| Place::from(base).is_ref(repacker.body(), repacker.tcx()))
+        {
+            return None;
+        }
+        let mut projections = projections.clone();
+        projections.repack(place, repacker);
+        projections.get(&place).copied()
+    }
 }
 
 struct CapabilitySummaryCompare<'a, 'tcx>(&'a CapabilitySummary<'tcx>, &'a CapabilitySummary<'tcx>, &'a str);
@@ -194,3 +390,48 @@ impl Debug for CapabilitySummaryCompare<'_, '_> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        run_combined_pcs,
+        rustc_interface::middle::mir::{Operand, Rvalue},
+        test_utils::{find_statement, local_named, run_on_fn},
+        PcsOptions,
+    };
+
+    /// After `t = move s`, `s` is listed in the JSON with capability `W`
+    #[test]
+    fn moved_out_local_is_write_in_json() {
+        run_on_fn("38_moved_out_local.rs", "main", |tcx, body| {
+            let s = Place::from(local_named(&body.body, "s"));
+            let location = find_statement(&body.body, |statement| {
+                matches!(
+                    statement.kind.as_assign(),
+                    Some((_, Rvalue::Use(Operand::Move(place)))) if place.local == s.local
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let rp = output.repacker();
+            let json = output
+                .get_all_for_bb(location.block)
+                .statements
+                .remove(location.statement_index)
+                .states
+                .after
+                .to_json(rp);
+            let local = json
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|local| local["local"] == s.to_json(rp))
+                .unwrap();
+            assert_eq!(
+                local["capabilities"],
+                json!([{ "place": s.to_json(rp), "capability": "W" }])
+            );
+        });
+    }
+}
@@ -14,12 +14,24 @@ use crate::{
 
 impl JoinSemiLattice for FreePlaceCapabilitySummary<'_, '_> {
     fn join(&mut self, other: &Self) -> bool {
-        self.after.join(&other.after, self.repacker)
+        let changes_changed = self.join_capability_changes(other);
+        let after_changed = self.after.join(&other.after, self.repacker);
+        changes_changed || after_changed
+    }
+}
+
+impl FreePlaceCapabilitySummary<'_, '_> {
+    /// Whether `self` is below `other` in the lattice, i.e. joining `self`
+    /// into `other` would not change `other`.
+    pub fn leq(&self, other: &Self) -> bool {
+        self.after.leq(&other.after, self.repacker)
     }
 }
 
 pub trait RepackingJoinSemiLattice<'tcx> {
     fn join(&mut self, other: &Self, repacker: PlaceRepacker<'_, 'tcx>) -> bool;
+    /// The ordering of the lattice, `a.join(b)` must be above both `a` and `b`
+    fn leq(&self, other: &Self, repacker: PlaceRepacker<'_, 'tcx>) -> bool;
 }
 impl<'tcx> RepackingJoinSemiLattice<'tcx> for CapabilitySummary<'tcx> {
     fn join(&mut self, other: &Self, repacker: PlaceRepacker<'_, 'tcx>) -> bool {
@@ -30,6 +42,10 @@ impl<'tcx> RepackingJoinSemiLattice<'tcx> for CapabilitySummary<'tcx> {
         }
         changed
     }
+    fn leq(&self, other: &Self, repacker: PlaceRepacker<'_, 'tcx>) -> bool {
+        self.iter_enumerated()
+            .all(|(l, local)| local.leq(&other[l], repacker))
+    }
 }
 
 impl<'tcx> RepackingJoinSemiLattice<'tcx> for CapabilityLocal<'tcx> {
@@ -39,6 +55,9 @@ impl<'tcx> RepackingJoinSemiLattice<'tcx> for CapabilityLocal<'tcx> {
             (CapabilityLocal::Allocated(to_places), CapabilityLocal::Allocated(from_places)) => {
                 to_places.join(from_places, repacker)
             }
+            // Allocated on only some of the incoming paths (e.g. a
+            // `StorageLive` in one branch): the meet is `Unallocated`, and the
+            // bridge from the paths where it is allocated deallocates it.
             (CapabilityLocal::Allocated(..), CapabilityLocal::Unallocated) => {
                 *self = CapabilityLocal::Unallocated;
                 true
@@ -47,6 +66,15 @@ impl<'tcx> RepackingJoinSemiLattice<'tcx> for CapabilityLocal<'tcx> {
             (CapabilityLocal::Unallocated, CapabilityLocal::Allocated(..)) => false,
         }
     }
+    fn leq(&self, other: &Self, repacker: PlaceRepacker<'_, 'tcx>) -> bool {
+        match (self, other) {
+            (_, CapabilityLocal::Unallocated) => true,
+            (CapabilityLocal::Allocated(from_places), CapabilityLocal::Allocated(to_places)) => {
+                from_places.leq(to_places, repacker)
+            }
+            (CapabilityLocal::Unallocated, CapabilityLocal::Allocated(..)) => false,
+        }
+    }
 }
 
 impl<'tcx> RepackingJoinSemiLattice<'tcx> for CapabilityProjections<'tcx> {
@@ -121,4 +149,73 @@ impl<'tcx> RepackingJoinSemiLattice<'tcx> for CapabilityProjections<'tcx> {
         }
         changed
     }
+    /// Every place of `self` must be related to some place of `other`, and
+    /// the capability of each related place of `other` must be at most that of
+    /// the place of `self`.
+    fn leq(&self, other: &Self, _repacker: PlaceRepacker<'_, 'tcx>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        self.iter().all(|(&place, &kind)| {
+            let mut related = other
+                .iter()
+                .filter(|(&other_place, _)| other_place.partial_cmp(place).is_some())
+                .peekable();
+            related.peek().is_some() && related.all(|(_, other_kind)| *other_kind <= kind)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        free_pcs::RepackOp,
+        run_combined_pcs,
+        rustc_interface::middle::mir::{StatementKind, TerminatorKind},
+        test_utils::run_on_fn,
+        PcsOptions,
+    };
+
+    /// `tmp` is only allocated on the `then` branch: it is unallocated at the
+    /// join, and the edge from the `then` branch deallocates it
+    #[test]
+    fn local_allocated_on_one_branch_is_unallocated_at_join() {
+        run_on_fn(
+            "15_storage_live_one_branch.rs",
+            "storage_live_one_branch",
+            |tcx, body| {
+                let (then_branch, tmp) = body
+                    .body
+                    .basic_blocks
+                    .iter_enumerated()
+                    .find_map(|(block, data)| {
+                        data.statements
+                            .iter()
+                            .find_map(|statement| match statement.kind {
+                                StatementKind::StorageLive(local) => Some((block, local)),
+                                _ => None,
+                            })
+                    })
+                    .unwrap();
+                let TerminatorKind::Goto { target: join } =
+                    body.body[then_branch].terminator().kind
+                else {
+                    panic!("The `then` branch should end with a `Goto`");
+                };
+                let mut output =
+                    run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+                let then_block = output.get_all_for_bb(then_branch);
+                assert!(!then_block.terminator.state[tmp].is_unallocated());
+                let edge = then_block
+                    .terminator
+                    .succs
+                    .iter()
+                    .find(|succ| succ.location.block == join)
+                    .unwrap();
+                assert!(edge.repacks_start.contains(&RepackOp::StorageDead(tmp)));
+                let join_block = output.get_all_for_bb(join);
+                assert!(join_block.statements[0].states.before_start[tmp].is_unallocated());
+            },
+        );
+    }
 }
@@ -44,6 +44,15 @@ pub enum CapabilityKind {
     /// [`CapabilityKind::Exclusive`] for everything not through a dereference,
     /// [`CapabilityKind::Write`] for everything through a dereference.
     ShallowExclusive,
+    /// [`CapabilityKind::Exclusive`], except that a raw pointer to the place
+    /// may be outstanding (e.g. after `&raw mut place`). Accesses through the
+    /// place may therefore alias accesses through that pointer.
+    Aliased,
+    /// Required by the accesses that only read the place, e.g. copying out of
+    /// it or taking a shared reference to it. It is never held by a place:
+    /// the capability that satisfies a read (e.g. `E`) is left as is, so any
+    /// number of reads of the place may follow.
+    Read,
 }
 impl Debug for CapabilityKind {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
@@ -51,6 +60,8 @@ impl Debug for CapabilityKind {
             CapabilityKind::Write => write!(f, "W"),
             CapabilityKind::Exclusive => write!(f, "E"),
             CapabilityKind::ShallowExclusive => write!(f, "e"),
+            CapabilityKind::Aliased => write!(f, "A"),
+            CapabilityKind::Read => write!(f, "R"),
         }
     }
 }
@@ -61,12 +72,19 @@ impl PartialOrd for CapabilityKind {
             return Some(Ordering::Equal);
         }
         match (self, other) {
-            // W < E, W < e
+            // R and W are incomparable: a place that can be read cannot
+            // necessarily be written to, and vice versa
+            (CapabilityKind::Read, CapabilityKind::Write)
+            | (CapabilityKind::Write, CapabilityKind::Read) => None,
+            // W < E, W < e, W < A, R < E, R < A
             (_, CapabilityKind::Exclusive)
-            | (CapabilityKind::Write, CapabilityKind::ShallowExclusive) => Some(Ordering::Less),
-            // E > W, e > W
+            | (CapabilityKind::Write, _)
+            | (CapabilityKind::Read, CapabilityKind::Aliased) => Some(Ordering::Less),
+            // E > W, e > W, A > W, E > R, A > R
             (CapabilityKind::Exclusive, _)
-            | (CapabilityKind::ShallowExclusive, CapabilityKind::Write) => Some(Ordering::Greater),
+            | (_, CapabilityKind::Write)
+            | (CapabilityKind::Aliased, CapabilityKind::Read) => Some(Ordering::Greater),
+            // e is incomparable with A and R
             _ => None,
         }
     }
@@ -82,10 +100,122 @@ impl CapabilityKind {
     pub fn is_shallow_exclusive(self) -> bool {
         matches!(self, CapabilityKind::ShallowExclusive)
     }
+    pub fn is_aliased(self) -> bool {
+        matches!(self, CapabilityKind::Aliased)
+    }
+    pub fn is_read(self) -> bool {
+        matches!(self, CapabilityKind::Read)
+    }
+    /// The greatest capability that is at most both `self` and `other`, e.g.
+    /// `E ⊓ W = W`, `e ⊓ E = e` and `e ⊓ A = W`. Returns `None` if there is
+    /// no such capability, i.e. for `R` and any of `W` or `e`: no capability
+    /// is below both a read and a write of the place.
     pub fn minimum(self, other: Self) -> Option<Self> {
-        match self.partial_cmp(&other)? {
-            Ordering::Greater => Some(other),
-            _ => Some(self),
+        match self.partial_cmp(&other) {
+            Some(Ordering::Greater) => Some(other),
+            Some(_) => Some(self),
+            None if self.is_read() || other.is_read() => None,
+            // Both are above `W`
+            None => Some(CapabilityKind::Write),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        free_pcs::CapabilityLocal,
+        run_combined_pcs,
+        rustc_interface::middle::mir::{BorrowKind, Location, Operand, Rvalue},
+        test_utils::{local_named, run_on_fn},
+        PcsOptions,
+    };
+
+    #[test]
+    fn minimum_of_all_pairs() {
+        use CapabilityKind::{
+            Aliased as A, Exclusive as E, Read as R, ShallowExclusive as e, Write as W,
+        };
+        let expected = [
+            (W, W, Some(W)),
+            (W, E, Some(W)),
+            (W, e, Some(W)),
+            (W, A, Some(W)),
+            (W, R, None),
+            (E, E, Some(E)),
+            (E, e, Some(e)),
+            (E, A, Some(A)),
+            (E, R, Some(R)),
+            (e, e, Some(e)),
+            (e, A, Some(W)),
+            (e, R, None),
+            (A, A, Some(A)),
+            (A, R, Some(R)),
+            (R, R, Some(R)),
+        ];
+        let mut checked = 0;
+        for (left, right, minimum) in expected {
+            assert_eq!(left.minimum(right), minimum, "{left:?} ⊓ {right:?}");
+            assert_eq!(right.minimum(left), minimum, "{right:?} ⊓ {left:?}");
+            checked += if left == right { 1 } else { 2 };
+        }
+        assert_eq!(checked, 25);
+    }
+
+    /// `a = &x`, `b = &x`, `c = x.0` and the read of `x.1` in the `assert!`
+    /// all require `R` of `x` (or of its fields): each is satisfied by the
+    /// `E` of `x`, which it leaves as is
+    #[test]
+    fn shared_reads_leave_capability_unchanged() {
+        run_on_fn("30_two_shared_reads.rs", "main", |tcx, body| {
+            let x = local_named(&body.body, "x");
+            let reads: Vec<_> = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .flat_map(|(block, data)| {
+                    data.statements
+                        .iter()
+                        .enumerate()
+                        .filter(move |(_, statement)| {
+                            statement.kind.as_assign().is_some_and(|(_, rvalue)| {
+                                matches!(
+                                    rvalue,
+                                    Rvalue::Ref(_, BorrowKind::Shared, place)
+                                        | Rvalue::Use(Operand::Copy(place))
+                                        if place.local == x
+                                )
+                            })
+                        })
+                        .map(move |(statement_index, _)| Location {
+                            block,
+                            statement_index,
+                        })
+                })
+                .collect();
+            assert_eq!(reads.len(), 4);
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            assert!(output.capability_errors().is_empty());
+            for read in reads {
+                for location in [read, read.successor_within_block()] {
+                    assert_eq!(
+                        output.capability_at(x.into(), location),
+                        Some(CapabilityKind::Exclusive),
+                        "{location:?}"
+                    );
+                }
+            }
+            for block in body.body.basic_blocks.indices() {
+                for statement in output.get_all_for_bb(block).statements {
+                    for local in statement.states.after.iter() {
+                        if let CapabilityLocal::Allocated(projections) = local {
+                            assert!(projections.iter().all(|(_, kind)| !kind.is_read()));
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
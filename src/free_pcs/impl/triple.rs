@@ -5,12 +5,12 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use rustc_interface::middle::mir::{
-    visit::Visitor, Local, Location, Operand, ProjectionElem, Rvalue, Statement, StatementKind,
-    Terminator, TerminatorKind, RETURN_PLACE,
+    visit::Visitor, BorrowKind, Local, Location, Operand, ProjectionElem, Rvalue, Statement,
+    StatementKind, Terminator, TerminatorKind, RETURN_PLACE,
 };
 
 use crate::{
-    free_pcs::CapabilityKind,
+    free_pcs::{CapabilityError, CapabilityKind},
     rustc_interface,
     utils::{Place, PlaceRepacker},
 };
@@ -60,6 +60,9 @@ pub(crate) struct TripleWalker<'a, 'b, 'tcx> {
     repacker: PlaceRepacker<'b, 'tcx>,
     stage: Stage,
     preparing: bool,
+    /// The preconditions of the program that could not be satisfied, see
+    /// [`Self::checked_triple`]
+    pub(crate) errors: Vec<CapabilityError<'tcx>>,
 }
 
 impl<'a, 'b, 'tcx> TripleWalker<'a, 'b, 'tcx> {
@@ -73,6 +76,7 @@ impl<'a, 'b, 'tcx> TripleWalker<'a, 'b, 'tcx> {
             repacker,
             stage,
             preparing: true,
+            errors: Vec::new(),
         }
     }
     pub(crate) fn apply(
@@ -85,27 +89,68 @@ impl<'a, 'b, 'tcx> TripleWalker<'a, 'b, 'tcx> {
             repacker,
             stage,
             preparing: false,
+            errors: Vec::new(),
         }
     }
     fn triple(&mut self, stage: Stage, t: Triple<'tcx>) {
+        self.triple_checked_at(stage, t, None);
+    }
+    /// Like [`Self::triple`], for a precondition that the program must satisfy
+    /// (e.g. only initialized places can be read), rather than one that only
+    /// drives the repacking. Failing to satisfy it is recorded as an error.
+    fn checked_triple(&mut self, stage: Stage, t: Triple<'tcx>, location: Location) {
+        self.triple_checked_at(stage, t, Some(location));
+    }
+    fn triple_checked_at(&mut self, stage: Stage, t: Triple<'tcx>, location: Option<Location>) {
         if stage != self.stage {
             return;
         }
         if self.preparing {
-            self.summary.requires(t.pre, self.repacker);
+            let pre = t.pre.clone();
+            if let Some(available) = self.summary.requires(t.pre, self.repacker)
+                && let (Some(location), Condition::Capability(place, required)) = (location, pre)
+            {
+                self.errors.push(CapabilityError {
+                    place,
+                    required,
+                    available,
+                    location,
+                });
+            }
         } else {
             self.summary.ensures(t, self.repacker);
         }
     }
 }
 
+impl<'tcx> CapabilitySummary<'tcx> {
+    /// The effect of a call returning to its target block: its destination,
+    /// which the call leaves with [`CapabilityKind::Write`], becomes
+    /// initialized. On the unwind edge, the destination stays uninitialized.
+    pub(crate) fn call_return(
+        &mut self,
+        destination: Place<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) {
+        let place = get_place_to_expand_to(destination, repacker);
+        self.ensures(
+            Triple::new(
+                Condition::Capability(place, CapabilityKind::Write),
+                Condition::Capability(place, CapabilityKind::Exclusive),
+            ),
+            repacker,
+        );
+    }
+}
+
 fn get_place_to_expand_to<'b, 'tcx>(
     place: Place<'tcx>,
     repacker: PlaceRepacker<'b, 'tcx>,
 ) -> Place<'tcx> {
     for (place, elem) in place.iter_projections() {
         let place: Place<'tcx> = place.into();
-        if elem == ProjectionElem::Deref && place.ty(repacker).ty.is_ref() {
+        let ty = place.ty(repacker).ty;
+        if elem == ProjectionElem::Deref && (ty.is_ref() || ty.is_unsafe_ptr()) {
             return place;
         }
     }
@@ -129,7 +174,7 @@ impl<'tcx> Visitor<'tcx> for TripleWalker<'_, '_, 'tcx> {
             Operand::Copy(place) => {
                 let place: Place<'tcx> = place.into();
                 let place_to_expand_to = get_place_to_expand_to(place, self.repacker);
-                let pre = Condition::Capability(place_to_expand_to, CapabilityKind::Exclusive);
+                let pre = Condition::Capability(place_to_expand_to, CapabilityKind::Read);
                 Triple {
                     pre,
                     post: Condition::Unchanged,
@@ -141,7 +186,7 @@ impl<'tcx> Visitor<'tcx> for TripleWalker<'_, '_, 'tcx> {
             },
             Operand::Constant(..) => return,
         };
-        self.triple(Stage::Before, t)
+        self.checked_triple(Stage::Before, t, location)
     }
 
     fn visit_rvalue(&mut self, rvalue: &Rvalue<'tcx>, location: Location) {
@@ -155,24 +200,62 @@ impl<'tcx> Visitor<'tcx> for TripleWalker<'_, '_, 'tcx> {
             | BinaryOp(_, _)
             | NullaryOp(_, _)
             | UnaryOp(_, _)
-            | Aggregate(_, _)
             | ShallowInitBox(_, _) => {}
 
-            &Ref(_, _, place)
-            | &Len(place)
-            | &Discriminant(place)
-            | &CopyForDeref(place) => {
+            // The fields are visited as operands by `super_rvalue`, which adds
+            // the requirement of each field individually (e.g. a moved field
+            // requires `Exclusive` and leaves `Write` behind). The region
+            // projection members of reference fields are tracked on the
+            // borrows side, see `BorrowsVisitor::visit_statement`.
+            Aggregate(_, _) => {}
+
+            &RawPtr(mutability, place) => {
                 let place: Place<'tcx> = place.into();
                 let place_to_expand_to = get_place_to_expand_to(place, self.repacker);
-                self.triple(
+                self.checked_triple(
                     Stage::Before,
                     Triple {
                         pre: Condition::Capability(place_to_expand_to, CapabilityKind::Exclusive),
+                        // A `*mut` may be used to mutate the place behind our
+                        // back, until we know better assume it lives forever.
+                        // Places behind references are handled by the borrows.
+                        post: if mutability.is_mut() && place == place_to_expand_to {
+                            Condition::Capability(place, CapabilityKind::Aliased)
+                        } else {
+                            Condition::Unchanged
+                        },
+                    },
+                    location,
+                )
+            }
+            &Ref(_, kind, place) => {
+                let place: Place<'tcx> = place.into();
+                let place_to_expand_to = get_place_to_expand_to(place, self.repacker);
+                let cap = match kind {
+                    BorrowKind::Shared | BorrowKind::Fake(_) => CapabilityKind::Read,
+                    BorrowKind::Mut { .. } => CapabilityKind::Exclusive,
+                };
+                self.checked_triple(
+                    Stage::Before,
+                    Triple {
+                        pre: Condition::Capability(place_to_expand_to, cap),
                         post: Condition::Unchanged,
                     },
+                    location,
+                )
+            }
+            &Len(place) | &Discriminant(place) | &CopyForDeref(place) => {
+                let place: Place<'tcx> = place.into();
+                let place_to_expand_to = get_place_to_expand_to(place, self.repacker);
+                self.checked_triple(
+                    Stage::Before,
+                    Triple {
+                        pre: Condition::Capability(place_to_expand_to, CapabilityKind::Read),
+                        post: Condition::Unchanged,
+                    },
+                    location,
                 )
             }
-            _ => todo!(),
         }
     }
 
@@ -196,13 +279,9 @@ impl<'tcx> Visitor<'tcx> for TripleWalker<'_, '_, 'tcx> {
                 ),
                 post: Condition::Unchanged,
             },
-            &PlaceMention(box place) => Triple {
-                pre: Condition::capability(
-                    get_place_to_expand_to(place.into(), self.repacker),
-                    CapabilityKind::Write,
-                ),
-                post: Condition::Unchanged,
-            },
+            // Mentioning a place neither reads nor writes it, requiring
+            // `Write` here would weaken initialized places
+            PlaceMention(..) => return,
             &SetDiscriminant { box place, .. } => Triple {
                 pre: Condition::capability(
                     get_place_to_expand_to(place.into(), self.repacker),
@@ -263,12 +342,13 @@ impl<'tcx> Visitor<'tcx> for TripleWalker<'_, '_, 'tcx> {
                     } else {
                         Condition::Unalloc(local)
                     };
-                    self.triple(
+                    self.checked_triple(
                         Stage::Main,
                         Triple {
                             pre,
                             post: Condition::Unchanged,
                         },
+                        location,
                     );
                 }
                 return;
@@ -283,6 +363,8 @@ impl<'tcx> Visitor<'tcx> for TripleWalker<'_, '_, 'tcx> {
                     CapabilityKind::Write,
                 ),
             },
+            // The destination is only initialized if the call returns, see
+            // `CapabilitySummary::call_return`
             &Call { destination, .. } => Triple {
                 pre: Condition::Capability(
                     get_place_to_expand_to(destination.into(), self.repacker),
@@ -290,7 +372,7 @@ impl<'tcx> Visitor<'tcx> for TripleWalker<'_, '_, 'tcx> {
                 ),
                 post: Condition::Capability(
                     get_place_to_expand_to(destination.into(), self.repacker),
-                    CapabilityKind::Exclusive,
+                    CapabilityKind::Write,
                 ),
             },
             &Yield { resume_arg, .. } => Triple {
@@ -324,9 +406,9 @@ impl ProducesCapability for Rvalue<'_> {
             | UnaryOp(_, _)
             | Discriminant(_)
             | Aggregate(_, _)
-            | CopyForDeref(_) => CapabilityKind::Exclusive,
+            | CopyForDeref(_)
+            | RawPtr(_, _) => CapabilityKind::Exclusive,
             ShallowInitBox(_, _) => CapabilityKind::ShallowExclusive,
-            _ => todo!(),
         }
     }
 }
@@ -12,10 +12,16 @@ use crate::{
 use super::{CapabilitySummary, triple::{Condition, Triple}};
 
 impl<'tcx> CapabilitySummary<'tcx> {
-    pub(crate) fn requires(&mut self, cond: Condition<'tcx>, repacker: PlaceRepacker<'_, 'tcx>) {
+    /// Repacks and weakens the capabilities to satisfy `cond`. If the place of
+    /// `cond` has an insufficient capability, that capability is returned.
+    pub(crate) fn requires(
+        &mut self,
+        cond: Condition<'tcx>,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Option<CapabilityKind> {
         match cond {
-            Condition::Unchanged => {}
-            Condition::Unalloc(_) => {},
+            Condition::Unchanged => None,
+            Condition::Unalloc(_) => None,
             Condition::AllocateOrDeallocate(local) => {
                 match &mut self[local] {
                     cap@CapabilityLocal::Unallocated => {
@@ -24,6 +30,7 @@ impl<'tcx> CapabilitySummary<'tcx> {
                         // precondition of SD can be met, but we'll catch this in
                         // `bridge` and emit a IgnoreSD op.
                         *cap = CapabilityLocal::Allocated(CapabilityProjections::new_uninit(local));
+                        None
                     }
                     CapabilityLocal::Allocated(_) =>
                         self.requires(Condition::Capability(local.into(), CapabilityKind::Write), repacker),
@@ -32,10 +39,17 @@ impl<'tcx> CapabilitySummary<'tcx> {
             Condition::Capability(place, cap) => {
                 let cp = self[place.local].get_allocated_mut();
                 cp.repack(place, repacker);
-                if cp[&place] > cap {
+                let available = cp[&place];
+                // Reading the place leaves its capability as is
+                if available > cap && !cap.is_read() {
                     // Requires write should deinit an exclusive
                     cp.insert(place, cap);
                 };
+                // An outstanding raw pointer does not prevent the accesses
+                // that require exclusive
+                let sufficient =
+                    available >= cap || (available.is_aliased() && cap.is_exclusive());
+                (!sufficient).then_some(available)
             }
         }
     }
@@ -51,16 +65,22 @@ impl<'tcx> CapabilitySummary<'tcx> {
             Condition::Capability(place, cap) => {
                 match cap {
                     CapabilityKind::Write => {
-                        // Cannot get write on a shared ref
-                        debug_assert!(place
-                            .is_mutable(LocalMutationIsAllowed::Yes, repacker)
-                            .is_ok());
+                        // Cannot get write on a shared ref. The captures of
+                        // closures (and hence the mutability of upvars) are
+                        // only available for bodies of the local crate.
+                        debug_assert!(
+                            !repacker.body().source.def_id().is_local()
+                                || place
+                                    .is_mutable(LocalMutationIsAllowed::Yes, repacker)
+                                    .is_ok()
+                        );
                     }
                     CapabilityKind::Exclusive => {
                         // Cannot get exclusive on a shared ref
                         // assert!(!place.projects_shared_ref(repacker));
                     }
-                    CapabilityKind::ShallowExclusive => unreachable!(),
+                    CapabilityKind::Read => {}
+                    CapabilityKind::ShallowExclusive | CapabilityKind::Aliased => unreachable!(),
                 }
 
                 let _cp = self[place.local].get_allocated_mut();
@@ -76,7 +96,14 @@ impl<'tcx> CapabilitySummary<'tcx> {
                 self[*local] = CapabilityLocal::Allocated(CapabilityProjections::new_uninit(*local));
             }
             Condition::Capability(place, cap) => {
-                self[place.local].get_allocated_mut().update_cap(*place, *cap);
+                let cp = self[place.local].get_allocated_mut();
+                // Writing to the place does not invalidate a raw pointer to it
+                let cap = if cap.is_exclusive() && cp.get(place).is_some_and(|c| c.is_aliased()) {
+                    CapabilityKind::Aliased
+                } else {
+                    *cap
+                };
+                cp.update_cap(*place, cap);
             }
         }
     }
@@ -5,10 +5,12 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use rustc_interface::{
+    data_structures::fx::FxHashMap,
     dataflow::Analysis,
     dataflow::ResultsCursor,
+    index::IndexVec,
     middle::{
-        mir::{BasicBlock, Body, Location},
+        mir::{BasicBlock, Body, Location, TerminatorKind},
         ty::TyCtxt,
     },
 };
@@ -17,10 +19,11 @@ use crate::{
     borrows::borrows_visitor::DebugCtx,
     combined_pcs::{PcsContext, PcsEngine, PlaceCapabilitySummary},
     free_pcs::{
-        CapabilitySummary, FreePlaceCapabilitySummary, RepackOp, RepackingBridgeSemiLattice,
+        CapabilityError, CapabilityKind, CapabilitySummary, FreePlaceCapabilitySummary, RepackOp,
+        RepackingBridgeSemiLattice,
     },
     rustc_interface,
-    utils::PlaceRepacker,
+    utils::{Place, PlaceRepacker},
 };
 
 pub trait HasFpcs<'mir, 'tcx> {
@@ -58,6 +61,11 @@ pub trait HasExtra<T> {
     type ExtraBridge;
     type BridgeCtx;
     fn get_extra(&self) -> T;
+    /// The extra state of an entry state of the results, in the form the
+    /// states computed after the fixpoint are in
+    fn get_canonical_extra(&self) -> T {
+        self.get_extra()
+    }
     fn bridge_between_stmts(
         lhs: T,
         rhs: T,
@@ -79,25 +87,38 @@ pub struct FreePcsAnalysis<
     E: Analysis<'tcx, Domain = D>,
 > {
     pub cursor: Cursor<'mir, 'tcx, E>,
+    /// The extra states of the entry states of the blocks, see
+    /// [`HasExtra::get_canonical_extra`]
+    entry_extras: IndexVec<BasicBlock, T>,
     curr_stmt: Option<Location>,
     end_stmt: Option<Location>,
-    _marker: std::marker::PhantomData<T>,
 }
 
 impl<
         'mir,
         'tcx,
-        T,
+        T: Clone,
         D: HasFpcs<'mir, 'tcx> + HasExtra<T, BridgeCtx = TyCtxt<'tcx>> + HasPrepare,
         E: Analysis<'tcx, Domain = D>,
     > FreePcsAnalysis<'mir, 'tcx, T, D, E>
 {
     pub(crate) fn new(cursor: Cursor<'mir, 'tcx, E>) -> Self {
+        let entry_extras = cursor
+            .body()
+            .basic_blocks
+            .indices()
+            .map(|block| {
+                cursor
+                    .results()
+                    .entry_set_for_block(block)
+                    .get_canonical_extra()
+            })
+            .collect();
         Self {
             cursor,
+            entry_extras,
             curr_stmt: None,
             end_stmt: None,
-            _marker: std::marker::PhantomData,
         }
     }
 
@@ -128,6 +149,69 @@ impl<
         &self.cursor.get().get_curr_fpcs().after
     }
 
+    /// The location of the last capability change of `place` before the
+    /// current position of the cursor. `None` unless the changes are
+    /// recorded, see [`crate::PcsOptions::record_capability_changes`].
+    pub fn last_capability_change(&self, place: Place<'tcx>) -> Option<Location> {
+        self.cursor
+            .get()
+            .get_curr_fpcs()
+            .last_capability_change(place)
+    }
+
+    /// The capability of `place` just before the statement or terminator at
+    /// `location`, i.e. before the repacks it requires (see
+    /// [`CapabilitySummary::capability_of`]). Resets the position of the
+    /// cursor.
+    pub fn capability_at(
+        &mut self,
+        place: Place<'tcx>,
+        location: Location,
+    ) -> Option<CapabilityKind> {
+        if location.statement_index == 0 {
+            self.cursor.seek_to_block_start(location.block);
+        } else {
+            self.cursor.seek_after_primary_effect(Location {
+                block: location.block,
+                statement_index: location.statement_index - 1,
+            });
+        }
+        self.curr_stmt = None;
+        self.end_stmt = None;
+        self.cursor
+            .get()
+            .get_curr_fpcs()
+            .after
+            .capability_of(place, self.repacker())
+    }
+
+    /// The accesses of the body to places that lack the capability required by
+    /// the access, e.g. reads of moved-out places. Resets the position of the
+    /// cursor.
+    pub fn capability_errors(&mut self) -> Vec<CapabilityError<'tcx>> {
+        let body = self.body();
+        let mut errors = Vec::new();
+        for (block, data) in body.basic_blocks.iter_enumerated() {
+            for statement_index in 0..=data.statements.len() {
+                self.cursor.seek_after_primary_effect(Location {
+                    block,
+                    statement_index,
+                });
+                errors.extend(
+                    self.cursor
+                        .get()
+                        .get_curr_fpcs()
+                        .capability_errors
+                        .iter()
+                        .cloned(),
+                );
+            }
+        }
+        self.curr_stmt = None;
+        self.end_stmt = None;
+        errors
+    }
+
     /// Returns the free pcs for the location `exp_loc` and iterates the cursor
     /// to the *end* of that location.
     pub fn next(&mut self, exp_loc: Location) -> FreePcsLocation<'tcx, T, D::ExtraBridge> {
@@ -138,7 +222,11 @@ impl<
         let state = self.cursor.get();
 
         let after = state.get_curr_fpcs().after.clone();
-        let extra_after = state.get_extra();
+        let extra_after = if location.statement_index == 0 {
+            self.entry_extras[location.block].clone()
+        } else {
+            state.get_extra()
+        };
 
         self.cursor.seek_after_primary_effect(location);
 
@@ -186,7 +274,20 @@ impl<
                 // Get repacks
                 let entry_set = self.cursor.results().entry_set_for_block(succ);
                 let to = entry_set.get_curr_fpcs();
-                let extra_to = entry_set.get_extra();
+                let extra_to = self.entry_extras[succ].clone();
+                // The return edge of a call initializes its destination
+                let from = match block.terminator().kind {
+                    TerminatorKind::Call {
+                        destination,
+                        target: Some(target),
+                        ..
+                    } if target == succ => {
+                        let mut from = state.after.clone();
+                        from.call_return(destination.into(), rp);
+                        from
+                    }
+                    _ => state.after.clone(),
+                };
                 FreePcsLocation {
                     location: Location {
                         block: succ,
@@ -198,15 +299,71 @@ impl<
                         start: to.start.clone(),
                         after: to.after.clone(),
                     },
-                    repacks_start: state.after.bridge(&to.after, rp),
+                    repacks_start: from.bridge(&to.after, rp),
                     repacks_middle: Vec::new(),
-                    extra: entry_set.get_extra(),
+                    extra: self.entry_extras[succ].clone(),
                     extra_start: D::bridge_terminator(&extra, extra_to, succ, rp.tcx()),
                     extra_middle: None,
                 }
             })
             .collect();
-        FreePcsTerminator { succs }
+        FreePcsTerminator {
+            succs,
+            state: state.after,
+            extra,
+        }
+    }
+
+    /// The repacks required before the `Call` terminator at `location`, i.e.
+    /// those bringing the arguments into the form expected by the callee.
+    /// `None` if `location` is not that of a `Call` terminator. Resets the
+    /// position of the cursor.
+    pub fn call_prerequisites(&mut self, location: Location) -> Option<Vec<RepackOp<'tcx>>> {
+        if self.body().terminator_loc(location.block) != location
+            || !matches!(
+                self.body()[location.block].terminator().kind,
+                TerminatorKind::Call { .. }
+            )
+        {
+            return None;
+        }
+        self.analysis_for_bb(location.block);
+        while self.curr_stmt.unwrap() != location {
+            self.next(self.curr_stmt.unwrap());
+        }
+        let repacks = self.next(location).repacks_start;
+        self.curr_stmt = None;
+        self.end_stmt = None;
+        Some(repacks)
+    }
+
+    /// Calls `visitor` on every location of the body (as in
+    /// [`Self::get_all_for_bb`], the location of the terminator included),
+    /// then on the transitions to the successors of the block. Blocks are
+    /// visited in reverse postorder. Resets the position of the cursor.
+    pub fn visit_all(&mut self, visitor: &mut impl PcsVisitor<'tcx, T, D::ExtraBridge>) {
+        for &block in self.body().basic_blocks.reverse_postorder() {
+            let pcs_block = self.get_all_for_bb(block);
+            for statement in &pcs_block.statements {
+                visitor.visit_statement(statement.location, statement);
+            }
+            visitor.visit_terminator(block, &pcs_block.terminator);
+        }
+    }
+
+    /// The results of [`Self::get_all_for_bb`] for every reachable block of
+    /// the body, for consumers that need the results of several blocks at
+    /// once. Unreachable blocks, which [`Self::visit_all`] skips as well,
+    /// have no entry.
+    pub fn into_results_for_all_blocks(
+        mut self,
+    ) -> FxHashMap<BasicBlock, FreePcsBasicBlock<'tcx, T, D::ExtraBridge>> {
+        self.body()
+            .basic_blocks
+            .reverse_postorder()
+            .iter()
+            .map(|&block| (block, self.get_all_for_bb(block)))
+            .collect()
     }
 
     /// Recommended interface.
@@ -229,6 +386,18 @@ impl<
     }
 }
 
+/// A consumer of the results of the analysis, see
+/// [`FreePcsAnalysis::visit_all`]
+pub trait PcsVisitor<'tcx, T, A> {
+    fn visit_statement(&mut self, _location: Location, _statement: &FreePcsLocation<'tcx, T, A>) {}
+    fn visit_terminator(
+        &mut self,
+        _block: BasicBlock,
+        _terminator: &FreePcsTerminator<'tcx, T, A>,
+    ) {
+    }
+}
+
 pub struct FreePcsBasicBlock<'tcx, T, A> {
     pub statements: Vec<FreePcsLocation<'tcx, T, A>>,
     pub terminator: FreePcsTerminator<'tcx, T, A>,
@@ -258,4 +427,226 @@ pub struct FreePcsLocation<'tcx, T, A> {
 #[derive(Debug)]
 pub struct FreePcsTerminator<'tcx, T, A> {
     pub succs: Vec<FreePcsLocation<'tcx, T, A>>,
+    /// The capability summary just before the terminator executes
+    pub state: CapabilitySummary<'tcx>,
+    /// The extra state (e.g. borrows) just before the terminator executes
+    pub extra: T,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        free_pcs::CapabilityLocal,
+        r#loop::LoopAnalysis,
+        run_combined_pcs,
+        rustc_interface::{
+            data_structures::fx::FxHashSet,
+            middle::mir::{Operand, Rvalue, START_BLOCK},
+        },
+        test_utils::{find_statement, local_named, run_on_fn},
+        PcsOptions,
+    };
+
+    /// The borrow checker rejects the programs that access places without
+    /// the required capability, so the error is provoked by starting the
+    /// analysis from a state where the argument `x` is uninitialized
+    #[test]
+    fn read_of_uninitialized_argument_is_capability_error() {
+        run_on_fn("14_reborrow_targets.rs", "copy_ref", |tcx, body| {
+            let x = local_named(&body.body, "x");
+            let y = local_named(&body.body, "y");
+            let read = find_statement(&body.body, |statement| {
+                statement
+                    .kind
+                    .as_assign()
+                    .is_some_and(|(target, _)| target.local == y)
+            });
+            let cgx = Rc::new(PcsContext::new(tcx, body));
+            let loops = Rc::new(LoopAnalysis::find_loops(&body.body));
+            let mut initial_state =
+                PlaceCapabilitySummary::new(cgx, loops, Some(START_BLOCK), None, None);
+            initial_state.fpcs.initialize_as_start_block();
+            initial_state.borrows.initialize_as_start_block();
+            initial_state.fpcs.after[x] = CapabilityLocal::new(x, CapabilityKind::Write);
+            let mut output = run_combined_pcs(
+                body,
+                tcx,
+                None,
+                false,
+                Some(initial_state),
+                None,
+                PcsOptions::default(),
+            );
+            let errors = output.capability_errors();
+            assert!(errors.iter().any(|error| {
+                error.place == x.into()
+                    && error.required == CapabilityKind::Read
+                    && error.available == CapabilityKind::Write
+                    && error.location == read
+            }));
+        });
+    }
+
+    #[test]
+    fn loop_variable_is_exclusive_at_loop_head() {
+        run_on_fn("02_list_zero.rs", "all_zero", |tcx, body| {
+            let l = local_named(&body.body, "l");
+            // The head of the `while let` loop is the only non-cleanup block
+            // entered from two blocks
+            let blocks = &body.body.basic_blocks;
+            let loop_head = blocks
+                .indices()
+                .find(|block| !blocks[*block].is_cleanup && blocks.predecessors()[*block].len() > 1)
+                .unwrap();
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            assert_eq!(
+                output.capability_at(l.into(), loop_head.start_location()),
+                Some(CapabilityKind::Exclusive)
+            );
+        });
+    }
+
+    /// `y` is expanded by the read of `y.0`, so it is not a place of the
+    /// free PCS at the joins, but it is only borrowed by shared reborrows
+    #[test]
+    fn shared_reborrows_of_read_place_are_kept_at_join() {
+        run_on_fn("34_shared_borrows_and_read.rs", "main", |tcx, body| {
+            let y = local_named(&body.body, "y");
+            let e = local_named(&body.body, "e");
+            // The read of `e.1`, after the `assert!(g == 3)`
+            let read = find_statement(&body.body, |statement| {
+                statement.kind.as_assign().is_some_and(|(_, rvalue)| {
+                    matches!(rvalue, Rvalue::Use(Operand::Copy(place)) if place.local == e)
+                })
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let state = output
+                .get_all_for_bb(read.block)
+                .statements
+                .remove(0)
+                .extra
+                .before_start;
+            let blocked_y = Place::from(y).into();
+            assert_eq!(
+                state
+                    .reborrows()
+                    .iter()
+                    .filter(|reborrow| reborrow.value.blocked_place == blocked_y)
+                    .count(),
+                2
+            );
+        });
+    }
+
+    #[test]
+    fn move_is_last_capability_change_of_moved_local() {
+        run_on_fn("38_moved_out_local.rs", "main", |tcx, body| {
+            let s = local_named(&body.body, "s");
+            let moved = find_statement(&body.body, |statement| {
+                statement.kind.as_assign().is_some_and(|(_, rvalue)| {
+                    matches!(rvalue, Rvalue::Use(Operand::Move(place)) if place.local == s)
+                })
+            });
+            let last_change = |options| {
+                let mut output = run_combined_pcs(body, tcx, None, false, None, None, options);
+                output.analysis_for_bb(moved.block);
+                for statement_index in 0..=moved.statement_index {
+                    output.next(Location {
+                        block: moved.block,
+                        statement_index,
+                    });
+                }
+                output.last_capability_change(s.into())
+            };
+            assert_eq!(
+                last_change(PcsOptions {
+                    record_capability_changes: true,
+                    ..Default::default()
+                }),
+                Some(moved)
+            );
+            assert_eq!(last_change(PcsOptions::default()), None);
+        });
+    }
+
+    #[derive(Default)]
+    struct CountingVisitor {
+        statements: FxHashMap<BasicBlock, usize>,
+        terminators: usize,
+    }
+
+    impl<'tcx, T, A> PcsVisitor<'tcx, T, A> for CountingVisitor {
+        fn visit_statement(
+            &mut self,
+            location: Location,
+            _statement: &FreePcsLocation<'tcx, T, A>,
+        ) {
+            *self.statements.entry(location.block).or_default() += 1;
+        }
+
+        fn visit_terminator(
+            &mut self,
+            _block: BasicBlock,
+            _terminator: &FreePcsTerminator<'tcx, T, A>,
+        ) {
+            self.terminators += 1;
+        }
+    }
+
+    #[test]
+    fn visit_all_visits_every_statement_and_terminator() {
+        run_on_fn("02_list_zero.rs", "all_zero", |tcx, body| {
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let mut visitor = CountingVisitor::default();
+            output.visit_all(&mut visitor);
+            let blocks = &body.body.basic_blocks;
+            assert_eq!(visitor.terminators, blocks.len());
+            // The location of the terminator is visited as a statement too
+            for (block, data) in blocks.iter_enumerated() {
+                assert_eq!(visitor.statements[&block], data.statements.len() + 1);
+            }
+        });
+    }
+
+    #[test]
+    fn results_for_all_blocks_match_results_per_block() {
+        run_on_fn("02_list_zero.rs", "all_zero", |tcx, body| {
+            let blocks = &body.body.basic_blocks;
+            let loop_head = blocks
+                .indices()
+                .find(|block| !blocks[*block].is_cleanup && blocks.predecessors()[*block].len() > 1)
+                .unwrap();
+            let run =
+                || run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let mut all = run().into_results_for_all_blocks();
+            assert_eq!(
+                all.keys().copied().collect::<FxHashSet<_>>(),
+                blocks.reverse_postorder().iter().copied().collect()
+            );
+            let collected = all.remove(&loop_head).unwrap();
+            let expected = run().get_all_for_bb(loop_head);
+            assert_eq!(collected.statements.len(), expected.statements.len());
+            for (collected, expected) in collected.statements.iter().zip(&expected.statements) {
+                assert_eq!(collected.location, expected.location);
+                assert_eq!(collected.repacks_start, expected.repacks_start);
+                assert_eq!(collected.repacks_middle, expected.repacks_middle);
+                assert!(collected.states.after == expected.states.after);
+                assert!(collected.extra == expected.extra);
+            }
+            let (collected, expected) = (&collected.terminator, &expected.terminator);
+            assert!(collected.extra == expected.extra);
+            assert_eq!(collected.succs.len(), expected.succs.len());
+            for (collected, expected) in collected.succs.iter().zip(&expected.succs) {
+                assert_eq!(collected.location, expected.location);
+                assert_eq!(collected.repacks_start, expected.repacks_start);
+                assert!(collected.extra == expected.extra);
+            }
+        });
+    }
 }
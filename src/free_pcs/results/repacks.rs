@@ -7,8 +7,14 @@
 use std::fmt::{Display, Formatter, Result};
 
 use rustc_interface::middle::mir::Local;
+use serde_json::json;
 
-use crate::{free_pcs::CapabilityKind, rustc_interface, utils::Place};
+use crate::{
+    borrows::domain::ToJsonWithRepacker,
+    free_pcs::CapabilityKind,
+    rustc_interface,
+    utils::{Place, PlaceRepacker},
+};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum RepackOp<'tcx> {
@@ -85,3 +91,43 @@ impl<'tcx> RepackOp<'tcx> {
         }
     }
 }
+
+impl<'tcx> ToJsonWithRepacker<'tcx> for RepackOp<'tcx> {
+    fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
+        let place = |place: Place<'tcx>| place.to_json(repacker);
+        let capability = |kind: CapabilityKind| format!("{:?}", kind);
+        match *self {
+            RepackOp::StorageDead(local) => json!({
+                "type": "StorageDead",
+                "place": place(local.into()),
+            }),
+            RepackOp::IgnoreStorageDead(local) => json!({
+                "type": "IgnoreStorageDead",
+                "place": place(local.into()),
+            }),
+            RepackOp::Weaken(weakened, from, to) => json!({
+                "type": "Weaken",
+                "place": place(weakened),
+                "from": capability(from),
+                "to": capability(to),
+            }),
+            RepackOp::Expand(from, guide, kind) => json!({
+                "type": "Expand",
+                "place": place(from),
+                "guide": place(guide),
+                "capability": capability(kind),
+            }),
+            RepackOp::Collapse(to, guide, kind) => json!({
+                "type": "Collapse",
+                "place": place(to),
+                "guide": place(guide),
+                "capability": capability(kind),
+            }),
+            RepackOp::DerefShallowInit(from, to) => json!({
+                "type": "DerefShallowInit",
+                "place": place(from),
+                "guide": place(to),
+            }),
+        }
+    }
+}
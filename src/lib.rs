@@ -16,18 +16,38 @@ pub mod rustc_interface;
 pub mod utils;
 pub mod visualization;
 
-use std::fs::create_dir_all;
+#[cfg(test)]
+mod test_utils;
+
+use std::{cell::RefCell, collections::BTreeMap, io::Write, rc::Rc};
 
 use borrows::{
-    borrows_graph::Conditioned, borrows_visitor::DebugCtx, deref_expansion::DerefExpansion,
-    domain::Reborrow, engine::BorrowsDomain, unblock_graph::UnblockGraph,
+    borrows_graph::{BorrowsEdgeKind, Conditioned},
+    borrows_state::{AgingPolicy, BorrowsState, SharedReborrowPolicy},
+    borrows_visitor::DebugCtx,
+    constraints::{Constraints, Fact},
+    deref_expansion::DerefExpansion,
+    domain::Reborrow,
+    engine::{BorrowsDomain, BorrowsEngine},
+    unblock_graph::{BorrowCycle, UnblockGraph},
+};
+use combined_pcs::{
+    BodyWithBorrowckFacts, Checkpoint, DotGraphs, PcsContext, PcsEngine, PcsStats,
+    PlaceCapabilitySummary, UnblockAction,
 };
-use combined_pcs::{BodyWithBorrowckFacts, PcsContext, PcsEngine, PlaceCapabilitySummary};
-use free_pcs::HasExtra;
+use free_pcs::{CapabilityLocal, CapabilitySummary, HasExtra};
 use rustc_interface::{
-    data_structures::fx::FxHashSet,
-    dataflow::Analysis,
-    middle::{mir::BasicBlock, ty::TyCtxt},
+    data_structures::fx::{FxHashMap, FxHashSet},
+    dataflow::{Analysis, JoinSemiLattice, ResultsCursor},
+    index::IndexVec,
+    middle::{
+        mir::{
+            self,
+            visit::{PlaceContext, Visitor},
+            BasicBlock, Body, Location,
+        },
+        ty::TyCtxt,
+    },
 };
 use serde_json::json;
 use utils::PlaceRepacker;
@@ -43,6 +63,18 @@ pub type FpcsOutput<'mir, 'tcx> = free_pcs::FreePcsAnalysis<
     PcsEngine<'mir, 'tcx>,
 >;
 
+/// The results of [`run_free_pcs`], which has no borrows state
+pub type FreePcsOutput<'mir, 'tcx> = free_pcs::FreePcsAnalysis<
+    'mir,
+    'tcx,
+    (),
+    free_pcs::FreePlaceCapabilitySummary<'mir, 'tcx>,
+    free_pcs::engine::FpcsEngine<'mir, 'tcx>,
+>;
+
+/// The results of [`run_borrows`], which has no free PCS
+pub type BorrowsOutput<'mir, 'tcx> = ResultsCursor<'mir, 'tcx, BorrowsEngine<'mir, 'tcx>>;
+
 #[derive(Clone, Debug)]
 pub struct ReborrowBridge<'tcx> {
     pub expands: FxHashSet<Conditioned<DerefExpansion<'tcx>>>,
@@ -74,6 +106,15 @@ impl<'mir, 'tcx> HasExtra<BorrowsDomain<'mir, 'tcx>> for PlaceCapabilitySummary<
         self.borrows.clone()
     }
 
+    /// Edges that only differ by their conditions are merged in the entry
+    /// states, the effects applied once the fixpoint is reached do the same
+    /// (see [`PcsEngine::set_fixpoint_reached`])
+    fn get_canonical_extra(&self) -> BorrowsDomain<'mir, 'tcx> {
+        let mut borrows = self.borrows.clone();
+        borrows.canonicalize();
+        borrows
+    }
+
     fn bridge_between_stmts(
         lhs: BorrowsDomain<'mir, 'tcx>,
         rhs: BorrowsDomain<'mir, 'tcx>,
@@ -100,18 +141,73 @@ lazy_static::lazy_static! {
     static ref RECORD_PCS: Mutex<bool> = Mutex::new(false);
 }
 
+/// Options of [`run_combined_pcs`] that do not depend on the body
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PcsOptions {
+    /// See [`PcsEngine::set_aging_policy`]
+    pub aging_policy: AgingPolicy,
+    /// See [`PcsEngine::set_shared_reborrow_policy`]
+    pub shared_reborrow_policy: SharedReborrowPolicy,
+    /// Collect statistics on the fixpoint computation (see
+    /// [`FpcsOutput::stats`]) and, if the visualization is written, write them
+    /// to `stats.json`
+    pub collect_stats: bool,
+    /// Record the location of the last capability change of each place, see
+    /// [`free_pcs::FreePcsAnalysis::last_capability_change`]
+    pub record_capability_changes: bool,
+    /// Record the violated assumptions of the analysis (see
+    /// [`utils::assumptions::take_assumption_violations`]) and skip the
+    /// constructs that violate them, rather than panicking
+    pub lenient: bool,
+}
+
+/// Runs the combined PCS analysis on `mir`.
+///
+/// If `visualization_output_path` is set, the JSON files for the visualization
+/// are written to that directory. With `stream_output`, the files of each block
+/// are written as soon as the results cursor reaches it, and the block is then
+/// recorded as one line of `completed_blocks.jsonl`.
+///
+/// If `initial_state` is set, the analysis starts from it rather than from the
+/// default state derived from the signature, see
+/// [`PcsEngine::set_initial_state`] for the conditions it must satisfy.
+///
+/// If `checkpoint` is set, the entry states it contains are used as the
+/// starting point of the fixpoint, see [`Checkpoint`].
+///
+/// The other parameters of the analysis are set by `options`, see
+/// [`PcsOptions`].
 pub fn run_combined_pcs<'mir, 'tcx>(
     mir: &'mir BodyWithBorrowckFacts<'tcx>,
     tcx: TyCtxt<'tcx>,
     visualization_output_path: Option<String>,
+    stream_output: bool,
+    initial_state: Option<PlaceCapabilitySummary<'mir, 'tcx>>,
+    checkpoint: Option<Checkpoint<'tcx>>,
+    options: PcsOptions,
 ) -> FpcsOutput<'mir, 'tcx> {
     let cgx = PcsContext::new(tcx, mir);
-    let fpcs = PcsEngine::new(cgx, visualization_output_path.clone());
+    let mut fpcs = PcsEngine::new(cgx, visualization_output_path.clone());
+    if let Some(initial_state) = initial_state {
+        fpcs.set_initial_state(initial_state);
+    }
+    if let Some(checkpoint) = checkpoint {
+        fpcs.set_checkpoint(checkpoint);
+    }
+    fpcs.set_aging_policy(options.aging_policy);
+    fpcs.set_shared_reborrow_policy(options.shared_reborrow_policy);
+    fpcs.set_lenient(options.lenient);
+    if options.collect_stats {
+        fpcs.enable_stats();
+    }
+    if options.record_capability_changes {
+        fpcs.enable_capability_changes();
+    }
     {
         let mut record_pcs = RECORD_PCS.lock().unwrap();
         *record_pcs = true;
     }
-    let analysis = fpcs
+    let mut analysis = fpcs
         .into_engine(tcx, &mir.body)
         .pass_name("free_pcs")
         .iterate_to_fixpoint();
@@ -119,43 +215,849 @@ pub fn run_combined_pcs<'mir, 'tcx>(
         let mut record_pcs = RECORD_PCS.lock().unwrap();
         *record_pcs = false;
     }
-    if let Some(dir_path) = &visualization_output_path {
-        for block in mir.body.basic_blocks.indices() {
-            let state = analysis.entry_set_for_block(block);
-            assert!(state.block() == block);
-            let block_iterations_json_file =
-                format!("{}/block_{}_iterations.json", dir_path, block.index());
-            state
-                .dot_graphs()
-                .borrow()
-                .write_json_file(&block_iterations_json_file);
-        }
-    }
+    analysis.analysis.set_fixpoint_reached();
     let mut fpcs_analysis = free_pcs::FreePcsAnalysis::new(analysis.into_results_cursor(&mir.body));
 
     if let Some(dir_path) = visualization_output_path {
-        generate_json_from_mir(&format!("{}/mir.json", dir_path), tcx, &mir.body)
-            .expect("Failed to generate JSON from MIR");
-
         let rp = PcsContext::new(tcx, mir).rp;
+        if stream_output {
+            generate_json_from_mir(&format!("{}/mir.json", dir_path), tcx, &mir.body)
+                .expect("Failed to generate JSON from MIR");
+            let mut completed_blocks =
+                std::fs::File::create(format!("{}/completed_blocks.jsonl", dir_path))
+                    .expect("Failed to create completed blocks file");
+            for block in mir.body.basic_blocks.indices() {
+                write_block_iterations_json(&fpcs_analysis, block, &dir_path);
+                write_block_borrows_json(&mut fpcs_analysis, block, &dir_path, rp);
+                writeln!(completed_blocks, "{}", json!({ "block": block.index() }))
+                    .and_then(|_| completed_blocks.flush())
+                    .expect("Failed to write completed block to JSONL file");
+            }
+        } else {
+            for block in mir.body.basic_blocks.indices() {
+                write_block_iterations_json(&fpcs_analysis, block, &dir_path);
+            }
+            generate_json_from_mir(&format!("{}/mir.json", dir_path), tcx, &mir.body)
+                .expect("Failed to generate JSON from MIR");
+            for block in mir.body.basic_blocks.indices() {
+                write_block_borrows_json(&mut fpcs_analysis, block, &dir_path, rp);
+            }
+        }
+        let lines_json = fpcs_analysis.results_by_line_json();
+        std::fs::write(
+            format!("{}/lines.json", dir_path),
+            serde_json::to_string_pretty(&lines_json).unwrap(),
+        )
+        .expect("Failed to write results by line to JSON file");
+        std::fs::write(
+            format!("{}/lifetimes.json", dir_path),
+            serde_json::to_string_pretty(&borrows::lifetimes::borrow_lifetimes_json(mir, rp))
+                .unwrap(),
+        )
+        .expect("Failed to write borrow lifetimes to JSON file");
+        if let Some(stats) = fpcs_analysis.stats() {
+            std::fs::write(
+                format!("{}/stats.json", dir_path),
+                serde_json::to_string_pretty(&stats.to_json()).unwrap(),
+            )
+            .expect("Failed to write statistics to JSON file");
+        }
+    }
 
-        // Iterate over each statement in the MIR
-        for (block, _data) in mir.body.basic_blocks.iter_enumerated() {
-            let pcs_block = fpcs_analysis.get_all_for_bb(block);
-            for (statement_index, statement) in pcs_block.statements.iter().enumerate() {
-                let borrows_file_path = format!(
-                    "{}/block_{}_stmt_{}_borrows.json",
-                    &dir_path,
-                    block.index(),
-                    statement_index
+    fpcs_analysis
+}
+
+/// Runs the free PCS analysis alone on `body`, which can be any MIR body, e.g.
+/// the `optimized_mir` of a function from another crate.
+///
+/// Unlike [`run_combined_pcs`], this doesn't require the borrow checker facts
+/// of the body, which are only available for the bodies of the local crate.
+/// The analysis is unaware of borrows: references are treated as ordinary
+/// values and no reborrows, region abstractions or repacks caused by the
+/// expiry of borrows are computed. Capabilities of places behind references
+/// are therefore only meaningful up to the dereference.
+pub fn run_free_pcs<'mir, 'tcx>(
+    body: &'mir Body<'tcx>,
+    tcx: TyCtxt<'tcx>,
+) -> FreePcsOutput<'mir, 'tcx> {
+    let fpcs = free_pcs::engine::FpcsEngine(PlaceRepacker::new(body, tcx));
+    let analysis = fpcs
+        .into_engine(tcx, body)
+        .pass_name("free_pcs")
+        .iterate_to_fixpoint();
+    free_pcs::FreePcsAnalysis::new(analysis.into_results_cursor(body))
+}
+
+/// Runs the borrows analysis alone on `mir`, without the free PCS.
+///
+/// The borrows state at each location is computed as in [`run_combined_pcs`],
+/// but without the capabilities of the places, and therefore without the
+/// repacks between locations. Only the policies of `options` are used.
+pub fn run_borrows<'mir, 'tcx>(
+    mir: &'mir BodyWithBorrowckFacts<'tcx>,
+    tcx: TyCtxt<'tcx>,
+    options: PcsOptions,
+) -> BorrowsOutput<'mir, 'tcx> {
+    let mut borrows = BorrowsEngine::new(
+        tcx,
+        &mir.body,
+        mir.location_table.as_ref().unwrap(),
+        mir.input_facts.as_ref().unwrap(),
+        mir.borrow_set.clone(),
+        mir.region_inference_context.clone(),
+        mir.output_facts.as_deref(),
+    );
+    borrows.aging_policy = options.aging_policy;
+    borrows.shared_reborrow_policy = options.shared_reborrow_policy;
+    borrows.lenient = options.lenient;
+    let analysis = borrows
+        .into_engine(tcx, &mir.body)
+        .pass_name("borrows")
+        .iterate_to_fixpoint();
+    analysis.into_results_cursor(&mir.body)
+}
+
+fn write_block_iterations_json(fpcs_analysis: &FpcsOutput<'_, '_>, block: BasicBlock, dir_path: &str) {
+    let state = fpcs_analysis.cursor.results().entry_set_for_block(block);
+    assert!(state.block() == block);
+    let block_iterations_json_file =
+        format!("{}/block_{}_iterations.json", dir_path, block.index());
+    state
+        .dot_graphs()
+        .borrow()
+        .write_json_file(&block_iterations_json_file);
+    let block_timeline_json_file = format!("{}/block_{}_timeline.json", dir_path, block.index());
+    std::fs::write(
+        &block_timeline_json_file,
+        serde_json::to_string_pretty(&state.dot_graphs().borrow().timeline_json()).unwrap(),
+    )
+    .expect("Failed to write timeline to JSON file");
+}
+
+fn write_block_borrows_json<'mir, 'tcx>(
+    fpcs_analysis: &mut FpcsOutput<'mir, 'tcx>,
+    block: BasicBlock,
+    dir_path: &str,
+    rp: PlaceRepacker<'mir, 'tcx>,
+) {
+    let pcs_block = fpcs_analysis.get_all_for_bb(block);
+    for (statement_index, statement) in pcs_block.statements.iter().enumerate() {
+        let borrows_file_path = format!(
+            "{}/block_{}_stmt_{}_borrows.json",
+            dir_path,
+            block.index(),
+            statement_index
+        );
+        let borrows_json = serde_json::to_string_pretty(&statement.extra.to_json(rp)).unwrap();
+        std::fs::write(&borrows_file_path, borrows_json)
+            .expect("Failed to write borrows to JSON file");
+        let fpcs_file_path = format!(
+            "{}/block_{}_stmt_{}_fpcs.json",
+            dir_path,
+            block.index(),
+            statement_index
+        );
+        let fpcs_json = serde_json::to_string_pretty(&statement.states.after.to_json(rp)).unwrap();
+        std::fs::write(&fpcs_file_path, fpcs_json)
+            .expect("Failed to write capability summary to JSON file");
+        let repacks_file_path = format!(
+            "{}/block_{}_stmt_{}_repacks.json",
+            dir_path,
+            block.index(),
+            statement_index
+        );
+        let repacks_json = json!({
+            "start": statement
+                .repacks_start
+                .iter()
+                .map(|op| op.to_json(rp))
+                .collect::<Vec<_>>(),
+            "middle": statement
+                .repacks_middle
+                .iter()
+                .map(|op| op.to_json(rp))
+                .collect::<Vec<_>>(),
+        });
+        std::fs::write(
+            &repacks_file_path,
+            serde_json::to_string_pretty(&repacks_json).unwrap(),
+        )
+        .expect("Failed to write repacks to JSON file");
+    }
+}
+
+/// The results of the analysis at a single location, see
+/// [`FpcsOutput::results_by_line`]
+pub struct LocationResults<'mir, 'tcx> {
+    pub location: Location,
+    /// The free PCS after the statement, or before the terminator
+    pub capabilities: CapabilitySummary<'tcx>,
+    /// The borrows after the statement, or before the terminator
+    pub borrows: BorrowsDomain<'mir, 'tcx>,
+}
+
+/// A join that still changes the entry state of a block once the fixpoint is
+/// reached, see [`FpcsOutput::check_fixpoint`]
+#[derive(Clone, Debug)]
+pub struct FixpointViolation {
+    pub block: BasicBlock,
+    /// The predecessor whose exit state was joined into the entry state of
+    /// `block`
+    pub predecessor: BasicBlock,
+    /// Whether the join reported a change. If not, the change went unnoticed
+    /// by the fixpoint computation.
+    pub join_reported_change: bool,
+    pub fpcs_changed: bool,
+    pub borrows_changed: bool,
+}
+
+impl std::fmt::Display for FixpointViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let changed = match (self.fpcs_changed, self.borrows_changed) {
+            (true, true) => "the free PCS and the borrows",
+            (true, false) => "the free PCS",
+            (false, true) => "the borrows",
+            (false, false) => unreachable!(),
+        };
+        write!(
+            f,
+            "joining the exit state of {:?} into the entry state of {:?} changed {} (join {} a change)",
+            self.predecessor,
+            self.block,
+            changed,
+            if self.join_reported_change {
+                "reported"
+            } else {
+                "did not report"
+            }
+        )
+    }
+}
+
+/// Statistics on how much work the analysis of a body required
+#[derive(Clone, Debug, Default)]
+pub struct ComplexityMetrics {
+    /// The longest chain of reborrows at any point in the body
+    pub max_reborrow_chain_length: usize,
+    /// The number of distinct region abstractions created in the body
+    pub num_region_abstractions: usize,
+    /// The longest chain of deref expansions at any point in the body
+    pub max_deref_expansion_depth: usize,
+    /// The number of times the fixpoint computation joined into each block
+    pub iterations_per_block: IndexVec<BasicBlock, usize>,
+}
+
+impl<'mir, 'tcx> FpcsOutput<'mir, 'tcx> {
+    pub fn complexity_metrics(&mut self) -> ComplexityMetrics {
+        let rp = self.repacker();
+        let mut metrics = ComplexityMetrics::default();
+        let mut region_abstractions = FxHashSet::default();
+        for block in rp.body().basic_blocks.indices() {
+            let entry_state = self.cursor.results().entry_set_for_block(block);
+            metrics
+                .iterations_per_block
+                .push(entry_state.dot_graphs().borrow().num_joins());
+            for statement in self.get_all_for_bb(block).statements {
+                let borrows = &statement.extra.after;
+                metrics.max_reborrow_chain_length = metrics
+                    .max_reborrow_chain_length
+                    .max(borrows.max_reborrow_chain_length());
+                metrics.max_deref_expansion_depth = metrics
+                    .max_deref_expansion_depth
+                    .max(borrows.max_deref_expansion_depth(rp));
+                region_abstractions.extend(
+                    borrows
+                        .region_abstractions()
+                        .into_iter()
+                        .map(|abstraction| abstraction.value.location()),
                 );
-                let borrows_json =
-                    serde_json::to_string_pretty(&statement.extra.to_json(rp)).unwrap();
-                std::fs::write(&borrows_file_path, borrows_json)
-                    .expect("Failed to write borrows to JSON file");
             }
         }
+        metrics.num_region_abstractions = region_abstractions.len();
+        metrics
     }
 
-    fpcs_analysis
+    /// The statistics of the fixpoint computation, if enabled (see
+    /// [`run_combined_pcs`]), with the number of joins into each block.
+    pub fn stats(&self) -> Option<PcsStats> {
+        let mut stats = self.cursor.analysis().stats()?.clone();
+        for block in self.repacker().body().basic_blocks.indices() {
+            let entry_state = self.cursor.results().entry_set_for_block(block);
+            stats.block_mut(block).num_joins = entry_state.dot_graphs().borrow().num_joins();
+        }
+        Some(stats)
+    }
+
+    /// Checks that the fixpoint was actually reached: joining the exit state
+    /// of each (reachable) block into the entry state of each of its
+    /// successors must leave the latter unchanged. A violation points to a
+    /// join that under-reports its changes, causing the fixpoint computation
+    /// to stop early. Joins that report a change without changing anything
+    /// are not violations. Resets the position of the cursor.
+    pub fn check_fixpoint(&mut self) -> Vec<FixpointViolation> {
+        let body = self.repacker().body();
+        let reachable = mir::traversal::reachable_as_bitset(body);
+        let mut violations = vec![];
+        for (predecessor, data) in body.basic_blocks.iter_enumerated() {
+            if !reachable.contains(predecessor) {
+                continue;
+            }
+            self.cursor.seek_to_block_end(predecessor);
+            let exit = self.cursor.get().clone();
+            for block in data.terminator().successors() {
+                if block == mir::START_BLOCK {
+                    continue;
+                }
+                let entry = self.cursor.results().entry_set_for_block(block);
+                let mut exit = exit.clone();
+                // The return edge of a call initializes its destination
+                if let mir::TerminatorKind::Call {
+                    destination,
+                    target: Some(target),
+                    ..
+                } = data.terminator().kind
+                {
+                    if target == block {
+                        exit.fpcs
+                            .after
+                            .call_return(destination.into(), self.repacker());
+                    }
+                }
+                let mut joined = entry.clone();
+                // Don't record the join in the iterations of the block
+                joined.set_dot_graphs(Rc::new(RefCell::new(DotGraphs::new())));
+                let join_reported_change = joined.join(&exit);
+                let fpcs_changed = joined.fpcs != entry.fpcs;
+                let borrows_changed = joined.borrows != entry.borrows;
+                if fpcs_changed || borrows_changed {
+                    violations.push(FixpointViolation {
+                        block,
+                        predecessor,
+                        join_reported_change,
+                        fpcs_changed,
+                        borrows_changed,
+                    });
+                }
+            }
+        }
+        violations
+    }
+
+    /// The free PCS at the entry of every block, to be saved with
+    /// [`Checkpoint::to_json`] and used to speed up a later run on an edited
+    /// version of the body.
+    pub fn checkpoint(&self) -> Checkpoint<'tcx> {
+        let body = self.repacker().body();
+        Checkpoint::new(
+            body.basic_blocks
+                .indices()
+                .map(|block| {
+                    Some(
+                        self.cursor
+                            .results()
+                            .entry_set_for_block(block)
+                            .fpcs
+                            .after
+                            .clone(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// The cycles in the borrows graph encountered while computing the
+    /// bridges of the results, with the location of the statement the bridge
+    /// leads to. The unblock actions of such bridges only approximate the
+    /// unblocking that is actually required.
+    pub fn borrow_cycles(&mut self) -> Vec<(Location, BorrowCycle<'tcx>)> {
+        let mut cycles = vec![];
+        for block in self.repacker().body().basic_blocks.indices() {
+            let pcs_block = self.get_all_for_bb(block);
+            for statement in pcs_block.statements {
+                let bridges =
+                    std::iter::once(&statement.extra_start).chain(statement.extra_middle.as_ref());
+                for bridge in bridges {
+                    cycles.extend(
+                        bridge
+                            .ug
+                            .cycles()
+                            .iter()
+                            .map(|cycle| (statement.location, cycle.clone())),
+                    );
+                }
+            }
+        }
+        cycles
+    }
+
+    /// The reborrows terminated by the bridges between the start of the block
+    /// of `location` and the statement at `location` (included), i.e. those
+    /// that must have been ended before the statement takes effect.
+    pub fn reborrows_ending_before(
+        &mut self,
+        location: Location,
+    ) -> Vec<Conditioned<Reborrow<'tcx>>> {
+        let rp = self.repacker();
+        let mut before = self
+            .cursor
+            .results()
+            .entry_set_for_block(location.block)
+            .borrows
+            .after
+            .clone();
+        let mut ending = vec![];
+        let pcs_block = self.get_all_for_bb(location.block);
+        for statement in pcs_block
+            .statements
+            .into_iter()
+            .take(location.statement_index + 1)
+        {
+            // The start bridge tears down reborrows of the state after the
+            // previous statement, the middle one those of `before_after`
+            let bridges = std::iter::once((&statement.extra_start, &before)).chain(
+                statement
+                    .extra_middle
+                    .as_ref()
+                    .map(|middle| (middle, &statement.extra.before_after)),
+            );
+            for (bridge, state) in bridges {
+                let reborrows = state.reborrows();
+                // The actions of a graph with cycles are an approximation,
+                // which is good enough to determine the ending reborrows
+                let actions = bridge
+                    .ug
+                    .simulate(rp)
+                    .unwrap_or_else(|err| err.approximate_actions);
+                for action in actions {
+                    if let UnblockAction::TerminateReborrow {
+                        reserve_location,
+                        blocked_place,
+                        assigned_place,
+                        ..
+                    } = action
+                    {
+                        let reborrow = reborrows.iter().find(|reborrow| {
+                            reborrow.value.reserve_location() == reserve_location
+                                && reborrow.value.blocked_place == blocked_place
+                                && reborrow.value.assigned_place == assigned_place
+                        });
+                        if let Some(reborrow) = reborrow
+                            && !ending.contains(reborrow)
+                        {
+                            ending.push(reborrow.clone());
+                        }
+                    }
+                }
+            }
+            before = statement.extra.after;
+        }
+        ending
+    }
+
+    /// The places mutated (assigned to or mutably borrowed) while a raw
+    /// pointer to them may be outstanding, i.e. while they have
+    /// [`free_pcs::CapabilityKind::Aliased`]. Such mutations potentially alias
+    /// accesses through the raw pointer.
+    pub fn potentially_aliasing_writes(&mut self) -> Vec<(Location, utils::Place<'tcx>)> {
+        let body = self.repacker().body();
+        let mut writes = vec![];
+        for block in body.basic_blocks.indices() {
+            let pcs_block = self.get_all_for_bb(block);
+            for statement in pcs_block.statements {
+                let Some(mir::StatementKind::Assign(box (target, rvalue))) = body[block]
+                    .statements
+                    .get(statement.location.statement_index)
+                    .map(|s| &s.kind)
+                else {
+                    continue;
+                };
+                let mut mutated = vec![*target];
+                if let mir::Rvalue::Ref(_, mir::BorrowKind::Mut { .. }, place)
+                | mir::Rvalue::RawPtr(mir::Mutability::Mut, place) = rvalue
+                {
+                    mutated.push(*place);
+                }
+                for place in mutated {
+                    let place: utils::Place<'tcx> = place.into();
+                    let free_pcs::CapabilityLocal::Allocated(projs) =
+                        &statement.states.before_start[place.local]
+                    else {
+                        continue;
+                    };
+                    if projs.iter().any(|(p, kind)| {
+                        kind.is_aliased() && (p.is_prefix(place) || place.is_prefix(*p))
+                    }) {
+                        writes.push((statement.location, place));
+                    }
+                }
+            }
+        }
+        writes
+    }
+
+    /// Deref expansions whose children were neither accessed nor connected to
+    /// another edge between the statement that created the expansion and the
+    /// one that collapsed it. Only expansions created and collapsed within
+    /// the same block are reported, since the others may be used in another
+    /// block.
+    pub fn redundant_expansions(&mut self) -> Vec<DerefExpansion<'tcx>> {
+        let rp = self.repacker();
+        let mut redundant = vec![];
+        for block in rp.body().basic_blocks.indices() {
+            // The expansions present after the previous statement, and whether
+            // one of their children has been used since they were created.
+            // Those present on entry were created in a predecessor and may
+            // have been used there.
+            let mut live: FxHashMap<DerefExpansion<'tcx>, bool> = self
+                .cursor
+                .results()
+                .entry_set_for_block(block)
+                .borrows
+                .after
+                .deref_expansions()
+                .into_iter()
+                .map(|expansion| (expansion.value, true))
+                .collect();
+            for statement in self.get_all_for_bb(block).statements {
+                let state = &statement.extra.after;
+                let expansions = state
+                    .deref_expansions()
+                    .into_iter()
+                    .map(|expansion| expansion.value)
+                    .collect::<FxHashSet<_>>();
+                redundant.extend(
+                    live.extract_if(|expansion, _| !expansions.contains(expansion))
+                        .filter(|(_, used)| !used)
+                        .map(|(expansion, _)| expansion),
+                );
+                let accessed = places_mentioned_at(rp.body(), statement.location);
+                // Places that some other edge is attached to (the children of
+                // an expansion are only connected to the expansion itself)
+                let connected = state
+                    .graph_edges()
+                    .flat_map(|edge| {
+                        let blocked_by = match edge.kind() {
+                            BorrowsEdgeKind::DerefExpansion(_) => FxHashSet::default(),
+                            _ => edge.blocked_by_places(rp),
+                        };
+                        edge.blocked_places()
+                            .into_iter()
+                            .filter_map(|place| place.as_local())
+                            .chain(blocked_by)
+                    })
+                    .collect::<FxHashSet<_>>();
+                for expansion in expansions {
+                    let used = expansion.expansion(rp).into_iter().any(|child| {
+                        connected.contains(&child)
+                            || (child.is_current()
+                                && accessed.iter().any(|place| child.place().is_prefix(*place)))
+                    });
+                    *live.entry(expansion).or_insert(false) |= used;
+                }
+            }
+        }
+        redundant
+    }
+
+    /// The results of the analysis grouped by the source line (1-based) of
+    /// the span of each location, in the order of the MIR. A line usually
+    /// corresponds to several statements; locations expanded from a macro are
+    /// attributed to the line of the macro call.
+    pub fn results_by_line(&mut self) -> BTreeMap<usize, Vec<LocationResults<'mir, 'tcx>>> {
+        let rp = self.repacker();
+        let source_map = rp.tcx().sess.source_map();
+        let mut results: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for block in rp.body().basic_blocks.indices() {
+            let pcs_block = self.get_all_for_bb(block);
+            let terminator_location = rp.body().terminator_loc(block);
+            let locations = pcs_block
+                .statements
+                .into_iter()
+                .map(|statement| LocationResults {
+                    location: statement.location,
+                    capabilities: statement.states.after,
+                    borrows: statement.extra,
+                })
+                .chain(std::iter::once(LocationResults {
+                    location: terminator_location,
+                    capabilities: pcs_block.terminator.state,
+                    borrows: pcs_block.terminator.extra,
+                }));
+            for location_results in locations {
+                let span = rp.body().source_info(location_results.location).span;
+                let line = source_map.lookup_char_pos(span.source_callsite().lo()).line;
+                results.entry(line).or_default().push(location_results);
+            }
+        }
+        results
+    }
+
+    /// [`Self::results_by_line`] as JSON, mapping each line to the list of
+    /// the locations on it, with their capabilities and borrows
+    pub fn results_by_line_json(&mut self) -> serde_json::Value {
+        let rp = self.repacker();
+        let lines = self
+            .results_by_line()
+            .into_iter()
+            .map(|(line, locations)| {
+                let locations = locations
+                    .iter()
+                    .map(|results| {
+                        let capabilities = results
+                            .capabilities
+                            .iter()
+                            .filter_map(|local| match local {
+                                CapabilityLocal::Unallocated => None,
+                                CapabilityLocal::Allocated(projections) => Some(projections),
+                            })
+                            .flat_map(|projections| projections.iter())
+                            .map(|(place, kind)| {
+                                (place.to_short_string(rp), json!(format!("{:?}", kind)))
+                            })
+                            .collect::<serde_json::Map<_, _>>();
+                        json!({
+                            "location": format!("{:?}", results.location),
+                            "capabilities": capabilities,
+                            "borrows": results.borrows.to_json(rp),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                (line.to_string(), json!(locations))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::Value::Object(lines)
+    }
+
+    /// The borrow relationships of the body as a flat fact base: the
+    /// `outlives` constraints of region inference, together with the
+    /// `blocks` and `reborrow` facts of every state reached by the analysis.
+    /// See [`Constraints`] for the textual format.
+    pub fn to_constraints(&mut self) -> Constraints {
+        let rp = self.repacker();
+        let mut constraints = Constraints::new();
+        for constraint in self
+            .cursor
+            .analysis()
+            .cgx
+            .mir
+            .region_inference_context
+            .outlives_constraints()
+        {
+            constraints.insert(Fact::Outlives {
+                sup: constraint.sup,
+                sub: constraint.sub,
+            });
+        }
+        for block in rp.body().basic_blocks.indices() {
+            let pcs_block = self.get_all_for_bb(block);
+            let states = pcs_block
+                .statements
+                .iter()
+                .map(|statement| &statement.extra.after)
+                .chain(std::iter::once(&pcs_block.terminator.extra.after));
+            for state in states {
+                add_borrows_facts(&mut constraints, state, rp);
+            }
+        }
+        constraints
+    }
+}
+
+/// The places accessed by the statement or terminator at `location`
+fn places_mentioned_at<'tcx>(
+    body: &mir::Body<'tcx>,
+    location: Location,
+) -> Vec<utils::Place<'tcx>> {
+    struct PlaceCollector<'tcx>(Vec<utils::Place<'tcx>>);
+    impl<'tcx> Visitor<'tcx> for PlaceCollector<'tcx> {
+        fn visit_place(&mut self, place: &mir::Place<'tcx>, _: PlaceContext, _: Location) {
+            self.0.push((*place).into());
+        }
+    }
+    let mut collector = PlaceCollector(vec![]);
+    let data = &body[location.block];
+    match data.statements.get(location.statement_index) {
+        Some(statement) => collector.visit_statement(statement, location),
+        None => collector.visit_terminator(data.terminator(), location),
+    }
+    collector.0
+}
+
+fn add_borrows_facts<'tcx>(
+    constraints: &mut Constraints,
+    state: &BorrowsState<'tcx>,
+    rp: PlaceRepacker<'_, 'tcx>,
+) {
+    for edge in state.graph_edges() {
+        for blocking in edge.blocked_by_places(rp) {
+            for blocked in edge.blocked_places() {
+                constraints.insert(Fact::Blocks {
+                    blocked: blocked.to_string(),
+                    blocking: blocking.to_string(),
+                });
+            }
+        }
+    }
+    for reborrow in state.reborrows() {
+        constraints.insert(Fact::Reborrow {
+            location: reborrow.value.reserve_location(),
+            blocked: reborrow.value.blocked_place.to_string(),
+            assigned: reborrow.value.assigned_place.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        borrows::domain::{MaybeOldPlace, ReborrowBlockedPlace},
+        test_utils::{find_statement, local_named, run_on_all_fns, run_on_fn},
+    };
+
+    /// Joining the exit state of any block into the entry state of its
+    /// successors leaves the entry state unchanged, in every function of the
+    /// programs in `tests/`
+    #[test]
+    fn fixpoint_is_reached_in_all_fixtures() {
+        let dir = format!("{}/tests", env!("CARGO_MANIFEST_DIR"));
+        let mut files: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|file| {
+                file.ends_with(".rs")
+                    && file.split_once('_').is_some_and(|(number, _)| {
+                        !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+                    })
+            })
+            .collect();
+        files.sort();
+        for file in files {
+            run_on_all_fns(&file, |tcx, bodies| {
+                for body in bodies {
+                    let mut output =
+                        run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+                    let violations = output.check_fixpoint();
+                    assert!(
+                        violations.is_empty(),
+                        "{}, {:?}: {:?}",
+                        file,
+                        body.body.source.def_id(),
+                        violations
+                    );
+                }
+            });
+        }
+    }
+
+    /// `r = &mut x` reborrows `x` into `*r`, which is reported both as a
+    /// `reborrow` and as a `blocks` fact
+    #[test]
+    fn constraints_contain_reborrow_and_blocks_facts() {
+        run_on_fn("10_terminate_reborrow.rs", "main", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = utils::Place::from(local_named(&body.body, "x"));
+            let r = utils::Place::from(local_named(&body.body, "r"));
+            let borrow = find_statement(&body.body, |statement| {
+                matches!(
+                    statement.kind.as_assign(),
+                    Some((place, mir::Rvalue::Ref(..))) if place.local == r.local
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let constraints = output.to_constraints();
+            let blocked = MaybeOldPlace::from(x).to_string();
+            let assigned = MaybeOldPlace::from(r.project_deref(rp)).to_string();
+            assert!(constraints.facts().any(|fact| *fact
+                == Fact::Reborrow {
+                    location: borrow,
+                    blocked: blocked.clone(),
+                    assigned: assigned.clone(),
+                }));
+            assert!(constraints.facts().any(|fact| *fact
+                == Fact::Blocks {
+                    blocked: blocked.clone(),
+                    blocking: assigned.clone(),
+                }));
+            assert!(constraints
+                .facts()
+                .any(|fact| matches!(fact, Fact::Outlives { .. })));
+            let text = constraints.to_string();
+            assert_eq!(text.lines().count(), constraints.len());
+            assert!(text.contains(&format!("blocks({:?}, {:?}).", blocked, assigned)));
+        });
+    }
+
+    /// `x = 2` writes `x` while `p = &raw mut x` may be outstanding; `y.1 = 3`
+    /// writes a sibling of the place `q` points to, which is not aliased
+    #[test]
+    fn writes_while_raw_pointer_outstanding_are_reported() {
+        run_on_fn("17_raw_mut.rs", "main", |tcx, body| {
+            let x = local_named(&body.body, "x");
+            let y = local_named(&body.body, "y");
+            let raw_borrow = find_statement(&body.body, |statement| {
+                matches!(
+                    statement.kind.as_assign(),
+                    Some((_, mir::Rvalue::RawPtr(_, place))) if place.local == x
+                )
+            });
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            assert_eq!(
+                output.capability_at(x.into(), raw_borrow.successor_within_block()),
+                Some(free_pcs::CapabilityKind::Aliased)
+            );
+            let writes = output.potentially_aliasing_writes();
+            let writes_of_x: Vec<_> = writes
+                .iter()
+                .filter(|(_, place)| place.local == x)
+                .collect();
+            assert_eq!(writes_of_x.len(), 1);
+            let (location, _) = writes_of_x[0];
+            assert_eq!(location.block, raw_borrow.block);
+            assert!(location.statement_index > raw_borrow.statement_index);
+            assert!(writes.iter().all(|(_, place)| place.local != y));
+        });
+    }
+
+    /// Run on its own, the borrows analysis starts from the remote reborrow
+    /// of the argument `x`, which `y = &mut *x` then reborrows
+    #[test]
+    fn borrows_analysis_runs_on_its_own() {
+        run_on_fn("29_mut_ref_arg.rs", "incr", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let x = utils::Place::from(local_named(&body.body, "x"));
+            let y = utils::Place::from(local_named(&body.body, "y"));
+            let reborrow = find_statement(&body.body, |statement| {
+                matches!(
+                    statement.kind.as_assign(),
+                    Some((place, mir::Rvalue::Ref(..))) if place.local == y.local
+                )
+            });
+            let mut cursor = run_borrows(body, tcx, PcsOptions::default());
+            cursor.seek_to_block_start(mir::START_BLOCK);
+            assert!(cursor
+                .get()
+                .after
+                .reborrows()
+                .iter()
+                .any(
+                    |rb| rb.value.blocked_place == ReborrowBlockedPlace::Remote(x.local)
+                        && rb.value.assigned_place == x.project_deref(rp).into()
+                ));
+            cursor.seek_after_primary_effect(reborrow);
+            assert!(cursor
+                .get()
+                .after
+                .reborrows()
+                .iter()
+                .any(|rb| rb.value.blocked_place == x.project_deref(rp).into()
+                    && rb.value.assigned_place == y.project_deref(rp).into()));
+            for block in body.body.basic_blocks.indices() {
+                cursor.seek_to_block_end(block);
+                cursor.get().after.assert_invariants_satisfied(rp);
+            }
+        });
+    }
 }
@@ -5,13 +5,20 @@ use std::io::Write;
 use std::{cmp::Ordering, fs::File};
 
 use std::cell::RefCell;
+use std::sync::atomic::{self, AtomicBool};
 
 use itertools::Itertools;
 use mir_state_analysis::visualization::dot_graph::{
     DotEdge, DotGraph, DotLabel, DotNode, EdgeDirection, EdgeOptions,
 };
 use mir_state_analysis::visualization::dot_graph::{DotStringAttr, DotSubgraph};
-use mir_state_analysis::{combined_pcs::BodyWithBorrowckFacts, run_combined_pcs, rustc_interface};
+use mir_state_analysis::{
+    borrows::borrows_state::{AgingPolicy, SharedReborrowPolicy},
+    combined_pcs::{BodyWithBorrowckFacts, Checkpoint},
+    run_borrows, run_combined_pcs, rustc_interface,
+    utils::{assumptions::take_assumption_violations, enables_feature, PlaceRepacker},
+    PcsOptions,
+};
 use regex::Regex;
 use rustc_interface::{
     borrowck::consumers,
@@ -28,7 +35,16 @@ use rustc_interface::{
     session::Session,
 };
 
-struct PcsCallbacks;
+struct PcsCallbacks {
+    stream_output: bool,
+    check_fixpoint: bool,
+    dry_run: bool,
+    borrows_only: bool,
+    options: PcsOptions,
+    only_fn: Option<String>,
+    /// See `--pcs-checkpoint-dir`
+    checkpoint_dir: Option<String>,
+}
 
 thread_local! {
     pub static BODIES:
@@ -36,8 +52,15 @@ thread_local! {
         RefCell::new(FxHashMap::default());
 }
 
+/// Whether the borrow checker is run with Polonius, see `--pcs-no-polonius`
+static USE_POLONIUS: AtomicBool = AtomicBool::new(true);
+
 fn mir_borrowck<'tcx>(tcx: TyCtxt<'tcx>, def_id: LocalDefId) -> MirBorrowck<'tcx> {
-    let consumer_opts = consumers::ConsumerOptions::PoloniusOutputFacts;
+    let consumer_opts = if USE_POLONIUS.load(atomic::Ordering::Relaxed) {
+        consumers::ConsumerOptions::PoloniusOutputFacts
+    } else {
+        consumers::ConsumerOptions::PoloniusInputFacts
+    };
     let body_with_facts = consumers::get_body_with_borrowck_facts(tcx, def_id, consumer_opts);
     unsafe {
         let body: BodyWithBorrowckFacts<'tcx> = body_with_facts.into();
@@ -53,7 +76,83 @@ fn mir_borrowck<'tcx>(tcx: TyCtxt<'tcx>, def_id: LocalDefId) -> MirBorrowck<'tcx
     original_mir_borrowck(tcx, def_id)
 }
 
-fn run_pcs_on_all_fns<'tcx>(tcx: TyCtxt<'tcx>) {
+/// Restricts the analysis to the functions selected with `--pcs-only-fn=NAME`
+/// (or the `PCS_ONLY_FN` environment variable): those named exactly `NAME` if
+/// there are any, otherwise those whose name contains `NAME`.
+struct FnFilter {
+    name: String,
+    exact: bool,
+}
+
+impl FnFilter {
+    fn new(tcx: TyCtxt<'_>, name: String) -> Self {
+        let exact = tcx.hir().body_owners().any(|def_id| {
+            matches!(
+                tcx.def_kind(def_id),
+                hir::def::DefKind::Fn | hir::def::DefKind::AssocFn
+            ) && tcx.item_name(def_id.to_def_id()).as_str() == name
+        });
+        FnFilter { name, exact }
+    }
+
+    fn matches(&self, item_name: &str) -> bool {
+        if self.exact {
+            item_name == self.name
+        } else {
+            item_name.contains(&self.name)
+        }
+    }
+}
+
+/// Prints, as JSON, the body owners that `run_pcs_on_all_fns` would analyze
+/// and those it would skip, with the reason they would be skipped.
+fn print_pcs_targets(tcx: TyCtxt<'_>, fn_filter: Option<&FnFilter>) {
+    let targets = tcx
+        .hir()
+        .body_owners()
+        .map(|def_id| {
+            let kind = tcx.def_kind(def_id);
+            let skip_reason = match kind {
+                hir::def::DefKind::Fn | hir::def::DefKind::AssocFn => match fn_filter {
+                    Some(filter) if !filter.matches(tcx.item_name(def_id.to_def_id()).as_str()) => {
+                        Some(format!("does not match --pcs-only-fn={}", filter.name))
+                    }
+                    _ => None,
+                },
+                unsupported_item_kind => {
+                    Some(format!("unsupported item: {unsupported_item_kind:?}"))
+                }
+            };
+            serde_json::json!({
+                "name": tcx.def_path_str(def_id.to_def_id()),
+                "kind": format!("{kind:?}"),
+                "analyze": skip_reason.is_none(),
+                "skip_reason": skip_reason,
+            })
+        })
+        .collect::<Vec<_>>();
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&targets).expect("Failed to serialize targets to JSON")
+    );
+}
+
+fn run_pcs_on_all_fns<'tcx>(tcx: TyCtxt<'tcx>, callbacks: &PcsCallbacks) {
+    let PcsCallbacks {
+        stream_output,
+        check_fixpoint,
+        dry_run,
+        borrows_only,
+        options,
+        ref only_fn,
+        ref checkpoint_dir,
+    } = *callbacks;
+    let fn_filter = only_fn.clone().map(|name| FnFilter::new(tcx, name));
+    if dry_run {
+        print_pcs_targets(tcx, fn_filter.as_ref());
+        return;
+    }
+
     let mut item_names = vec![];
 
     let vis_dir = if std::env::var("PCS_VISUALIZATION").unwrap_or_default() == "true" {
@@ -70,20 +169,90 @@ fn run_pcs_on_all_fns<'tcx>(tcx: TyCtxt<'tcx>) {
         std::fs::create_dir_all(path).expect("Failed to create visualization directory");
     }
 
+    if let Some(dir) = checkpoint_dir {
+        std::fs::create_dir_all(dir).expect("Failed to create checkpoint directory");
+    }
+
     for def_id in tcx.hir().body_owners() {
         let kind = tcx.def_kind(def_id);
         match kind {
             hir::def::DefKind::Fn | hir::def::DefKind::AssocFn => {
                 let item_name = format!("{}", tcx.item_name(def_id.to_def_id()));
+                if fn_filter
+                    .as_ref()
+                    .is_some_and(|filter| !filter.matches(&item_name))
+                {
+                    continue;
+                }
                 let body = BODIES.with(|state| {
                     let mut map = state.borrow_mut();
                     unsafe { std::mem::transmute(map.remove(&def_id).unwrap()) }
                 });
-                run_combined_pcs(
+                if borrows_only {
+                    let body: &BodyWithBorrowckFacts<'tcx> = &body;
+                    let mut cursor = run_borrows(body, tcx, options);
+                    for block in body.body.basic_blocks.indices() {
+                        cursor.seek_to_block_end(block);
+                        let state = cursor.get();
+                        state.after.assert_invariants_satisfied(state.repacker);
+                    }
+                    item_names.push(item_name);
+                    continue;
+                }
+                // The checkpoint saved by the previous run, if any; its entry
+                // states that the edits since then invalidated are discarded
+                let checkpoint_path = checkpoint_dir
+                    .as_ref()
+                    .map(|dir| format!("{}/{}.json", dir, item_name));
+                let checkpoint = checkpoint_path.as_ref().and_then(|path| {
+                    let json = std::fs::read_to_string(path).ok()?;
+                    let json = serde_json::from_str(&json).ok()?;
+                    Some(Checkpoint::from_json(
+                        &json,
+                        PlaceRepacker::new(&body.body, tcx),
+                    ))
+                });
+                let mut output = run_combined_pcs(
                     &body,
                     tcx,
                     vis_dir.map(|dir| format!("{}/{}", dir, item_name)),
+                    stream_output,
+                    None,
+                    checkpoint,
+                    options,
                 );
+                if let Some(path) = &checkpoint_path {
+                    let json = output.checkpoint().to_json(output.repacker());
+                    std::fs::write(path, json.to_string()).expect("Failed to write checkpoint");
+                }
+                if check_fixpoint {
+                    for violation in output.check_fixpoint() {
+                        eprintln!(
+                            "error: fixpoint not reached in `{}`: {}",
+                            item_name, violation
+                        );
+                    }
+                }
+                for (location, cycle) in output.borrow_cycles() {
+                    eprintln!(
+                        "warning: self-referential borrows in `{}` at {:?}, the results are approximate: {}",
+                        item_name, location, cycle
+                    );
+                }
+                for error in output.capability_errors() {
+                    eprintln!("error: capability error in `{}` {}", item_name, error);
+                }
+                let violations = take_assumption_violations();
+                if !violations.is_empty() {
+                    eprintln!(
+                        "warning: {} analysis assumption(s) violated in `{}`, the analysis skipped the affected constructs:",
+                        violations.len(),
+                        item_name
+                    );
+                    for violation in violations {
+                        eprintln!("  {}", violation);
+                    }
+                }
                 item_names.push(item_name);
             }
             unsupported_item_kind => {
@@ -92,6 +261,13 @@ fn run_pcs_on_all_fns<'tcx>(tcx: TyCtxt<'tcx>) {
         }
     }
 
+    if let Some(filter) = fn_filter.as_ref().filter(|_| item_names.is_empty()) {
+        eprintln!(
+            "warning: no function matches `--pcs-only-fn={}`",
+            filter.name
+        );
+    }
+
     if let Some(dir_path) = &vis_dir {
         let file_path = format!("{}/functions.json", dir_path);
 
@@ -122,7 +298,10 @@ impl driver::Callbacks for PcsCallbacks {
         _compiler: &Compiler,
         queries: &'tcx Queries<'tcx>,
     ) -> Compilation {
-        queries.global_ctxt().unwrap().enter(run_pcs_on_all_fns);
+        queries
+            .global_ctxt()
+            .unwrap()
+            .enter(|tcx| run_pcs_on_all_fns(tcx, self));
         Compilation::Stop
     }
 }
@@ -133,16 +312,86 @@ fn main() {
     let mut rustc_args = vec![
         "--cfg=prusti".to_string(),
         "--edition=2018".to_string(),
-        "-Zpolonius=next".to_string(),
         "-L".to_string(),
         "dependency=../prusti-dev/target/verify/debug/deps".to_string(),
     ];
     rustc_args.push("-Zcrate-attr=feature(register_tool)".to_owned());
     rustc_args.push("-Zcrate-attr=register_tool(prusti)".to_owned());
-    rustc_args.push("-Zcrate-attr=feature(stmt_expr_attributes)".to_owned());
 
-    rustc_args.extend(std::env::args().skip(1));
-    let mut callbacks = PcsCallbacks;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let enables_stmt_expr_attributes = args
+        .iter()
+        .filter(|arg| arg.ends_with(".rs"))
+        .filter_map(|file| std::fs::read_to_string(file).ok())
+        .any(|source| enables_feature(&source, "stmt_expr_attributes"));
+    if !enables_stmt_expr_attributes {
+        rustc_args.push("-Zcrate-attr=feature(stmt_expr_attributes)".to_owned());
+    }
+    let stream_output = args.iter().any(|arg| arg == "--pcs-stream");
+    let check_fixpoint = args.iter().any(|arg| arg == "--pcs-check-fixpoint");
+    let dry_run = args.iter().any(|arg| arg == "--pcs-dry-run");
+    let borrows_only = args.iter().any(|arg| arg == "--pcs-borrows-only");
+    let options = PcsOptions {
+        aging_policy: match args
+            .iter()
+            .find_map(|arg| arg.strip_prefix("--pcs-aging-policy="))
+        {
+            None | Some("subtree") => AgingPolicy::Subtree,
+            Some("leaf-only") => AgingPolicy::LeafOnly,
+            Some(policy) => {
+                panic!("Unknown aging policy `{policy}`, expected `subtree` or `leaf-only`")
+            }
+        },
+        shared_reborrow_policy: match args
+            .iter()
+            .find_map(|arg| arg.strip_prefix("--pcs-shared-reborrow-policy="))
+        {
+            None | Some("keep-in-scope") => SharedReborrowPolicy::KeepInScope,
+            Some("structural") => SharedReborrowPolicy::Structural,
+            Some(policy) => panic!(
+                "Unknown shared reborrow policy `{policy}`, expected `keep-in-scope` or `structural`"
+            ),
+        },
+        collect_stats: args.iter().any(|arg| arg == "--pcs-stats"),
+        lenient: args.iter().any(|arg| arg == "--pcs-lenient"),
+        ..Default::default()
+    };
+    let only_fn = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--pcs-only-fn="))
+        .map(|name| name.to_string())
+        .or_else(|| std::env::var("PCS_ONLY_FN").ok());
+    let checkpoint_dir = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--pcs-checkpoint-dir="))
+        .map(|dir| dir.to_string());
+    if args.iter().any(|arg| arg == "--pcs-no-polonius") {
+        USE_POLONIUS.store(false, atomic::Ordering::Relaxed);
+    } else {
+        rustc_args.push("-Zpolonius=next".to_string());
+    }
+    rustc_args.extend(args.into_iter().filter(|arg| {
+        arg != "--pcs-stream"
+            && arg != "--pcs-lenient"
+            && arg != "--pcs-check-fixpoint"
+            && arg != "--pcs-dry-run"
+            && arg != "--pcs-borrows-only"
+            && arg != "--pcs-no-polonius"
+            && arg != "--pcs-stats"
+            && !arg.starts_with("--pcs-only-fn=")
+            && !arg.starts_with("--pcs-aging-policy=")
+            && !arg.starts_with("--pcs-shared-reborrow-policy=")
+            && !arg.starts_with("--pcs-checkpoint-dir=")
+    }));
+    let mut callbacks = PcsCallbacks {
+        stream_output,
+        check_fixpoint,
+        dry_run,
+        borrows_only,
+        options,
+        only_fn,
+        checkpoint_dir,
+    };
     driver::RunCompiler::new(&rustc_args, &mut callbacks)
         .run()
         .unwrap();
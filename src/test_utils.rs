@@ -0,0 +1,198 @@
+//! Runs the analysis on the programs in `tests/` from unit tests.
+//!
+//! The programs are compiled in-process, with the same options as `pcs_bin`,
+//! and the test inspects the borrow checker facts of one of their functions.
+
+use std::{cell::RefCell, sync::Mutex};
+
+use crate::{
+    combined_pcs::BodyWithBorrowckFacts,
+    rustc_interface::{
+        borrowck::{self, consumers},
+        data_structures::fx::FxHashMap,
+        driver::{self, Compilation},
+        hir::{def::DefKind, def_id::LocalDefId},
+        interface::{interface::Compiler, Config, Queries},
+        middle::{
+            mir::{Body, Local, Location, Statement, VarDebugInfoContents},
+            query::queries::mir_borrowck::ProvidedValue as MirBorrowck,
+            ty::TyCtxt,
+            util::Providers,
+        },
+        session::Session,
+    },
+    utils::enables_feature,
+};
+
+thread_local! {
+    static BODIES: RefCell<FxHashMap<LocalDefId, BodyWithBorrowckFacts<'static>>> =
+        RefCell::new(FxHashMap::default());
+}
+
+/// Compilations are serialized: whether the fixpoint computation is recorded
+/// is configured globally (see `RECORD_PCS`).
+static COMPILING: Mutex<()> = Mutex::new(());
+
+fn mir_borrowck<'tcx>(tcx: TyCtxt<'tcx>, def_id: LocalDefId) -> MirBorrowck<'tcx> {
+    let body_with_facts = consumers::get_body_with_borrowck_facts(
+        tcx,
+        def_id,
+        consumers::ConsumerOptions::PoloniusOutputFacts,
+    );
+    let body: BodyWithBorrowckFacts<'tcx> = body_with_facts.into();
+    // SAFETY: the bodies are only used while the `TyCtxt` is alive, see
+    // `RunOnFn::after_analysis`
+    let body: BodyWithBorrowckFacts<'static> = unsafe { std::mem::transmute(body) };
+    BODIES.with(|bodies| bodies.borrow_mut().insert(def_id, body));
+    let mut providers = Providers::default();
+    borrowck::provide(&mut providers);
+    (providers.mir_borrowck)(tcx, def_id)
+}
+
+fn set_mir_borrowck(_session: &Session, providers: &mut Providers) {
+    providers.mir_borrowck = mir_borrowck;
+}
+
+type TestFn<'a> =
+    Box<dyn for<'tcx> FnOnce(TyCtxt<'tcx>, &[BodyWithBorrowckFacts<'tcx>]) + Send + 'a>;
+
+struct RunOnFn<'a> {
+    /// `None` for all the functions of the program
+    fn_names: Option<&'a [&'a str]>,
+    test: Option<TestFn<'a>>,
+}
+
+impl driver::Callbacks for RunOnFn<'_> {
+    fn config(&mut self, config: &mut Config) {
+        config.override_queries = Some(set_mir_borrowck);
+    }
+
+    fn after_analysis<'tcx>(
+        &mut self,
+        _compiler: &Compiler,
+        queries: &'tcx Queries<'tcx>,
+    ) -> Compilation {
+        queries.global_ctxt().unwrap().enter(|tcx| {
+            let def_ids: Vec<LocalDefId> = match self.fn_names {
+                Some(fn_names) => fn_names
+                    .iter()
+                    .map(|fn_name| {
+                        tcx.hir()
+                            .body_owners()
+                            .find(|def_id| {
+                                tcx.opt_item_name(def_id.to_def_id())
+                                    .is_some_and(|name| name.as_str() == *fn_name)
+                                    || tcx.def_path_str(def_id.to_def_id()) == *fn_name
+                            })
+                            .unwrap_or_else(|| panic!("No function named `{}`", fn_name))
+                    })
+                    .collect(),
+                None => tcx
+                    .hir()
+                    .body_owners()
+                    .filter(|def_id| {
+                        matches!(tcx.def_kind(*def_id), DefKind::Fn | DefKind::AssocFn)
+                    })
+                    .collect(),
+            };
+            let bodies: Vec<BodyWithBorrowckFacts<'tcx>> = def_ids
+                .into_iter()
+                .map(|def_id| {
+                    let body = BODIES.with(|bodies| bodies.borrow_mut().remove(&def_id).unwrap());
+                    unsafe { std::mem::transmute(body) }
+                })
+                .collect();
+            (self.test.take().unwrap())(tcx, &bodies);
+        });
+        BODIES.with(|bodies| bodies.borrow_mut().clear());
+        Compilation::Stop
+    }
+}
+
+/// Compiles `tests/{file}` and calls `test` with the borrow checker facts of
+/// its function named `fn_name`. Closures and coroutines, which have no name,
+/// are found by their path instead (e.g. `main::{closure#0}`).
+pub(crate) fn run_on_fn<'a>(
+    file: &str,
+    fn_name: &'a str,
+    test: impl for<'tcx> FnOnce(TyCtxt<'tcx>, &BodyWithBorrowckFacts<'tcx>) + Send + 'a,
+) {
+    run_on_fns(file, &[fn_name], |tcx, bodies| test(tcx, &bodies[0]));
+}
+
+/// Like [`run_on_fn`], for tests that inspect several functions of the
+/// program (e.g. a caller and its callee): the facts of the functions named
+/// `fn_names` are given in the same order.
+pub(crate) fn run_on_fns<'a>(
+    file: &str,
+    fn_names: &'a [&'a str],
+    test: impl for<'tcx> FnOnce(TyCtxt<'tcx>, &[BodyWithBorrowckFacts<'tcx>]) + Send + 'a,
+) {
+    run_on(file, Some(fn_names), test);
+}
+
+/// Like [`run_on_fns`], for all the functions of the program (in the order of
+/// their definitions), as analyzed by `pcs_bin`
+pub(crate) fn run_on_all_fns<'a>(
+    file: &str,
+    test: impl for<'tcx> FnOnce(TyCtxt<'tcx>, &[BodyWithBorrowckFacts<'tcx>]) + Send + 'a,
+) {
+    run_on(file, None, test);
+}
+
+fn run_on<'a>(
+    file: &str,
+    fn_names: Option<&'a [&'a str]>,
+    test: impl for<'tcx> FnOnce(TyCtxt<'tcx>, &[BodyWithBorrowckFacts<'tcx>]) + Send + 'a,
+) {
+    let path = format!("{}/tests/{}", env!("CARGO_MANIFEST_DIR"), file);
+    let mut args = vec![
+        "rustc".to_string(),
+        path.clone(),
+        "--edition=2018".to_string(),
+        "-Zpolonius=next".to_string(),
+        "--out-dir".to_string(),
+        std::env::temp_dir().to_str().unwrap().to_string(),
+    ];
+    let source = std::fs::read_to_string(&path).unwrap();
+    if !enables_feature(&source, "stmt_expr_attributes") {
+        args.push("-Zcrate-attr=feature(stmt_expr_attributes)".to_string());
+    }
+    let mut callbacks = RunOnFn {
+        fn_names,
+        test: Some(Box::new(test)),
+    };
+    let _compiling = COMPILING.lock().unwrap_or_else(|err| err.into_inner());
+    driver::RunCompiler::new(&args, &mut callbacks)
+        .run()
+        .unwrap();
+}
+
+/// The local of `body` that is named `name` in the source
+pub(crate) fn local_named(body: &Body<'_>, name: &str) -> Local {
+    body.var_debug_info
+        .iter()
+        .find_map(|info| match info.value {
+            VarDebugInfoContents::Place(place) if info.name.as_str() == name => place.as_local(),
+            _ => None,
+        })
+        .unwrap_or_else(|| panic!("No local named `{name}`"))
+}
+
+/// The location of the first statement of `body` (in the order of the
+/// blocks) that satisfies `predicate`
+pub(crate) fn find_statement<'tcx>(
+    body: &Body<'tcx>,
+    mut predicate: impl FnMut(&Statement<'tcx>) -> bool,
+) -> Location {
+    body.basic_blocks
+        .iter_enumerated()
+        .find_map(|(block, data)| {
+            let statement_index = data.statements.iter().position(&mut predicate)?;
+            Some(Location {
+                block,
+                statement_index,
+            })
+        })
+        .expect("No statement satisfies the predicate")
+}
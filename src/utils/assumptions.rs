@@ -0,0 +1,70 @@
+use std::cell::RefCell;
+
+use crate::rustc_interface::middle::mir::Location;
+
+thread_local! {
+    static VIOLATIONS: RefCell<Vec<AssumptionViolation>> = const { RefCell::new(vec![]) };
+}
+
+/// An assumption of the analysis that did not hold, and was skipped over
+/// because the analysis runs in lenient mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssumptionViolation {
+    /// The location being analyzed when the violation was detected, if known
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl std::fmt::Display for AssumptionViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "at {:?}: {}", location, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Returns whether the assumption `holds`.
+///
+/// If it doesn't, this panics with `message`, unless the analysis is
+/// `lenient` (see [`crate::PcsOptions::lenient`]), in which case the
+/// violation is recorded (see [`take_assumption_violations`]) and the caller
+/// is expected to skip whatever relies on the assumption.
+pub fn check_assumption(
+    lenient: bool,
+    holds: bool,
+    location: Option<Location>,
+    message: impl FnOnce() -> String,
+) -> bool {
+    if holds {
+        return true;
+    }
+    if !lenient {
+        panic!(
+            "Analysis assumption violated {}",
+            AssumptionViolation {
+                location,
+                message: message(),
+            }
+        );
+    }
+    record_assumption_violation(location, message());
+    false
+}
+
+/// Records the violation of an assumption that the caller skips over in
+/// every mode, not only if the analysis is lenient. A violation is recorded
+/// once, although its location may be analyzed several times.
+pub fn record_assumption_violation(location: Option<Location>, message: String) {
+    let violation = AssumptionViolation { location, message };
+    VIOLATIONS.with_borrow_mut(|violations| {
+        if !violations.contains(&violation) {
+            violations.push(violation);
+        }
+    });
+}
+
+/// The assumption violations recorded on this thread since the last call
+pub fn take_assumption_violations() -> Vec<AssumptionViolation> {
+    VIOLATIONS.take()
+}
@@ -46,6 +46,42 @@ impl<'tcx> PlaceDisplay<'tcx> {
 }
 
 impl<'tcx> Place<'tcx> {
+    /// A compact code for each projection element of this place, e.g. `*` for
+    /// a deref, `.1` for the second field or `@0` for a downcast to the first
+    /// variant. Unlike the `Debug` output, this does not depend on the compiler
+    /// version.
+    pub fn projection_codes(&self) -> Vec<String> {
+        self.projection
+            .iter()
+            .map(|elem| match elem {
+                ProjectionElem::Deref => "*".to_string(),
+                ProjectionElem::Field(field, _) => format!(".{}", field.as_usize()),
+                ProjectionElem::Index(local) => format!("[{}]", local.as_usize()),
+                ProjectionElem::ConstantIndex {
+                    offset,
+                    min_length,
+                    from_end,
+                } => format!(
+                    "[{}{offset} of {min_length}]",
+                    if *from_end { "-" } else { "" }
+                ),
+                ProjectionElem::Subslice { from, to, from_end } => {
+                    format!("[{from}..{}{to}]", if *from_end { "-" } else { "" })
+                }
+                ProjectionElem::Downcast(_, variant) => format!("@{}", variant.as_usize()),
+                ProjectionElem::OpaqueCast(_) => "#opaque".to_string(),
+                ProjectionElem::Subtype(_) => "#subtype".to_string(),
+            })
+            .collect()
+    }
+
+    /// The place as `{"local": <local index>, "projection": <projection codes>}`
+    pub fn to_encoded_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "local": self.local.as_usize(),
+            "projection": self.projection_codes(),
+        })
+    }
 
     pub fn to_json(&self, repacker: PlaceRepacker<'_, 'tcx>) -> serde_json::Value {
         let place_str = match self.to_string(repacker) {
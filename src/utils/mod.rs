@@ -4,6 +4,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+pub mod assumptions;
 pub mod place;
 pub(crate) mod repacker;
 pub mod display;
@@ -18,3 +19,20 @@ pub use mutable::*;
 pub use place::*;
 pub use place_snapshot::*;
 pub use repacker::*;
+
+/// Whether the crate root `source` enables `feature` itself: the driver only
+/// enables the features it needs when the crate doesn't, since enabling a
+/// feature twice is an error.
+pub fn enables_feature(source: &str, feature: &str) -> bool {
+    source.lines().any(|line| {
+        line.trim_start()
+            .strip_prefix("#![feature(")
+            .is_some_and(|features| {
+                features
+                    .trim_end()
+                    .trim_end_matches(")]")
+                    .split(',')
+                    .any(|enabled| enabled.trim() == feature)
+            })
+    })
+}
@@ -62,10 +62,17 @@ impl<'tcx> Place<'tcx> {
         idx: usize,
         repacker: PlaceRepacker<'_, 'tcx>,
     ) -> RegionProjection<'tcx> {
-        RegionProjection::new(
-            self.region_projections(repacker)[idx].region,
-            self.clone().into(),
-        )
+        let projections = self.region_projections(repacker);
+        let Some(projection) = projections.get(idx) else {
+            panic!(
+                "Region projection {} requested for {:?} of type {:?}, which only has {} region projections",
+                idx,
+                self,
+                self.ty(repacker).ty,
+                projections.len()
+            );
+        };
+        RegionProjection::new(projection.region, self.clone().into())
     }
 
     pub fn region_projections(
@@ -109,10 +116,11 @@ impl<'tcx> Place<'tcx> {
     }
 
     pub fn project_deref(&self, repacker: PlaceRepacker<'_, 'tcx>) -> Self {
+        let ty = self.ty(repacker).ty;
         assert!(
-            self.ty(repacker).ty.is_ref() || self.ty(repacker).ty.is_box(),
-            "Expected ref or box, got {:?}",
-            self.ty(repacker).ty
+            ty.is_ref() || ty.is_unsafe_ptr() || ty.is_box(),
+            "Expected ref, raw pointer or box, got {:?}",
+            ty
         );
         Place::new(
             self.0.local,
@@ -5,6 +5,13 @@ use super::{Place, PlaceRepacker};
 #[derive(PartialEq, Eq, Clone, Debug, Hash, Copy)]
 pub enum SnapshotLocation {
     Location(Location),
+    /// In the middle of the statement at the location: the snapshots taken
+    /// by the effects applied before the statement (e.g. the moves out of its
+    /// operands and the loans it invalidates), see
+    /// `BorrowsVisitor::snapshot_location`. Without it, a value given to a
+    /// place before the statement and the value given to it by the statement
+    /// would have the same snapshot.
+    BeforeStatement(Location),
     Join(BasicBlock),
 }
 
@@ -15,7 +15,7 @@ use rustc_interface::{
         },
         ty::{Region, RegionVid, Ty, TyCtxt, TyKind},
     },
-    target::abi::FieldIdx,
+    target::abi::{FieldIdx, VariantIdx},
 };
 
 // use crate::utils::ty::{DeepTypeVisitable, DeepTypeVisitor, Stack};
@@ -319,6 +319,29 @@ impl<'tcx> Place<'tcx> {
         places
     }
 
+    /// Expands a place `x` of enum type into a vector of places for each of
+    /// the fields of its variant `variant_idx`, e.g. `{(x as Cons).0, (x as
+    /// Cons).1}` for the variant `Cons` of `x`. Panics if `x` is not of enum
+    /// type, e.g. for a variant of a coroutine.
+    pub fn expand_variant(
+        self,
+        variant_idx: VariantIdx,
+        repacker: PlaceRepacker<'_, 'tcx>,
+    ) -> Vec<Self> {
+        let TyKind::Adt(def, _) = self.ty(repacker).ty.kind() else {
+            panic!("Expected {self:?} to have an enum type");
+        };
+        assert!(def.is_enum(), "Expected {self:?} to have an enum type");
+        let variant_name = def.variant(variant_idx).name;
+        let variant_place: Self = self
+            .project_deeper(
+                &[ProjectionElem::Downcast(Some(variant_name), variant_idx)],
+                repacker.tcx,
+            )
+            .into();
+        variant_place.expand_field(None, repacker)
+    }
+
     // /// Pop the last projection from the place and return the new place with the popped element.
     // pub fn pop_one_level(self, place: Place<'tcx>) -> (PlaceElem<'tcx>, Place<'tcx>) {
     //     assert!(place.projection.len() > 0);
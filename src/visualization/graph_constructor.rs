@@ -5,19 +5,19 @@ use crate::{
         borrows_visitor::{extract_nested_lifetimes, get_vid},
         domain::{
             AbstractionInputTarget, AbstractionOutputTarget, AbstractionTarget, MaybeOldPlace,
-            ReborrowBlockedPlace, RegionProjection,
+            ReborrowBlockedPlace, ReborrowKind, RegionProjection,
         },
         region_abstraction::AbstractionEdge,
         unblock_graph::UnblockGraph,
     },
     free_pcs::{CapabilityKind, CapabilityLocal, CapabilitySummary},
     rustc_interface::{self, middle::mir::Local},
-    utils::{Place, PlaceRepacker, PlaceSnapshot, SnapshotLocation},
+    utils::{assumptions::check_assumption, Place, PlaceRepacker, PlaceSnapshot, SnapshotLocation},
     visualization::dot_graph::RankAnnotation,
 };
 
 use std::{
-    collections::{BTreeSet, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet},
     ops::Deref,
 };
 
@@ -31,7 +31,7 @@ use rustc_interface::{
 
 use super::{dot_graph::DotSubgraph, Graph, GraphEdge, GraphNode, NodeId, NodeType};
 
-#[derive(Eq, PartialEq, Hash)]
+#[derive(Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct GraphCluster {
     label: String,
     id: String,
@@ -71,12 +71,15 @@ impl GraphCluster {
 
 struct GraphConstructor<'mir, 'tcx> {
     remote_nodes: IdLookup<Local>,
+    constant_nodes: IdLookup<Location>,
     place_nodes: IdLookup<(Place<'tcx>, Option<SnapshotLocation>)>,
-    region_projection_nodes: IdLookup<RegionProjection<'tcx>>,
-    region_clusters: HashMap<Location, GraphCluster>,
+    region_projection_nodes: IdLookup<RegionProjection<'tcx, ReborrowBlockedPlace<'tcx>>>,
+    region_clusters: BTreeMap<Location, GraphCluster>,
     nodes: Vec<GraphNode>,
-    edges: HashSet<GraphEdge>,
+    edges: BTreeSet<GraphEdge>,
     repacker: PlaceRepacker<'mir, 'tcx>,
+    /// See [`crate::PcsOptions::lenient`]
+    lenient: bool,
 }
 
 struct IdLookup<T>(char, Vec<T>);
@@ -104,15 +107,17 @@ impl<T: Eq + Clone> IdLookup<T> {
 }
 
 impl<'a, 'tcx> GraphConstructor<'a, 'tcx> {
-    fn new(repacker: PlaceRepacker<'a, 'tcx>) -> Self {
+    fn new(repacker: PlaceRepacker<'a, 'tcx>, lenient: bool) -> Self {
         Self {
             remote_nodes: IdLookup::new('a'),
+            constant_nodes: IdLookup::new('k'),
             place_nodes: IdLookup::new('p'),
             region_projection_nodes: IdLookup::new('r'),
-            region_clusters: HashMap::new(),
+            region_clusters: BTreeMap::new(),
             nodes: vec![],
-            edges: HashSet::new(),
+            edges: BTreeSet::new(),
             repacker,
+            lenient,
         }
     }
 
@@ -141,6 +146,7 @@ impl<'a, 'tcx> GraphConstructor<'a, 'tcx> {
                     self.insert_place_node(place.place(), place.location(), None)
                 }
                 ReborrowBlockedPlace::Remote(local) => self.insert_remote_node(local),
+                ReborrowBlockedPlace::Constant(location) => self.insert_constant_node(location),
             },
             AbstractionTarget::RegionProjection(projection) => {
                 self.insert_region_projection_node(projection)
@@ -157,25 +163,38 @@ impl<'a, 'tcx> GraphConstructor<'a, 'tcx> {
                 self.insert_place_node(place.place(), place.location(), None)
             }
             AbstractionTarget::RegionProjection(projection) => {
-                self.insert_region_projection_node(projection)
+                self.insert_region_projection_node(projection.into())
             }
         }
     }
 
-    fn insert_region_projection_node(&mut self, projection: RegionProjection<'tcx>) -> NodeId {
+    fn insert_region_projection_node(
+        &mut self,
+        projection: RegionProjection<'tcx, ReborrowBlockedPlace<'tcx>>,
+    ) -> NodeId {
         if let Some(id) = self.region_projection_nodes.existing_id(&projection) {
             return id;
         }
         let id = self.region_projection_nodes.node_id(&projection);
+        let label = match projection.place {
+            ReborrowBlockedPlace::Local(place) => format!(
+                "{}↓{:?}",
+                place.to_short_string(self.repacker),
+                projection.region
+            ),
+            ReborrowBlockedPlace::Remote(local) => {
+                format!("Region {:?} of input {:?}", projection.region, local)
+            }
+            ReborrowBlockedPlace::Constant(location) => {
+                format!(
+                    "Region {:?} of constant at {:?}",
+                    projection.region, location
+                )
+            }
+        };
         let node = GraphNode {
             id,
-            node_type: NodeType::RegionProjectionNode {
-                label: format!(
-                    "{}↓{:?}",
-                    projection.place.to_short_string(self.repacker),
-                    projection.region
-                ),
-            },
+            node_type: NodeType::RegionProjectionNode { label },
         };
         self.insert_node(node);
         id
@@ -203,7 +222,14 @@ impl<'a, 'tcx> GraphConstructor<'a, 'tcx> {
             });
         }
 
-        assert!(!input_nodes.is_empty());
+        if !check_assumption(
+            self.lenient,
+            !input_nodes.is_empty(),
+            Some(region_abstraction.location()),
+            || "Region abstraction without inputs".to_string(),
+        ) {
+            return;
+        }
         let cluster = GraphCluster {
             id: format!(
                 "c{:?}_{}",
@@ -238,6 +264,22 @@ impl<'a, 'tcx> GraphConstructor<'a, 'tcx> {
         id
     }
 
+    fn insert_constant_node(&mut self, location: Location) -> NodeId {
+        if let Some(id) = self.constant_nodes.existing_id(&location) {
+            return id;
+        }
+        let id = self.constant_nodes.node_id(&location);
+        let node = GraphNode {
+            id,
+            node_type: NodeType::ReborrowingDagNode {
+                label: format!("Constant at {:?}", location),
+                location: None,
+            },
+        };
+        self.insert_node(node);
+        id
+    }
+
     fn insert_place_node(
         &mut self,
         place: Place<'tcx>,
@@ -266,13 +308,13 @@ impl<'a, 'tcx> GraphConstructor<'a, 'tcx> {
         };
         if place.is_owned(self.repacker.body(), self.repacker.tcx()) {
             for lifetime in extract_nested_lifetimes(place.ty(self.repacker).ty) {
-                let region_projection = RegionProjection {
-                    place: MaybeOldPlace::Current {
+                let region_projection = RegionProjection::new(
+                    get_vid(&lifetime).unwrap(),
+                    MaybeOldPlace::Current {
                         place: place.clone(),
                     },
-                    region: get_vid(&lifetime).unwrap(),
-                };
-                self.insert_region_projection_node(region_projection);
+                );
+                self.insert_region_projection_node(region_projection.into());
             }
         }
         let node = GraphNode { id, node_type };
@@ -288,9 +330,10 @@ pub struct UnblockGraphConstructor<'a, 'tcx> {
 
 impl<'a, 'tcx> UnblockGraphConstructor<'a, 'tcx> {
     pub fn new(unblock_graph: UnblockGraph<'tcx>, repacker: PlaceRepacker<'a, 'tcx>) -> Self {
+        let lenient = unblock_graph.is_lenient();
         Self {
             unblock_graph,
-            constructor: GraphConstructor::new(repacker),
+            constructor: GraphConstructor::new(repacker, lenient),
         }
     }
 
@@ -312,6 +355,9 @@ impl<'mir, 'tcx> PlaceGrapher<'mir, 'tcx> for UnblockGraphConstructor<'mir, 'tcx
         match place {
             ReborrowBlockedPlace::Local(place) => self.insert_maybe_old_place(place),
             ReborrowBlockedPlace::Remote(local) => self.constructor.insert_remote_node(local),
+            ReborrowBlockedPlace::Constant(location) => {
+                self.constructor.insert_constant_node(location)
+            }
         }
     }
 
@@ -350,8 +396,12 @@ trait PlaceGrapher<'mir, 'tcx: 'mir> {
                     borrowed_place,
                     assigned_place,
                     location: reborrow.reserve_location(),
-                    region: format!("{:?}", reborrow.region),
+                    region: match reborrow.kind {
+                        ReborrowKind::Ref(region) => format!("{:?}", region),
+                        ReborrowKind::RawPtr => "raw".to_string(),
+                    },
                     path_conditions: format!("{}", edge.conditions()),
+                    mutability: reborrow.mutability,
                 });
             }
             BorrowsEdgeKind::RegionAbstraction(abstraction) => {
@@ -361,7 +411,7 @@ trait PlaceGrapher<'mir, 'tcx: 'mir> {
                 let place = self.insert_maybe_old_place(member.place);
                 let region_projection = self
                     .constructor()
-                    .insert_region_projection_node(member.projection);
+                    .insert_region_projection_node(member.projection.into());
                 self.constructor()
                     .edges
                     .insert(GraphEdge::RegionProjectionMemberEdge {
@@ -373,12 +423,16 @@ trait PlaceGrapher<'mir, 'tcx: 'mir> {
     }
 }
 
+/// A predicate selecting the places that should be shown in a graph
+pub type PlaceFilter<'a, 'tcx> = Box<dyn Fn(Place<'tcx>) -> bool + 'a>;
+
 pub struct PCSGraphConstructor<'a, 'tcx> {
     summary: &'a CapabilitySummary<'tcx>,
     borrows_domain: &'a BorrowsState<'tcx>,
     borrow_set: &'a BorrowSet<'tcx>,
     constructor: GraphConstructor<'a, 'tcx>,
     repacker: PlaceRepacker<'a, 'tcx>,
+    place_filter: Option<PlaceFilter<'a, 'tcx>>,
 }
 
 impl<'a, 'tcx> PlaceGrapher<'a, 'tcx> for PCSGraphConstructor<'a, 'tcx> {
@@ -404,6 +458,9 @@ impl<'a, 'tcx> PlaceGrapher<'a, 'tcx> for PCSGraphConstructor<'a, 'tcx> {
         match place {
             ReborrowBlockedPlace::Local(place) => self.insert_maybe_old_place(place),
             ReborrowBlockedPlace::Remote(local) => self.constructor.insert_remote_node(local),
+            ReborrowBlockedPlace::Constant(location) => {
+                self.constructor.insert_constant_node(location)
+            }
         }
     }
 }
@@ -414,16 +471,33 @@ impl<'a, 'tcx> PCSGraphConstructor<'a, 'tcx> {
         repacker: PlaceRepacker<'a, 'tcx>,
         borrows_domain: &'a BorrowsState<'tcx>,
         borrow_set: &'a BorrowSet<'tcx>,
+        place_filter: Option<PlaceFilter<'a, 'tcx>>,
     ) -> Self {
         Self {
             summary,
             borrows_domain,
             borrow_set,
-            constructor: GraphConstructor::new(repacker),
+            constructor: GraphConstructor::new(repacker, borrows_domain.is_lenient()),
             repacker,
+            place_filter,
         }
     }
 
+    fn shows_place(&self, place: Place<'tcx>) -> bool {
+        self.place_filter
+            .as_ref()
+            .map_or(true, |filter| filter(place))
+    }
+
+    /// Whether all places connected by `edge` pass the place filter
+    fn shows_edge(&self, edge: &BorrowsEdge<'tcx>) -> bool {
+        edge.blocked_places()
+            .into_iter()
+            .flat_map(|p| p.as_local())
+            .chain(edge.blocked_by_places(self.repacker))
+            .all(|p| self.shows_place(p.place()))
+    }
+
     fn insert_place_and_previous_projections(
         &mut self,
         place: Place<'tcx>,
@@ -439,6 +513,9 @@ impl<'a, 'tcx> PCSGraphConstructor<'a, 'tcx> {
         while !projection.is_empty() {
             projection = &projection[..projection.len() - 1];
             let place = Place::new(place.local, &projection);
+            if !self.shows_place(place) {
+                break;
+            }
             let node = self.constructor.insert_place_node(place, None, None);
             self.constructor.edges.insert(GraphEdge::ProjectionEdge {
                 source: node,
@@ -473,20 +550,133 @@ impl<'a, 'tcx> PCSGraphConstructor<'a, 'tcx> {
     }
 
     pub fn construct_graph(mut self) -> Graph {
-        for (_local, capability) in self.summary.iter().enumerate() {
-            match capability {
-                CapabilityLocal::Unallocated => {}
-                CapabilityLocal::Allocated(projections) => {
-                    for (place, kind) in projections.iter() {
-                        self.insert_place_and_previous_projections(*place, None, Some(*kind));
-                    }
-                }
+        for (place, kind) in self.summary.flatten() {
+            if self.shows_place(place) {
+                self.insert_place_and_previous_projections(place, None, Some(kind));
             }
         }
-        for edge in self.borrows_domain.graph_edges() {
-            self.draw_borrows_edge(edge);
+        for edge in self
+            .borrows_domain
+            .ordered_graph_edges(self.constructor.repacker)
+        {
+            if self.shows_edge(edge) {
+                self.draw_borrows_edge(edge);
+            }
         }
 
         self.constructor.to_graph()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        run_combined_pcs,
+        rustc_interface::{
+            ast::Mutability,
+            middle::mir::{Rvalue, TerminatorKind},
+        },
+        test_utils::{find_statement, local_named, run_on_fn},
+        PcsOptions,
+    };
+
+    /// `take(h)` passes on the argument `h` of `forward` unchanged, so the
+    /// region of the input of the call is a region of the caller's value
+    #[test]
+    fn region_of_remote_input_is_drawn() {
+        run_on_fn("50_remote_input_region.rs", "forward", |tcx, body| {
+            let h = local_named(&body.body, "h");
+            let call = body
+                .body
+                .basic_blocks
+                .iter_enumerated()
+                .find(|(_, data)| matches!(data.terminator().kind, TerminatorKind::Call { .. }))
+                .map(|(block, _)| block)
+                .unwrap();
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let repacker = output.repacker();
+            let state = output
+                .get_all_for_bb(call)
+                .terminator
+                .succs
+                .remove(0)
+                .extra
+                .after;
+            let input = state
+                .graph()
+                .abstraction_edges()
+                .into_iter()
+                .flat_map(|edge| edge.value.inputs())
+                .find(|input| {
+                    matches!(
+                        input,
+                        AbstractionTarget::RegionProjection(projection)
+                            if projection.place == ReborrowBlockedPlace::Remote(h)
+                    )
+                })
+                .unwrap();
+            let mut constructor = GraphConstructor::new(repacker, false);
+            let id = constructor.insert_abstraction_input_target(input);
+            let node = constructor.nodes.iter().find(|node| node.id == id).unwrap();
+            assert!(matches!(
+                &node.node_type,
+                NodeType::RegionProjectionNode { label }
+                    if label.starts_with("Region") && label.ends_with(&format!("of input {:?}", h))
+            ));
+        });
+    }
+
+    /// The reborrow of `x` by `&x` is drawn as shared, the one of `h` by
+    /// `&mut h` as mutable
+    #[test]
+    fn reborrow_edges_show_mutability() {
+        run_on_fn("39_copy_reference_into_field.rs", "main", |tcx, body| {
+            let mut output =
+                run_combined_pcs(body, tcx, None, false, None, None, PcsOptions::default());
+            let repacker = output.repacker();
+            for (name, mutability, color, kind) in [
+                ("x", Mutability::Not, "slategray", "shared"),
+                ("h", Mutability::Mut, "orange", "mut"),
+            ] {
+                let local = local_named(&body.body, name);
+                let location = find_statement(&body.body, |statement| {
+                    matches!(
+                        statement.kind.as_assign(),
+                        Some((_, Rvalue::Ref(_, borrow_kind, place)))
+                            if place.local == local
+                                && borrow_kind.mutability() == mutability
+                    )
+                });
+                let statement = output
+                    .get_all_for_bb(location.block)
+                    .statements
+                    .remove(location.statement_index);
+                let graph = PCSGraphConstructor::new(
+                    &statement.states.after,
+                    repacker,
+                    &statement.extra.after,
+                    &body.borrow_set,
+                    None,
+                )
+                .construct_graph();
+                let edges: Vec<_> = graph
+                    .edges
+                    .iter()
+                    .filter_map(|edge| match edge {
+                        GraphEdge::ReborrowEdge { mutability: m, .. } if *m == mutability => {
+                            Some(edge.to_dot_edge().to_string())
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                assert!(!edges.is_empty(), "{name}");
+                for edge in edges {
+                    assert!(edge.contains(&format!("color=\"{color}\"")), "{edge}");
+                    assert!(edge.contains(&format!("({kind})\"")), "{edge}");
+                }
+            }
+        });
+    }
+}
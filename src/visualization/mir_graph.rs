@@ -87,17 +87,28 @@ fn format_operand<'tcx>(operand: &Operand<'tcx>, repacker: PlaceRepacker<'_, 'tc
 fn format_rvalue<'tcx>(rvalue: &Rvalue<'tcx>, repacker: PlaceRepacker<'_, 'tcx>) -> String {
     match rvalue {
         Rvalue::Use(operand) => format_operand(operand, repacker),
-        Rvalue::Repeat(_, _) => todo!(),
+        Rvalue::Repeat(operand, count) => {
+            format!("[{}; {}]", format_operand(operand, repacker), count)
+        }
         Rvalue::Ref(_region, kind, place) => {
             let kind = match kind {
                 mir::BorrowKind::Shared => "",
                 mir::BorrowKind::Mut { .. } => "mut",
-                mir::BorrowKind::Fake(_) => todo!(),
+                mir::BorrowKind::Fake(_) => "fake",
             };
             format!("&{} {}", kind, format_place(place, repacker))
         }
-        Rvalue::ThreadLocalRef(_) => todo!(),
-        Rvalue::Len(_) => todo!(),
+        Rvalue::RawPtr(mutability, place) => {
+            format!(
+                "&raw {} {}",
+                mutability.ptr_str(),
+                format_place(place, repacker)
+            )
+        }
+        Rvalue::ThreadLocalRef(def_id) => {
+            format!("ThreadLocalRef({})", repacker.tcx().def_path_str(*def_id))
+        }
+        Rvalue::Len(place) => format!("Len({})", format_place(place, repacker)),
         Rvalue::Cast(_, operand, ty) => format!("{} as {}", format_operand(operand, repacker), ty),
         Rvalue::BinaryOp(op, box (lhs, rhs)) => {
             format!(
@@ -107,7 +118,7 @@ fn format_rvalue<'tcx>(rvalue: &Rvalue<'tcx>, repacker: PlaceRepacker<'_, 'tcx>)
                 format_operand(rhs, repacker)
             )
         }
-        Rvalue::NullaryOp(_, _) => todo!(),
+        Rvalue::NullaryOp(op, ty) => format!("{:?}({})", op, ty),
         Rvalue::UnaryOp(op, val) => {
             format!("{:?} {}", op, format_operand(val, repacker))
         }
@@ -122,9 +133,14 @@ fn format_rvalue<'tcx>(rvalue: &Rvalue<'tcx>, repacker: PlaceRepacker<'_, 'tcx>)
                     .join(", ")
             )
         }
-        Rvalue::ShallowInitBox(_, _) => todo!(),
-        Rvalue::CopyForDeref(_) => todo!(),
-        _ => todo!(),
+        Rvalue::ShallowInitBox(operand, ty) => {
+            format!(
+                "ShallowInitBox({}, {})",
+                format_operand(operand, repacker),
+                ty
+            )
+        }
+        Rvalue::CopyForDeref(place) => format!("CopyForDeref({})", format_place(place, repacker)),
     }
 }
 fn format_terminator<'tcx>(
@@ -171,7 +187,9 @@ fn format_stmt<'tcx>(stmt: &Statement<'tcx>, repacker: PlaceRepacker<'_, 'tcx>)
             place: _,
             variant_index: _,
         } => todo!(),
-        mir::StatementKind::Deinit(_) => todo!(),
+        mir::StatementKind::Deinit(box place) => {
+            format!("Deinit({})", format_place(place, repacker))
+        }
         mir::StatementKind::StorageLive(local) => {
             format!("StorageLive({})", format_local(local, repacker))
         }
@@ -236,7 +254,7 @@ fn mk_mir_graph<'mir, 'tcx>(tcx: TyCtxt<'tcx>, body: &'mir Body<'tcx>) -> MirGra
                 });
             }
             TerminatorKind::UnwindResume => {}
-            TerminatorKind::UnwindTerminate(_) => todo!(),
+            TerminatorKind::UnwindTerminate(_) => {}
             TerminatorKind::Return => {}
             TerminatorKind::Unreachable => {}
             TerminatorKind::Drop {
@@ -307,10 +325,23 @@ fn mk_mir_graph<'mir, 'tcx>(tcx: TyCtxt<'tcx>, body: &'mir Body<'tcx>) -> MirGra
             }
             TerminatorKind::Yield {
                 value: _,
-                resume: _,
+                resume,
                 resume_arg: _,
-                drop: _,
-            } => todo!(),
+                drop,
+            } => {
+                edges.push(MirEdge {
+                    source: format!("{:?}", bb),
+                    target: format!("{:?}", resume),
+                    label: "resume".to_string(),
+                });
+                if let Some(drop) = drop {
+                    edges.push(MirEdge {
+                        source: format!("{:?}", bb),
+                        target: format!("{:?}", drop),
+                        label: "drop".to_string(),
+                    });
+                }
+            }
             TerminatorKind::FalseEdge {
                 real_target,
                 imaginary_target: _,
@@ -332,10 +363,25 @@ fn mk_mir_graph<'mir, 'tcx>(tcx: TyCtxt<'tcx>, body: &'mir Body<'tcx>) -> MirGra
                 });
             }
             TerminatorKind::InlineAsm {
-                ..
-            } => todo!(),
-            TerminatorKind::CoroutineDrop => todo!(),
-            _ => todo!(),
+                targets, unwind, ..
+            } => {
+                for target in targets.iter() {
+                    edges.push(MirEdge {
+                        source: format!("{:?}", bb),
+                        target: format!("{:?}", target),
+                        label: "asm".to_string(),
+                    });
+                }
+                if let UnwindAction::Cleanup(cleanup) = unwind {
+                    edges.push(MirEdge {
+                        source: format!("{:?}", bb),
+                        target: format!("{:?}", cleanup),
+                        label: "unwind".to_string(),
+                    });
+                }
+            }
+            TerminatorKind::CoroutineDrop => {}
+            TerminatorKind::TailCall { .. } => {}
         }
     }
 
@@ -351,3 +397,56 @@ pub fn generate_json_from_mir<'mir, 'tcx>(
     serde_json::to_writer(&mut file, &mir_graph)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{local_named, run_on_fn};
+
+    #[test]
+    fn format_rvalue_of_repeat_and_other_rvalues() {
+        run_on_fn("24_array_repeat.rs", "main", |tcx, body| {
+            let rp = PlaceRepacker::new(&body.body, tcx);
+            let formatted: Vec<_> = body
+                .body
+                .basic_blocks
+                .iter()
+                .flat_map(|data| &data.statements)
+                .filter_map(|stmt| match &stmt.kind {
+                    mir::StatementKind::Assign(box (_, rvalue)) => Some(format_rvalue(rvalue, rp)),
+                    _ => None,
+                })
+                .collect();
+            // `vec![a[i], a[0]]` allocates the box of the array in place
+            for expected in [
+                "[const 0_u32; 8]",
+                "Len(a)",
+                "SizeOf([u32; 2])",
+                "AlignOf([u32; 2])",
+            ] {
+                assert!(
+                    formatted.iter().any(|rvalue| rvalue == expected),
+                    "{} is not in {:?}",
+                    expected,
+                    formatted
+                );
+            }
+            assert!(formatted
+                .iter()
+                .any(|rvalue| rvalue.starts_with("ShallowInitBox(move ")
+                    && rvalue.ends_with(", [u32; 2])")));
+            // Deref copies are only inserted after borrow checking and
+            // thread-local statics are unstable, so these are built by hand
+            let v = tcx.mk_place_deref(local_named(&body.body, "v").into());
+            assert_eq!(
+                format_rvalue(&Rvalue::CopyForDeref(v), rp),
+                "CopyForDeref(*v)"
+            );
+            let def_id = body.body.source.def_id();
+            assert_eq!(
+                format_rvalue(&Rvalue::ThreadLocalRef(def_id), rp),
+                "ThreadLocalRef(main)"
+            );
+        });
+    }
+}
@@ -16,13 +16,14 @@ use crate::{
     utils::{Place, PlaceRepacker, SnapshotLocation},
 };
 use std::{
-    collections::HashSet,
+    collections::BTreeSet,
     fs::File,
     io::{self, Write},
 };
 
 use dot::escape_html;
 use rustc_interface::{
+    ast::Mutability,
     borrowck::{
         borrow_set::BorrowSet,
         consumers::{BorrowIndex, PoloniusInput},
@@ -41,7 +42,7 @@ use self::{
 };
 
 pub fn place_id<'tcx>(place: &Place<'tcx>) -> String {
-    format!("{:?}", place)
+    format!("_{}{}", place.local.as_usize(), place.projection_codes().join(""))
 }
 
 struct GraphDrawer<T: io::Write> {
@@ -106,6 +107,16 @@ impl GraphNode {
                     } else {
                         "black"
                     };
+                // The shape also encodes the capability, so that it can be told
+                // apart without relying on the color
+                let shape = match capability {
+                    Some(CapabilityKind::Exclusive) => "ellipse",
+                    Some(CapabilityKind::Write) => "box",
+                    Some(CapabilityKind::ShallowExclusive) => "diamond",
+                    Some(CapabilityKind::Aliased) => "hexagon",
+                    Some(CapabilityKind::Read) => "parallelogram",
+                    None => "rect",
+                };
                 let region_html = match region {
                     Some(r) => format!("<br/>{}", r),
                     None => "".to_string(),
@@ -122,7 +133,7 @@ impl GraphNode {
                     label: DotLabel::Html(label),
                     color: DotStringAttr(color.to_string()),
                     font_color: DotStringAttr(color.to_string()),
-                    shape: DotStringAttr("rect".to_string()),
+                    shape: DotStringAttr(shape.to_string()),
                     style: None,
                     penwidth: None,
                 }
@@ -174,7 +185,7 @@ impl std::fmt::Display for ReferenceEdgeType {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
 enum GraphEdge {
     AbstractEdge {
         blocked: NodeId,
@@ -186,6 +197,7 @@ enum GraphEdge {
         location: Location,
         region: String,
         path_conditions: String,
+        mutability: Mutability,
     },
     ProjectionEdge {
         source: NodeId,
@@ -215,13 +227,20 @@ impl GraphEdge {
                 location: _,
                 region,
                 path_conditions,
-            } => DotEdge {
-                to: assigned_place.to_string(),
-                from: borrowed_place.to_string(),
-                options: EdgeOptions::directed(EdgeDirection::Backward)
-                    .with_color("orange".to_string())
-                    .with_label(format!("{} - {}", region, path_conditions)),
-            },
+                mutability,
+            } => {
+                let (color, kind) = match mutability {
+                    Mutability::Mut => ("orange", "mut"),
+                    Mutability::Not => ("slategray", "shared"),
+                };
+                DotEdge {
+                    to: assigned_place.to_string(),
+                    from: borrowed_place.to_string(),
+                    options: EdgeOptions::directed(EdgeDirection::Backward)
+                        .with_color(color.to_string())
+                        .with_label(format!("{} - {} ({})", region, path_conditions, kind)),
+                }
+            }
             GraphEdge::DerefExpansionEdge { source, target } => DotEdge {
                 from: source.to_string(),
                 to: target.to_string(),
@@ -246,15 +265,15 @@ impl GraphEdge {
 
 pub struct Graph {
     nodes: Vec<GraphNode>,
-    edges: HashSet<GraphEdge>,
-    clusters: HashSet<GraphCluster>,
+    edges: BTreeSet<GraphEdge>,
+    clusters: BTreeSet<GraphCluster>,
 }
 
 impl Graph {
     fn new(
         nodes: Vec<GraphNode>,
-        edges: HashSet<GraphEdge>,
-        clusters: HashSet<GraphCluster>,
+        edges: BTreeSet<GraphEdge>,
+        clusters: BTreeSet<GraphCluster>,
     ) -> Self {
         Self {
             nodes,
@@ -283,7 +302,8 @@ pub fn generate_dot_graph<'a, 'tcx: 'a>(
     borrow_set: &BorrowSet<'tcx>,
     file_path: &str,
 ) -> io::Result<()> {
-    let constructor = PCSGraphConstructor::new(summary, repacker, borrows_domain, borrow_set);
+    let constructor =
+        PCSGraphConstructor::new(summary, repacker, borrows_domain, borrow_set, None);
     let graph = constructor.construct_graph();
     let drawer = GraphDrawer::new(File::create(file_path).unwrap_or_else(|e| {
         panic!("Failed to create file at path: {}: {}", file_path, e);
@@ -0,0 +1,15 @@
+fn conditional_reborrow<'a>(flag: bool, r: &'a mut i32, other: &'a mut i32) -> &'a mut i32 {
+	let mut out = other;
+	if flag && *r > 0 {
+		out = &mut *r;
+	}
+	out
+}
+
+fn main() {
+	let mut x = 1;
+	let mut y = 2;
+	let z = conditional_reborrow(true, &mut x, &mut y);
+	*z = 0;
+	assert!(x == 0);
+}
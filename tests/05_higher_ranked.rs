@@ -0,0 +1,17 @@
+fn first(x: &i32) -> &i32 {
+	x
+}
+
+struct Holder {
+	f: for<'a> fn(&'a i32) -> &'a i32,
+}
+
+fn store(f: for<'a> fn(&'a i32) -> &'a i32) -> Holder {
+	let g = f;
+	let h = Holder { f: g };
+	h
+}
+
+fn main() {
+	let _h = store(first);
+}
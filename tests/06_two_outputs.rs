@@ -0,0 +1,19 @@
+fn pair<'a, 'b>(x: &'a mut i32, y: &'b mut i32) -> (&'a mut i32, &'b mut i32) {
+	(x, y)
+}
+
+fn same<'a>(x: &'a mut i32, y: &'a mut i32) -> (&'a mut i32, &'a mut i32) {
+	(x, y)
+}
+
+fn main() {
+	let mut a = 1;
+	let mut b = 2;
+	let (ra, rb) = pair(&mut a, &mut b);
+	*ra = 0;
+	*rb = 0;
+	let (sa, sb) = same(&mut a, &mut b);
+	*sa = 1;
+	*sb = 1;
+	assert!(a == b);
+}
@@ -0,0 +1,22 @@
+struct Inner<'a> {
+	r: &'a mut i32,
+}
+
+struct Outer<'a> {
+	inner: Inner<'a>,
+	n: i32,
+}
+
+fn consume(_inner: Inner<'_>) {}
+
+fn main() {
+	let mut x = 1;
+	let outer = Outer {
+		inner: Inner { r: &mut x },
+		n: 0,
+	};
+	*outer.inner.r += outer.n;
+	consume(outer.inner);
+	x = 2;
+	assert!(x == 2);
+}
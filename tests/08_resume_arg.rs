@@ -0,0 +1,22 @@
+#![feature(coroutines, coroutine_trait, stmt_expr_attributes)]
+
+use std::ops::{Coroutine, CoroutineState};
+use std::pin::Pin;
+
+fn main() {
+	let mut co = #[coroutine]
+	|mut r: &mut i32| {
+		loop {
+			*r += 1;
+			r = yield *r;
+		}
+	};
+	let mut a = 0;
+	let mut b = 10;
+	if let CoroutineState::Yielded(n) = Pin::new(&mut co).resume(&mut a) {
+		assert!(n == 1);
+	}
+	if let CoroutineState::Yielded(n) = Pin::new(&mut co).resume(&mut b) {
+		assert!(n == 11);
+	}
+}
@@ -0,0 +1,20 @@
+use std::cell::Cell;
+
+struct Node<'a> {
+	next: Cell<Option<&'a Node<'a>>>,
+	value: i32,
+}
+
+fn link<'a>(node: &'a Node<'a>) {
+	node.next.set(Some(node));
+}
+
+fn main() {
+	let node = Node {
+		next: Cell::new(None),
+		value: 1,
+	};
+	link(&node);
+	let r = &node;
+	assert!(r.next.get().unwrap().value == 1);
+}
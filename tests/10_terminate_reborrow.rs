@@ -0,0 +1,7 @@
+fn main() {
+	let mut x = 1;
+	let r = &mut x;
+	*r = 2;
+	x = 3;
+	assert!(x == 3);
+}
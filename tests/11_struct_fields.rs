@@ -0,0 +1,26 @@
+struct Pair<'a> {
+	owned: Box<i32>,
+	borrowed: &'a mut i32,
+}
+
+struct Shared<'a> {
+	owned: Box<i32>,
+	borrowed: &'a i32,
+}
+
+const FIVE: &i32 = &5;
+
+fn main() {
+	let b = Box::new(1);
+	let mut x = 2;
+	let p = Pair {
+		owned: b,
+		borrowed: &mut x,
+	};
+	*p.borrowed += *p.owned;
+	let s = Shared {
+		owned: Box::new(1),
+		borrowed: FIVE,
+	};
+	assert!(*s.borrowed + *s.owned == 6);
+}
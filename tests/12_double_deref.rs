@@ -0,0 +1,16 @@
+fn reborrow_inner<'a, 'b>(r: &'a mut &'b mut i32) -> &'a mut i32 {
+	let inner = &mut **r;
+	*inner += 1;
+	inner
+}
+
+fn main() {
+	let mut x = 1;
+	let mut rx = &mut x;
+	let rrx = &mut rx;
+	let y = &mut **rrx;
+	*y = 2;
+	let z = reborrow_inner(&mut rx);
+	*z = 3;
+	assert!(x == 3);
+}
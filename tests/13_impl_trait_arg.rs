@@ -0,0 +1,18 @@
+fn first<'a>(f: impl FnOnce() -> &'a mut i32) -> &'a mut i32 {
+	f()
+}
+
+fn pick<'a>(x: &'a mut i32, _g: impl Fn(&i32) -> bool) -> &'a mut i32 {
+	x
+}
+
+fn main() {
+	let mut x = 1;
+	let rx = &mut x;
+	let r = first(move || rx);
+	*r = 2;
+	let mut y = 3;
+	let s = pick(&mut y, |v| *v > 0);
+	*s = 4;
+	assert!(y == 4);
+}
@@ -0,0 +1,20 @@
+fn copy_ref<'a>(x: &'a i32) -> &'a i32 {
+	let y = x;
+	let z = &*y;
+	z
+}
+
+fn reborrow_field(p: &mut (i32, i32)) -> &mut i32 {
+	let r = &mut p.1;
+	*r += 1;
+	r
+}
+
+fn main() {
+	let a = 1;
+	let b = copy_ref(&a);
+	let mut pair = (*b, 2);
+	let c = reborrow_field(&mut pair);
+	*c = 3;
+	assert!(pair.1 == 3);
+}
@@ -0,0 +1,34 @@
+#![feature(custom_mir, core_intrinsics)]
+
+use std::intrinsics::mir::*;
+
+// `tmp` is only allocated on the `then` branch, so at the join it is
+// allocated on one incoming path but not on the other.
+#[custom_mir(dialect = "built")]
+fn storage_live_one_branch(c: bool) -> i32 {
+	mir! {
+		let tmp: i32;
+		{
+			match c {
+				true => then_branch,
+				_ => join,
+			}
+		}
+
+		then_branch = {
+			StorageLive(tmp);
+			tmp = 1;
+			RET = tmp;
+			Goto(join)
+		}
+
+		join = {
+			RET = 0;
+			Return()
+		}
+	}
+}
+
+fn main() {
+	assert!(storage_live_one_branch(true) == 0);
+}
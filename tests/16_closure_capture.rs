@@ -0,0 +1,6 @@
+fn main() {
+	let mut x = 0;
+	let mut c = || x += 1;
+	c();
+	assert!(x == 1);
+}
@@ -0,0 +1,15 @@
+fn main() {
+	let mut x = 1;
+	let p = std::ptr::addr_of_mut!(x);
+	x = 2;
+	unsafe {
+		*p = 3;
+	}
+	let mut y = (1, 2);
+	let q = std::ptr::addr_of_mut!(y.0);
+	y.1 = 3;
+	unsafe {
+		*q = 4;
+	}
+	let _z = x + y.0;
+}
@@ -0,0 +1,12 @@
+fn consume(_s: String) {}
+
+fn main() {
+	let mut s = String::new();
+	consume(s);
+	s = String::from("again");
+	let _n = s.len();
+	let mut p = (String::new(), String::new());
+	consume(p.0);
+	p.0 = p.1.clone();
+	let _m = p.0.len();
+}
@@ -0,0 +1,27 @@
+fn reborrow_in_both(x: &mut i32, c: bool) {
+	let r = &mut *x;
+	let s;
+	if c {
+		s = &mut *r;
+	} else {
+		s = &mut *r;
+	}
+	*s = 1;
+}
+
+fn pick<'a>(x: &'a mut i32, y: &'a mut i32, c: u8) -> &'a mut i32 {
+	let mut r = &mut *x;
+	if c == 0 {
+		r = &mut *y;
+	} else if c == 1 {
+		r = &mut *y;
+	}
+	r
+}
+
+fn main() {
+	let mut a = 0;
+	let mut b = 0;
+	reborrow_in_both(&mut a, true);
+	*pick(&mut a, &mut b, 1) = 2;
+}
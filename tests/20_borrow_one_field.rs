@@ -0,0 +1,16 @@
+struct Pair {
+	a: i32,
+	b: i32,
+	c: (i32, i32),
+}
+
+fn main() {
+	let mut x = Pair { a: 0, b: 0, c: (0, 0) };
+	let r = &mut x.a;
+	x.b = 1;
+	let s = &mut x.c.0;
+	x.c.1 = 2;
+	*r = 3;
+	*s = 4;
+	x.b = x.a;
+}
@@ -0,0 +1,32 @@
+struct S {
+	f: i32,
+}
+
+fn read_twice(x: &S) -> i32 {
+	let r = &*x;
+	let y = &r.f;
+	let a = *y;
+	a + *y + r.f
+}
+
+fn field(x: &S) -> &i32 {
+	&x.f
+}
+
+fn reassign(s: S, other: S) -> i32 {
+	let mut r = &s;
+	let f = field(r);
+	r = &other;
+	*f + r.f
+}
+
+fn main() {
+	let s = S { f: 1 };
+	let r = &s;
+	let t = &*r;
+	let u = &t.f;
+	let v = *u;
+	let w = read_twice(t) + *u + v;
+	assert!(w > 0);
+	assert!(reassign(S { f: 1 }, S { f: 2 }) == 3);
+}
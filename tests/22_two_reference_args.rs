@@ -0,0 +1,17 @@
+fn pick<'a>(x: &'a mut i32, y: &'a mut i32, first: bool) -> &'a mut i32 {
+	if first {
+		*y += 1;
+		x
+	} else {
+		*x += 1;
+		y
+	}
+}
+
+fn main() {
+	let mut a = 1;
+	let mut b = 2;
+	let r = pick(&mut a, &mut b, true);
+	*r += 1;
+	assert!(a == 3);
+}
@@ -0,0 +1,10 @@
+fn count(r: &[i32]) -> usize {
+	r.len()
+}
+
+fn main() {
+	let r: &[i32] = &[1, 2, 3];
+	let s = "hello";
+	let x = count(r);
+	assert!(x == 3 && s.len() == 5);
+}
@@ -0,0 +1,17 @@
+fn sum(v: &Vec<u32>) -> u32 {
+	let mut total = 0;
+	for x in v.iter() {
+		total += *x;
+	}
+	total
+}
+
+fn main() {
+	let mut a = [0u32; 8];
+	let i = 3;
+	a[i] = 1;
+	let v = vec![a[i]; 4];
+	assert!(sum(&v) == 4);
+	let w = vec![a[i], a[0]];
+	assert!(sum(&w) == 1);
+}
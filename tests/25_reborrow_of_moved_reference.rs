@@ -0,0 +1,11 @@
+fn consume(r: &mut i32) {
+	*r += 1;
+}
+
+fn main() {
+	let mut x = 1;
+	let r = &mut x;
+	let s = &mut *r;
+	consume(s);
+	assert!(x == 2);
+}
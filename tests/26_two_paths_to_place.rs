@@ -0,0 +1,16 @@
+struct Pair {
+	a: i32,
+	b: i32,
+}
+
+fn choose<'a>(x: &'a mut i32, y: &'a mut i32, first: bool) -> &'a mut i32 {
+	if first { x } else { y }
+}
+
+fn main() {
+	let mut p = Pair { a: 1, b: 2 };
+	let r = &mut p;
+	let z = choose(&mut r.a, &mut r.b, true);
+	*z += 1;
+	assert!(p.a == 2);
+}
@@ -0,0 +1,12 @@
+fn main() {
+	let mut a = 1;
+	let mut b = 2;
+	let mut c = 3;
+	let t = (1, &mut a);
+	*t.1 += 1;
+	let arr = [&mut b, &mut c];
+	for r in arr {
+		*r += 1;
+	}
+	assert!(a == 2 && b == 3 && c == 4);
+}
@@ -0,0 +1,10 @@
+fn main() {
+	let mut x = 1;
+	let p = &raw mut x;
+	unsafe {
+		*p = 2;
+	}
+	let y = 3;
+	let q = &raw const y;
+	let _z = unsafe { *q } + x;
+}
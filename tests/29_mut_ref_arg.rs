@@ -0,0 +1,8 @@
+fn incr(x: &mut i32) -> &mut i32 {
+	*x += 1;
+	let y = &mut *x;
+	*y += 1;
+	x
+}
+
+fn main() {}
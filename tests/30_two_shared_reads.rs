@@ -0,0 +1,8 @@
+fn main() {
+	let x = (1, 2);
+	let a = &x;
+	let b = &x;
+	let c = x.0;
+	let d = a.1 + b.0 + c;
+	assert!(d == 4 && x.1 == 2);
+}
@@ -0,0 +1,29 @@
+struct List {
+	head: i32,
+	tail: Option<Box<List>>,
+}
+
+fn zero_all(lists: &mut List) {
+	let mut outer = Some(lists);
+	while let Some(l) = outer {
+		let mut inner = &mut l.tail;
+		while let Some(n) = inner {
+			n.head = 0;
+			inner = &mut n.tail;
+		}
+		l.head = 0;
+		outer = match &mut l.tail {
+			Some(t) => Some(&mut **t),
+			None => None,
+		};
+	}
+}
+
+fn main() {
+	let mut l = List {
+		head: 1,
+		tail: Some(Box::new(List { head: 2, tail: None })),
+	};
+	zero_all(&mut l);
+	assert!(l.head == 0);
+}
@@ -0,0 +1,26 @@
+fn constrain<F: Fn(&mut i32) -> &mut i32>(f: F) -> F {
+	f
+}
+
+fn apply<F: FnMut(&mut i32) -> &mut i32>(mut f: F, x: &mut i32) -> &mut i32 {
+	f(x)
+}
+
+fn id(r: &mut i32) -> &mut i32 {
+	r
+}
+
+fn main() {
+	let mut x = 1;
+	let f = constrain(|r| r);
+	let s = f(&mut x);
+	*s = 2;
+	let g: fn(&mut i32) -> &mut i32 = id;
+	let t = g(&mut x);
+	*t = 3;
+	let h: &dyn Fn(&mut i32) -> &mut i32 = &f;
+	let u = h(&mut x);
+	*u = 4;
+	*apply(|r| r, &mut x) = 5;
+	assert!(x == 5);
+}
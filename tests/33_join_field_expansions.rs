@@ -0,0 +1,19 @@
+struct S<'a> {
+	f: &'a mut i32,
+	g: &'a mut i32,
+}
+
+fn main() {
+	let mut a = 0;
+	let mut b = 0;
+	let mut s = S { f: &mut a, g: &mut b };
+	let x = &mut s;
+	let c = std::env::args().count() > 1;
+	if c {
+		*x.f += 1;
+	} else {
+		*x.g += 2;
+	}
+	*x.f = 3;
+	assert!(a == 3);
+}
@@ -0,0 +1,15 @@
+fn main() {
+	let mut x = (1, 2);
+	let r = &mut x;
+	let a = &r.0;
+	let b = &*r;
+	let c = r.1;
+	let d = *a + b.0 + c;
+	assert!(d == 4);
+	let y = (3, 4);
+	let e = &y;
+	let f = &y;
+	let g = y.0;
+	assert!(g == 3);
+	assert!(e.1 + f.0 == 7);
+}
@@ -0,0 +1,16 @@
+fn reborrow(x: &mut i32) -> &mut i32 {
+	&mut *x
+}
+
+fn first<'a>(x: &'a mut i32, _y: &'a mut i32) -> &'a mut i32 {
+	x
+}
+
+fn main() {
+	let mut a = 1;
+	let mut b = 2;
+	let r = reborrow(&mut a);
+	*r += 1;
+	let s = first(&mut a, &mut b);
+	*s += 1;
+}
@@ -0,0 +1,14 @@
+fn main() {
+	let mut opt = Some(1);
+	match &mut opt {
+		Some(x) if *x > 0 => *x += 1,
+		Some(x) => *x = 0,
+		None => {}
+	}
+	let mut pair = (Some(2), 3);
+	match pair {
+		(Some(ref mut y), z) if z > 1 => *y += z,
+		_ => pair.1 = 0,
+	}
+	assert!(opt == Some(2));
+}
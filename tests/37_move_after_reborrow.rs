@@ -0,0 +1,14 @@
+fn take(r: &mut i32) -> &mut i32 {
+	r
+}
+
+fn main() {
+	let mut x = 1;
+	let mut r = &mut x;
+	let s = &mut *r;
+	*s += 1;
+	r = take(r);
+	*r += 1;
+	let t = r;
+	*t += 1;
+}
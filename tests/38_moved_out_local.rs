@@ -0,0 +1,10 @@
+fn consume(s: String) -> usize {
+	s.len()
+}
+
+fn main() {
+	let s = String::new();
+	let t = s;
+	let n = consume(t);
+	assert!(n == 0);
+}
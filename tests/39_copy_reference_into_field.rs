@@ -0,0 +1,28 @@
+#![feature(custom_mir, core_intrinsics)]
+
+use std::intrinsics::mir::*;
+
+struct Holder<'a> {
+	r: &'a i32,
+}
+
+// Stores a copy of the shared reference `r` in `h.r`: the borrow checker
+// MIR of `h.r = r` reborrows `*r` first, so the copy is written by hand.
+#[custom_mir(dialect = "built")]
+fn copy_into_field<'a>(h: &mut Holder<'a>, r: &'a i32) {
+	mir! {
+		{
+			(*h).r = r;
+			RET = ();
+			Return()
+		}
+	}
+}
+
+fn main() {
+	let x = 1;
+	let y = 2;
+	let mut h = Holder { r: &x };
+	copy_into_field(&mut h, &y);
+	assert!(*h.r == 2);
+}
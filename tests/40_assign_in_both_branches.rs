@@ -0,0 +1,49 @@
+#![feature(custom_mir, core_intrinsics)]
+
+use std::intrinsics::mir::*;
+
+// `x` is assigned in both arms of the `if`, reborrowed, then deallocated: the
+// reborrowed place is made old in each arm, with the snapshot of `x` in that
+// arm.
+#[custom_mir(dialect = "built")]
+fn assign_in_both_branches(c: bool) -> i32 {
+	mir! {
+		let a: i32;
+		let b: i32;
+		let x: &mut i32;
+		let r: &mut i32;
+		{
+			a = 1;
+			b = 2;
+			match c {
+				true => then_branch,
+				_ => else_branch,
+			}
+		}
+
+		then_branch = {
+			StorageLive(x);
+			x = &mut a;
+			r = &mut (*x);
+			StorageDead(x);
+			Goto(join)
+		}
+
+		else_branch = {
+			StorageLive(x);
+			x = &mut b;
+			r = &mut (*x);
+			StorageDead(x);
+			Goto(join)
+		}
+
+		join = {
+			RET = *r;
+			Return()
+		}
+	}
+}
+
+fn main() {
+	assert!(assign_in_both_branches(true) == 1);
+}
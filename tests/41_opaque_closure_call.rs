@@ -0,0 +1,10 @@
+fn make() -> impl Fn(&mut i32) -> &mut i32 {
+	|r| r
+}
+
+fn main() {
+	let mut x = 1;
+	let f = make();
+	*f(&mut x) = 2;
+	assert!(x == 2);
+}
@@ -0,0 +1,19 @@
+trait Tr<'a> {
+	type Out;
+}
+
+impl<'a> Tr<'a> for () {
+	type Out = u32;
+}
+
+fn f<'a, T: Tr<'a>>(_x: <T as Tr<'a>>::Out, r: &'a mut i32) -> &'a mut i32 {
+	r
+}
+
+fn main() {
+	let mut v = 1;
+	let n: u32 = 2;
+	let r = f::<()>(n, &mut v);
+	*r = 3;
+	assert!(v == 3);
+}
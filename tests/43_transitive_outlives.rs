@@ -0,0 +1,11 @@
+fn shorten<'a: 'b, 'b: 'c, 'c>(x: &'a mut i32, _y: &'b i32) -> &'c mut i32 {
+	x
+}
+
+fn main() {
+	let mut x = 1;
+	let y = 2;
+	let r = shorten(&mut x, &y);
+	*r = 3;
+	assert!(x == 3);
+}
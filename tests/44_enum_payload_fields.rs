@@ -0,0 +1,19 @@
+enum List {
+	Cons(u32, Box<List>),
+	Nil,
+}
+
+fn bump_head(list: &mut List) {
+	match list {
+		List::Cons(head, tail) => {
+			*head += 1;
+			bump_head(tail);
+		}
+		List::Nil => {}
+	}
+}
+
+fn main() {
+	let mut list = List::Cons(1, Box::new(List::Cons(2, Box::new(List::Nil))));
+	bump_head(&mut list);
+}
@@ -0,0 +1,14 @@
+fn first<'a>(v: &'a mut Vec<i32>) -> &'a mut i32 {
+	&mut v[0]
+}
+
+fn first_of_arg<'a>(v: &'a mut Vec<i32>) -> &'a mut i32 {
+	first(v)
+}
+
+fn main() {
+	let mut v = vec![1, 2];
+	let x = first_of_arg(&mut v);
+	*x = 3;
+	assert!(v[0] == 3);
+}
@@ -0,0 +1,9 @@
+fn make() -> String {
+	String::from("a")
+}
+
+fn main() {
+	let s = String::new();
+	let t = make();
+	assert!(s.len() < t.len());
+}
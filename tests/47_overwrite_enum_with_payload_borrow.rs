@@ -0,0 +1,8 @@
+fn main() {
+	let mut opt = Some(1);
+	if let Some(x) = &mut opt {
+		*x += 1;
+	}
+	opt = None;
+	assert!(opt.is_none());
+}
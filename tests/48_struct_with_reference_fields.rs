@@ -0,0 +1,13 @@
+struct Pair<'a> {
+	a: &'a mut i32,
+	b: &'a mut i32,
+}
+
+fn main() {
+	let mut x = 1;
+	let mut y = 2;
+	let p = Pair { a: &mut x, b: &mut y };
+	*p.a = 3;
+	*p.b = 4;
+	assert!(x == 3 && y == 4);
+}
@@ -0,0 +1,58 @@
+#![feature(custom_mir, core_intrinsics)]
+
+use std::intrinsics::mir::*;
+
+// `after_edit` is `before_edit` where the block moving out of `s` returns
+// instead of going to `join`: `join` is unchanged, but no longer has a
+// predecessor where `s` is moved out.
+#[custom_mir(dialect = "built")]
+fn before_edit(c: bool, s: String) -> i32 {
+	mir! {
+		let t: String;
+		{
+			match c {
+				true => moved,
+				_ => join,
+			}
+		}
+
+		moved = {
+			t = Move(s);
+			Goto(join)
+		}
+
+		join = {
+			RET = 0;
+			Return()
+		}
+	}
+}
+
+#[custom_mir(dialect = "built")]
+fn after_edit(c: bool, s: String) -> i32 {
+	mir! {
+		let t: String;
+		{
+			match c {
+				true => moved,
+				_ => join,
+			}
+		}
+
+		moved = {
+			t = Move(s);
+			RET = 1;
+			Return()
+		}
+
+		join = {
+			RET = 0;
+			Return()
+		}
+	}
+}
+
+fn main() {
+	assert!(before_edit(false, String::new()) == 0);
+	assert!(after_edit(true, String::new()) == 1);
+}
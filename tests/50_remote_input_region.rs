@@ -0,0 +1,18 @@
+struct Holder<'a> {
+	r: &'a mut i32,
+}
+
+fn take<'a>(h: Holder<'a>) -> &'a mut i32 {
+	h.r
+}
+
+fn forward<'a>(h: Holder<'a>) -> &'a mut i32 {
+	take(h)
+}
+
+fn main() {
+	let mut x = 1;
+	let r = forward(Holder { r: &mut x });
+	*r = 2;
+	assert!(x == 2);
+}
@@ -0,0 +1,15 @@
+fn first(v: &mut (i32, i32)) -> &mut i32 {
+	&mut v.0
+}
+
+const FIRST: for<'a> fn(&'a mut (i32, i32)) -> &'a mut i32 = first;
+
+fn main() {
+	let mut p = (1, 2);
+	let x = FIRST(&mut p);
+	*x = 3;
+	let f = FIRST;
+	let y = f(&mut p);
+	*y = 4;
+	assert!(p.0 == 4);
+}
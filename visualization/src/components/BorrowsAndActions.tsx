@@ -6,6 +6,7 @@ import {
   Reborrow,
   ReborrowAction,
   MaybeOldPlace,
+  ReborrowBlockedPlace,
   ReborrowBridge,
   PlaceExpand,
 } from "../types";
@@ -18,11 +19,31 @@ function MaybeOldPlaceDisplay({
 }) {
   return (
     <div>
-      {maybeOldPlace.place} {maybeOldPlace.at ? `at ${maybeOldPlace.at}` : ""}
+      {maybeOldPlace.display} {maybeOldPlace.at ? `at ${maybeOldPlace.at}` : ""}
     </div>
   );
 }
 
+function ReborrowBlockedPlaceDisplay({
+  blockedPlace,
+}: {
+  blockedPlace: ReborrowBlockedPlace;
+}) {
+  if ("type" in blockedPlace) {
+    switch (blockedPlace.type) {
+      case "remote":
+        return (
+          <div>
+            Target of input {blockedPlace.name ?? blockedPlace.local}
+          </div>
+        );
+      case "constant":
+        return <div>Constant at {blockedPlace.location}</div>;
+    }
+  }
+  return <MaybeOldPlaceDisplay maybeOldPlace={blockedPlace} />;
+}
+
 function ReborrowDisplay({ reborrow }: { reborrow: Reborrow }) {
   return (
     <div>
@@ -32,7 +53,7 @@ function ReborrowDisplay({ reborrow }: { reborrow: Reborrow }) {
       </p>
       <p>
         Blocked Place:{" "}
-        <MaybeOldPlaceDisplay maybeOldPlace={reborrow?.blocked_place} />
+        <ReborrowBlockedPlaceDisplay blockedPlace={reborrow?.blocked_place} />
       </p>
       <p>Mutable: {reborrow?.is_mut ? "Yes" : "No"}</p>
     </div>
@@ -44,7 +65,8 @@ function BridgeExpands({ expands }: { expands: PlaceExpand[] }) {
     <div>
       {expands.map((expand, idx) => (
         <div key={`expand-${idx}`}>
-          {expand.base.place} -&gt; {expand.expansion.join(", ")}
+          {expand.base.display} -&gt;{" "}
+          {expand.expansion.map((p) => p.display).join(", ")}
         </div>
       ))}
     </div>
@@ -54,8 +76,8 @@ function BridgeExpands({ expands }: { expands: PlaceExpand[] }) {
 function BorrowDisplay({ borrow }: { borrow: Borrow }) {
   return (
     <div>
-      <p>Assigned: {borrow?.assigned_place?.place}</p>
-      <p>Borrowed: {borrow?.borrowed_place?.place}</p>
+      <p>Assigned: {borrow?.assigned_place?.display}</p>
+      <p>Borrowed: {borrow?.borrowed_place?.display}</p>
       <p>Is Mutable: {borrow?.is_mut ? "Yes" : "No"}</p>
       <p>Kind: {borrow?.kind}</p>
     </div>
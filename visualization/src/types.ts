@@ -37,8 +37,14 @@ export type DagreNode<T> = {
   height: number;
 };
 
+export type PlaceEncoding = {
+  local: number;
+  projection: string[];
+};
+
 export type MaybeOldPlace = {
-  place: string;
+  place: PlaceEncoding;
+  display: string;
   at?: string;
 };
 
@@ -49,12 +55,51 @@ export type Borrow = {
   kind: string;
 };
 
+export type ReborrowBlockedPlace =
+  | MaybeOldPlace
+  | {
+      type: "remote";
+      local: string;
+      name: string | null;
+    }
+  | {
+      type: "constant";
+      location: string;
+    };
+
 export type Reborrow = {
-  blocked_place: MaybeOldPlace;
+  blocked_place: ReborrowBlockedPlace;
   assigned_place: MaybeOldPlace;
   is_mut: boolean;
+  is_raw_pointer: boolean;
 };
 
+export type RegionProjection<P = MaybeOldPlace> = {
+  place: P;
+  region: string;
+};
+
+export type AbstractionBlockEdge = {
+  input: ReborrowBlockedPlace | RegionProjection<ReborrowBlockedPlace>;
+  output: MaybeOldPlace | RegionProjection;
+};
+
+export type RegionAbstraction =
+  | {
+      type: "FunctionCall";
+      location: string;
+      def_id: string | null;
+      function: string | null;
+      substs: string;
+      edges: (AbstractionBlockEdge & { arg: number })[];
+    }
+  | {
+      type: "Loop";
+      location: string;
+      block: string;
+      edges: AbstractionBlockEdge[];
+    };
+
 export type BorrowAction = {
   action: "AddBorrow" | "RemoveBorrow";
   borrow: Borrow;
@@ -77,7 +122,7 @@ export type Conditioned<T> = {
 
 export type PlaceExpand = {
   base: MaybeOldPlace,
-  expansion: string[]
+  expansion: MaybeOldPlace[]
 }
 
 export type ReborrowBridge = {
@@ -102,3 +147,31 @@ export type PathData = {
   repacks_middle: string[];
   repacks_start: string[];
 };
+
+export type RepackOp =
+  | {
+      type: "StorageDead" | "IgnoreStorageDead";
+      place: string;
+    }
+  | {
+      type: "Weaken";
+      place: string;
+      from: string;
+      to: string;
+    }
+  | {
+      type: "Expand" | "Collapse";
+      place: string;
+      guide: string;
+      capability: string;
+    }
+  | {
+      type: "DerefShallowInit";
+      place: string;
+      guide: string;
+    };
+
+export type Repacks = {
+  start: RepackOp[];
+  middle: RepackOp[];
+};